@@ -0,0 +1,67 @@
+/*
+ * C interface to plife, a GPU-accelerated simulation of particle life.
+ *
+ * Link against the plife_ffi library built by `cargo build --release -p plife-ffi`. A
+ * simulation owns a GPU device of its own. Functions returning a pointer return NULL on
+ * failure, and the read functions return SIZE_MAX, after which plife_last_error says why.
+ */
+#ifndef PLIFE_H
+#define PLIFE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct PlifeSimulation PlifeSimulation;
+
+/* Formats of the config text given to plife_create */
+#define PLIFE_FORMAT_YAML 0
+#define PLIFE_FORMAT_JSON 1
+#define PLIFE_FORMAT_TOML 2
+
+/* Creates a simulation from the text of a config, sampling its random parts from seed */
+PlifeSimulation *plife_create(const char *config, uint32_t format, uint64_t seed);
+
+/* Creates a simulation from a config file, whose format is chosen by its extension */
+PlifeSimulation *plife_create_from_file(const char *path, uint64_t seed);
+
+/* Runs steps steps. They may still be running on the GPU when this returns. */
+void plife_step(PlifeSimulation *simulation, uint32_t steps);
+
+/* Number of particles, which faucets, drains and lifetimes change as the simulation runs */
+uint32_t plife_particle_count(const PlifeSimulation *simulation);
+
+/* Number of particle types */
+uint32_t plife_type_count(const PlifeSimulation *simulation);
+
+/*
+ * Waits for the steps run so far and writes x0, y0, x1, y1, ... for up to capacity particles to
+ * positions, which must have room for 2 * capacity floats. Returns the number of particles there
+ * are, which may be more than capacity, or SIZE_MAX if reading them failed.
+ */
+size_t plife_read_positions(PlifeSimulation *simulation, float *positions, size_t capacity);
+
+/*
+ * Waits for the steps run so far and writes the types of up to capacity particles to types, in
+ * the same order as plife_read_positions. Returns the number of particles there are, or SIZE_MAX
+ * if reading them failed.
+ */
+size_t plife_read_types(PlifeSimulation *simulation, uint32_t *types, size_t capacity);
+
+/* Frees a simulation and its GPU device. Does nothing when given NULL. */
+void plife_destroy(PlifeSimulation *simulation);
+
+/*
+ * Why the most recent failing call on this thread failed, or NULL if none has. The string
+ * stays valid until the next failing call on the same thread.
+ */
+const char *plife_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* PLIFE_H */
@@ -0,0 +1,272 @@
+//! C interface for embedding plife in game engines and creative coding frameworks. Every function
+//! here is declared in `include/plife.h`, which is the stable interface; the Rust names may
+//! change.
+
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString},
+    os::raw::c_char,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    ptr, slice,
+};
+
+use plife::{
+    serialize::{Config, ConfigFormat},
+    simulation::{default_workgroup_size, Simulation, STORAGE_BUFFERS_PER_STAGE},
+    util::seed_rng,
+};
+use wgpu::{
+    BackendBit, DeviceDescriptor, Features, Instance, Limits, PowerPreference,
+    RequestAdapterOptions,
+};
+
+thread_local! {
+    /// Why the last call on this thread failed, for `plife_last_error`
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+fn set_last_error(error: String) {
+    // Messages never contain nul bytes, but C strings cannot hold them if one ever does
+    let error = CString::new(error.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(error));
+}
+
+/// Runs `f`, recording its error or panic for `plife_last_error` instead of letting either reach
+/// the caller
+fn guard<T>(f: impl FnOnce() -> Result<T, String>) -> Option<T> {
+    let result = panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|panic| {
+        Err(panic
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "plife panicked".to_owned()))
+    });
+    result.map_err(set_last_error).ok()
+}
+
+/// A simulation together with the GPU device it runs on
+pub struct PlifeSimulation {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    simulation: Simulation,
+}
+
+impl PlifeSimulation {
+    fn new(config: Config, seed: u64) -> Result<Box<Self>, String> {
+        let instance = Instance::new(BackendBit::all());
+        let adapter =
+            futures::executor::block_on(instance.request_adapter(&RequestAdapterOptions {
+                power_preference: PowerPreference::HighPerformance,
+                compatible_surface: None,
+            }))
+            .ok_or_else(|| "Unable to find a suitable graphics adapter".to_owned())?;
+        let (device, queue) = futures::executor::block_on(adapter.request_device(
+            &DeviceDescriptor {
+                label: Some("embedded device"),
+                features: Features::default(),
+                limits: Limits {
+                    max_storage_buffers_per_shader_stage: STORAGE_BUFFERS_PER_STAGE,
+                    ..Limits::default()
+                },
+            },
+            None,
+        ))
+        .map_err(|e| format!("Failed to get device handle: {}", e))?;
+        seed_rng(seed);
+        let workgroup_size = default_workgroup_size(&adapter.get_info());
+        let simulation =
+            Simulation::from_config(&device, config, workgroup_size).map_err(|e| e.0)?;
+        Ok(Box::new(Self {
+            device,
+            queue,
+            simulation,
+        }))
+    }
+}
+
+/// `PLIFE_FORMAT_*` in the header
+fn config_format(format: u32) -> Result<ConfigFormat, String> {
+    match format {
+        0 => Ok(ConfigFormat::Yaml),
+        1 => Ok(ConfigFormat::Json),
+        2 => Ok(ConfigFormat::Toml),
+        _ => Err(format!("Unknown config format {}", format)),
+    }
+}
+
+unsafe fn str_arg<'a>(string: *const c_char, name: &str) -> Result<&'a str, String> {
+    if string.is_null() {
+        return Err(format!("{} is null", name));
+    }
+    CStr::from_ptr(string)
+        .to_str()
+        .map_err(|_| format!("{} is not valid UTF-8", name))
+}
+
+/// Creates a simulation from the text of a config.
+///
+/// # Safety
+///
+/// `config` must be a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn plife_create(
+    config: *const c_char,
+    format: u32,
+    seed: u64,
+) -> *mut PlifeSimulation {
+    guard(|| {
+        let config = Config::from_reader(
+            str_arg(config, "config")?.as_bytes(),
+            config_format(format)?,
+            &[],
+        )
+        .map_err(|e| format!("Invalid config:\n{}", e))?;
+        PlifeSimulation::new(config, seed)
+    })
+    .map_or(ptr::null_mut(), Box::into_raw)
+}
+
+/// Creates a simulation from a config file, whose format is chosen by its extension.
+///
+/// # Safety
+///
+/// `path` must be a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn plife_create_from_file(
+    path: *const c_char,
+    seed: u64,
+) -> *mut PlifeSimulation {
+    guard(|| {
+        let path = Path::new(str_arg(path, "path")?);
+        let file = std::fs::File::open(path)
+            .map_err(|e| format!("Cannot open config file {}: {}", path.display(), e))?;
+        let config = Config::from_reader(file, ConfigFormat::from_path(path), &[])
+            .map_err(|e| format!("Invalid config file:\n{}", e))?;
+        PlifeSimulation::new(config, seed)
+    })
+    .map_or(ptr::null_mut(), Box::into_raw)
+}
+
+/// Runs `steps` steps. They may still be running on the GPU when this returns.
+///
+/// # Safety
+///
+/// `simulation` must have come from `plife_create` and not have been destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn plife_step(simulation: *mut PlifeSimulation, steps: u32) {
+    let simulation = &mut *simulation;
+    guard(|| {
+        simulation
+            .simulation
+            .step_many(&simulation.device, &simulation.queue, steps as u64);
+        Ok(())
+    });
+}
+
+/// Number of particles, which faucets, drains and lifetimes change as the simulation runs.
+///
+/// # Safety
+///
+/// `simulation` must have come from `plife_create` and not have been destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn plife_particle_count(simulation: *const PlifeSimulation) -> u32 {
+    (*simulation).simulation.num_points
+}
+
+/// Number of particle types.
+///
+/// # Safety
+///
+/// `simulation` must have come from `plife_create` and not have been destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn plife_type_count(simulation: *const PlifeSimulation) -> u32 {
+    (*simulation).simulation.ruleset.num_point_types
+}
+
+/// Waits for the steps run so far and writes the `x` and `y` of up to `capacity` particles to
+/// `positions`. Returns the number of particles there are, or `usize::MAX` if reading them failed.
+///
+/// # Safety
+///
+/// `simulation` must have come from `plife_create` and not have been destroyed, and `positions`
+/// must have room for `2 * capacity` floats.
+#[no_mangle]
+pub unsafe extern "C" fn plife_read_positions(
+    simulation: *mut PlifeSimulation,
+    positions: *mut f32,
+    capacity: usize,
+) -> usize {
+    let simulation = &*simulation;
+    guard(|| {
+        Ok(simulation
+            .simulation
+            .read_points(&simulation.device, &simulation.queue))
+    })
+    .map_or(usize::MAX, |points| {
+        let count = points.len().min(capacity);
+        if count > 0 {
+            let out = slice::from_raw_parts_mut(positions, count * 2);
+            for (out, &(x, y, _)) in out.chunks_exact_mut(2).zip(&points) {
+                out[0] = x;
+                out[1] = y;
+            }
+        }
+        points.len()
+    })
+}
+
+/// Waits for the steps run so far and writes the types of up to `capacity` particles to `types`,
+/// in the same order as `plife_read_positions`. Returns the number of particles there are, or
+/// `usize::MAX` if reading them failed.
+///
+/// # Safety
+///
+/// `simulation` must have come from `plife_create` and not have been destroyed, and `types` must
+/// have room for `capacity` integers.
+#[no_mangle]
+pub unsafe extern "C" fn plife_read_types(
+    simulation: *mut PlifeSimulation,
+    types: *mut u32,
+    capacity: usize,
+) -> usize {
+    let simulation = &*simulation;
+    guard(|| {
+        Ok(simulation
+            .simulation
+            .read_points(&simulation.device, &simulation.queue))
+    })
+    .map_or(usize::MAX, |points| {
+        let count = points.len().min(capacity);
+        if count > 0 {
+            let out = slice::from_raw_parts_mut(types, count);
+            for (out, &(_, _, type_)) in out.iter_mut().zip(&points) {
+                *out = type_.unwrap_or(0);
+            }
+        }
+        points.len()
+    })
+}
+
+/// Frees a simulation and its GPU device. Does nothing when given null.
+///
+/// # Safety
+///
+/// `simulation` must be null or have come from `plife_create` and not have been destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn plife_destroy(simulation: *mut PlifeSimulation) {
+    if !simulation.is_null() {
+        drop(Box::from_raw(simulation));
+    }
+}
+
+/// Why the most recent failing call on this thread failed, or null if none has. The string
+/// stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn plife_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |error| error.as_ptr())
+    })
+}
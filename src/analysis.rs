@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::simulation::{Point, PointType};
+
+/// Particles closer than this belong to the same cluster
+pub const CLUSTER_RADIUS: f32 = 10.0;
+/// Groups with fewer particles than this are not counted as clusters
+pub const MIN_CLUSTER_SIZE: usize = 5;
+
+/// Mean squared distance of the points from their centroid
+pub fn spatial_variance(points: &[Point]) -> f32 {
+    if points.is_empty() {
+        return 0.0;
+    }
+    let n = points.len() as f32;
+    let (sx, sy) = points
+        .iter()
+        .fold((0.0, 0.0), |(sx, sy), &(x, y, _)| (sx + x, sy + y));
+    let (cx, cy) = (sx / n, sy / n);
+    points
+        .iter()
+        .map(|&(x, y, _)| (x - cx) * (x - cx) + (y - cy) * (y - cy))
+        .sum::<f32>()
+        / n
+}
+
+/// Groups points into clusters of particles within `radius` of a neighbour, and returns the
+/// size of every cluster with at least `min_size` particles
+pub fn clusters(points: &[Point], radius: f32, min_size: usize) -> Vec<usize> {
+    let cell_of = |x: f32, y: f32| ((x / radius).floor() as i64, (y / radius).floor() as i64);
+    let mut grid: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    for (i, &(x, y, _)) in points.iter().enumerate() {
+        if x.is_finite() && y.is_finite() {
+            grid.entry(cell_of(x, y)).or_default().push(i);
+        }
+    }
+
+    let mut parents = (0..points.len()).collect::<Vec<_>>();
+    fn find(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+
+    for (&(cx, cy), members) in &grid {
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                let neighbours = match grid.get(&(cx + dx, cy + dy)) {
+                    Some(neighbours) => neighbours,
+                    None => continue,
+                };
+                for &i in members {
+                    for &j in neighbours {
+                        if j <= i {
+                            continue;
+                        }
+                        let (ax, ay, _) = points[i];
+                        let (bx, by, _) = points[j];
+                        if (ax - bx) * (ax - bx) + (ay - by) * (ay - by) <= radius * radius {
+                            let (a, b) = (find(&mut parents, i), find(&mut parents, j));
+                            parents[a] = b;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let mut sizes: HashMap<usize, usize> = HashMap::new();
+    for i in grid.values().flatten() {
+        *sizes.entry(find(&mut parents, *i)).or_default() += 1;
+    }
+    sizes
+        .into_values()
+        .filter(|&size| size >= min_size)
+        .collect()
+}
+
+/// Number and sizes of the clusters in a set of points
+#[derive(Serialize)]
+pub struct ClusterStats {
+    pub count: usize,
+    pub largest: usize,
+    pub mean_size: f64,
+    /// Size of every cluster, largest first
+    pub sizes: Vec<usize>,
+}
+
+impl ClusterStats {
+    pub fn measure(points: &[Point], radius: f32) -> Self {
+        let mut sizes = clusters(points, radius, MIN_CLUSTER_SIZE);
+        sizes.sort_unstable_by(|a, b| b.cmp(a));
+        let count = sizes.len();
+        Self {
+            count,
+            largest: sizes.first().copied().unwrap_or(0),
+            mean_size: if count == 0 {
+                0.0
+            } else {
+                sizes.iter().sum::<usize>() as f64 / count as f64
+            },
+            sizes,
+        }
+    }
+}
+
+/// How fast particles are moving, treating every particle as having unit mass
+#[derive(Serialize)]
+pub struct MotionStats {
+    pub kinetic_energy: f64,
+    pub mean_speed: f64,
+}
+
+impl MotionStats {
+    pub fn measure(velocities: &[[f32; 2]]) -> Self {
+        let (energy, speed) = velocities
+            .iter()
+            .fold((0.0, 0.0), |(energy, speed), &[vx, vy]| {
+                let v2 = (vx * vx + vy * vy) as f64;
+                (energy + 0.5 * v2, speed + v2.sqrt())
+            });
+        Self {
+            kinetic_energy: energy,
+            mean_speed: if velocities.is_empty() {
+                0.0
+            } else {
+                speed / velocities.len() as f64
+            },
+        }
+    }
+}
+
+/// Population and spatial extent of one type
+#[derive(Serialize)]
+pub struct TypeStats {
+    #[serde(rename = "type")]
+    pub type_: PointType,
+    pub count: usize,
+    /// Center of mass, or the origin if there are no particles of this type. With wrapping walls
+    /// this is the plain average, which is misleading for groups that straddle a wall.
+    pub center: [f64; 2],
+    /// Distance from the center to the furthest particle of this type
+    pub radius: f64,
+}
+
+/// Measures every type in `0..num_types`, in order
+pub fn type_stats(points: &[Point], num_types: PointType) -> Vec<TypeStats> {
+    let mut sums = vec![(0, 0.0, 0.0); num_types as usize];
+    for &(x, y, type_) in points {
+        if let Some(sum) = type_.and_then(|type_| sums.get_mut(type_ as usize)) {
+            *sum = (sum.0 + 1, sum.1 + x as f64, sum.2 + y as f64);
+        }
+    }
+    let mut stats = sums
+        .into_iter()
+        .enumerate()
+        .map(|(type_, (count, sx, sy))| TypeStats {
+            type_: type_ as PointType,
+            count,
+            center: if count == 0 {
+                [0.0, 0.0]
+            } else {
+                [sx / count as f64, sy / count as f64]
+            },
+            radius: 0.0,
+        })
+        .collect::<Vec<_>>();
+    for &(x, y, type_) in points {
+        if let Some(stats) = type_.and_then(|type_| stats.get_mut(type_ as usize)) {
+            let (dx, dy) = (x as f64 - stats.center[0], y as f64 - stats.center[1]);
+            stats.radius = stats.radius.max((dx * dx + dy * dy).sqrt());
+        }
+    }
+    stats
+}
+
+/// Distance from each point to the nearest other point of any type, or infinity if it is alone
+pub fn nearest_neighbour_distances(points: &[Point]) -> Vec<f32> {
+    let finite = points
+        .iter()
+        .filter(|&&(x, y, _)| x.is_finite() && y.is_finite())
+        .collect::<Vec<_>>();
+    let (min, max) = finite.iter().fold(
+        ([f32::INFINITY; 2], [f32::NEG_INFINITY; 2]),
+        |(min, max), &&(x, y, _)| {
+            (
+                [min[0].min(x), min[1].min(y)],
+                [max[0].max(x), max[1].max(y)],
+            )
+        },
+    );
+    // Cells that hold about one point each on average
+    let side = (max[0] - min[0]).max(max[1] - min[1]).max(f32::EPSILON);
+    let cell_size = (side * side / finite.len().max(1) as f32)
+        .sqrt()
+        .max(f32::EPSILON);
+    let max_ring = (side / cell_size).ceil() as i64 + 1;
+    let cell_of = |x: f32, y: f32| {
+        (
+            (x / cell_size).floor() as i64,
+            (y / cell_size).floor() as i64,
+        )
+    };
+    let mut grid: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    for (i, &(x, y, _)) in points.iter().enumerate() {
+        if x.is_finite() && y.is_finite() {
+            grid.entry(cell_of(x, y)).or_default().push(i);
+        }
+    }
+
+    points
+        .iter()
+        .enumerate()
+        .map(|(i, &(x, y, _))| {
+            if !x.is_finite() || !y.is_finite() {
+                return f32::INFINITY;
+            }
+            let (cx, cy) = cell_of(x, y);
+            let mut best = f32::INFINITY;
+            for ring in 0..=max_ring {
+                // Points in later rings are at least this far away
+                if best <= (ring - 1).max(0) as f32 * cell_size {
+                    break;
+                }
+                for dx in -ring..=ring {
+                    for dy in -ring..=ring {
+                        if dx.abs() != ring && dy.abs() != ring {
+                            continue;
+                        }
+                        for &j in grid.get(&(cx + dx, cy + dy)).into_iter().flatten() {
+                            if j != i {
+                                let (ox, oy, _) = points[j];
+                                best = best.min(((ox - x) * (ox - x) + (oy - y) * (oy - y)).sqrt());
+                            }
+                        }
+                    }
+                }
+            }
+            best
+        })
+        .collect()
+}
+
+/// Distribution of nearest neighbour distances among the particles of one type
+#[derive(Serialize)]
+pub struct NeighbourStats {
+    #[serde(rename = "type")]
+    pub type_: PointType,
+    pub mean: f64,
+    pub p10: f64,
+    pub median: f64,
+    pub p90: f64,
+}
+
+/// Measures every type in `0..num_types`, in order. Types without particles get `NaN`s.
+pub fn neighbour_stats(points: &[Point], num_types: PointType) -> Vec<NeighbourStats> {
+    let mut by_type = vec![Vec::new(); num_types as usize];
+    for (&(_, _, type_), distance) in points.iter().zip(nearest_neighbour_distances(points)) {
+        if let Some(distances) = type_.and_then(|type_| by_type.get_mut(type_ as usize)) {
+            if distance.is_finite() {
+                distances.push(distance as f64);
+            }
+        }
+    }
+    by_type
+        .into_iter()
+        .enumerate()
+        .map(|(type_, mut distances)| {
+            distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let quantile = |q: f64| {
+                if distances.is_empty() {
+                    f64::NAN
+                } else {
+                    distances[((distances.len() - 1) as f64 * q).round() as usize]
+                }
+            };
+            NeighbourStats {
+                type_: type_ as PointType,
+                mean: distances.iter().sum::<f64>() / distances.len() as f64,
+                p10: quantile(0.1),
+                median: quantile(0.5),
+                p90: quantile(0.9),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,138 @@
+use wgpu::{util::*, *};
+
+use crate::{
+    serialize::FlowConfig,
+    util::{BindableBuffer, VEC2_SIZE},
+};
+
+/// World distance between neighbouring arrows
+const ARROW_SPACING: f32 = 60.0;
+const ARROW_LENGTH: f32 = 20.0;
+const HEAD_LENGTH: f32 = 6.0;
+/// Angle between the shaft and each side of the arrow head
+const HEAD_ANGLE: f32 = 2.6;
+const MAX_ARROWS_PER_SIDE: u32 = 32;
+
+/// Dim arrows showing the direction of flow regions, drawn underneath the particles
+pub struct FlowArrows {
+    pipeline: RenderPipeline,
+    bind_group: BindGroup,
+    vertices: Buffer,
+    num_vertices: u32,
+}
+
+impl FlowArrows {
+    /// Returns `None` if there is nothing to draw
+    pub fn new(
+        device: &Device,
+        render_globals: &BindableBuffer,
+        format: TextureFormat,
+        flows: &[FlowConfig],
+    ) -> Option<Self> {
+        let lines = arrow_lines(flows);
+        if lines.is_empty() {
+            return None;
+        }
+        let mut contents = Vec::with_capacity(lines.len() * VEC2_SIZE);
+        for (x, y) in &lines {
+            contents.extend_from_slice(&x.to_le_bytes());
+            contents.extend_from_slice(&y.to_le_bytes());
+        }
+        let vertices = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("flow_arrows"),
+            contents: &contents,
+            usage: BufferUsage::VERTEX,
+        });
+
+        let shader = device.create_shader_module(&ShaderModuleDescriptor {
+            label: Some("overlay_shader"),
+            source: ShaderSource::Wgsl(include_str!("overlay.wgsl").into()),
+            flags: ShaderFlags::VALIDATION,
+        });
+        let bind_group_layout = BindableBuffer::bind_group_layout(device, &[render_globals]);
+        let bind_group = BindableBuffer::bind_group(device, &[render_globals]);
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("overlay_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("overlay_pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "main",
+                buffers: &[VertexBufferLayout {
+                    array_stride: VEC2_SIZE as u64,
+                    step_mode: InputStepMode::Vertex,
+                    attributes: &[VertexAttribute {
+                        format: VertexFormat::Float2,
+                        offset: 0,
+                        shader_location: 0,
+                    }],
+                }],
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::LineList,
+                ..PrimitiveState::default()
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "main",
+                targets: &[format.into()],
+            }),
+        });
+
+        Some(Self {
+            pipeline,
+            bind_group,
+            vertices,
+            num_vertices: lines.len() as u32,
+        })
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertices.slice(..));
+        render_pass.draw(0..self.num_vertices, 0..1);
+    }
+}
+
+/// Line list vertices for a grid of arrows covering each flow region
+fn arrow_lines(flows: &[FlowConfig]) -> Vec<(f32, f32)> {
+    let mut lines = Vec::new();
+    for flow in flows {
+        let [vx, vy] = flow.velocity;
+        let speed = (vx * vx + vy * vy).sqrt();
+        if speed == 0.0 {
+            continue;
+        }
+        let angle = vy.atan2(vx);
+        let region = flow.region;
+        let (width, height) = (region.x[1] - region.x[0], region.y[1] - region.y[0]);
+        let columns = ((width / ARROW_SPACING).ceil() as u32).clamp(1, MAX_ARROWS_PER_SIDE);
+        let rows = ((height / ARROW_SPACING).ceil() as u32).clamp(1, MAX_ARROWS_PER_SIDE);
+        for row in 0..rows {
+            for column in 0..columns {
+                let cx = region.x[0] + (column as f32 + 0.5) * width / columns as f32;
+                let cy = region.y[0] + (row as f32 + 0.5) * height / rows as f32;
+                let half = ARROW_LENGTH / 2.0;
+                let tail = (cx - angle.cos() * half, cy - angle.sin() * half);
+                let tip = (cx + angle.cos() * half, cy + angle.sin() * half);
+                lines.push(tail);
+                lines.push(tip);
+                for &side in [HEAD_ANGLE, -HEAD_ANGLE].iter() {
+                    lines.push(tip);
+                    lines.push((
+                        tip.0 + (angle + side).cos() * HEAD_LENGTH,
+                        tip.1 + (angle + side).sin() * HEAD_LENGTH,
+                    ));
+                }
+            }
+        }
+    }
+    lines
+}
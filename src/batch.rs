@@ -0,0 +1,208 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use anyhow::Context;
+use structopt::StructOpt;
+use tracing::{info, warn};
+use wgpu::*;
+
+use crate::{
+    analysis::{neighbour_stats, type_stats, ClusterStats, MotionStats, CLUSTER_RADIUS},
+    error::ConfigError,
+    serialize::{write_csv_points, Config, ConfigFormat},
+    simulation::Simulation,
+    snapshot,
+    stats::{CheckpointStats, StatsFormat, StatsWriter},
+};
+
+#[derive(StructOpt)]
+pub struct BatchOptions {
+    /// Directory of YAML, JSON or TOML config files
+    dir: PathBuf,
+    /// Steps to run each config for
+    #[structopt(long)]
+    steps: u64,
+    /// Directory to write results to, one subdirectory per config
+    #[structopt(long, default_value = "results")]
+    out: PathBuf,
+    /// Record statistics every this many steps
+    #[structopt(long)]
+    checkpoint: Option<u64>,
+    /// Also save an image of the final state
+    #[structopt(long)]
+    screenshot: bool,
+    /// Count particle clusters at every checkpoint
+    #[structopt(long)]
+    clusters: bool,
+    /// Measure the count, center of mass and radius of every type at every checkpoint
+    #[structopt(long)]
+    type_stats: bool,
+    /// Measure nearest neighbour distances for every type at every checkpoint
+    #[structopt(long)]
+    neighbour_stats: bool,
+}
+
+pub fn config_files(dir: &Path) -> Result<Vec<PathBuf>, ConfigError> {
+    let mut files = fs::read_dir(dir)
+        .map_err(|e| {
+            ConfigError(format!(
+                "Cannot read config directory {}: {}",
+                dir.display(),
+                e
+            ))
+        })?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && ConfigFormat::from_extension(path).is_some())
+        .collect::<Vec<_>>();
+    files.sort();
+    Ok(files)
+}
+
+/// Subdirectories of `out` that the results of each config go to, named after the files without
+/// their extensions. Fails if two configs would share one, as `a.yml` and `a.json` would. Names
+/// differing only in case count as the same, since some file systems do not tell them apart.
+fn out_dirs(out: &Path, files: &[PathBuf]) -> Result<Vec<PathBuf>, ConfigError> {
+    let mut taken = HashMap::new();
+    for path in files {
+        let stem = path.file_stem().unwrap_or_default();
+        if let Some(other) = taken.insert(stem.to_string_lossy().to_lowercase(), path) {
+            return Err(ConfigError(format!(
+                "{} and {} would both write their results to {}; rename one of them",
+                other.display(),
+                path.display(),
+                out.join(stem).display()
+            )));
+        }
+    }
+    Ok(files
+        .iter()
+        .map(|path| out.join(path.file_stem().unwrap_or_default()))
+        .collect())
+}
+
+/// Runs every config in `options.dir` on the same device, skipping ones that fail to load
+pub fn run(
+    device: &Device,
+    queue: &Queue,
+    options: BatchOptions,
+    workgroup_size: u32,
+) -> anyhow::Result<()> {
+    let files = config_files(&options.dir)?;
+    if files.is_empty() {
+        warn!("No config files found in {}", options.dir.display());
+        return Ok(());
+    }
+    let out_dirs = out_dirs(&options.out, &files)?;
+    let mut failed = 0;
+    for (i, (path, out_dir)) in files.iter().zip(&out_dirs).enumerate() {
+        info!("[{}/{}] {}", i + 1, files.len(), path.display());
+        let config = File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| Config::from_reader(file, ConfigFormat::from_path(path), &[]));
+        let config = match config {
+            Ok(config) => config,
+            Err(e) => {
+                warn!("Skipping invalid config {}:\n{}", path.display(), e);
+                failed += 1;
+                continue;
+            }
+        };
+        fs::create_dir_all(out_dir)
+            .with_context(|| format!("Cannot create results directory {}", out_dir.display()))?;
+        run_one(device, queue, config, workgroup_size, out_dir, &options)?;
+    }
+    info!(
+        "Finished {} of {} configs, results are in {}",
+        files.len() - failed,
+        files.len(),
+        options.out.display()
+    );
+    Ok(())
+}
+
+fn run_one(
+    device: &Device,
+    queue: &Queue,
+    config: Config,
+    workgroup_size: u32,
+    out_dir: &Path,
+    options: &BatchOptions,
+) -> anyhow::Result<()> {
+    let mut simulation = Simulation::from_config(device, config, workgroup_size)?;
+    let stats_path = out_dir.join("stats.jsonl");
+    let mut stats_writer = options
+        .checkpoint
+        .map(|_| StatsWriter::create(&stats_path, StatsFormat::Json))
+        .transpose()
+        .with_context(|| format!("Cannot create {}", stats_path.display()))?;
+
+    let start = Instant::now();
+    let mut last_checkpoint = start;
+    // Steps between checkpoints are submitted together
+    let checkpoint = options.checkpoint.unwrap_or(options.steps).max(1);
+    let mut step = 0;
+    while step < options.steps {
+        let batch = (checkpoint - step % checkpoint).min(options.steps - step);
+        simulation.step_many(device, queue, batch);
+        step += batch;
+        if let (Some(checkpoint), Some(stats_writer)) = (options.checkpoint, &mut stats_writer) {
+            if step % checkpoint == 0 {
+                device.poll(Maintain::Wait);
+                let now = Instant::now();
+                let points = if options.clusters || options.type_stats || options.neighbour_stats {
+                    simulation.read_points(device, queue)
+                } else {
+                    Vec::new()
+                };
+                stats_writer
+                    .write(&CheckpointStats {
+                        checkpoint: step / checkpoint,
+                        steps: step,
+                        wall_time: (now - start).as_secs_f64(),
+                        tps: checkpoint as f64 / (now - last_checkpoint).as_secs_f64(),
+                        motion: Some(MotionStats::measure(
+                            &simulation.read_velocities(device, queue),
+                        )),
+                        clusters: if options.clusters {
+                            Some(ClusterStats::measure(&points, CLUSTER_RADIUS))
+                        } else {
+                            None
+                        },
+                        gpu: None,
+                        types: if options.type_stats {
+                            type_stats(&points, simulation.ruleset.num_point_types)
+                        } else {
+                            Vec::new()
+                        },
+                        neighbours: if options.neighbour_stats {
+                            neighbour_stats(&points, simulation.ruleset.num_point_types)
+                        } else {
+                            Vec::new()
+                        },
+                    })
+                    .with_context(|| format!("Cannot write {}", stats_path.display()))?;
+                last_checkpoint = now;
+            }
+        }
+    }
+
+    let points = simulation.read_points(device, queue);
+    let final_path = out_dir.join("final.csv");
+    write_csv_points(&final_path, &points)
+        .with_context(|| format!("Cannot write {}", final_path.display()))?;
+    if options.screenshot {
+        let image_path = out_dir.join("final.png");
+        snapshot::save_png(
+            &image_path,
+            &points,
+            simulation.ruleset.num_point_types,
+            &simulation.walls,
+        )
+        .with_context(|| format!("Cannot write {}", image_path.display()))?;
+    }
+    Ok(())
+}
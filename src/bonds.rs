@@ -0,0 +1,172 @@
+use std::{io::Write, mem::size_of};
+
+use wgpu::*;
+
+use crate::{
+    serialize::BondConfig,
+    util::{BindableBuffer, VEC2_SIZE},
+};
+
+const WORKGROUP_SIZE: u32 = 64;
+/// Bytes per entry of the bond list: the other particle, rest length and stiffness as a vec4
+const ENTRY_SIZE: usize = VEC2_SIZE * 2;
+
+/// Springs between pairs of particles, pulling them back toward a rest length in a compute pass
+/// after every step
+pub struct Bonds {
+    bonds: Vec<BondConfig>,
+    /// Number of particles the offsets have room for
+    capacity: u32,
+    /// Where the bonds of every particle start in `entries`
+    offsets: BindableBuffer,
+    /// Every bond twice, once under either end
+    entries: BindableBuffer,
+    pipeline: ComputePipeline,
+    /// One for each of the simulation's position buffers being current
+    bind_groups: [BindGroup; 2],
+}
+
+impl Bonds {
+    /// `None` if there are no bonds, so that simulations without them need no extra pass
+    pub fn new(
+        device: &Device,
+        bonds: Vec<BondConfig>,
+        capacity: u32,
+        position_buffers: &[BindableBuffer; 2],
+        velocities: &BindableBuffer,
+        globals: &BindableBuffer,
+    ) -> Option<Self> {
+        if bonds.is_empty() {
+            return None;
+        }
+        let (offsets_bytes, entries_bytes) = Self::layout(&bonds, capacity);
+        let buffer = |usage: BufferUsage, bytes: Vec<u8>| {
+            BindableBuffer::new(
+                device,
+                usage | BufferUsage::COPY_DST,
+                ShaderStage::COMPUTE,
+                false,
+                bytes.len(),
+                |buffer| {
+                    let slice = buffer.slice(..);
+                    let mut view = slice.get_mapped_range_mut();
+                    view.copy_from_slice(&bytes);
+                },
+            )
+        };
+        let offsets = buffer(BufferUsage::STORAGE, offsets_bytes);
+        let entries = buffer(BufferUsage::STORAGE, entries_bytes);
+
+        let buffers =
+            |positions: &BindableBuffer| [positions, velocities, globals, &offsets, &entries];
+        let layout = BindableBuffer::bind_group_layout(device, &buffers(&position_buffers[0]));
+        let bind_groups =
+            [0, 1].map(|i| BindableBuffer::bind_group(device, &buffers(&position_buffers[i])));
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("bonds_compute"),
+            layout: Some(&device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("bonds_layout"),
+                bind_group_layouts: &[&layout],
+                push_constant_ranges: &[],
+            })),
+            module: &device.create_shader_module(&ShaderModuleDescriptor {
+                label: Some("bonds_shader"),
+                source: ShaderSource::Wgsl(include_str!("bonds.wgsl").into()),
+                flags: ShaderFlags::VALIDATION,
+            }),
+            entry_point: "main",
+        });
+
+        Some(Self {
+            bonds,
+            capacity,
+            offsets,
+            entries,
+            pipeline,
+            bind_groups,
+        })
+    }
+
+    /// The offsets and entries buffers for `bonds`: the bonds of every particle one after the
+    /// other, in order of the particle
+    fn layout(bonds: &[BondConfig], capacity: u32) -> (Vec<u8>, Vec<u8>) {
+        let mut per_particle = vec![Vec::new(); capacity as usize];
+        for bond in bonds {
+            per_particle[bond.a as usize].push((bond.b, bond));
+            per_particle[bond.b as usize].push((bond.a, bond));
+        }
+        let mut offsets = Vec::with_capacity((capacity as usize + 1) * size_of::<u32>());
+        let mut entries = Vec::with_capacity(bonds.len() * 2 * ENTRY_SIZE);
+        let mut offset = 0u32;
+        for particle in &per_particle {
+            offsets.write_all(&offset.to_le_bytes()).unwrap();
+            for (other, bond) in particle {
+                for value in [*other as f32, bond.length, bond.stiffness, 0.0].iter() {
+                    entries.write_all(&value.to_le_bytes()).unwrap();
+                }
+            }
+            offset += particle.len() as u32;
+        }
+        offsets.write_all(&offset.to_le_bytes()).unwrap();
+        (offsets, entries)
+    }
+
+    pub fn bonds(&self) -> &[BondConfig] {
+        &self.bonds
+    }
+
+    /// Drops the bonds of the particles marked in `removed`, and renumbers the rest for the
+    /// remaining particles moving up to fill the gaps
+    pub fn removed(&mut self, queue: &Queue, removed: &[bool]) {
+        let mut new_index = Vec::with_capacity(removed.len());
+        let mut kept = 0;
+        for &removed in removed {
+            if removed {
+                new_index.push(None);
+            } else {
+                new_index.push(Some(kept));
+                kept += 1;
+            }
+        }
+        let index = |old: u32| new_index.get(old as usize).copied().flatten();
+        self.bonds = self
+            .bonds
+            .iter()
+            .filter_map(|bond| {
+                Some(BondConfig {
+                    a: index(bond.a)?,
+                    b: index(bond.b)?,
+                    ..bond.clone()
+                })
+            })
+            .collect();
+        self.upload(queue);
+    }
+
+    /// Drops every bond, for when every particle was replaced
+    pub fn clear(&mut self, queue: &Queue) {
+        self.bonds.clear();
+        self.upload(queue);
+    }
+
+    fn upload(&self, queue: &Queue) {
+        let (offsets, entries) = Self::layout(&self.bonds, self.capacity);
+        queue.write_buffer(&self.offsets.buffer, 0, &offsets);
+        if !entries.is_empty() {
+            queue.write_buffer(&self.entries.buffer, 0, &entries);
+        }
+    }
+
+    /// Records the spring forces of one step, with `current` the index of the position buffer
+    /// the step wrote
+    pub fn encode(&self, encoder: &mut CommandEncoder, current: usize, num_points: u32) {
+        let mut bonds_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("bonds_pass"),
+        });
+        bonds_pass.push_debug_group("pull bonds");
+        bonds_pass.set_bind_group(0, &self.bind_groups[current], &[]);
+        bonds_pass.set_pipeline(&self.pipeline);
+        bonds_pass.dispatch(num_points.div_ceil(WORKGROUP_SIZE), 1, 1);
+        bonds_pass.pop_debug_group();
+    }
+}
@@ -0,0 +1,97 @@
+// Bonds: after every step, particles bonded to others are pulled toward or pushed away from each
+// of them, like springs of a given rest length. Every invocation only changes the velocity of its
+// own particle, so each bond is listed once for either end.
+
+[[block]]
+struct Positions {
+    data : [[stride(8)]] array< vec2<f32> >;
+};
+
+[[block]]
+struct Velocities {
+    data : [[stride(8)]] array< vec2<f32> >;
+};
+
+[[block]]
+struct Globals {
+    num_points : u32;
+    num_types : u32;
+    friction : f32;
+    wrapping : u32;
+    dist : f32;
+};
+
+// Where the bonds of each particle start in `bonds`, with one more entry for the end of the last
+[[block]]
+struct Offsets {
+    data : [[stride(4)]] array<u32>;
+};
+
+// (other particle, rest length, stiffness, 0)
+[[block]]
+struct Bonds {
+    data : [[stride(16)]] array< vec4<f32> >;
+};
+
+[[group(0), binding(0)]] var<storage> positions : [[access(read)]] Positions;
+[[group(0), binding(1)]] var<storage> velocities : [[access(read_write)]] Velocities;
+[[group(0), binding(2)]] var<uniform> globals : Globals;
+[[group(0), binding(3)]] var<storage> offsets : [[access(read)]] Offsets;
+[[group(0), binding(4)]] var<storage> bonds : [[access(read)]] Bonds;
+
+[[builtin(global_invocation_id)]] var<in> global_invocation_id : vec3<u32>;
+
+// The shortest way from one point to another, as in compute.wgsl
+fn nearest_image(direct : vec2<f32>) -> vec2<f32> {
+    var delta : vec2<f32> = direct;
+    if (globals.wrapping != 0u) {
+        if (delta.x > globals.dist) {
+            delta.x = delta.x - globals.dist * 2.0;
+        } else {
+            if (delta.x < -globals.dist) {
+                delta.x = delta.x + globals.dist * 2.0;
+            }
+        }
+
+        if (delta.y > globals.dist) {
+            delta.y = delta.y - globals.dist * 2.0;
+        } else {
+            if (delta.y < -globals.dist) {
+                delta.y = delta.y + globals.dist * 2.0;
+            }
+        }
+    }
+    return delta;
+}
+
+[[stage(compute), workgroup_size(64)]]
+fn main() -> void {
+    var i : u32 = global_invocation_id.x;
+    if (i >= globals.num_points) {
+        return;
+    }
+    var p : vec2<f32> = positions.data[i];
+    var velocity : vec2<f32> = velocities.data[i];
+    var k : u32 = offsets.data[i];
+    loop {
+        if (k >= offsets.data[i + 1u]) {
+            break;
+        }
+        var bond : vec4<f32> = bonds.data[k];
+        var other : u32 = u32(bond.x);
+        if (other >= globals.num_points) {
+            continue;
+        }
+        var delta : vec2<f32> = nearest_image(positions.data[other] - p);
+        var r : f32 = length(delta);
+        if (r < 0.01) {
+            continue;
+        }
+        var stretch : f32 = (r - bond.y) * bond.z;
+        velocity = velocity + delta / vec2<f32>(r, r) * vec2<f32>(stretch, stretch);
+        continuing {
+            k = k + 1u;
+        }
+    }
+    velocities.data[i] = velocity;
+}
@@ -0,0 +1,110 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde_yaml::Value;
+use structopt::StructOpt;
+use tracing::info;
+
+use crate::{
+    error::ConfigError,
+    evolve::{cross, mutate, with_ruleset},
+    serialize::{write_yaml, Config},
+    simulation::Ruleset,
+    source,
+    util::seed_rng,
+};
+
+/// Options for writing a copy of a config with its rules changed a little
+#[derive(StructOpt)]
+pub struct MutateOptions {
+    /// Config to mutate; procedural rulesets are sampled first
+    config_file: PathBuf,
+    /// Standard deviation of the noise added to attractions; radii get ten times as much
+    #[structopt(long, default_value = "0.1")]
+    strength: f32,
+    /// File to write the new config to, as YAML; printed if omitted
+    #[structopt(long)]
+    out: Option<PathBuf>,
+    /// Seed for sampling and mutating, to get the same config again; random if omitted
+    #[structopt(long)]
+    seed: Option<u64>,
+}
+
+/// Options for writing a config whose rules come from two others
+#[derive(StructOpt)]
+pub struct CrossOptions {
+    /// Parent whose walls, points and other settings the child keeps
+    a: PathBuf,
+    /// Other parent, with the same number of types
+    b: PathBuf,
+    /// File to write the new config to, as YAML; printed if omitted
+    #[structopt(long)]
+    out: Option<PathBuf>,
+    /// Seed for sampling and crossing, to get the same config again; random if omitted
+    #[structopt(long)]
+    seed: Option<u64>,
+}
+
+/// Reads a config document, along with a ruleset sampled from it
+fn read(path: &Path) -> anyhow::Result<(Value, Ruleset)> {
+    let text = source::read_config(path).map_err(ConfigError)?;
+    let text = String::from_utf8(text).map_err(|_| {
+        ConfigError(format!(
+            "Invalid config file {}: not valid UTF-8",
+            path.display()
+        ))
+    })?;
+    let invalid =
+        |e: String| ConfigError(format!("Invalid config file {}:\n{}", path.display(), e));
+    let document = source::config_format(path)
+        .parse_value(&text)
+        .map_err(invalid)?;
+    let (ruleset, _, _) = Config::from_value(document.clone())
+        .map_err(invalid)?
+        .sample();
+    Ok((document, ruleset))
+}
+
+fn write(document: &Value, out: Option<&Path>) -> anyhow::Result<()> {
+    write_yaml(document, out).with_context(|| match out {
+        Some(path) => format!("Cannot write {}", path.display()),
+        None => "Cannot print config".to_owned(),
+    })?;
+    if let Some(path) = out {
+        info!("Wrote {}", path.display());
+    }
+    Ok(())
+}
+
+/// Writes the config with noise added to its rules
+pub fn run_mutate(options: &MutateOptions) -> anyhow::Result<()> {
+    if let Some(seed) = options.seed {
+        seed_rng(seed);
+    }
+    let (document, mut ruleset) = read(&options.config_file)?;
+    mutate(&mut ruleset, options.strength);
+    write(&with_ruleset(&document, &ruleset), options.out.as_deref())
+}
+
+/// Writes the first config with each type's rules taken from either config
+pub fn run_cross(options: &CrossOptions) -> anyhow::Result<()> {
+    if let Some(seed) = options.seed {
+        seed_rng(seed);
+    }
+    let (document, a) = read(&options.a)?;
+    let (_, b) = read(&options.b)?;
+    if a.num_point_types != b.num_point_types {
+        return Err(ConfigError(format!(
+            "{} has {} types, but {} has {}; only rulesets with the same number of types can be crossed",
+            options.a.display(),
+            a.num_point_types,
+            options.b.display(),
+            b.num_point_types
+        ))
+        .into());
+    }
+    write(
+        &with_ruleset(&document, &cross(&a, &b)),
+        options.out.as_deref(),
+    )
+}
@@ -0,0 +1,73 @@
+#[cfg(feature = "renderdoc")]
+use renderdoc::{RenderDoc, V110};
+#[cfg(feature = "renderdoc")]
+use tracing::info;
+use tracing::warn;
+
+/// Captures a single frame (or headless step) with an attached graphics debugger
+pub struct FrameCapture {
+    frame: u64,
+    #[cfg(feature = "renderdoc")]
+    renderdoc: Option<RenderDoc<V110>>,
+}
+
+impl FrameCapture {
+    #[cfg(feature = "renderdoc")]
+    pub fn new(frame: u64) -> Self {
+        let renderdoc = match RenderDoc::new() {
+            Ok(renderdoc) => Some(renderdoc),
+            Err(e) => {
+                warn!(
+                    "Cannot capture frame {}: RenderDoc is not attached ({})",
+                    frame, e
+                );
+                None
+            }
+        };
+        Self { frame, renderdoc }
+    }
+
+    #[cfg(not(feature = "renderdoc"))]
+    pub fn new(frame: u64) -> Self {
+        warn!(
+            "Cannot capture frame {}: plife was built without the `renderdoc` feature",
+            frame
+        );
+        Self { frame }
+    }
+
+    /// Call before submitting any work for `frame`
+    pub fn begin(&mut self, frame: u64) {
+        if frame == self.frame {
+            self.start();
+        }
+    }
+
+    /// Call after all work for `frame` has been submitted
+    pub fn end(&mut self, frame: u64) {
+        if frame == self.frame {
+            self.finish();
+        }
+    }
+
+    #[cfg(feature = "renderdoc")]
+    fn start(&mut self) {
+        if let Some(renderdoc) = &mut self.renderdoc {
+            renderdoc.start_frame_capture(std::ptr::null(), std::ptr::null());
+        }
+    }
+
+    #[cfg(feature = "renderdoc")]
+    fn finish(&mut self) {
+        if let Some(renderdoc) = &mut self.renderdoc {
+            renderdoc.end_frame_capture(std::ptr::null(), std::ptr::null());
+            info!("Captured frame {}", self.frame);
+        }
+    }
+
+    #[cfg(not(feature = "renderdoc"))]
+    fn start(&mut self) {}
+
+    #[cfg(not(feature = "renderdoc"))]
+    fn finish(&mut self) {}
+}
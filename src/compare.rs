@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use crate::simulation::Ruleset;
+
+/// Options for running two configs side by side
+#[derive(StructOpt)]
+pub struct CompareOptions {
+    /// Config shown on the left
+    pub a: PathBuf,
+    /// Config shown on the right
+    pub b: PathBuf,
+    /// Seed both configs are sampled from, so that whatever they have in common turns out the same
+    #[structopt(long, default_value = "0")]
+    pub seed: u64,
+}
+
+/// Every entry of two sampled rulesets with the same number of types that differs between them
+pub fn differences(a: &Ruleset, b: &Ruleset) -> Vec<String> {
+    let mut differences = Vec::new();
+    if a.friction != b.friction {
+        differences.push(format!("friction: {} -> {}", a.friction, b.friction));
+    }
+    let tables = [
+        ("attractions", &a.attractions, &b.attractions),
+        ("min_r", &a.min_r, &b.min_r),
+        ("max_r", &a.max_r, &b.max_r),
+    ];
+    for (name, a, b) in tables.iter() {
+        for (from, (a, b)) in a.iter().zip(b.iter()).enumerate() {
+            for (to, (a, b)) in a.iter().zip(b).enumerate() {
+                if a != b {
+                    differences.push(format!("{}[{}][{}]: {} -> {}", name, from, to, a, b));
+                }
+            }
+        }
+    }
+    differences
+}
+
+/// Prints where the rulesets of the two sides differ
+pub fn report(a: &Ruleset, b: &Ruleset) {
+    let differences = differences(a, b);
+    if differences.is_empty() {
+        println!("The rulesets are the same");
+        return;
+    }
+    println!("The rulesets differ in {} entries:", differences.len());
+    for difference in &differences {
+        println!("  {}", difference);
+    }
+}
@@ -1,5 +1,12 @@
 const R_SMOOTH : f32 = 2.0;
 
+// Values of `integrator` in the globals, as in `Integrator` in serialize.rs
+const INTEGRATOR_EULER : u32 = 0u;
+const INTEGRATOR_VERLET : u32 = 2u;
+// Values of `drag` in the globals, as in `Drag` in serialize.rs
+const DRAG_LINEAR : u32 = 0u;
+const DRAG_QUADRATIC : u32 = 1u;
+
 [[block]]
 struct Positions {
     data : [[stride(8)]] array< vec2<f32> >;
@@ -32,6 +39,41 @@ struct Globals {
     friction : f32;
     wrapping: u32;
     dist: f32;
+    attraction_scale : f32;
+    impulse_x : f32;
+    impulse_y : f32;
+    impulse_strength : f32;
+    impulse_radius : f32;
+    num_flows : u32;
+    restitution : f32;
+    cursor_x : f32;
+    cursor_y : f32;
+    cursor_strength : f32;
+    cursor_radius : f32;
+    max_velocity : f32;
+    integrator : u32;
+    drag : u32;
+    num_zones : u32;
+    gravity : f32;
+    softening : f32;
+};
+
+// Two entries per flow region: (min.x, min.y, max.x, max.y) and (velocity.x, velocity.y, 0, 0)
+[[block]]
+struct Flows {
+    data : [[stride(16)]] array<vec4<f32>, 32u>;
+};
+
+[[block]]
+struct Masses {
+    data : [[stride(4)]] array<f32>;
+};
+
+// Two entries per zone: its shape, (min.x, min.y, max.x, max.y) for a rectangle or
+// (center.x, center.y, radius, 0) for a circle, then (attraction, friction, is_circle, 0)
+[[block]]
+struct Zones {
+    data : [[stride(16)]] array<vec4<f32>, 32u>;
 };
 
 [[group(0), binding(0)]] var<storage> positions : [[access(read_write)]] Positions;
@@ -42,81 +84,245 @@ struct Globals {
 [[group(0), binding(5)]] var<storage> cache_min_r : [[access(read)]] CacheRadius;
 [[group(0), binding(6)]] var<storage> cache_attraction : [[access(read)]] CacheAttraction;
 [[group(0), binding(7)]] var<uniform> globals : Globals;
+[[group(0), binding(8)]] var<uniform> flows : Flows;
+// Forces of the last step, which the verlet integrator averages with this step's
+[[group(0), binding(9)]] var<storage> accelerations : [[access(read_write)]] Velocities;
+[[group(0), binding(10)]] var<uniform> zones : Zones;
+[[group(0), binding(11)]] var<storage> masses : [[access(read)]] Masses;
 
 [[builtin(global_invocation_id)]] var<in> global_invocation_id : vec3<u32>;
+[[builtin(local_invocation_id)]] var<in> local_invocation_id : vec3<u32>;
+
+// Particles are compared against in tiles of one per invocation, each invocation loading one
+// particle of the tile into workgroup memory. `{WORKGROUP_SIZE}` is filled in with the workgroup
+// size chosen in simulation.rs before the shader is compiled.
+const TILE_SIZE : u32 = {WORKGROUP_SIZE}u;
+
+var<workgroup> tile_positions : array<vec2<f32>, {WORKGROUP_SIZE}>;
+var<workgroup> tile_types : array<u32, {WORKGROUP_SIZE}>;
 
 fn tovec(float : f32) -> vec2<f32> {
     return vec2<f32>(float, float);
 }
 
-[[stage(compute), workgroup_size(256)]]
+// Runaway particles are slowed down before they are flung so far that positions lose precision
+fn capped(velocity : vec2<f32>) -> vec2<f32> {
+    var speed : f32 = length(velocity);
+    if (globals.max_velocity > 0.0 && speed > globals.max_velocity) {
+        return velocity * tovec(globals.max_velocity / speed);
+    }
+    return velocity;
+}
+
+// The shortest way from one point to another. With wrapping walls that can be across the seam,
+// so that particles near opposite edges act on each other as they would on a torus.
+fn nearest_image(direct : vec2<f32>) -> vec2<f32> {
+    var delta : vec2<f32> = direct;
+    if (globals.wrapping != 0u) {
+        if (delta.x > globals.dist) {
+            delta.x = delta.x - globals.dist * 2.0;
+        } else {
+            if (delta.x < -globals.dist) {
+                delta.x = delta.x + globals.dist * 2.0;
+            }
+        }
+
+        if (delta.y > globals.dist) {
+            delta.y = delta.y - globals.dist * 2.0;
+        } else {
+            if (delta.y < -globals.dist) {
+                delta.y = delta.y + globals.dist * 2.0;
+            }
+        }
+    }
+    return delta;
+}
+
+// Attraction and friction multipliers of every zone a particle at `p` is in, multiplied together
+fn zone_multipliers(p : vec2<f32>) -> vec2<f32> {
+    var multipliers : vec2<f32> = vec2<f32>(1.0, 1.0);
+    var k : u32 = 0u;
+    loop {
+        if (k >= globals.num_zones) {
+            break;
+        }
+        var shape : vec4<f32> = zones.data[k * 2u];
+        var zone : vec4<f32> = zones.data[k * 2u + 1u];
+        var inside : bool;
+        if (zone.z != 0.0) {
+            inside = distance(p, vec2<f32>(shape.x, shape.y)) <= shape.z;
+        } else {
+            inside = p.x >= shape.x && p.y >= shape.y && p.x <= shape.z && p.y <= shape.w;
+        }
+        if (inside) {
+            multipliers = multipliers * vec2<f32>(zone.x, zone.y);
+        }
+        continuing {
+            k = k + 1u;
+        }
+    }
+    return multipliers;
+}
+
+[[stage(compute), workgroup_size({WORKGROUP_SIZE})]]
 fn main() -> void {
     var i : u32 = global_invocation_id.x;
-    if (i >= globals.num_points) {
-        return;
+    var local : u32 = local_invocation_id.x;
+    // Invocations past the last particle still load their part of every tile, since the whole
+    // workgroup has to reach each barrier
+    var active : bool = i < globals.num_points;
+    var p : vec2<f32> = vec2<f32>(0.0, 0.0);
+    var p_type : u32 = 0u;
+    var velocity : vec2<f32> = vec2<f32>(0.0, 0.0);
+    // Velocity gained this step
+    var force : vec2<f32> = vec2<f32>(0.0, 0.0);
+    if (active) {
+        p = positions_old.data[i];
+        p_type = types.data[i];
+        velocity = velocities.data[i];
     }
-    var p : vec2<f32> = positions_old.data[i];
-    var p_type : u32 = types.data[i];
+    var multipliers : vec2<f32> = zone_multipliers(p);
 
-    var j : u32 = 0u;
+    var tile_start : u32 = 0u;
     loop {
-        var q : vec2<f32> = positions_old.data[j];
-        var q_type : u32 = types.data[j];
-        var pair_idx : u32 = (p_type * globals.num_types) + q_type;
-        var delta : vec2<f32> = q - p;
-
-        if (globals.wrapping != 0u) {
-            if (delta.x > globals.dist) {
-                delta.x = delta.x - globals.dist * 2.0;
-            } else {
-                if (delta.x < -globals.dist) {
-                    delta.x = delta.x + globals.dist * 2.0;
+        if (tile_start >= globals.num_points) {
+            break;
+        }
+        var loaded : u32 = tile_start + local;
+        if (loaded < globals.num_points) {
+            tile_positions[local] = positions_old.data[loaded];
+            tile_types[local] = types.data[loaded];
+        }
+        workgroupBarrier();
+
+        if (active) {
+            var tile_end : u32 = min(TILE_SIZE, globals.num_points - tile_start);
+            var k : u32 = 0u;
+            loop {
+                if (k >= tile_end) {
+                    break;
                 }
-            }
+                var q : vec2<f32> = tile_positions[k];
+                var q_type : u32 = tile_types[k];
+                var pair_idx : u32 = (p_type * globals.num_types) + q_type;
+                var delta : vec2<f32> = nearest_image(q - p);
 
-            if (delta.y > globals.dist) {
-                delta.y = delta.y - globals.dist * 2.0;
-            } else {
-                if (delta.y < -globals.dist) {
-                    delta.y = delta.y + globals.dist * 2.0;
+                var r2 : f32 = delta.x * delta.x + delta.y * delta.y;
+                if (r2 < 0.01) {
+                    continue;
+                }
+
+                // Gravity reaches past max_r, to every other particle
+                if (globals.gravity != 0.0) {
+                    var soft2 : f32 = r2 + globals.softening * globals.softening;
+                    var pull : f32 = globals.gravity * masses.data[q_type] / (soft2 * sqrt(soft2));
+                    force = force + delta * tovec(pull);
+                }
+
+                var max_r : f32 = cache_max_r.data[pair_idx];
+                if (r2 > max_r * max_r) {
+                    continue;
+                }
+
+                var min_r : f32 = cache_min_r.data[pair_idx];
+                var attraction : f32 = cache_attraction.data[pair_idx] * globals.attraction_scale * multipliers.x;
+
+                var r : f32 = sqrt(r2);
+                delta = delta / tovec(r);
+
+                var f : f32;
+                if (r > min_r) {
+                    var numer : f32 = 2.0 * abs(r - 0.5 * (max_r + min_r));
+                    var denom : f32 = max_r - min_r;
+                    f = attraction * (1.0 - numer / denom);
+                } else {
+                    f = R_SMOOTH * min_r * (1.0 / (min_r + R_SMOOTH) - 1.0 / (r + R_SMOOTH));
+                }
+
+                force = force + delta * tovec(f);
+
+                continuing {
+                    k = k + 1u;
                 }
             }
         }
+        // The tile is overwritten next, so everyone has to be done with it first
+        workgroupBarrier();
 
-        var r2 : f32 = delta.x * delta.x + delta.y * delta.y;
-        var max_r : f32 = cache_max_r.data[pair_idx];
-
-        if (r2 > max_r * max_r || r2 < 0.01) {
-            continue;
+        continuing {
+            tile_start = tile_start + TILE_SIZE;
         }
+    }
 
-        var min_r : f32 = cache_min_r.data[pair_idx];
-        var attraction : f32 = cache_attraction.data[pair_idx];
+    if (!active) {
+        return;
+    }
 
-        var r : f32 = sqrt(r2);
-        delta = delta / tovec(r);
+    if (globals.impulse_strength != 0.0) {
+        var away : vec2<f32> = nearest_image(p - vec2<f32>(globals.impulse_x, globals.impulse_y));
+        var d : f32 = length(away);
+        if (d < globals.impulse_radius && d > 0.01) {
+            var falloff : f32 = 1.0 - d / globals.impulse_radius;
+            force = force + away / tovec(d) * tovec(globals.impulse_strength * falloff);
+        }
+    }
 
-        var f : f32;
-        if (r > min_r) {
-            var numer : f32 = 2.0 * abs(r - 0.5 * (max_r + min_r));
-            var denom : f32 = max_r - min_r;
-            f = attraction * (1.0 - numer / denom);
+    // Force tool, pulling toward the cursor or pushing away from it
+    if (globals.cursor_strength != 0.0) {
+        var toward : vec2<f32> = nearest_image(vec2<f32>(globals.cursor_x, globals.cursor_y) - p);
+        var d : f32 = length(toward);
+        if (d < globals.cursor_radius && d > 0.01) {
+            var falloff : f32 = 1.0 - d / globals.cursor_radius;
+            force = force + toward / tovec(d) * tovec(globals.cursor_strength * falloff);
+        }
+    }
+
+    // How far the particle moves this step
+    var movement : vec2<f32>;
+    if (globals.integrator == INTEGRATOR_EULER) {
+        // Moves as fast as it was going before the forces act
+        movement = capped(velocity);
+        velocity = capped(velocity + force);
+    } else {
+        if (globals.integrator == INTEGRATOR_VERLET) {
+            // The velocity takes the average of the forces of the last step and this one, and the
+            // particle moves as if this step's forces acted for half of the step
+            velocity = capped(velocity + (accelerations.data[i] + force) * tovec(0.5));
+            movement = capped(velocity + force * tovec(0.5));
+            accelerations.data[i] = force;
         } else {
-            f = R_SMOOTH * min_r * (1.0 / (min_r + R_SMOOTH) - 1.0 / (r + R_SMOOTH));
+            velocity = capped(velocity + force);
+            movement = velocity;
         }
+    }
+    velocities.data[i] = velocity;
 
-        velocities.data[i] = velocities.data[i] + delta * tovec(f);
+    positions.data[i] = p + movement;
 
+    var k : u32 = 0u;
+    loop {
+        if (k >= globals.num_flows) {
+            break;
+        }
+        var bounds : vec4<f32> = flows.data[k * 2u];
+        if (p.x >= bounds.x && p.y >= bounds.y && p.x <= bounds.z && p.y <= bounds.w) {
+            var velocity : vec4<f32> = flows.data[k * 2u + 1u];
+            positions.data[i] = positions.data[i] + vec2<f32>(velocity.x, velocity.y);
+        }
         continuing {
-            j = j + 1u;
-            if (j >= globals.num_points) {
-                break;
-            }
+            k = k + 1u;
+        }
+    }
+    // Drag grows with speed for linear drag, and with its square for quadratic drag
+    var friction : f32 = min(globals.friction * multipliers.y, 1.0);
+    if (globals.drag == DRAG_LINEAR) {
+        velocities.data[i] = velocities.data[i] * tovec(1.0 - friction);
+    } else {
+        if (globals.drag == DRAG_QUADRATIC) {
+            var speed : f32 = length(velocities.data[i]);
+            velocities.data[i] = velocities.data[i] / tovec(1.0 + friction * speed);
         }
     }
-
-    positions.data[i] = positions.data[i] + velocities.data[i];
-    velocities.data[i] = velocities.data[i] * tovec(1.0 - globals.friction);
 
     if (globals.wrapping) {
         if (positions.data[i].x < -globals.dist) {
@@ -137,21 +343,21 @@ fn main() -> void {
     } else {
         if (globals.dist != 0.0) {
             if (positions.data[i].x < -globals.dist) {
-                velocities.data[i].x = -velocities.data[i].x;
+                velocities.data[i].x = -velocities.data[i].x * globals.restitution;
                 positions.data[i].x = -globals.dist;
             } else {
                 if (positions.data[i].x >= globals.dist) {
-                    velocities.data[i].x = -velocities.data[i].x;
+                    velocities.data[i].x = -velocities.data[i].x * globals.restitution;
                     positions.data[i].x = globals.dist;
                 }
             }
 
             if (positions.data[i].y < -globals.dist) {
-                velocities.data[i].y = -velocities.data[i].y;
+                velocities.data[i].y = -velocities.data[i].y * globals.restitution;
                 positions.data[i].y = -globals.dist;
             } else {
                 if (positions.data[i].y >= globals.dist) {
-                    velocities.data[i].y = -velocities.data[i].y;
+                    velocities.data[i].y = -velocities.data[i].y * globals.restitution;
                     positions.data[i].y = globals.dist;
                 }
             }
@@ -0,0 +1,227 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use wgpu::{Device, Queue};
+
+use crate::{
+    serialize::{write_csv_points, ScheduledParameter},
+    simulation::Simulation,
+};
+
+/// How often a paused simulation looks for new commands
+pub const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Commands scripts send to control a run, one JSON object per line such as
+/// `{"command": "set", "parameter": "friction", "value": 0.1}`
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum ControlCommand {
+    Pause,
+    Resume,
+    /// Runs this many more steps, then pauses
+    Step {
+        #[serde(default = "default_steps")]
+        steps: u64,
+    },
+    /// Pauses once the simulation reaches this tick
+    PauseAt {
+        tick: u64,
+    },
+    Set {
+        parameter: ScheduledParameter,
+        value: f32,
+    },
+    /// Writes every particle to a CSV points file, which configs can load again
+    Snapshot {
+        path: PathBuf,
+    },
+    /// Does nothing but reply, with the tick, the number of particles and whether it is paused
+    Status,
+}
+
+fn default_steps() -> u64 {
+    1
+}
+
+/// Reply to every command, as a line of JSON
+#[derive(Serialize)]
+struct Response {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tick: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    paused: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    particles: Option<u32>,
+}
+
+impl Response {
+    fn error(error: String) -> Self {
+        Self {
+            ok: false,
+            error: Some(error),
+            tick: None,
+            paused: None,
+            particles: None,
+        }
+    }
+}
+
+/// Takes commands from scripts connected over TCP, in the window or headless. Commands are read
+/// in the background and carried out between steps, when `handle` is called.
+pub struct Controller {
+    requests: Receiver<(ControlCommand, Sender<Response>)>,
+    /// Tick at which to pause, after `step` or `pause_at`
+    run_until: Option<u64>,
+}
+
+impl Controller {
+    /// Starts accepting connections on `address` in the background
+    pub fn start(address: SocketAddr) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let sender = sender.clone();
+                        thread::spawn(move || serve_client(stream, sender));
+                    }
+                    Err(e) => warn!("Cannot accept a control connection: {}", e),
+                }
+            }
+        });
+        Ok(Self {
+            requests,
+            run_until: None,
+        })
+    }
+
+    /// Steps from `ticks` until the simulation has to pause for `step` or `pause_at`
+    pub fn due_in(&self, ticks: u64) -> u64 {
+        self.run_until
+            .map_or(u64::MAX, |until| until.saturating_sub(ticks))
+    }
+
+    /// Pauses if a tick asked for was reached, and carries out the commands that arrived since
+    /// the last call. Returns whether anything changed.
+    pub fn handle(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        simulation: &mut Simulation,
+        ticks: u64,
+        paused: &mut bool,
+    ) -> bool {
+        let mut changed = false;
+        if self.due_in(ticks) == 0 {
+            self.run_until = None;
+            *paused = true;
+            changed = true;
+        }
+        while let Ok((command, reply)) = self.requests.try_recv() {
+            let response = match self.apply(command, device, queue, simulation, ticks, paused) {
+                Ok(()) => Response {
+                    ok: true,
+                    error: None,
+                    tick: Some(ticks),
+                    paused: Some(*paused),
+                    particles: Some(simulation.num_points),
+                },
+                Err(e) => Response::error(e),
+            };
+            // The client may have gone away in the meantime
+            reply.send(response).ok();
+            changed = true;
+        }
+        changed
+    }
+
+    fn apply(
+        &mut self,
+        command: ControlCommand,
+        device: &Device,
+        queue: &Queue,
+        simulation: &mut Simulation,
+        ticks: u64,
+        paused: &mut bool,
+    ) -> Result<(), String> {
+        match command {
+            ControlCommand::Pause => {
+                self.run_until = None;
+                *paused = true;
+            }
+            ControlCommand::Resume => {
+                self.run_until = None;
+                *paused = false;
+            }
+            ControlCommand::Step { steps } => {
+                if steps > 0 {
+                    self.run_until = Some(ticks + steps);
+                    *paused = false;
+                }
+            }
+            ControlCommand::PauseAt { tick } => {
+                if tick <= ticks {
+                    return Err(format!("tick {} has already been reached", tick));
+                }
+                self.run_until = Some(tick);
+            }
+            ControlCommand::Set { parameter, value } => {
+                simulation.set_parameter(parameter, value)?;
+            }
+            ControlCommand::Snapshot { path } => {
+                write_csv_points(&path, &simulation.read_points(device, queue))
+                    .map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+            }
+            ControlCommand::Status => {}
+        }
+        Ok(())
+    }
+}
+
+/// Reads commands from a connection, passes them on to be carried out and writes back the replies
+fn serve_client(stream: TcpStream, requests: Sender<(ControlCommand, Sender<Response>)>) {
+    let mut out = match stream.try_clone() {
+        Ok(out) => out,
+        Err(_) => return,
+    };
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str(&line) {
+            Ok(command) => {
+                let (reply, response) = mpsc::channel();
+                if requests.send((command, reply)).is_err() {
+                    return;
+                }
+                match response.recv() {
+                    Ok(response) => response,
+                    Err(_) => return,
+                }
+            }
+            Err(e) => Response::error(format!("invalid command: {}", e)),
+        };
+        let written = serde_json::to_writer(&mut out, &response)
+            .map_err(std::io::Error::from)
+            .and_then(|_| writeln!(out));
+        if written.is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use rand::Rng;
+
+use crate::{
+    serialize::{BondConfig, Drag, FlowConfig, Integrator, ReactionConfig, ZoneConfig},
+    simulation::{Attraction, Globals, Point, PointType, Radius, Ruleset},
+    util::with_rng,
+};
+
+/// `R_SMOOTH` in compute.wgsl
+const R_SMOOTH: f32 = 2.0;
+
+/// The particles of a simulation stepped on the CPU, the same way compute.wgsl steps them on the
+/// GPU. Far slower, but needs no compute shaders, and serves as a reference for what the shader
+/// should do.
+pub struct CpuSimulation {
+    num_types: usize,
+    /// Rules between every pair of types, the particle's own type first, as in the GPU caches
+    min_r: Vec<Radius>,
+    max_r: Vec<Radius>,
+    attraction: Vec<Attraction>,
+    pub positions: Vec<[f32; 2]>,
+    pub velocities: Vec<[f32; 2]>,
+    /// Forces of the last step, for the verlet integrator
+    pub accelerations: Vec<[f32; 2]>,
+    pub types: Vec<PointType>,
+}
+
+impl CpuSimulation {
+    /// Pairs for which `enabled` is false feel no attraction, only the repulsion up close
+    pub fn new(
+        ruleset: &Ruleset,
+        enabled: impl Fn(PointType, PointType) -> bool,
+        points: &[Point],
+        velocities: &[[f32; 2]],
+        accelerations: &[[f32; 2]],
+    ) -> Self {
+        let num_types = ruleset.num_point_types as usize;
+        let per_pair = |values: &[Vec<f32>]| values.iter().flatten().copied().collect::<Vec<_>>();
+        let mut attraction = per_pair(&ruleset.attractions);
+        for (index, attraction) in attraction.iter_mut().enumerate() {
+            let (a, b) = (index / num_types, index % num_types);
+            if !enabled(a as PointType, b as PointType) {
+                *attraction = 0.0;
+            }
+        }
+        Self {
+            num_types,
+            min_r: per_pair(&ruleset.min_r),
+            max_r: per_pair(&ruleset.max_r),
+            attraction,
+            positions: points.iter().map(|&(x, y, _)| [x, y]).collect(),
+            velocities: velocities.to_vec(),
+            accelerations: accelerations.to_vec(),
+            types: points
+                .iter()
+                .map(|&(_, _, type_)| type_.unwrap_or(0))
+                .collect(),
+        }
+    }
+
+    pub fn points(&self) -> Vec<Point> {
+        self.positions
+            .iter()
+            .zip(&self.types)
+            .map(|(&[x, y], &type_)| (x, y, Some(type_)))
+            .collect()
+    }
+
+    /// Moves every particle one step. All of them are moved from where the others were before the
+    /// step, as on the GPU. `masses` holds the mass of every type, for gravity.
+    pub fn step(
+        &mut self,
+        globals: &Globals,
+        flows: &[FlowConfig],
+        zones: &[ZoneConfig],
+        masses: &[f32],
+    ) {
+        let old = self.positions.clone();
+        for (i, &p) in old.iter().enumerate() {
+            let p_type = self.types[i] as usize;
+            let mut velocity = self.velocities[i];
+            let mut force = [0.0; 2];
+            let (attraction_scale, friction) = zones
+                .iter()
+                .take(globals.num_zones as usize)
+                .filter(|zone| zone.shape.contains(p[0], p[1]))
+                .fold(
+                    (globals.attraction_scale, globals.friction),
+                    |(a, f), zone| (a * zone.attraction, f * zone.friction),
+                );
+            let friction = friction.min(1.0);
+            for (&q, &q_type) in old.iter().zip(&self.types) {
+                let pair = p_type * self.num_types + q_type as usize;
+                let delta = nearest_image([q[0] - p[0], q[1] - p[1]], globals);
+
+                let r2 = delta[0] * delta[0] + delta[1] * delta[1];
+                if r2 < 0.01 {
+                    continue;
+                }
+                if globals.gravity != 0.0 {
+                    let soft2 = r2 + globals.softening * globals.softening;
+                    let pull = globals.gravity * masses[q_type as usize] / (soft2 * soft2.sqrt());
+                    force[0] += delta[0] * pull;
+                    force[1] += delta[1] * pull;
+                }
+                let max_r = self.max_r[pair];
+                if r2 > max_r * max_r {
+                    continue;
+                }
+                let min_r = self.min_r[pair];
+                let attraction = self.attraction[pair] * attraction_scale;
+                let r = r2.sqrt();
+                let f = if r > min_r {
+                    let numer = 2.0 * (r - 0.5 * (max_r + min_r)).abs();
+                    let denom = max_r - min_r;
+                    attraction * (1.0 - numer / denom)
+                } else {
+                    R_SMOOTH * min_r * (1.0 / (min_r + R_SMOOTH) - 1.0 / (r + R_SMOOTH))
+                };
+                force[0] += delta[0] / r * f;
+                force[1] += delta[1] / r * f;
+            }
+
+            if globals.impulse_strength != 0.0 {
+                let away = nearest_image(
+                    [p[0] - globals.impulse_x, p[1] - globals.impulse_y],
+                    globals,
+                );
+                push(
+                    &mut force,
+                    away,
+                    globals.impulse_radius,
+                    globals.impulse_strength,
+                );
+            }
+            if globals.cursor_strength != 0.0 {
+                let toward =
+                    nearest_image([globals.cursor_x - p[0], globals.cursor_y - p[1]], globals);
+                push(
+                    &mut force,
+                    toward,
+                    globals.cursor_radius,
+                    globals.cursor_strength,
+                );
+            }
+
+            let movement = match globals.integrator {
+                Integrator::Euler => {
+                    let movement = capped(velocity, globals);
+                    velocity = capped(add(velocity, force, 1.0), globals);
+                    movement
+                }
+                Integrator::SemiImplicit => {
+                    velocity = capped(add(velocity, force, 1.0), globals);
+                    velocity
+                }
+                Integrator::Verlet => {
+                    let both_steps = add(self.accelerations[i], force, 1.0);
+                    velocity = capped(add(velocity, both_steps, 0.5), globals);
+                    self.accelerations[i] = force;
+                    capped(add(velocity, force, 0.5), globals)
+                }
+            };
+
+            let mut position = [p[0] + movement[0], p[1] + movement[1]];
+            for flow in flows.iter().take(globals.num_flows as usize) {
+                let region = flow.region;
+                if p[0] >= region.x[0]
+                    && p[1] >= region.y[0]
+                    && p[0] <= region.x[1]
+                    && p[1] <= region.y[1]
+                {
+                    position[0] += flow.velocity[0];
+                    position[1] += flow.velocity[1];
+                }
+            }
+            let slowed_by = match globals.drag {
+                Drag::Linear => 1.0 - friction,
+                Drag::Quadratic => {
+                    let speed = (velocity[0] * velocity[0] + velocity[1] * velocity[1]).sqrt();
+                    1.0 / (1.0 + friction * speed)
+                }
+                Drag::None => 1.0,
+            };
+            for v in velocity.iter_mut() {
+                *v *= slowed_by;
+            }
+
+            for axis in 0..2 {
+                if globals.wrapping {
+                    if position[axis] < -globals.dist {
+                        position[axis] += globals.dist * 2.0;
+                    } else if position[axis] >= globals.dist {
+                        position[axis] -= globals.dist * 2.0;
+                    }
+                } else if globals.dist != 0.0 {
+                    if position[axis] < -globals.dist {
+                        velocity[axis] = -velocity[axis] * globals.restitution;
+                        position[axis] = -globals.dist;
+                    } else if position[axis] >= globals.dist {
+                        velocity[axis] = -velocity[axis] * globals.restitution;
+                        position[axis] = globals.dist;
+                    }
+                }
+            }
+            self.positions[i] = position;
+            self.velocities[i] = velocity;
+        }
+    }
+
+    /// Pulls bonded particles toward the rest length of their bonds, as bonds.wgsl does
+    pub fn pull_bonds(&mut self, bonds: &[BondConfig], globals: &Globals) {
+        for bond in bonds {
+            let (a, b) = (bond.a as usize, bond.b as usize);
+            if a >= self.positions.len() || b >= self.positions.len() {
+                continue;
+            }
+            let (p, q) = (self.positions[a], self.positions[b]);
+            let delta = nearest_image([q[0] - p[0], q[1] - p[1]], globals);
+            let r = (delta[0] * delta[0] + delta[1] * delta[1]).sqrt();
+            if r < 0.01 {
+                continue;
+            }
+            let stretch = (r - bond.length) * bond.stiffness;
+            for axis in 0..2 {
+                self.velocities[a][axis] += delta[axis] / r * stretch;
+                self.velocities[b][axis] -= delta[axis] / r * stretch;
+            }
+        }
+    }
+
+    /// Turns reactants near their catalysts into products, as reactions.wgsl does, though with
+    /// different random numbers
+    pub fn react(&mut self, reactions: &[ReactionConfig], globals: &Globals) {
+        let old = self.types.clone();
+        for (i, &p) in self.positions.iter().enumerate() {
+            for reaction in reactions {
+                if old[i] != reaction.reactant
+                    || !with_rng(|rng| rng.gen_bool(reaction.probability as f64))
+                {
+                    continue;
+                }
+                let near = self
+                    .positions
+                    .iter()
+                    .zip(&old)
+                    .enumerate()
+                    .any(|(j, (&q, &q_type))| {
+                        let delta = nearest_image([q[0] - p[0], q[1] - p[1]], globals);
+                        j != i
+                            && q_type == reaction.catalyst
+                            && delta[0] * delta[0] + delta[1] * delta[1]
+                                <= reaction.radius * reaction.radius
+                    });
+                if near {
+                    self.types[i] = reaction.product;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// The shortest way from one point to another, which with wrapping walls can be across the seam
+fn nearest_image(mut delta: [f32; 2], globals: &Globals) -> [f32; 2] {
+    if globals.wrapping {
+        for d in delta.iter_mut() {
+            if *d > globals.dist {
+                *d -= globals.dist * 2.0;
+            } else if *d < -globals.dist {
+                *d += globals.dist * 2.0;
+            }
+        }
+    }
+    delta
+}
+
+/// `a` plus `b` scaled by `scale`
+fn add(a: [f32; 2], b: [f32; 2], scale: f32) -> [f32; 2] {
+    [a[0] + b[0] * scale, a[1] + b[1] * scale]
+}
+
+/// Slows `velocity` down to `max_velocity` if it is faster
+fn capped(velocity: [f32; 2], globals: &Globals) -> [f32; 2] {
+    let speed = (velocity[0] * velocity[0] + velocity[1] * velocity[1]).sqrt();
+    if globals.max_velocity > 0.0 && speed > globals.max_velocity {
+        let scale = globals.max_velocity / speed;
+        [velocity[0] * scale, velocity[1] * scale]
+    } else {
+        velocity
+    }
+}
+
+/// Adds velocity along `direction`, fading out to nothing at `radius`
+fn push(velocity: &mut [f32; 2], direction: [f32; 2], radius: f32, strength: f32) {
+    let d = (direction[0] * direction[0] + direction[1] * direction[1]).sqrt();
+    if d < radius && d > 0.01 {
+        let falloff = 1.0 - d / radius;
+        velocity[0] += direction[0] / d * strength * falloff;
+        velocity[1] += direction[1] / d * strength * falloff;
+    }
+}
@@ -0,0 +1,186 @@
+use wgpu::*;
+
+use crate::util::{BindableBuffer, VEC2_SIZE};
+
+/// Width and height of a density texel in physical pixels, `CELL_SIZE` in density.wgsl
+const CELL_SIZE: u32 = 4;
+/// Blendable, and exact for counts of up to 2048 particles per texel
+const DENSITY_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+/// Shows how many particles are in each part of the screen instead of drawing them one by one,
+/// which stays readable with hundreds of thousands of particles
+pub struct Density {
+    /// Screen size the texture was made for
+    width: u32,
+    height: u32,
+    view: TextureView,
+    splat_pipeline: RenderPipeline,
+    splat_bind_group: BindGroup,
+    show_pipeline: RenderPipeline,
+    show_bind_group: BindGroup,
+}
+
+impl Density {
+    pub fn new(
+        device: &Device,
+        format: TextureFormat,
+        render_globals: &BindableBuffer,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("density"),
+            size: Extent3d {
+                width: width.div_ceil(CELL_SIZE).max(1),
+                height: height.div_ceil(CELL_SIZE).max(1),
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: DENSITY_FORMAT,
+            usage: TextureUsage::RENDER_ATTACHMENT | TextureUsage::SAMPLED,
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+
+        let shader = device.create_shader_module(&ShaderModuleDescriptor {
+            label: Some("density_shader"),
+            source: ShaderSource::Wgsl(include_str!("density.wgsl").into()),
+            flags: ShaderFlags::VALIDATION,
+        });
+
+        let splat_layout = BindableBuffer::bind_group_layout(device, &[render_globals]);
+        let splat_bind_group = BindableBuffer::bind_group(device, &[render_globals]);
+        let splat_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("density_splat_pipeline"),
+            layout: Some(&device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("density_splat_layout"),
+                bind_group_layouts: &[&splat_layout],
+                push_constant_ranges: &[],
+            })),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "splat_vertex",
+                buffers: &[VertexBufferLayout {
+                    array_stride: VEC2_SIZE as u64,
+                    step_mode: InputStepMode::Vertex,
+                    attributes: &[VertexAttribute {
+                        format: VertexFormat::Float2,
+                        offset: 0,
+                        shader_location: 0,
+                    }],
+                }],
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::PointList,
+                ..PrimitiveState::default()
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "splat_fragment",
+                targets: &[ColorTargetState {
+                    format: DENSITY_FORMAT,
+                    alpha_blend: BlendState::REPLACE,
+                    color_blend: BlendState {
+                        src_factor: BlendFactor::One,
+                        dst_factor: BlendFactor::One,
+                        operation: BlendOperation::Add,
+                    },
+                    write_mask: ColorWrite::ALL,
+                }],
+            }),
+        });
+
+        let show_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("density_show_layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        });
+        let show_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("density_show_bind_group"),
+            layout: &show_layout,
+            entries: &[BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(&view),
+            }],
+        });
+        let show_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("density_show_pipeline"),
+            layout: Some(&device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("density_show_layout"),
+                bind_group_layouts: &[&show_layout],
+                push_constant_ranges: &[],
+            })),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "show_vertex",
+                buffers: &[],
+            },
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "show_fragment",
+                targets: &[format.into()],
+            }),
+        });
+
+        Self {
+            width,
+            height,
+            view,
+            splat_pipeline,
+            splat_bind_group,
+            show_pipeline,
+            show_bind_group,
+        }
+    }
+
+    /// Whether the texture was made for a screen of this size
+    pub fn fits(&self, width: u32, height: u32) -> bool {
+        self.width == width && self.height == height
+    }
+
+    /// Particle counts, one texel per `CELL_SIZE` pixels along each side
+    pub fn view(&self) -> &TextureView {
+        &self.view
+    }
+
+    /// Counts the particles in each texel. Must be recorded after the render globals for this
+    /// frame have been written.
+    pub fn update(&self, encoder: &mut CommandEncoder, positions: &Buffer, num_points: u32) {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("density_pass"),
+            color_attachments: &[RenderPassColorAttachmentDescriptor {
+                attachment: &self.view,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::TRANSPARENT),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.splat_pipeline);
+        render_pass.set_bind_group(0, &self.splat_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, positions.slice(..));
+        render_pass.draw(0..num_points, 0..1);
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        render_pass.set_pipeline(&self.show_pipeline);
+        render_pass.set_bind_group(0, &self.show_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
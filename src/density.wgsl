@@ -0,0 +1,80 @@
+// Density view: particles are first added up as single points in a low resolution texture, which
+// is then stretched over the screen through a colormap.
+
+// Screen pixels per texel along each side, must match `CELL_SIZE` in density.rs
+const CELL_SIZE : f32 = 4.0;
+// Particles per texel shown at the top of the colormap
+const FULL_DENSITY : f32 = 64.0;
+
+[[block]]
+struct RenderGlobals {
+    x : f32;
+    y : f32;
+    width : u32;
+    height : u32;
+    zoom : f32;
+};
+
+[[group(0), binding(0)]] var<uniform> render_globals : RenderGlobals;
+
+[[location(0)]]
+var<in> in_point_pos: vec2<f32>;
+[[builtin(position)]]
+var<out> out_pos: vec4<f32>;
+
+[[stage(vertex)]]
+fn splat_vertex() {
+    var width : f32 = f32(render_globals.width);
+    var height : f32 = f32(render_globals.height);
+    var camera_pos : vec2<f32> = vec2<f32>(render_globals.x, render_globals.y);
+    var size : vec2<f32> = vec2<f32>(width, height);
+    var smallest_side : f32 = min(width, height);
+    var aspect_ratio : vec2<f32> = size / vec2<f32>(smallest_side, smallest_side);
+    var pos : vec2<f32> = (in_point_pos - camera_pos) / aspect_ratio * vec2<f32>(render_globals.zoom, render_globals.zoom);
+    out_pos = vec4<f32>(pos, 0.0, 1.0);
+}
+
+[[location(0)]]
+var<out> out_color: vec4<f32>;
+
+// Added to whatever is already in the texel
+[[stage(fragment)]]
+fn splat_fragment() {
+    out_color = vec4<f32>(1.0, 0.0, 0.0, 1.0);
+}
+
+[[builtin(vertex_index)]]
+var<in> in_vertex_index : u32;
+
+// A single triangle covering the whole screen
+[[stage(vertex)]]
+fn show_vertex() {
+    var x : f32 = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    var y : f32 = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    out_pos = vec4<f32>(x, y, 0.0, 1.0);
+}
+
+[[group(0), binding(1)]] var density : texture_2d<f32>;
+[[builtin(frag_coord)]] var<in> frag_coord : vec4<f32>;
+
+// Black through dark blue and teal to pale yellow
+fn density_color(t : f32) -> vec3<f32> {
+    var scaled : f32 = clamp(t, 0.0, 1.0) * 3.0;
+    if (scaled < 1.0) {
+        return mix(vec3<f32>(0.0, 0.0, 0.0), vec3<f32>(0.1, 0.1, 0.5), vec3<f32>(scaled, scaled, scaled));
+    }
+    if (scaled < 2.0) {
+        var u : f32 = scaled - 1.0;
+        return mix(vec3<f32>(0.1, 0.1, 0.5), vec3<f32>(0.1, 0.7, 0.6), vec3<f32>(u, u, u));
+    }
+    var v : f32 = scaled - 2.0;
+    return mix(vec3<f32>(0.1, 0.7, 0.6), vec3<f32>(1.0, 1.0, 0.7), vec3<f32>(v, v, v));
+}
+
+[[stage(fragment)]]
+fn show_fragment() {
+    var texel : vec2<i32> = vec2<i32>(i32(frag_coord.x / CELL_SIZE), i32(frag_coord.y / CELL_SIZE));
+    var count : f32 = textureLoad(density, texel, 0).x;
+    // Logarithmic, so that sparse areas stay visible next to dense clusters
+    out_color = vec4<f32>(density_color(log(1.0 + count) / log(1.0 + FULL_DENSITY)), 1.0);
+}
@@ -0,0 +1,225 @@
+use std::{io::Write, mem::size_of};
+
+use wgpu::*;
+
+use crate::{
+    density::Density,
+    simulation::Simulation,
+    util::{BindableBuffer, VEC2_SIZE},
+};
+
+const WORKGROUP_SIZE: u32 = 64;
+
+/// Puts particles in order from the most to the least crowded part of the screen before they are
+/// drawn, so that translucent particles blend the same way from one frame to the next
+pub struct DepthSort {
+    /// Number of entries, the particle capacity rounded up to a power of two
+    num_entries: u32,
+    key_pipeline: ComputePipeline,
+    sort_pipeline: ComputePipeline,
+    /// The first bitonic stage bound with each of the simulation's position buffers, for finding
+    /// the sort keys of whichever is current
+    key_bind_groups: [BindGroup; 2],
+    /// One per bitonic stage, for every power of two up to `num_entries`, in the order they run
+    stage_bind_groups: Vec<BindGroup>,
+    density_layout: BindGroupLayout,
+    /// Counts particles on screen, rebuilt when the window size changes
+    density: Option<(Density, BindGroup)>,
+    /// Positions and sorted entries, for drawing the particles in order, one per position buffer
+    draw_layout: BindGroupLayout,
+    draw_bind_groups: [BindGroup; 2],
+}
+
+impl DepthSort {
+    pub fn new(device: &Device, render_globals: &BindableBuffer, simulation: &Simulation) -> Self {
+        let capacity = (simulation.positions().size / VEC2_SIZE as u64) as u32;
+        let num_entries = capacity.next_power_of_two().max(WORKGROUP_SIZE);
+        let entries = BindableBuffer::new(
+            device,
+            BufferUsage::STORAGE,
+            ShaderStage::COMPUTE | ShaderStage::VERTEX,
+            false,
+            num_entries as usize * size_of::<[u32; 2]>(),
+            |_| {},
+        );
+        let stage = |distance: u32, run: u32| {
+            BindableBuffer::new(
+                device,
+                BufferUsage::UNIFORM,
+                ShaderStage::COMPUTE,
+                true,
+                size_of::<u32>() * 2,
+                |buffer| {
+                    let slice = buffer.slice(..);
+                    let mut view = slice.get_mapped_range_mut();
+                    let mut cursor = std::io::Cursor::new(&mut *view);
+                    cursor.write_all(&distance.to_le_bytes()).unwrap();
+                    cursor.write_all(&run.to_le_bytes()).unwrap();
+                },
+            )
+        };
+        let position_buffers = simulation.position_buffers();
+        let bind_group = |stage: &BindableBuffer, positions: &BindableBuffer| {
+            BindableBuffer::bind_group(
+                device,
+                &[
+                    &simulation.globals,
+                    render_globals,
+                    stage,
+                    positions,
+                    &entries,
+                ],
+            )
+        };
+
+        // Sorted runs double in size, and each is merged by comparing entries half a run apart,
+        // then a quarter, and so on
+        let mut stages = Vec::new();
+        let mut run = 2;
+        while run <= num_entries {
+            let mut distance = run / 2;
+            while distance >= 1 {
+                stages.push(stage(distance, run));
+                distance /= 2;
+            }
+            run *= 2;
+        }
+        // Only finding the keys reads the positions, so the sorting stages can bind either buffer
+        let key_bind_groups = [0, 1].map(|i| bind_group(&stages[0], &position_buffers[i]));
+        let stage_bind_groups = stages
+            .iter()
+            .map(|stage| bind_group(stage, &position_buffers[0]))
+            .collect::<Vec<_>>();
+
+        let layout = BindableBuffer::bind_group_layout(
+            device,
+            &[
+                &simulation.globals,
+                render_globals,
+                &stages[0],
+                &position_buffers[0],
+                &entries,
+            ],
+        );
+        let density_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("depth_sort_density_layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStage::COMPUTE,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("depth_sort_layout"),
+            bind_group_layouts: &[&layout, &density_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(&ShaderModuleDescriptor {
+            label: Some("depth_sort_shader"),
+            source: ShaderSource::Wgsl(include_str!("depth_sort.wgsl").into()),
+            flags: ShaderFlags::VALIDATION,
+        });
+        let compute_pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("depth_sort_compute"),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+
+        let draw_layout =
+            BindableBuffer::bind_group_layout(device, &[&position_buffers[0], &entries]);
+        let draw_bind_groups =
+            [0, 1].map(|i| BindableBuffer::bind_group(device, &[&position_buffers[i], &entries]));
+
+        Self {
+            num_entries,
+            key_pipeline: compute_pipeline("key"),
+            sort_pipeline: compute_pipeline("sort"),
+            key_bind_groups,
+            stage_bind_groups,
+            density_layout,
+            density: None,
+            draw_layout,
+            draw_bind_groups,
+        }
+    }
+
+    /// Layout of the bind groups `draw_bind_group` returns, for pipelines drawing sorted particles
+    pub fn draw_layout(&self) -> &BindGroupLayout {
+        &self.draw_layout
+    }
+
+    /// Bind group for drawing the particles in order from the current position buffer
+    pub fn draw_bind_group(&self, simulation: &Simulation) -> &BindGroup {
+        &self.draw_bind_groups[simulation.current_positions()]
+    }
+
+    /// Rebuilds the density counts if the window size changed
+    pub fn fit(
+        &mut self,
+        device: &Device,
+        format: TextureFormat,
+        render_globals: &BindableBuffer,
+        width: u32,
+        height: u32,
+    ) {
+        if self
+            .density
+            .as_ref()
+            .is_some_and(|(density, _)| density.fits(width, height))
+        {
+            return;
+        }
+        let density = Density::new(device, format, render_globals, width, height);
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("depth_sort_density_bind_group"),
+            layout: &self.density_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(density.view()),
+            }],
+        });
+        self.density = Some((density, bind_group));
+    }
+
+    /// Sorts the particles. Must be recorded after the render globals for this frame have been
+    /// written, and after `fit`.
+    pub fn update(&self, encoder: &mut CommandEncoder, simulation: &Simulation) {
+        let num_points = simulation.num_points;
+        let (density, density_bind_group) = self
+            .density
+            .as_ref()
+            .expect("Depth sort used before being fitted to the window");
+        density.update(encoder, &simulation.positions().buffer, num_points);
+
+        // Only the runs covering the particles need sorting; the padding after them is already
+        // in place
+        let sorted = num_points.next_power_of_two();
+        let levels = sorted.trailing_zeros() as usize;
+        let num_stages = levels * (levels + 1) / 2;
+
+        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("depth_sort_pass"),
+        });
+        pass.set_bind_group(
+            0,
+            &self.key_bind_groups[simulation.current_positions()],
+            &[],
+        );
+        pass.set_bind_group(1, density_bind_group, &[]);
+        pass.set_pipeline(&self.key_pipeline);
+        pass.dispatch(self.num_entries / WORKGROUP_SIZE, 1, 1);
+        pass.set_pipeline(&self.sort_pipeline);
+        for bind_group in &self.stage_bind_groups[..num_stages] {
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch(sorted.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+// Depth sort: every particle is keyed by how crowded its part of the screen is, and the
+// (key, particle) pairs are then put in order by a bitonic sort, one compare and swap stage per
+// dispatch.
+
+// Screen pixels per density texel along each side, must match `CELL_SIZE` in density.rs
+const CELL_SIZE : f32 = 4.0;
+// Largest key a particle can get, for a particle with nothing around it
+const SPARSE_KEY : u32 = 65535u;
+// Key of the entries after the last particle, which sort after all of them
+const PADDING_KEY : u32 = 65536u;
+
+[[block]]
+struct Globals {
+    num_points : u32;
+};
+
+[[block]]
+struct RenderGlobals {
+    x : f32;
+    y : f32;
+    width : u32;
+    height : u32;
+    zoom : f32;
+};
+
+[[block]]
+struct Stage {
+    // Distance between the two entries compared by each invocation
+    distance : u32;
+    // Size of the runs sorted in the same direction
+    run : u32;
+};
+
+[[block]]
+struct Positions {
+    data : [[stride(8)]] array< vec2<f32> >;
+};
+
+// Key in x and particle index in y
+[[block]]
+struct Entries {
+    data : [[stride(8)]] array< vec2<u32> >;
+};
+
+[[group(0), binding(0)]] var<uniform> globals : Globals;
+[[group(0), binding(1)]] var<uniform> render_globals : RenderGlobals;
+[[group(0), binding(2)]] var<uniform> stage : Stage;
+[[group(0), binding(3)]] var<storage> positions : [[access(read)]] Positions;
+[[group(0), binding(4)]] var<storage> entries : [[access(read_write)]] Entries;
+[[group(1), binding(0)]] var density : texture_2d<f32>;
+
+[[builtin(global_invocation_id)]] var<in> global_invocation_id : vec3<u32>;
+
+// Most crowded first, so that particles in sparse areas are drawn over dense clusters
+[[stage(compute), workgroup_size(64)]]
+fn key() -> void {
+    var i : u32 = global_invocation_id.x;
+    if (i >= globals.num_points) {
+        entries.data[i] = vec2<u32>(PADDING_KEY, i);
+        return;
+    }
+    var size : vec2<f32> = vec2<f32>(f32(render_globals.width), f32(render_globals.height));
+    var smallest_side : f32 = min(size.x, size.y);
+    var ndc : vec2<f32> = (positions.data[i] - vec2<f32>(render_globals.x, render_globals.y))
+        / (size / vec2<f32>(smallest_side, smallest_side))
+        * vec2<f32>(render_globals.zoom, render_globals.zoom);
+    var px : f32 = (ndc.x + 1.0) * 0.5 * size.x;
+    var py : f32 = (1.0 - ndc.y) * 0.5 * size.y;
+    var key : u32 = SPARSE_KEY;
+    if (px >= 0.0 && py >= 0.0 && px < size.x && py < size.y) {
+        var texel : vec2<i32> = vec2<i32>(i32(px / CELL_SIZE), i32(py / CELL_SIZE));
+        var count : u32 = u32(textureLoad(density, texel, 0).x);
+        key = SPARSE_KEY - min(count, SPARSE_KEY);
+    }
+    entries.data[i] = vec2<u32>(key, i);
+}
+
+// Ties are broken by particle index, so that the order is the same every frame
+[[stage(compute), workgroup_size(64)]]
+fn sort() -> void {
+    var i : u32 = global_invocation_id.x;
+    var partner : u32 = i ^ stage.distance;
+    if (partner <= i) {
+        return;
+    }
+    var a : vec2<u32> = entries.data[i];
+    var b : vec2<u32> = entries.data[partner];
+    var ascending : bool = (i & stage.run) == 0u;
+    var in_order : bool = a.x < b.x || (a.x == b.x && a.y < b.y);
+    if (in_order != ascending) {
+        entries.data[i] = b;
+        entries.data[partner] = a;
+    }
+}
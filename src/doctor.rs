@@ -0,0 +1,185 @@
+use std::{fs, path::PathBuf, time::Instant};
+
+use wgpu::*;
+
+use crate::{
+    serialize::*,
+    simulation::{max_workgroup_size, Simulation},
+};
+
+const PROBE_SIZES: [u32; 4] = [1024, 4096, 16384, 65536];
+const WARMUP_STEPS: u64 = 5;
+const TIMED_STEPS: u64 = 20;
+/// Steps per second needed to keep up with a 60 Hz display at one tick per frame
+const REALTIME_TPS: f32 = 60.0;
+/// Workgroup sizes tried by `tune_workgroup_size`
+const WORKGROUP_SIZES: [u32; 4] = [64, 128, 256, 512];
+/// Particles simulated while comparing workgroup sizes, enough to fill many workgroups
+const TUNING_POINTS: u32 = 8192;
+/// Real-time particle counts below this get `--profile performance`, which switches to the
+/// density map at 20000 particles
+const PERFORMANCE_BELOW: u32 = 20_000;
+/// Real-time particle counts from this up get `--profile quality`, which leaves headroom for its
+/// trails and depth sorting
+const QUALITY_FROM: u32 = 200_000;
+
+/// Returns the path of the file marking that the probe has already run once,
+/// or `None` if no suitable configuration directory could be found.
+fn first_run_marker() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join("plife").join("probed"))
+}
+
+pub fn is_first_run() -> bool {
+    first_run_marker()
+        .map(|marker| !marker.exists())
+        .unwrap_or(false)
+}
+
+pub fn mark_probed() {
+    if let Some(marker) = first_run_marker() {
+        if let Some(dir) = marker.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let _ = fs::write(marker, b"");
+    }
+}
+
+fn probe_config(num_points: u32) -> Config {
+    let mut config: Config = serde_yaml::from_str(include_str!("../examples/cool.yml"))
+        .expect("Built-in probe config is invalid");
+    config.points = PointsConfig::Simple(Distribution::Const(num_points));
+    config
+}
+
+/// Runs a short simulation with `num_points` particles and returns the measured steps per second.
+fn measure_tps(device: &Device, queue: &Queue, num_points: u32, workgroup_size: u32) -> f32 {
+    let mut simulation = Simulation::from_config(device, probe_config(num_points), workgroup_size)
+        .expect("Built-in probe config is invalid");
+    simulation.step_many(device, queue, WARMUP_STEPS);
+    device.poll(Maintain::Wait);
+    let start = Instant::now();
+    simulation.step_many(device, queue, TIMED_STEPS);
+    device.poll(Maintain::Wait);
+    TIMED_STEPS as f32 / start.elapsed().as_secs_f32()
+}
+
+/// Times a short simulation with each of `WORKGROUP_SIZES` up to `max`, and returns the fastest
+/// along with the steps per second measured for every size tried.
+pub fn tune_workgroup_size(device: &Device, queue: &Queue, max: u32) -> (u32, Vec<(u32, f32)>) {
+    let timings = WORKGROUP_SIZES
+        .iter()
+        .filter(|&&size| size <= max)
+        .map(|&size| (size, measure_tps(device, queue, TUNING_POINTS, size)))
+        .collect::<Vec<_>>();
+    let (fastest, _) =
+        timings
+            .iter()
+            .copied()
+            .fold((WORKGROUP_SIZES[0].min(max), 0.0), |best, timing| {
+                if timing.1 > best.1 {
+                    timing
+                } else {
+                    best
+                }
+            });
+    (fastest, timings)
+}
+
+fn backend_warnings(info: &AdapterInfo) -> Vec<&'static str> {
+    let mut warnings = Vec::new();
+    match info.backend {
+        Backend::Gl => warnings.push(
+            "The OpenGL backend is experimental and may lack compute shader support; prefer Vulkan, Metal or DirectX 12",
+        ),
+        Backend::Dx11 => warnings.push(
+            "The DirectX 11 backend has incomplete storage buffer support; prefer DirectX 12 or Vulkan",
+        ),
+        Backend::BrowserWebGpu => {
+            warnings.push("WebGPU in browsers may throttle long-running compute dispatches")
+        }
+        _ => {}
+    }
+    match info.device_type {
+        DeviceType::Cpu => warnings.push(
+            "This is a software renderer; expect only a few thousand particles at interactive speeds",
+        ),
+        DeviceType::VirtualGpu => {
+            warnings.push("Virtualized GPUs often have unreliable timing and present support")
+        }
+        DeviceType::IntegratedGpu => warnings
+            .push("Integrated GPUs share memory bandwidth with the CPU; keep particle counts modest"),
+        _ => {}
+    }
+    warnings
+}
+
+pub fn run(device: &Device, queue: &Queue, adapter: &Adapter, workgroup_size: u32) {
+    let info = adapter.get_info();
+    println!("Probing simulation throughput (this takes a few seconds)...");
+
+    let mut best: Option<(u32, f32)> = None;
+    for &num_points in PROBE_SIZES.iter() {
+        let tps = measure_tps(device, queue, num_points, workgroup_size);
+        println!(
+            "  {:>6} particles: {:>8.1} steps per second",
+            num_points, tps
+        );
+        best = Some((num_points, tps));
+        if tps < REALTIME_TPS {
+            break;
+        }
+    }
+
+    if let Some((num_points, tps)) = best {
+        // Every particle interacts with every other one, so cost grows with the square of the count
+        let realtime_points = (num_points as f32 * (tps / REALTIME_TPS).sqrt()) as u32;
+        println!(
+            "Recommended particle count for real-time viewing: {} to {}",
+            realtime_points / 2,
+            realtime_points
+        );
+        let small_tps = tps * (num_points as f32 / (realtime_points / 2).max(1) as f32).powi(2);
+        let ticks_per_frame = ((small_tps / REALTIME_TPS) as u32).max(1);
+        println!(
+            "At {} particles, up to {} ticks per frame should stay smooth (adjust with [ and ])",
+            realtime_points / 2,
+            ticks_per_frame
+        );
+        let profile = if realtime_points < PERFORMANCE_BELOW {
+            "performance"
+        } else if realtime_points < QUALITY_FROM {
+            "balanced"
+        } else {
+            "quality"
+        };
+        println!(
+            "Recommended display settings: --profile {}, or `density_threshold: {}` under `display` in the config to show the density map once there are more particles than that",
+            profile, realtime_points
+        );
+    }
+
+    println!(
+        "Comparing workgroup sizes at {} particles...",
+        TUNING_POINTS
+    );
+    let (fastest, timings) = tune_workgroup_size(device, queue, max_workgroup_size(&info));
+    for (size, tps) in timings {
+        println!("  {:>6} per workgroup: {:>8.1} steps per second", size, tps);
+    }
+    println!(
+        "Fastest workgroup size: {} (use it with --workgroup-size {}, or pick it on every start with --workgroup-size auto)",
+        fastest, fastest
+    );
+
+    let warnings = backend_warnings(&info);
+    if warnings.is_empty() {
+        println!("No known problems with this adapter and backend");
+    }
+    for warning in warnings {
+        println!("Warning: {}", warning);
+    }
+}
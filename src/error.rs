@@ -0,0 +1,49 @@
+use std::{error::Error, fmt};
+
+/// Exit status for failures not covered by the others
+pub const EXIT_FAILURE: i32 = 1;
+/// Exit status for mistakes in the arguments or in config, session and recording files
+pub const EXIT_CONFIG: i32 = 2;
+/// Exit status when no usable graphics adapter or device could be found
+pub const EXIT_GPU: i32 = 3;
+
+/// A mistake in the arguments or in a config, session or recording file, which the user can fix
+#[derive(Debug)]
+pub struct ConfigError(pub String);
+
+/// No usable graphics adapter or device
+#[derive(Debug)]
+pub struct GpuError(pub String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for ConfigError {}
+
+impl fmt::Display for GpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for GpuError {}
+
+/// Exit status for the kind of failure `error` is, or was caused by
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    if error.chain().any(|cause| cause.is::<ConfigError>()) {
+        EXIT_CONFIG
+    } else if error.chain().any(|cause| cause.is::<GpuError>()) {
+        EXIT_GPU
+    } else {
+        EXIT_FAILURE
+    }
+}
+
+/// Prints `error` along with what caused it, and exits with the status for its kind
+pub fn exit_with(error: anyhow::Error) -> ! {
+    eprintln!("{:#}", error);
+    std::process::exit(exit_code(&error))
+}
@@ -0,0 +1,73 @@
+use rand::Rng;
+
+use crate::{
+    serialize::{position_distribution, EventConfig},
+    simulation::{Globals, Walls},
+    util::with_rng,
+};
+
+/// Rolls for the configured random events and applies them to the simulation globals
+pub struct Events {
+    events: Vec<EventConfig>,
+    /// Whether an impulse was applied in the previous step and needs to be cleared
+    impulse_active: bool,
+    /// Steps left until the current attraction inversion ends
+    inversion_left: u32,
+}
+
+impl Events {
+    pub fn new(events: Vec<EventConfig>) -> Self {
+        Self {
+            events,
+            impulse_active: false,
+            inversion_left: 0,
+        }
+    }
+
+    /// Advances one step, returning whether `globals` changed and need to be uploaded again
+    pub fn step(&mut self, globals: &mut Globals, walls: &Walls) -> bool {
+        let mut changed = false;
+        if self.impulse_active {
+            globals.impulse_strength = 0.0;
+            self.impulse_active = false;
+            changed = true;
+        }
+        if self.inversion_left > 0 {
+            self.inversion_left -= 1;
+            if self.inversion_left == 0 {
+                globals.attraction_scale = 1.0;
+                changed = true;
+            }
+        }
+
+        for event in &self.events {
+            match event {
+                EventConfig::Impulse(impulse) => {
+                    if self.impulse_active
+                        || !with_rng(|rng| rng.gen_bool(1.0 / impulse.every as f64))
+                    {
+                        continue;
+                    }
+                    let x = impulse.x.clone();
+                    let y = impulse.y.clone();
+                    globals.impulse_x = x.unwrap_or_else(|| position_distribution(walls)).sample();
+                    globals.impulse_y = y.unwrap_or_else(|| position_distribution(walls)).sample();
+                    globals.impulse_strength = impulse.strength.clone().sample();
+                    globals.impulse_radius = impulse.radius.clone().sample();
+                    self.impulse_active = true;
+                    changed = true;
+                }
+                EventConfig::Inversion { every, duration } => {
+                    if self.inversion_left > 0 || !with_rng(|rng| rng.gen_bool(1.0 / *every as f64))
+                    {
+                        continue;
+                    }
+                    globals.attraction_scale = -1.0;
+                    self.inversion_left = *duration;
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+}
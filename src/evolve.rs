@@ -0,0 +1,264 @@
+use std::{fs, path::PathBuf, str::FromStr};
+
+use anyhow::Context;
+use rand::{seq::SliceRandom, thread_rng, Rng};
+use rand_distr::Normal;
+use serde::Serialize;
+use serde_yaml::Value;
+use structopt::StructOpt;
+use tracing::info;
+use wgpu::*;
+
+use crate::{
+    analysis,
+    error::ConfigError,
+    serialize::{Config, ConfigFormat},
+    simulation::{Point, Ruleset, Simulation, Walls},
+    util::with_rng,
+};
+
+#[derive(StructOpt)]
+pub struct EvolveOptions {
+    /// Config to start from; its walls, points and other settings are kept for every candidate
+    config_file: PathBuf,
+    /// Rulesets evaluated per generation
+    #[structopt(long, default_value = "16")]
+    population: usize,
+    #[structopt(long, default_value = "10")]
+    generations: u32,
+    /// Steps each candidate is simulated for before it is scored
+    #[structopt(long, default_value = "500")]
+    steps: u64,
+    /// What makes a ruleset good: clusters or variance
+    #[structopt(long, default_value = "clusters")]
+    fitness: Fitness,
+    /// Standard deviation of the noise added to attractions by mutation; radii get ten times as much
+    #[structopt(long, default_value = "0.1")]
+    mutation: f32,
+    /// Directory the best rulesets are written to as configs
+    #[structopt(long, default_value = "evolved")]
+    out: PathBuf,
+    /// Number of best rulesets to write out
+    #[structopt(long, default_value = "3")]
+    keep: usize,
+}
+
+#[derive(Clone, Copy)]
+pub enum Fitness {
+    /// Number of distinct clusters of particles
+    Clusters,
+    /// How far particles spread from their centroid
+    Variance,
+}
+
+impl FromStr for Fitness {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "clusters" => Ok(Fitness::Clusters),
+            "variance" => Ok(Fitness::Variance),
+            _ => Err(format!(
+                "Unknown fitness `{}`, expected clusters or variance",
+                s
+            )),
+        }
+    }
+}
+
+/// The part of a precise ruleset config written for each candidate
+#[derive(Serialize)]
+struct PreciseRuleset<'a> {
+    types: Vec<PreciseType<'a>>,
+    friction: f32,
+}
+
+#[derive(Serialize)]
+struct PreciseType<'a> {
+    attractions: &'a [f32],
+    min_r: &'a [f32],
+    max_r: &'a [f32],
+}
+
+/// Returns a copy of the base config document with its ruleset replaced by `ruleset`
+pub fn with_ruleset(base: &Value, ruleset: &Ruleset) -> Value {
+    let precise = PreciseRuleset {
+        types: (0..ruleset.num_point_types as usize)
+            .map(|i| PreciseType {
+                attractions: &ruleset.attractions[i],
+                min_r: &ruleset.min_r[i],
+                max_r: &ruleset.max_r[i],
+            })
+            .collect(),
+        friction: ruleset.friction,
+    };
+    let mut value = base.clone();
+    if let Value::Mapping(mapping) = &mut value {
+        mapping.insert(
+            Value::from("ruleset"),
+            serde_yaml::to_value(precise).expect("Cannot serialize ruleset"),
+        );
+    }
+    value
+}
+
+/// Adds noise with a standard deviation of `amount` to every attraction, and ten times as much to
+/// every radius
+pub fn mutate(ruleset: &mut Ruleset, amount: f32) {
+    let noise = Normal::new(0.0, amount).unwrap();
+    let radius_noise = Normal::new(0.0, amount * 10.0).unwrap();
+    with_rng(|rng| {
+        for row in &mut ruleset.attractions {
+            for attraction in row {
+                *attraction += rng.sample(noise);
+            }
+        }
+        for (min_row, max_row) in ruleset.min_r.iter_mut().zip(&mut ruleset.max_r) {
+            for (min_r, max_r) in min_row.iter_mut().zip(max_row) {
+                *min_r = (*min_r + rng.sample(radius_noise)).max(0.0);
+                *max_r = (*max_r + rng.sample(radius_noise)).max(*min_r + 1.0);
+            }
+        }
+    })
+}
+
+/// Builds a child that takes each type's row of rules from one of the parents, which must have the
+/// same number of types
+pub fn cross(a: &Ruleset, b: &Ruleset) -> Ruleset {
+    let mut child = a.clone();
+    with_rng(|rng| {
+        for i in 0..child.num_point_types as usize {
+            if rng.gen_bool(0.5) {
+                child.attractions[i] = b.attractions[i].clone();
+                child.min_r[i] = b.min_r[i].clone();
+                child.max_r[i] = b.max_r[i].clone();
+            }
+        }
+    });
+    child
+}
+
+impl Fitness {
+    /// Scores the current state of a simulation, higher being better
+    pub fn measure(self, points: &[Point]) -> f32 {
+        match self {
+            Fitness::Clusters => {
+                analysis::clusters(points, analysis::CLUSTER_RADIUS, analysis::MIN_CLUSTER_SIZE)
+                    .len() as f32
+            }
+            Fitness::Variance => analysis::spatial_variance(points),
+        }
+    }
+}
+
+fn score(
+    device: &Device,
+    queue: &Queue,
+    config: Config,
+    workgroup_size: u32,
+    steps: u64,
+    fitness: Fitness,
+) -> Result<f32, ConfigError> {
+    let mut simulation = Simulation::from_config(device, config, workgroup_size)?;
+    simulation.step_many(device, queue, steps);
+    Ok(fitness.measure(&simulation.read_points(device, queue)))
+}
+
+/// Returns a copy of the base config document with its ruleset and wall distance replaced by ones
+/// that were sampled from it, and its points by a plain count if one is given
+pub fn with_sampled(
+    base: &Value,
+    ruleset: &Ruleset,
+    walls: &Walls,
+    num_points: Option<u32>,
+) -> Value {
+    let mut value = with_ruleset(base, ruleset);
+    if let Value::Mapping(mapping) = &mut value {
+        if let Some(num_points) = num_points {
+            mapping.insert(Value::from("points"), Value::from(num_points));
+        }
+        if let (Walls::Square(dist) | Walls::Wrapping(dist), Some(Value::Mapping(walls))) =
+            (walls, mapping.get_mut(&Value::from("walls")))
+        {
+            walls.insert(Value::from("dist"), Value::from(*dist as f64));
+        }
+    }
+    value
+}
+
+/// Evolves rulesets for the chosen fitness, keeping the better half of every generation
+pub fn run(
+    device: &Device,
+    queue: &Queue,
+    options: EvolveOptions,
+    workgroup_size: u32,
+) -> anyhow::Result<()> {
+    let path = &options.config_file;
+    let invalid =
+        |e: String| ConfigError(format!("Invalid config file {}:\n{}", path.display(), e));
+    let format = ConfigFormat::from_path(path);
+    let text = fs::read_to_string(path)
+        .map_err(|e| ConfigError(format!("Cannot open config file {}: {}", path.display(), e)))?;
+    let base = format.parse_value(&text).map_err(invalid)?;
+    let config = |value: Value| Config::from_value(value).map_err(invalid);
+
+    // Procedural rulesets give a varied first generation; precise ones start as mutated copies
+    let population_size = options.population.max(2);
+    let mut population = (0..population_size)
+        .map(|i| {
+            let (mut ruleset, _, _) = config(base.clone())?.sample();
+            if i > 0 {
+                mutate(&mut ruleset, options.mutation);
+            }
+            Ok(ruleset)
+        })
+        .collect::<Result<Vec<_>, ConfigError>>()?;
+
+    let mut scored: Vec<(f32, Ruleset)> = Vec::new();
+    for generation in 1..=options.generations {
+        scored = population
+            .drain(..)
+            .map(|ruleset| {
+                let candidate = config(with_ruleset(&base, &ruleset))?;
+                let score = score(
+                    device,
+                    queue,
+                    candidate,
+                    workgroup_size,
+                    options.steps,
+                    options.fitness,
+                )?;
+                Ok((score, ruleset))
+            })
+            .collect::<Result<_, ConfigError>>()?;
+        scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+        info!(
+            "Generation {}: best {:.2}, median {:.2}",
+            generation,
+            scored[0].0,
+            scored[scored.len() / 2].0
+        );
+
+        let parents = &scored[..(scored.len() / 2).max(1)];
+        let mut rng = thread_rng();
+        population = parents.iter().map(|(_, ruleset)| ruleset.clone()).collect();
+        while population.len() < population_size {
+            let (_, a) = parents.choose(&mut rng).unwrap();
+            let (_, b) = parents.choose(&mut rng).unwrap();
+            let mut child = cross(a, b);
+            mutate(&mut child, options.mutation);
+            population.push(child);
+        }
+    }
+
+    fs::create_dir_all(&options.out)
+        .with_context(|| format!("Cannot create output directory {}", options.out.display()))?;
+    for (i, (score, ruleset)) in scored.iter().take(options.keep).enumerate() {
+        let path = options.out.join(format!("best-{}.yml", i + 1));
+        let text =
+            serde_yaml::to_string(&with_ruleset(&base, ruleset)).expect("Cannot serialize config");
+        fs::write(&path, text).with_context(|| format!("Cannot write {}", path.display()))?;
+        info!("Wrote {} (score {:.2})", path.display(), score);
+    }
+    Ok(())
+}
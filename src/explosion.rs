@@ -0,0 +1,233 @@
+use std::fmt;
+
+use structopt::StructOpt;
+use wgpu::{Device, Queue};
+
+use crate::{
+    lint::{max_attraction, top_speed},
+    serialize::Drag,
+    simulation::{Point, Simulation},
+};
+
+/// Most unstable type pairs listed as likely causes
+const MAX_CAUSES: usize = 3;
+
+/// Options for catching simulations that blow up
+#[derive(StructOpt)]
+pub struct ExplosionCheck {
+    /// Steps between checks for particles whose positions are no longer numbers or that move
+    /// absurdly fast, each of which reads all particles back from the GPU; 0 never checks
+    #[structopt(long = "explosion-check", default_value = "0")]
+    interval: u64,
+    /// Speed, in world units per step, above which a particle counts as exploded
+    #[structopt(long = "explosion-speed", default_value = "100")]
+    max_speed: f32,
+    /// Go back to the particles of the last check that passed when an explosion is caught
+    #[structopt(long = "explosion-restore")]
+    restore: bool,
+}
+
+impl ExplosionCheck {
+    pub fn is_active(&self) -> bool {
+        self.interval > 0
+    }
+
+    pub fn start(&self) -> ExplosionCheckState {
+        ExplosionCheckState {
+            last_check: None,
+            last_good: None,
+        }
+    }
+}
+
+/// Particles read back at a check that passed
+struct Snapshot {
+    ticks: u64,
+    points: Vec<Point>,
+    velocities: Vec<[f32; 2]>,
+}
+
+pub struct ExplosionCheckState {
+    last_check: Option<u64>,
+    /// Only kept with `--explosion-restore`
+    last_good: Option<Snapshot>,
+}
+
+impl ExplosionCheckState {
+    /// Ticks from `ticks` until the next check is due
+    pub fn due_in(&self, check: &ExplosionCheck, ticks: u64) -> u64 {
+        if !check.is_active() {
+            return u64::MAX;
+        }
+        self.last_check
+            .map_or(0, |last| (last + check.interval).saturating_sub(ticks))
+    }
+
+    /// Reads the particles back if a check is due, and returns what went wrong if they exploded.
+    /// With `--explosion-restore` the simulation is then taken back to the last check that
+    /// passed, if there was one.
+    pub fn check(
+        &mut self,
+        check: &ExplosionCheck,
+        device: &Device,
+        queue: &Queue,
+        simulation: &mut Simulation,
+        ticks: u64,
+    ) -> Option<Explosion> {
+        if self.due_in(check, ticks) > 0 {
+            return None;
+        }
+        self.last_check = Some(ticks);
+        let points = simulation.read_points(device, queue);
+        let velocities = simulation.read_velocities(device, queue);
+        let mut explosion = match Explosion::find(&points, &velocities, check.max_speed, ticks) {
+            Some(explosion) => explosion,
+            None => {
+                if check.restore {
+                    self.last_good = Some(Snapshot {
+                        ticks,
+                        points,
+                        velocities,
+                    });
+                }
+                return None;
+            }
+        };
+        explosion.causes = likely_causes(simulation);
+        if let Some(good) = &self.last_good {
+            simulation.rewind(queue, &good.points, &good.velocities);
+            explosion.restored_to = Some(good.ticks);
+        }
+        explosion.restore = check.restore;
+        Some(explosion)
+    }
+}
+
+/// Particles that went off to infinity or stopped being numbers at all, and what likely made them
+pub struct Explosion {
+    pub ticks: u64,
+    /// Particles whose position or velocity is infinite or not a number
+    pub non_finite: usize,
+    /// Other particles moving faster than `--explosion-speed`
+    pub too_fast: usize,
+    pub max_speed: f32,
+    /// Speed of the fastest particle that still has a finite velocity
+    pub fastest: f32,
+    /// Settings that likely caused the explosion, most likely first
+    pub causes: Vec<String>,
+    /// Tick the simulation was taken back to, with `--explosion-restore`
+    pub restored_to: Option<u64>,
+    restore: bool,
+}
+
+impl Explosion {
+    /// `None` if every particle is where it could be
+    fn find(points: &[Point], velocities: &[[f32; 2]], max_speed: f32, ticks: u64) -> Option<Self> {
+        let mut non_finite = 0;
+        let mut too_fast = 0;
+        let mut fastest: f32 = 0.0;
+        for (&(x, y, _), &[vx, vy]) in points.iter().zip(velocities) {
+            let speed = (vx * vx + vy * vy).sqrt();
+            if !(x.is_finite() && y.is_finite() && speed.is_finite()) {
+                non_finite += 1;
+                continue;
+            }
+            fastest = fastest.max(speed);
+            if speed > max_speed {
+                too_fast += 1;
+            }
+        }
+        if non_finite == 0 && too_fast == 0 {
+            return None;
+        }
+        Some(Self {
+            ticks,
+            non_finite,
+            too_fast,
+            max_speed,
+            fastest,
+            causes: Vec::new(),
+            restored_to: None,
+            restore: false,
+        })
+    }
+}
+
+impl fmt::Display for Explosion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The simulation exploded by tick {}: {} particles have positions or velocities that are infinite or not a number, and {} move faster than {} per step (the fastest at {:.1})",
+            self.ticks, self.non_finite, self.too_fast, self.max_speed, self.fastest
+        )?;
+        if self.causes.is_empty() {
+            write!(f, "\nNo setting stands out; the force tool, impulse events or particles painted on top of each other can also fling particles apart")?;
+        } else {
+            write!(f, "\nLikely causes:")?;
+            for cause in &self.causes {
+                write!(f, "\n  {}", cause)?;
+            }
+        }
+        match (self.restored_to, self.restore) {
+            (Some(ticks), _) => write!(f, "\nWent back to the particles at tick {}", ticks),
+            (None, true) => write!(
+                f,
+                "\nNo check had passed yet, so there was nothing to go back to"
+            ),
+            (None, false) => write!(
+                f,
+                "\nPass --explosion-restore to go back to the last particles that were fine"
+            ),
+        }
+    }
+}
+
+/// Settings of the sampled ruleset that let particles speed up without bound or overshoot each
+/// other, as `plife lint` finds them in configs
+fn likely_causes(simulation: &Simulation) -> Vec<String> {
+    let ruleset = &simulation.ruleset;
+    let friction = ruleset.friction as f64;
+    let drag = simulation.drag();
+    if drag == Drag::None {
+        return vec![
+            "drag is none, so nothing slows particles down and attractions keep speeding them up; use linear or quadratic drag".to_owned(),
+        ];
+    }
+    if friction <= 0.0 {
+        return vec![
+            "ruleset.friction is 0, so nothing slows particles down and attractions keep speeding them up; use at least 0.01".to_owned(),
+        ];
+    }
+    let num_types = ruleset.num_point_types;
+    let mut unstable = Vec::new();
+    for a in 0..num_types {
+        for b in 0..num_types {
+            if !simulation.interaction_enabled(a, b) {
+                continue;
+            }
+            let (a, b) = (a as usize, b as usize);
+            let attraction = ruleset.attractions[a][b] as f64;
+            let reach = (ruleset.max_r[a][b] as f64).max(1.0) / 2.0;
+            let speed = top_speed(attraction, friction, drag);
+            if speed > reach {
+                unstable.push((speed / reach, a, b, attraction, speed, reach));
+            }
+        }
+    }
+    unstable.sort_by(|x, y| y.0.partial_cmp(&x.0).unwrap());
+    unstable
+        .iter()
+        .take(MAX_CAUSES)
+        .map(|&(_, a, b, attraction, speed, reach)| {
+            format!(
+                "type {} pulls on type {} with an attraction of {:.3}, which at a friction of {:.3} lets particles reach {:.1} per step, more than half of their max_r; keep it between -{5:.3} and {5:.3}",
+                a,
+                b,
+                attraction,
+                friction,
+                speed,
+                max_attraction(reach, friction, drag)
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,136 @@
+use rand::Rng;
+
+use crate::{
+    serialize::{DrainConfig, EmitterConfig, FaucetConfig, LifetimeConfig},
+    simulation::{Point, PointType},
+    util::with_rng,
+};
+
+/// Steps per second of simulated time, i.e. one tick per frame on a 60 Hz display
+const STEPS_PER_SECOND: f32 = 60.0;
+/// Steps between drain checks, since each check reads every particle back from the GPU
+const DRAIN_INTERVAL: u64 = 10;
+
+/// Decides when faucets and emitters add particles, and which particles drains and lifetimes
+/// remove
+pub struct Faucets {
+    faucets: Vec<FaucetConfig>,
+    emitters: Vec<EmitterConfig>,
+    drains: Vec<DrainConfig>,
+    lifetimes: Vec<LifetimeConfig>,
+    /// Fractional particles each faucet has accumulated but not yet added
+    owed: Vec<f32>,
+    /// The same for each emitter
+    emitter_owed: Vec<f32>,
+    steps_until_drain: u64,
+    /// Steps taken so far
+    ticks: u64,
+    /// Step each particle was added in, in the order of the particle buffers
+    born: Vec<u64>,
+}
+
+impl Faucets {
+    pub fn new(
+        faucets: Vec<FaucetConfig>,
+        emitters: Vec<EmitterConfig>,
+        drains: Vec<DrainConfig>,
+        lifetimes: Vec<LifetimeConfig>,
+        num_points: u32,
+    ) -> Self {
+        Self {
+            owed: vec![0.0; faucets.len()],
+            emitter_owed: vec![0.0; emitters.len()],
+            faucets,
+            emitters,
+            drains,
+            lifetimes,
+            steps_until_drain: DRAIN_INTERVAL,
+            ticks: 0,
+            born: vec![0; num_points as usize],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.has_faucets() && self.drains.is_empty() && self.lifetimes.is_empty()
+    }
+
+    /// Whether anything adds particles
+    pub fn has_faucets(&self) -> bool {
+        !self.faucets.is_empty() || !self.emitters.is_empty()
+    }
+
+    /// Returns the particles to add in the current step, and moves on to the next
+    pub fn spawn(&mut self, num_types: PointType) -> Vec<Point> {
+        self.ticks += 1;
+        let mut points = Vec::new();
+        for (faucet, owed) in self.faucets.iter().zip(&mut self.owed) {
+            *owed += faucet.per_second / STEPS_PER_SECOND;
+            while *owed >= 1.0 {
+                let (x, y) = faucet.region.sample();
+                points.push((x, y, Some(faucet.type_)));
+                *owed -= 1.0;
+            }
+        }
+        for (emitter, owed) in self.emitters.iter().zip(&mut self.emitter_owed) {
+            *owed += emitter.per_second / STEPS_PER_SECOND;
+            while *owed >= 1.0 {
+                let type_ = match emitter.type_.clone() {
+                    // Unbounded distributions can still come out past the last type
+                    Some(type_) => type_.sample().min(num_types - 1),
+                    None => with_rng(|rng| rng.gen_range(0..num_types)),
+                };
+                let (x, y) = (emitter.x.clone().sample(), emitter.y.clone().sample());
+                points.push((x, y, Some(type_)));
+                *owed -= 1.0;
+            }
+        }
+        points
+    }
+
+    /// Whether drains and lifetimes should be checked in the current step
+    pub fn drain_due(&mut self) -> bool {
+        if self.drains.is_empty() && self.lifetimes.is_empty() {
+            return false;
+        }
+        self.steps_until_drain -= 1;
+        if self.steps_until_drain == 0 {
+            self.steps_until_drain = DRAIN_INTERVAL;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn drains(&self, x: f32, y: f32, type_: PointType) -> bool {
+        self.drains.iter().any(|drain| {
+            drain.type_.map(|t| t == type_).unwrap_or(true) && drain.region.contains(x, y)
+        })
+    }
+
+    /// Whether particle `index`, now of type `type_`, has outlived the lifetime of its type
+    pub fn expired(&self, index: usize, type_: PointType) -> bool {
+        let age = self.ticks - self.born.get(index).copied().unwrap_or(self.ticks);
+        self.lifetimes
+            .iter()
+            .any(|lifetime| lifetime.type_ == type_ && age >= lifetime.steps)
+    }
+
+    /// Notes that `count` particles were added after the existing ones
+    pub fn added(&mut self, count: usize) {
+        let ticks = self.ticks;
+        self.born.extend(std::iter::repeat(ticks).take(count));
+    }
+
+    /// Forgets the particles marked in `removed`, whose followers moved up to fill the gaps
+    pub fn removed(&mut self, removed: &[bool]) {
+        let mut removed = removed.iter();
+        self.born
+            .retain(|_| !removed.next().copied().unwrap_or(false));
+    }
+
+    /// Starts over with `count` particles just added, after every particle was replaced
+    pub fn replaced(&mut self, count: usize) {
+        self.born.clear();
+        self.added(count);
+    }
+}
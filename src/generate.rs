@@ -0,0 +1,191 @@
+use std::{io, path::PathBuf};
+
+use rand::{rngs::StdRng, Rng};
+use serde_yaml::{Mapping, Value};
+use structopt::StructOpt;
+use tracing::info;
+
+use crate::{
+    serialize::write_yaml,
+    util::{seed_rng, with_rng},
+};
+
+/// Options for writing a random config to start from
+#[derive(StructOpt)]
+pub struct GenerateOptions {
+    /// Number of particle types
+    #[structopt(long, default_value = "6")]
+    types: u32,
+    /// File to write the config to, as YAML; printed if omitted
+    #[structopt(long)]
+    out: Option<PathBuf>,
+    /// Seed to generate the config from, to get the same one again; random if omitted
+    #[structopt(long)]
+    seed: Option<u64>,
+}
+
+fn map(entries: Vec<(&str, Value)>) -> Value {
+    Value::Mapping(
+        entries
+            .into_iter()
+            .map(|(key, value)| (Value::from(key), value))
+            .collect::<Mapping>(),
+    )
+}
+
+/// `x` to three decimals, which is plenty for anyone reading or editing the config
+fn round(x: f64) -> Value {
+    Value::from((x * 1000.0).round() / 1000.0)
+}
+
+/// A distribution of attractions centered a little either side of zero, of any kind there is,
+/// along with the strongest attraction it is likely to give
+fn attractions(rng: &mut StdRng) -> (Value, f64) {
+    let spread = rng.gen_range(0.01..0.05);
+    match rng.gen_range(0..3) {
+        0 => {
+            let mean = rng.gen_range(-0.01..0.01);
+            let distribution = map(vec![("mean", round(mean)), ("std", round(spread))]);
+            // Normal distributions are taken to stay within three standard deviations
+            (distribution, f64::abs(mean) + 3.0 * spread)
+        }
+        1 => (
+            map(vec![("min", round(-spread)), ("max", round(spread))]),
+            spread,
+        ),
+        _ => {
+            let values = (0..rng.gen_range(2..6))
+                .map(|_| round(rng.gen_range(-spread..spread)))
+                .collect();
+            (map(vec![("values", Value::Sequence(values))]), spread)
+        }
+    }
+}
+
+/// A random but sensible config: a procedural ruleset with `types` types, sometimes with its
+/// attractions tied by a symmetry, walls and particles in them, and sometimes impulses to stir
+/// things up. `plife lint` finds nothing wrong with it.
+pub fn generate(rng: &mut StdRng, types: u32) -> Value {
+    // Repulsion ends before attraction can start, which keeps every pair of types from only
+    // pushing each other apart
+    let min_r = rng.gen_range(5.0..25.0);
+    let max_r_low = min_r + rng.gen_range(5.0..20.0);
+    let max_r_high = max_r_low + rng.gen_range(40.0..250.0);
+    let (attractions, strongest) = attractions(rng);
+    // Enough friction that even the strongest pull cannot fling particles past each other in a
+    // single step, with a margin for rounding
+    let reach = max_r_low / 2.0;
+    let friction = f64::max(
+        rng.gen_range(0.02..0.1),
+        1.5 * strongest / (strongest + reach),
+    );
+    let mut ruleset = vec![
+        ("types", Value::from(types.max(1))),
+        ("attractions", attractions),
+        (
+            "min_r",
+            map(vec![("min", round(0.0)), ("max", round(min_r))]),
+        ),
+        (
+            "max_r",
+            map(vec![("min", round(max_r_low)), ("max", round(max_r_high))]),
+        ),
+        ("friction", round(friction)),
+    ];
+    match rng.gen_range(0..6) {
+        0 => ruleset.push(("symmetry", Value::from("symmetric"))),
+        1 => ruleset.push(("symmetry", Value::from("antisymmetric"))),
+        2 => ruleset.push(("zero_diagonal", Value::from(true))),
+        _ => {}
+    }
+
+    // Room for every particle to keep its neighbours at arm's length
+    let num_points = rng.gen_range(10..=40u32) * 100;
+    let dist = (num_points as f64).sqrt() * max_r_high * rng.gen_range(0.3..0.6);
+    let walls = if rng.gen_bool(0.5) {
+        map(vec![
+            ("type", Value::from("wrapping")),
+            ("dist", round(dist.round())),
+        ])
+    } else {
+        map(vec![
+            ("type", Value::from("square")),
+            ("dist", round(dist.round())),
+            ("restitution", round(rng.gen_range(0.5..1.0))),
+        ])
+    };
+
+    let everywhere = map(vec![
+        ("min", round(-dist.round())),
+        ("max", round(dist.round())),
+    ]);
+    let mut points = vec![map(vec![
+        ("num", Value::from(num_points)),
+        ("x", everywhere.clone()),
+        ("y", everywhere),
+    ])];
+    if rng.gen_bool(0.5) {
+        let center = map(vec![
+            ("mean", round(0.0)),
+            ("std", round((dist / 5.0).round())),
+        ]);
+        points.push(map(vec![
+            ("num", Value::from(num_points / 2)),
+            ("x", center.clone()),
+            ("y", center),
+        ]));
+    }
+
+    let mut config = vec![
+        ("ruleset", map(ruleset)),
+        ("walls", walls),
+        ("points", Value::Sequence(points)),
+    ];
+    if rng.gen_bool(0.3) {
+        config.push((
+            "events",
+            Value::Sequence(vec![map(vec![
+                ("type", Value::from("impulse")),
+                ("every", Value::from(rng.gen_range(5..20) * 100)),
+                ("strength", round(rng.gen_range(1.0..5.0))),
+                ("radius", round((max_r_high * 2.0).round())),
+            ])]),
+        ));
+    }
+    map(config)
+}
+
+/// Writes a random config to `--out`, or prints it
+pub fn run(options: &GenerateOptions) -> io::Result<()> {
+    if let Some(seed) = options.seed {
+        seed_rng(seed);
+    }
+    let config = with_rng(|rng| generate(rng, options.types));
+    write_yaml(&config, options.out.as_deref())?;
+    if let Some(path) = &options.out {
+        info!(
+            "Wrote a config with {} types to {}",
+            options.types,
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::{assert_valid_config, Config};
+
+    #[test]
+    fn generated_configs_are_valid() {
+        for seed in 0..50 {
+            seed_rng(seed);
+            let config = with_rng(|rng| generate(rng, seed as u32 % 10 + 1));
+            assert_valid_config(
+                &format!("config generated from seed {}", seed),
+                Config::from_value(config),
+            );
+        }
+    }
+}
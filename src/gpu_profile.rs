@@ -0,0 +1,131 @@
+use std::{convert::TryInto, mem::size_of};
+
+use serde::Serialize;
+use wgpu::*;
+
+/// Steps timed per submission; longer runs of steps are split over several submissions
+const MAX_TIMED_STEPS: u32 = 64;
+/// A start and an end timestamp per step
+const TIMESTAMP_PAIR_SIZE: u64 = size_of::<u64>() as u64 * 2;
+
+/// How long the GPU spent simulating, measured with `--profile-gpu`
+#[derive(Serialize, Clone, Copy)]
+pub struct GpuTimings {
+    /// Steps timed
+    pub steps: u64,
+    /// Average milliseconds the compute pass of a step took on the GPU
+    pub compute_ms: f64,
+    /// Fraction of the wall time the GPU spent in compute passes. Well below 1 means the steps
+    /// are waiting on submissions or other CPU work rather than on the GPU.
+    pub busy: f64,
+}
+
+impl GpuTimings {
+    /// Whether the GPU was kept busy for most of the time, so that only faster compute passes
+    /// would speed the simulation up
+    pub fn is_compute_bound(&self) -> bool {
+        self.busy >= 0.8
+    }
+}
+
+/// Writes GPU timestamps around the compute pass of every step, and adds them up after each
+/// submission. Reading them back waits for the GPU, which slows the steps down a little.
+pub struct GpuProfiler {
+    query_set: QuerySet,
+    resolved: Buffer,
+    /// Nanoseconds per timestamp tick
+    period: f32,
+    /// Steps timed in the submission being recorded
+    pending: u32,
+    steps: u64,
+    total_ns: f64,
+}
+
+impl GpuProfiler {
+    /// `None` if the device was not created with `Features::TIMESTAMP_QUERY`
+    pub fn new(device: &Device, queue: &Queue) -> Option<Self> {
+        if !device.features().contains(Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let query_set = device.create_query_set(&QuerySetDescriptor {
+            ty: QueryType::Timestamp,
+            count: MAX_TIMED_STEPS * 2,
+        });
+        let resolved = device.create_buffer(&BufferDescriptor {
+            label: Some("timestamps"),
+            size: MAX_TIMED_STEPS as u64 * TIMESTAMP_PAIR_SIZE,
+            usage: BufferUsage::MAP_READ | BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Some(Self {
+            query_set,
+            resolved,
+            period: queue.get_timestamp_period(),
+            pending: 0,
+            steps: 0,
+            total_ns: 0.0,
+        })
+    }
+
+    /// Whether the submission being recorded has no room to time another step
+    pub fn is_full(&self) -> bool {
+        self.pending == MAX_TIMED_STEPS
+    }
+
+    pub fn begin_step(&self, encoder: &mut CommandEncoder) {
+        encoder.write_timestamp(&self.query_set, self.pending * 2);
+    }
+
+    pub fn end_step(&mut self, encoder: &mut CommandEncoder) {
+        encoder.write_timestamp(&self.query_set, self.pending * 2 + 1);
+        self.pending += 1;
+    }
+
+    /// Copies the timestamps of the steps recorded so far to where `read` finds them. Must be
+    /// recorded last in the submission.
+    pub fn resolve(&self, encoder: &mut CommandEncoder) {
+        if self.pending > 0 {
+            encoder.resolve_query_set(&self.query_set, 0..self.pending * 2, &self.resolved, 0);
+        }
+    }
+
+    /// Waits for the submission `resolve` was recorded into and adds up its timestamps
+    pub fn read(&mut self, device: &Device) {
+        if self.pending == 0 {
+            return;
+        }
+        let slice = self
+            .resolved
+            .slice(..self.pending as u64 * TIMESTAMP_PAIR_SIZE);
+        let mapped = slice.map_async(MapMode::Read);
+        device.poll(Maintain::Wait);
+        futures::executor::block_on(mapped).expect("Failed to read timestamps from GPU");
+        for pair in slice
+            .get_mapped_range()
+            .chunks_exact(TIMESTAMP_PAIR_SIZE as usize)
+        {
+            let start = u64::from_le_bytes(pair[..8].try_into().unwrap());
+            let end = u64::from_le_bytes(pair[8..].try_into().unwrap());
+            self.total_ns += end.saturating_sub(start) as f64 * self.period as f64;
+        }
+        self.resolved.unmap();
+        self.steps += self.pending as u64;
+        self.pending = 0;
+    }
+
+    /// Timings of the steps since the last call, which took `wall_time` seconds in all, or
+    /// `None` if none were timed
+    pub fn take(&mut self, wall_time: f64) -> Option<GpuTimings> {
+        if self.steps == 0 {
+            return None;
+        }
+        let timings = GpuTimings {
+            steps: self.steps,
+            compute_ms: self.total_ns / 1e6 / self.steps as f64,
+            busy: (self.total_ns / 1e9 / wall_time.max(f64::EPSILON)).min(1.0),
+        };
+        self.steps = 0;
+        self.total_ns = 0.0;
+        Some(timings)
+    }
+}
@@ -0,0 +1,254 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    mem::size_of,
+    path::Path,
+};
+
+use wgpu::*;
+
+use crate::{
+    simulation::{PointType, Simulation, Walls},
+    util::{read_buffer, BindableBuffer, VEC2_SIZE},
+};
+
+/// Cells along each side, `SIZE` in heatmap.wgsl
+const SIZE: u32 = 512;
+const WORKGROUP_SIZE: u32 = 64;
+/// Blendable, and exact for counts of up to 2048 particles per cell in a single sample
+const SAMPLE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+/// Counts how often particles of one type have been in each cell of a grid over the simulation
+/// area, which shows the territories and paths of a type over a long run. The counting happens
+/// on the GPU, so the particles are only read back once, and only without walls.
+pub struct Heatmap {
+    /// Particles of the type counted in the latest sample
+    sample_view: TextureView,
+    /// Running count for each cell, row by row from the top
+    counts: BindableBuffer,
+    splat_pipeline: RenderPipeline,
+    splat_bind_group: BindGroup,
+    accumulate_pipeline: ComputePipeline,
+    accumulate_bind_group: BindGroup,
+}
+
+impl Heatmap {
+    pub fn new(device: &Device, queue: &Queue, simulation: &Simulation, type_: PointType) -> Self {
+        // Half the width of the area covered; without walls, fixed by where the particles of the
+        // type are now
+        let extent = match simulation.walls {
+            Walls::Square(dist) | Walls::Wrapping(dist) => dist,
+            Walls::None => simulation
+                .read_points(device, queue)
+                .iter()
+                .filter(|&&(_, _, t)| t == Some(type_))
+                .map(|&(x, y, _)| x.abs().max(y.abs()))
+                .filter(|d| d.is_finite())
+                .fold(1.0, f32::max),
+        };
+        let globals = BindableBuffer::new(
+            device,
+            BufferUsage::UNIFORM,
+            ShaderStage::VERTEX,
+            true,
+            size_of::<f32>() + size_of::<u32>(),
+            |buffer| {
+                let slice = buffer.slice(..);
+                let mut view = slice.get_mapped_range_mut();
+                view[..4].copy_from_slice(&extent.to_le_bytes());
+                view[4..].copy_from_slice(&type_.to_le_bytes());
+            },
+        );
+        let counts = BindableBuffer::new(
+            device,
+            BufferUsage::STORAGE | BufferUsage::COPY_SRC,
+            ShaderStage::COMPUTE,
+            false,
+            (SIZE * SIZE) as usize * size_of::<u32>(),
+            |_| {},
+        );
+
+        let sample = device.create_texture(&TextureDescriptor {
+            label: Some("heatmap_sample"),
+            size: Extent3d {
+                width: SIZE,
+                height: SIZE,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: SAMPLE_FORMAT,
+            usage: TextureUsage::RENDER_ATTACHMENT | TextureUsage::SAMPLED,
+        });
+        let sample_view = sample.create_view(&TextureViewDescriptor::default());
+
+        let shader = device.create_shader_module(&ShaderModuleDescriptor {
+            label: Some("heatmap_shader"),
+            source: ShaderSource::Wgsl(include_str!("heatmap.wgsl").into()),
+            flags: ShaderFlags::VALIDATION,
+        });
+
+        let splat_layout = BindableBuffer::bind_group_layout(device, &[&globals]);
+        let splat_bind_group = BindableBuffer::bind_group(device, &[&globals]);
+        let splat_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("heatmap_splat_pipeline"),
+            layout: Some(&device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("heatmap_splat_layout"),
+                bind_group_layouts: &[&splat_layout],
+                push_constant_ranges: &[],
+            })),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "splat_vertex",
+                buffers: &[
+                    VertexBufferLayout {
+                        array_stride: VEC2_SIZE as u64,
+                        step_mode: InputStepMode::Vertex,
+                        attributes: &[VertexAttribute {
+                            format: VertexFormat::Float2,
+                            offset: 0,
+                            shader_location: 0,
+                        }],
+                    },
+                    VertexBufferLayout {
+                        array_stride: size_of::<PointType>() as u64,
+                        step_mode: InputStepMode::Vertex,
+                        attributes: &[VertexAttribute {
+                            format: VertexFormat::Uint,
+                            offset: 0,
+                            shader_location: 1,
+                        }],
+                    },
+                ],
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::PointList,
+                ..PrimitiveState::default()
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "splat_fragment",
+                targets: &[ColorTargetState {
+                    format: SAMPLE_FORMAT,
+                    alpha_blend: BlendState::REPLACE,
+                    color_blend: BlendState {
+                        src_factor: BlendFactor::One,
+                        dst_factor: BlendFactor::One,
+                        operation: BlendOperation::Add,
+                    },
+                    write_mask: ColorWrite::ALL,
+                }],
+            }),
+        });
+
+        let accumulate_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("heatmap_accumulate_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStage::COMPUTE,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                counts.bind_group_layout_entry(2),
+            ],
+        });
+        let accumulate_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("heatmap_accumulate_bind_group"),
+            layout: &accumulate_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&sample_view),
+                },
+                counts.bind_group_entry(2),
+            ],
+        });
+        let accumulate_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("heatmap_accumulate_pipeline"),
+            layout: Some(&device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("heatmap_accumulate_layout"),
+                bind_group_layouts: &[&accumulate_layout],
+                push_constant_ranges: &[],
+            })),
+            module: &shader,
+            entry_point: "accumulate",
+        });
+
+        Self {
+            sample_view,
+            counts,
+            splat_pipeline,
+            splat_bind_group,
+            accumulate_pipeline,
+            accumulate_bind_group,
+        }
+    }
+
+    /// Counts where the particles of the type are now, without waiting for the GPU
+    pub fn add(&self, device: &Device, queue: &Queue, simulation: &Simulation) {
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("heatmap"),
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("heatmap_splat_pass"),
+                color_attachments: &[RenderPassColorAttachmentDescriptor {
+                    attachment: &self.sample_view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Color::TRANSPARENT),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&self.splat_pipeline);
+            render_pass.set_bind_group(0, &self.splat_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, simulation.positions().buffer.slice(..));
+            render_pass.set_vertex_buffer(1, simulation.types.buffer.slice(..));
+            render_pass.draw(0..simulation.num_points, 0..1);
+        }
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("heatmap_accumulate_pass"),
+            });
+            compute_pass.set_pipeline(&self.accumulate_pipeline);
+            compute_pass.set_bind_group(0, &self.accumulate_bind_group, &[]);
+            compute_pass.dispatch((SIZE * SIZE + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE, 1, 1);
+        }
+        queue.submit(Some(encoder.finish()));
+    }
+
+    /// Saves the counts as a PNG, going from black through red and yellow to white. Brightness
+    /// is logarithmic so that rarely visited cells still show up next to crowded ones.
+    pub fn save_png(&self, device: &Device, queue: &Queue, path: &Path) -> io::Result<()> {
+        let counts = read_buffer(device, queue, &self.counts.buffer, self.counts.size)
+            .chunks_exact(size_of::<u32>())
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect::<Vec<_>>();
+        let max = counts.iter().copied().max().unwrap_or(0).max(1) as f32;
+        let mut pixels = Vec::with_capacity((SIZE * SIZE * 3) as usize);
+        for &count in &counts {
+            let t = (1.0 + count as f32).ln() / (1.0 + max).ln();
+            for offset in 0..3 {
+                let channel = (t * 3.0 - offset as f32).clamp(0.0, 1.0);
+                pixels.push((channel * 255.0) as u8);
+            }
+        }
+
+        let file = File::create(path)?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), SIZE, SIZE);
+        encoder.set_color(png::ColorType::RGB);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&pixels)?;
+        Ok(())
+    }
+}
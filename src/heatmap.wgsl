@@ -0,0 +1,59 @@
+// Heat map: every sample, the particles of one type are added up as single points in a texture
+// covering the simulation area, which is then added to a running count for each cell.
+
+// Cells along each side, must match `SIZE` in heatmap.rs
+const SIZE : u32 = 512u;
+
+[[block]]
+struct HeatmapGlobals {
+    extent : f32;
+    point_type : u32;
+};
+
+[[block]]
+struct Counts {
+    data : [[stride(4)]] array<u32>;
+};
+
+[[group(0), binding(0)]] var<uniform> globals : HeatmapGlobals;
+
+[[location(0)]]
+var<in> in_point_pos : vec2<f32>;
+[[location(1)]]
+var<in> in_point_type : u32;
+[[builtin(position)]]
+var<out> out_pos : vec4<f32>;
+
+[[stage(vertex)]]
+fn splat_vertex() {
+    if (in_point_type == globals.point_type) {
+        out_pos = vec4<f32>(in_point_pos / vec2<f32>(globals.extent, globals.extent), 0.0, 1.0);
+    } else {
+        // Outside the clip volume, so never drawn
+        out_pos = vec4<f32>(2.0, 2.0, 0.0, 1.0);
+    }
+}
+
+[[location(0)]]
+var<out> out_color : vec4<f32>;
+
+// Added to whatever is already in the texel
+[[stage(fragment)]]
+fn splat_fragment() {
+    out_color = vec4<f32>(1.0, 0.0, 0.0, 1.0);
+}
+
+[[group(0), binding(1)]] var sample : texture_2d<f32>;
+[[group(0), binding(2)]] var<storage> counts : [[access(read_write)]] Counts;
+
+[[builtin(global_invocation_id)]] var<in> global_invocation_id : vec3<u32>;
+
+[[stage(compute), workgroup_size(64)]]
+fn accumulate() -> void {
+    var i : u32 = global_invocation_id.x;
+    if (i >= SIZE * SIZE) {
+        return;
+    }
+    var texel : vec2<i32> = vec2<i32>(i32(i % SIZE), i32(i / SIZE));
+    counts.data[i] = counts.data[i] + u32(textureLoad(sample, texel, 0).x);
+}
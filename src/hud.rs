@@ -0,0 +1,299 @@
+use std::time::{Duration, Instant};
+
+use wgpu::{util::*, *};
+
+use crate::util::BindableBuffer;
+
+/// Glyphs are 3 by 5 pixels, given row by row from the top with the leftmost pixel in the highest
+/// bit. Lowercase letters are drawn as uppercase ones and unknown characters are left blank.
+const FONT: &[(char, u16)] = &[
+    ('0', 0b111_101_101_101_111),
+    ('1', 0b010_110_010_010_111),
+    ('2', 0b111_001_111_100_111),
+    ('3', 0b111_001_111_001_111),
+    ('4', 0b101_101_111_001_001),
+    ('5', 0b111_100_111_001_111),
+    ('6', 0b111_100_111_101_111),
+    ('7', 0b111_001_001_001_001),
+    ('8', 0b111_101_111_101_111),
+    ('9', 0b111_101_111_001_111),
+    ('A', 0b010_101_111_101_101),
+    ('B', 0b110_101_110_101_110),
+    ('C', 0b011_100_100_100_011),
+    ('D', 0b110_101_101_101_110),
+    ('E', 0b111_100_110_100_111),
+    ('F', 0b111_100_110_100_100),
+    ('G', 0b011_100_101_101_011),
+    ('H', 0b101_101_111_101_101),
+    ('I', 0b111_010_010_010_111),
+    ('J', 0b001_001_001_101_010),
+    ('K', 0b101_101_110_101_101),
+    ('L', 0b100_100_100_100_111),
+    ('M', 0b101_111_111_101_101),
+    ('N', 0b110_101_101_101_101),
+    ('O', 0b010_101_101_101_010),
+    ('P', 0b110_101_110_100_100),
+    ('Q', 0b010_101_101_110_011),
+    ('R', 0b110_101_110_101_101),
+    ('S', 0b011_100_010_001_110),
+    ('T', 0b111_010_010_010_010),
+    ('U', 0b101_101_101_101_111),
+    ('V', 0b101_101_101_101_010),
+    ('W', 0b101_101_111_111_101),
+    ('X', 0b101_101_010_101_101),
+    ('Y', 0b101_101_010_010_010),
+    ('Z', 0b111_001_010_100_111),
+    ('.', 0b000_000_000_000_010),
+    (':', 0b000_010_000_010_000),
+    ('/', 0b001_001_010_100_100),
+    ('-', 0b000_000_111_000_000),
+];
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+/// Size of a glyph pixel in logical pixels
+const PIXEL_SIZE: f64 = 2.0;
+/// How much larger text is drawn with the high contrast display setting
+const HIGH_CONTRAST_SCALE: f64 = 1.5;
+/// Space around the text and between lines, in glyph pixels
+const MARGIN: u32 = 2;
+const TEXT_BRIGHTNESS: f32 = 1.0;
+const BACKING_BRIGHTNESS: f32 = 0.05;
+/// How long frames and ticks are counted for before the rates are updated
+const RATE_WINDOW: Duration = Duration::from_millis(500);
+/// x, y and brightness
+const VERTEX_SIZE: usize = std::mem::size_of::<f32>() * 3;
+
+/// Accented letters are drawn as the letter without the accent
+fn base_letter(c: char) -> char {
+    match c {
+        'À' | 'Á' | 'Â' | 'Ä' => 'A',
+        'Ç' => 'C',
+        'È' | 'É' | 'Ê' | 'Ë' => 'E',
+        'Ì' | 'Í' | 'Î' | 'Ï' => 'I',
+        'Ñ' => 'N',
+        'Ò' | 'Ó' | 'Ô' | 'Ö' => 'O',
+        'Ù' | 'Ú' | 'Û' | 'Ü' => 'U',
+        c => c,
+    }
+}
+
+fn glyph(c: char) -> u16 {
+    let c = c.to_uppercase().next().map_or(c, base_letter);
+    FONT.iter()
+        .find(|&&(glyph_char, _)| glyph_char == c)
+        .map_or(0, |&(_, bits)| bits)
+}
+
+/// Size of a glyph pixel in physical pixels, for the given physical pixels per logical pixel
+fn pixel_size(scale_factor: f64, high_contrast: bool) -> f32 {
+    let scale = if high_contrast {
+        HIGH_CONTRAST_SCALE
+    } else {
+        1.0
+    };
+    (PIXEL_SIZE * scale * scale_factor).round().max(1.0) as f32
+}
+
+/// Two triangles for the backing behind the text, then two for every lit glyph pixel
+fn text_vertices(lines: &[String], pixel: f32) -> Vec<u8> {
+    let mut contents = Vec::new();
+    let mut rect = |x: u32, y: u32, width: u32, height: u32, brightness: f32| {
+        let (left, top) = (x as f32 * pixel, y as f32 * pixel);
+        let (right, bottom) = ((x + width) as f32 * pixel, (y + height) as f32 * pixel);
+        for &(x, y) in [
+            (left, top),
+            (left, bottom),
+            (right, top),
+            (right, top),
+            (left, bottom),
+            (right, bottom),
+        ]
+        .iter()
+        {
+            for value in [x, y, brightness].iter() {
+                contents.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+    };
+
+    let columns = lines
+        .iter()
+        .map(|line| line.chars().count() as u32)
+        .max()
+        .unwrap_or(0);
+    let line_height = GLYPH_HEIGHT + MARGIN;
+    rect(
+        0,
+        0,
+        (columns * (GLYPH_WIDTH + 1)).saturating_sub(1) + MARGIN * 2,
+        lines.len() as u32 * line_height + MARGIN,
+        BACKING_BRIGHTNESS,
+    );
+    for (row, line) in lines.iter().enumerate() {
+        let top = MARGIN + row as u32 * line_height;
+        for (column, c) in line.chars().enumerate() {
+            let left = MARGIN + column as u32 * (GLYPH_WIDTH + 1);
+            let bits = glyph(c);
+            for y in 0..GLYPH_HEIGHT {
+                for x in 0..GLYPH_WIDTH {
+                    let bit = (GLYPH_HEIGHT - 1 - y) * GLYPH_WIDTH + (GLYPH_WIDTH - 1 - x);
+                    if bits & (1 << bit) != 0 {
+                        rect(left + x, top + y, 1, 1, TEXT_BRIGHTNESS);
+                    }
+                }
+            }
+        }
+    }
+    contents
+}
+
+/// Lines of text in the top left corner of the window, drawn with a tiny built-in pixel font
+pub struct Hud {
+    pipeline: RenderPipeline,
+    bind_group: BindGroup,
+    vertices: Option<Buffer>,
+    num_vertices: u32,
+    /// Whether the text is drawn larger, for the high contrast display setting
+    high_contrast: bool,
+    /// Start of the current rate window, and the frames and ticks counted in it so far
+    window_start: Instant,
+    frames: u32,
+    ticks: u32,
+    pub fps: f32,
+    pub tps: f32,
+}
+
+impl Hud {
+    pub fn new(
+        device: &Device,
+        render_globals: &BindableBuffer,
+        format: TextureFormat,
+        high_contrast: bool,
+    ) -> Self {
+        let shader = device.create_shader_module(&ShaderModuleDescriptor {
+            label: Some("hud_shader"),
+            source: ShaderSource::Wgsl(include_str!("hud.wgsl").into()),
+            flags: ShaderFlags::VALIDATION,
+        });
+        let bind_group_layout = BindableBuffer::bind_group_layout(device, &[render_globals]);
+        let bind_group = BindableBuffer::bind_group(device, &[render_globals]);
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("hud_pipeline"),
+            layout: Some(&device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("hud_layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            })),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "main",
+                buffers: &[VertexBufferLayout {
+                    array_stride: VERTEX_SIZE as u64,
+                    step_mode: InputStepMode::Vertex,
+                    attributes: &[
+                        VertexAttribute {
+                            format: VertexFormat::Float2,
+                            offset: 0,
+                            shader_location: 0,
+                        },
+                        VertexAttribute {
+                            format: VertexFormat::Float,
+                            offset: std::mem::size_of::<f32>() as u64 * 2,
+                            shader_location: 1,
+                        },
+                    ],
+                }],
+            },
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "main",
+                targets: &[format.into()],
+            }),
+        });
+        Self {
+            pipeline,
+            bind_group,
+            vertices: None,
+            num_vertices: 0,
+            high_contrast,
+            window_start: Instant::now(),
+            frames: 0,
+            ticks: 0,
+            fps: 0.0,
+            tps: 0.0,
+        }
+    }
+
+    /// Counts a drawn frame that ran `ticks` simulation ticks. Returns whether the frame and tick
+    /// rates were just updated.
+    pub fn record_frame(&mut self, ticks: u16) -> bool {
+        self.frames += 1;
+        self.ticks += ticks as u32;
+        let elapsed = self.window_start.elapsed();
+        if elapsed < RATE_WINDOW {
+            return false;
+        }
+        self.fps = self.frames as f32 / elapsed.as_secs_f32();
+        self.tps = self.ticks as f32 / elapsed.as_secs_f32();
+        self.window_start = Instant::now();
+        self.frames = 0;
+        self.ticks = 0;
+        true
+    }
+
+    /// Replaces the text shown, sizing it for the given physical pixels per logical pixel
+    pub fn set_text(&mut self, device: &Device, lines: &[String], scale_factor: f64) {
+        let contents = text_vertices(lines, pixel_size(scale_factor, self.high_contrast));
+        self.num_vertices = (contents.len() / VERTEX_SIZE) as u32;
+        self.vertices = Some(device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("hud"),
+            contents: &contents,
+            usage: BufferUsage::VERTEX,
+        }));
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        if let Some(vertices) = &self.vertices {
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, &self.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, vertices.slice(..));
+            render_pass.draw(0..self.num_vertices, 0..1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Left, top, right and bottom of the `index`th rectangle, in physical pixels
+    fn quad(vertices: &[u8], index: usize) -> [f32; 4] {
+        let float = |vertex: usize, component: usize| {
+            let start = (index * 6 + vertex) * VERTEX_SIZE + component * 4;
+            f32::from_le_bytes([
+                vertices[start],
+                vertices[start + 1],
+                vertices[start + 2],
+                vertices[start + 3],
+            ])
+        };
+        [float(0, 0), float(0, 1), float(5, 0), float(5, 1)]
+    }
+
+    #[test]
+    fn high_contrast_draws_larger_quads() {
+        let lines = vec!["FPS 60".to_owned()];
+        let normal = text_vertices(&lines, pixel_size(1.0, false));
+        let high_contrast = text_vertices(&lines, pixel_size(1.0, true));
+        assert_eq!(normal.len(), high_contrast.len());
+        // 6 glyphs of 3 pixels with 1 between them, plus margins, by one line and its margins
+        assert_eq!(quad(&normal, 0), [0.0, 0.0, 54.0, 18.0]);
+        assert_eq!(quad(&high_contrast, 0), [0.0, 0.0, 81.0, 27.0]);
+        // The top left pixel of the F
+        assert_eq!(quad(&normal, 1), [4.0, 4.0, 6.0, 6.0]);
+        assert_eq!(quad(&high_contrast, 1), [6.0, 6.0, 9.0, 9.0]);
+    }
+}
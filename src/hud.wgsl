@@ -0,0 +1,39 @@
+// On-screen statistics: rectangles given in physical pixels from the top left corner, each
+// either a lit pixel of a glyph or part of the dark backing box
+
+[[block]]
+struct RenderGlobals {
+    x : f32;
+    y : f32;
+    width : u32;
+    height : u32;
+};
+
+[[group(0), binding(0)]] var<uniform> render_globals : RenderGlobals;
+
+[[location(0)]]
+var<in> in_pos: vec2<f32>;
+[[location(1)]]
+var<in> in_brightness: f32;
+[[builtin(position)]]
+var<out> out_pos: vec4<f32>;
+[[location(0)]]
+var<out> out_brightness: f32;
+
+[[stage(vertex)]]
+fn main() {
+    var size : vec2<f32> = vec2<f32>(f32(render_globals.width), f32(render_globals.height));
+    var pos : vec2<f32> = in_pos / size * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    out_pos = vec4<f32>(pos, 0.0, 1.0);
+    out_brightness = in_brightness;
+}
+
+[[location(0)]]
+var<in> in_frag_brightness: f32;
+[[location(0)]]
+var<out> out_color: vec4<f32>;
+
+[[stage(fragment)]]
+fn main() {
+    out_color = vec4<f32>(in_frag_brightness, in_frag_brightness, in_frag_brightness, 1.0);
+}
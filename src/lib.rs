@@ -0,0 +1,55 @@
+//! The particle life simulation and everything around it, shared by the `plife` program and the
+//! `plife-ffi` library that embeds it in other engines
+
+pub mod analysis;
+pub mod arrows;
+pub mod batch;
+pub mod bonds;
+pub mod breed;
+pub mod capture;
+pub mod compare;
+pub mod control;
+pub mod cpu;
+pub mod density;
+pub mod depth_sort;
+pub mod doctor;
+pub mod error;
+pub mod events;
+pub mod evolve;
+pub mod explosion;
+pub mod faucets;
+pub mod generate;
+pub mod gpu_profile;
+pub mod heatmap;
+pub mod hud;
+pub mod lint;
+pub mod locale;
+pub mod matrix;
+pub mod metrics;
+pub mod midi;
+pub mod npz;
+pub mod osc;
+pub mod presets;
+pub mod profile;
+pub mod reactions;
+pub mod recording;
+pub mod reroll;
+pub mod rewind;
+pub mod schedules;
+pub mod selftest;
+pub mod serialize;
+pub mod serve;
+pub mod session;
+pub mod simulation;
+pub mod snapshot;
+pub mod source;
+pub mod stats;
+pub mod sweep;
+pub mod territory;
+pub mod throttle;
+pub mod trails;
+pub mod trajectory;
+pub mod util;
+pub mod validate;
+pub mod visualization;
+pub mod watch;
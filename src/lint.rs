@@ -0,0 +1,258 @@
+use std::{f64::consts::PI, fs::File, path::PathBuf};
+
+use structopt::StructOpt;
+
+use crate::serialize::{
+    Config, ConfigFormat, Distribution, Drag, PointsConfig, RulesetConfig, WallsConfig,
+};
+
+/// Fraction of an area that equal discs can cover at most, when packed hexagonally
+const PACKING_DENSITY: f64 = 0.9;
+
+#[derive(StructOpt)]
+pub struct LintOptions {
+    config_file: PathBuf,
+    /// Config file format (yaml, json or toml); guessed from the file extension if omitted
+    #[structopt(long)]
+    format: Option<ConfigFormat>,
+}
+
+/// Likely mistakes found in a config, each with the path of the field and a way to fix it
+#[derive(Default)]
+struct Lints(Vec<(String, String, String)>);
+
+impl Lints {
+    fn push(&mut self, path: &str, message: impl AsRef<str>, suggestion: impl AsRef<str>) {
+        self.0.push((
+            path.to_owned(),
+            message.as_ref().to_owned(),
+            suggestion.as_ref().to_owned(),
+        ));
+    }
+}
+
+/// The smallest range covering all of the given ones
+fn union(ranges: impl IntoIterator<Item = Option<(f64, f64)>>) -> Option<(f64, f64)> {
+    ranges
+        .into_iter()
+        .flatten()
+        .fold(None, |union, (low, high)| match union {
+            Some((union_low, union_high)) => Some((low.min(union_low), high.max(union_high))),
+            None => Some((low, high)),
+        })
+}
+
+/// The smallest range covering the likely ranges of all entries of a precise ruleset
+fn union_of<'a>(rows: impl Iterator<Item = &'a Vec<Distribution<f32>>>) -> Option<(f64, f64)> {
+    union(rows.flatten().map(Distribution::likely_range))
+}
+
+/// Flags `max_r` ranges that reach below `min_r`, which makes some pairs of types only repel
+fn lint_radii(min_r: &Distribution<f32>, max_r: &Distribution<f32>, path: &str, lints: &mut Lints) {
+    if let (Some((_, min_r_high)), Some((max_r_low, _))) =
+        (min_r.likely_range(), max_r.likely_range())
+    {
+        if max_r_low < min_r_high {
+            lints.push(
+                path,
+                format!(
+                    "max_r can be {:.1}, below min_r (up to {:.1}); pairs sampled that way only push each other apart",
+                    max_r_low, min_r_high
+                ),
+                format!("keep max_r at least {:.1}", min_r_high),
+            );
+        }
+    }
+}
+
+/// Speed per step that a constant pull of `attraction` settles at against `friction`
+pub fn top_speed(attraction: f64, friction: f64, drag: Drag) -> f64 {
+    let attraction = attraction.abs();
+    if friction <= 0.0 {
+        return f64::INFINITY;
+    }
+    match drag {
+        Drag::Linear => attraction * (1.0 - friction) / friction,
+        Drag::Quadratic => {
+            // Speed after the pull, once it loses as much to drag as it gains
+            let pulled = (attraction * friction
+                + (attraction * attraction * friction * friction + 4.0 * attraction * friction)
+                    .sqrt())
+                / (2.0 * friction);
+            pulled - attraction
+        }
+        Drag::None => f64::INFINITY,
+    }
+}
+
+/// Strongest attraction whose top speed against `friction` stays below `speed`
+pub fn max_attraction(speed: f64, friction: f64, drag: Drag) -> f64 {
+    match drag {
+        Drag::Linear => speed * friction / (1.0 - friction),
+        Drag::Quadratic if friction * speed >= 1.0 => f64::INFINITY,
+        Drag::Quadratic => friction * speed * speed / (1.0 - friction * speed),
+        Drag::None => 0.0,
+    }
+}
+
+/// Friction needed to keep the top speed of a pull of `attraction` below `speed`
+fn min_friction(attraction: f64, speed: f64, drag: Drag) -> f64 {
+    match drag {
+        Drag::Linear | Drag::None => attraction / (attraction + speed),
+        Drag::Quadratic => attraction / (speed * (speed + attraction)),
+    }
+}
+
+/// Flags attractions strong enough to accelerate particles across the smallest interaction range
+/// in a single step before friction can slow them down
+fn lint_stability(
+    attraction: (f64, f64),
+    max_r: (f64, f64),
+    friction: (f64, f64),
+    drag: Drag,
+    lints: &mut Lints,
+) {
+    let strongest = attraction.0.abs().max(attraction.1.abs());
+    let friction = friction.0;
+    if strongest == 0.0 {
+        return;
+    }
+    if drag == Drag::None {
+        lints.push(
+            "drag",
+            "is none, so nothing slows particles down and attractions keep speeding them up",
+            "use linear or quadratic drag",
+        );
+        return;
+    }
+    if friction <= 0.0 {
+        lints.push(
+            "ruleset.friction",
+            "can be 0, so nothing slows particles down and attractions keep speeding them up",
+            "use a friction of at least 0.01",
+        );
+        return;
+    }
+    let top_speed = top_speed(strongest, friction, drag);
+    let reach = max_r.0.max(1.0) / 2.0;
+    if top_speed > reach {
+        lints.push(
+            "ruleset.attractions",
+            format!(
+                "can be {:.3} at a friction of {:.3}, which lets particles reach {:.1} per step, more than half of the smallest max_r ({:.1}); they will overshoot each other and the system is likely to explode",
+                strongest, friction, top_speed, max_r.0
+            ),
+            format!(
+                "keep attractions between -{0:.3} and {0:.3}, or raise friction to at least {1:.3}",
+                max_attraction(reach, friction, drag),
+                min_friction(strongest, reach, drag)
+            ),
+        );
+    }
+}
+
+/// Flags more particles than fit inside the walls while keeping `min_r` apart
+fn lint_crowding(config: &Config, min_r: (f64, f64), lints: &mut Lints) {
+    let dist = match &config.walls {
+        WallsConfig::None => return,
+        WallsConfig::Wrapping { dist } | WallsConfig::Square { dist, .. } => {
+            match dist.likely_range() {
+                Some((low, _)) => low,
+                None => return,
+            }
+        }
+    };
+    let num_points = match &config.points {
+        PointsConfig::Simple(num) => num.likely_range().map(|(_, high)| high),
+        PointsConfig::Complex(spawns) => spawns
+            .iter()
+            .map(|spawn| spawn.num.likely_range().map(|(_, high)| high))
+            .sum(),
+        PointsConfig::File { .. } => None,
+    };
+    let num_points = match (num_points, config.max_points) {
+        (Some(num), Some(max)) if !config.faucets.is_empty() => num.max(max as f64),
+        (Some(num), _) => num,
+        (None, _) => return,
+    };
+    // Each particle keeps others out of a disc about min_r across
+    let spacing = (min_r.0 + min_r.1) / 2.0;
+    let needed = num_points * PI * (spacing / 2.0).powi(2);
+    let area = (dist * 2.0).powi(2);
+    if needed > area * PACKING_DENSITY {
+        lints.push(
+            "points",
+            format!(
+                "up to {:.0} particles kept about {:.1} apart need an area of {:.0}, but the walls only enclose {:.0}; they will be squeezed together and scatter violently",
+                num_points, spacing, needed, area
+            ),
+            format!(
+                "use at most {} particles, or a walls.dist of at least {:.0}",
+                (area * PACKING_DENSITY / (PI * (spacing / 2.0).powi(2))) as u64,
+                (needed / PACKING_DENSITY).sqrt() / 2.0
+            ),
+        );
+    }
+}
+
+/// Looks for settings that are valid but likely to give a broken or dull simulation
+fn lint(config: &Config) -> Lints {
+    let mut lints = Lints::default();
+    let (attraction, min_r, max_r, friction) = match &config.ruleset {
+        RulesetConfig::Procedural(rules) => {
+            lint_radii(&rules.min_r, &rules.max_r, "ruleset.max_r", &mut lints);
+            (
+                rules.attractions.likely_range(),
+                rules.min_r.likely_range(),
+                rules.max_r.likely_range(),
+                rules.friction.likely_range(),
+            )
+        }
+        RulesetConfig::Precise { types, friction } => {
+            for (i, type_) in types.iter().enumerate() {
+                for (j, (min_r, max_r)) in type_.min_r.iter().zip(&type_.max_r).enumerate() {
+                    let path = format!("ruleset.types[{}].max_r[{}]", i, j);
+                    lint_radii(min_r, max_r, &path, &mut lints);
+                }
+            }
+            (
+                union_of(types.iter().map(|type_| &type_.attractions)),
+                union_of(types.iter().map(|type_| &type_.min_r)),
+                union_of(types.iter().map(|type_| &type_.max_r)),
+                friction.likely_range(),
+            )
+        }
+    };
+    if let (Some(attraction), Some(max_r), Some(friction)) = (attraction, max_r, friction) {
+        lint_stability(attraction, max_r, friction, config.drag, &mut lints);
+    }
+    if let Some(min_r) = min_r {
+        lint_crowding(config, min_r, &mut lints);
+    }
+    lints
+}
+
+/// Prints likely mistakes in the config. Returns whether there were none.
+pub fn run(options: &LintOptions) -> bool {
+    let format = options
+        .format
+        .unwrap_or_else(|| ConfigFormat::from_path(&options.config_file));
+    let config = File::open(&options.config_file)
+        .map_err(|e| e.to_string())
+        .and_then(|file| Config::from_reader(file, format, &[]));
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid config file:\n{}", e);
+            return false;
+        }
+    };
+    let lints = lint(&config);
+    for (path, message, suggestion) in &lints.0 {
+        println!("{}: {}\n    Suggestion: {}", path, message, suggestion);
+    }
+    if lints.0.is_empty() {
+        println!("No likely mistakes found");
+    }
+    lints.0.is_empty()
+}
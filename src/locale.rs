@@ -0,0 +1,149 @@
+use std::{collections::HashMap, fs::File};
+
+use crate::error::ConfigError;
+
+/// Built-in translations of on-screen text, keyed by language code
+const TRANSLATIONS: &[(&str, &[(&str, &str)])] = &[
+    (
+        "en",
+        &[
+            ("window_title", "plife visualization"),
+            ("clusters", "clusters"),
+            ("kinetic_energy", "kinetic energy"),
+            ("mean_speed", "mean speed"),
+            ("fps", "FPS"),
+            ("tps", "TPS"),
+            ("frame_time", "frame"),
+            ("step_time", "step"),
+            ("ticks", "ticks"),
+            ("particles", "particles"),
+            ("particle", "particle"),
+            ("type", "type"),
+            ("position", "position"),
+            ("velocity", "velocity"),
+            ("rewind", "rewind"),
+        ],
+    ),
+    (
+        "de",
+        &[
+            ("window_title", "plife-Visualisierung"),
+            ("clusters", "Cluster"),
+            ("kinetic_energy", "kinetische Energie"),
+            ("mean_speed", "mittlere Geschwindigkeit"),
+            ("fps", "FPS"),
+            ("tps", "TPS"),
+            ("frame_time", "Bild"),
+            ("step_time", "Schritt"),
+            ("ticks", "Ticks"),
+            ("particles", "Teilchen"),
+            ("particle", "Teilchen"),
+            ("type", "Typ"),
+            ("position", "Position"),
+            ("velocity", "Geschwindigkeit"),
+            ("rewind", "zurück"),
+        ],
+    ),
+    (
+        "es",
+        &[
+            ("window_title", "visualización de plife"),
+            ("clusters", "cúmulos"),
+            ("kinetic_energy", "energía cinética"),
+            ("mean_speed", "velocidad media"),
+            ("fps", "FPS"),
+            ("tps", "TPS"),
+            ("frame_time", "fotograma"),
+            ("step_time", "paso"),
+            ("ticks", "ticks"),
+            ("particles", "partículas"),
+            ("particle", "partícula"),
+            ("type", "tipo"),
+            ("position", "posición"),
+            ("velocity", "velocidad"),
+            ("rewind", "rebobinado"),
+        ],
+    ),
+    (
+        "fr",
+        &[
+            ("window_title", "visualisation plife"),
+            ("clusters", "amas"),
+            ("kinetic_energy", "énergie cinétique"),
+            ("mean_speed", "vitesse moyenne"),
+            ("fps", "IPS"),
+            ("tps", "TPS"),
+            ("frame_time", "image"),
+            ("step_time", "pas"),
+            ("ticks", "ticks"),
+            ("particles", "particules"),
+            ("particle", "particule"),
+            ("type", "type"),
+            ("position", "position"),
+            ("velocity", "vitesse"),
+            ("rewind", "retour"),
+        ],
+    ),
+];
+
+const FALLBACK_LANGUAGE: &str = "en";
+
+/// Looks up on-screen text in the selected language, falling back to English for missing keys
+#[derive(Clone)]
+pub struct Locale {
+    strings: HashMap<String, String>,
+}
+
+impl Locale {
+    /// Selects a language from, in order of priority, the given language, the `PLIFE_LANG`
+    /// environment variable, and the `LANG` environment variable. Strings from a YAML map at
+    /// `PLIFE_LOCALE_FILE`, if set, override the built-in ones.
+    pub fn new(language: Option<&str>) -> Result<Self, ConfigError> {
+        let language = language
+            .map(str::to_owned)
+            .or_else(|| std::env::var("PLIFE_LANG").ok())
+            .or_else(|| std::env::var("LANG").ok())
+            .map(|lang| normalize_language(&lang))
+            .unwrap_or_else(|| FALLBACK_LANGUAGE.to_owned());
+
+        let mut strings = HashMap::new();
+        for code in [FALLBACK_LANGUAGE, language.as_str()].iter() {
+            if let Some((_, table)) = TRANSLATIONS.iter().find(|(lang, _)| lang == code) {
+                for (key, value) in table.iter() {
+                    strings.insert((*key).to_owned(), (*value).to_owned());
+                }
+            }
+        }
+        if let Some(path) = std::env::var_os("PLIFE_LOCALE_FILE") {
+            let file = File::open(&path).map_err(|e| {
+                ConfigError(format!(
+                    "Cannot open locale file {}: {}",
+                    path.to_string_lossy(),
+                    e
+                ))
+            })?;
+            let overrides: HashMap<String, String> =
+                serde_yaml::from_reader(file).map_err(|e| {
+                    ConfigError(format!(
+                        "Invalid locale file {}: {}",
+                        path.to_string_lossy(),
+                        e
+                    ))
+                })?;
+            strings.extend(overrides);
+        }
+        Ok(Self { strings })
+    }
+
+    pub fn get<'a>(&'a self, key: &'a str) -> &'a str {
+        self.strings.get(key).map(String::as_str).unwrap_or(key)
+    }
+}
+
+/// Turns locale names like `de_DE.UTF-8` into bare language codes like `de`
+fn normalize_language(lang: &str) -> String {
+    lang.split(&['_', '-', '.'][..])
+        .next()
+        .unwrap_or(lang)
+        .to_ascii_lowercase()
+}
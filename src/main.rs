@@ -1,124 +1,1194 @@
-mod serialize;
-mod simulation;
-mod util;
-mod visualization;
-
 use std::{
-    fs::File,
-    path::PathBuf,
+    mem,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
-    time::Instant,
+    time::{Duration, Instant},
 };
 
+use analysis::{ClusterStats, MotionStats};
+use anyhow::Context;
+use batch::BatchOptions;
+use breed::{CrossOptions, MutateOptions};
+use capture::FrameCapture;
+use compare::CompareOptions;
+use control::Controller;
+use error::{exit_with, ConfigError, GpuError, EXIT_CONFIG, EXIT_FAILURE};
+use evolve::EvolveOptions;
+use explosion::ExplosionCheck;
+use generate::GenerateOptions;
+use gpu_profile::{GpuProfiler, GpuTimings};
+use heatmap::Heatmap;
+use lint::LintOptions;
+use locale::Locale;
+use metrics::MetricsServer;
+use midi::MidiControls;
+use npz::NpzExporter;
+use osc::OscSender;
+use plife::{
+    analysis, batch, breed, capture, compare, control, doctor, error, evolve, explosion, generate,
+    gpu_profile, heatmap, lint, locale, metrics, midi, npz, osc, presets, profile, recording,
+    reroll, rewind, selftest, serialize, serve, session, simulation, source, stats, sweep,
+    throttle, trajectory, util, visualization, watch,
+};
+use presets::{Preset, PRESETS};
+use profile::Profile;
+use recording::{Recorder, ReplayOptions};
+use reroll::RerollOptions;
+use rewind::Rewind;
+use serialize::{write_csv_particles, Config, ConfigFormat, ConfigOverride, PresentModeChoice};
+use serve::Server;
+use session::{Session, SessionTarget};
 use simulation::*;
+use stats::{CheckpointFormat, CheckpointStats, StatsFormat, StatsWriter};
 use structopt::StructOpt;
+use sweep::SweepOptions;
+use throttle::Throttle;
+use tracing::{debug, debug_span, info, warn};
+use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
+use trajectory::TrajectoryWriter;
+use util::seed_rng;
 use visualization::*;
+use watch::WatchOptions;
 use wgpu::*;
 use winit::{
     dpi::LogicalSize,
     event_loop::EventLoop,
-    window::{Window, WindowBuilder},
+    window::{Fullscreen, Window, WindowBuilder},
 };
 
 #[derive(StructOpt)]
 /// Particle life simulator
+///
+/// Given a config file and no command, plife runs it the same as `plife run`, or as `plife
+/// headless` with `--headless`.
 struct Args {
-    config_file: PathBuf,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+    /// Run without a window, the same as `plife headless`
     #[structopt(long)]
     headless: bool,
+    /// Log more about what plife is doing: once for details, twice for everything, including the
+    /// graphics backend. The `RUST_LOG` environment variable overrides this.
+    #[structopt(short, long, parse(from_occurrences))]
+    verbose: u8,
+    #[structopt(flatten)]
+    source: SourceOptions,
+    #[structopt(flatten)]
+    output: OutputOptions,
+    #[structopt(flatten)]
+    headless_options: HeadlessOptions,
+    #[structopt(flatten)]
+    window_options: WindowOptions,
+    #[structopt(flatten)]
+    watch: WatchOptions,
+    #[structopt(flatten)]
+    gpu: GpuOptions,
+}
+
+impl Args {
+    /// Whether only errors should be logged, whichever way the run was started
+    fn quiet(&self) -> bool {
+        match &self.command {
+            Some(Command::Headless { options, .. }) => options.quiet,
+            _ => self.headless_options.quiet,
+        }
+    }
+}
+
+/// Where the simulation comes from
+#[derive(StructOpt)]
+struct SourceOptions {
+    /// Config file to run, `-` to read it from standard input, or an `http://` or `https://` URL
+    /// to fetch it from
+    config_file: Option<PathBuf>,
+    /// Continue a session saved with F5 instead of starting from a config file
+    #[structopt(long, conflicts_with = "config-file")]
+    session: Option<PathBuf>,
+    /// Run a config built into plife instead of a config file, such as `cells`; see
+    /// `--list-presets`
+    #[structopt(long, conflicts_with_all = &["config-file", "session"])]
+    preset: Option<Preset>,
+    /// List the configs built into plife and exit
+    #[structopt(long)]
+    list_presets: bool,
+    /// Config file format (yaml, json or toml); guessed from the file extension if omitted
+    #[structopt(long)]
+    format: Option<ConfigFormat>,
+    /// Override a config value, e.g. `--set ruleset.friction=0.1`; may be repeated
+    #[structopt(long = "set", number_of_values = 1)]
+    overrides: Vec<ConfigOverride>,
+    #[structopt(flatten)]
+    reroll: RerollOptions,
+}
+
+/// What a run hands its particles to, and takes commands from, in the window or headless
+#[derive(StructOpt)]
+struct OutputOptions {
+    /// Capture the given frame (or step, when headless) with RenderDoc; needs the `renderdoc` feature
+    #[structopt(long)]
+    capture_frame: Option<u64>,
+    /// Record the positions and types of all particles to this file, for `plife replay`
+    #[structopt(long)]
+    record_positions: Option<PathBuf>,
+    /// Ticks between the frames written to `--record-positions`
+    #[structopt(long, default_value = "10")]
+    record_interval: u64,
+    /// Export the positions of all particles to this NumPy `.npz` file, written when the run
+    /// ends, with the arrays `positions[T, N, 2]`, `ticks[T]` and `types[N]`
+    #[structopt(long)]
+    export_npz: Option<PathBuf>,
+    /// Ticks between the snapshots exported with `--export-npz`
+    #[structopt(long, default_value = "10")]
+    npz_interval: u64,
+    /// Export the position and type of every particle to this Parquet file as the run goes, one
+    /// row per particle per snapshot
+    #[structopt(long)]
+    export_parquet: Option<PathBuf>,
+    /// Ticks between the snapshots exported with `--export-parquet`
+    #[structopt(long, default_value = "10")]
+    parquet_interval: u64,
+    /// Stream the positions and types of all particles to WebSocket clients connecting to this
+    /// address, e.g. `0.0.0.0:9000`
+    #[structopt(long)]
+    serve: Option<SocketAddr>,
+    /// Ticks between the frames streamed with `--serve`
+    #[structopt(long, default_value = "10")]
+    serve_interval: u64,
+    /// Take commands such as pause, step, set and snapshot from scripts connecting to this
+    /// address, e.g. `127.0.0.1:9001`, as lines of JSON
+    #[structopt(long)]
+    control: Option<SocketAddr>,
+    /// Send metrics such as the number of clusters, the kinetic energy and the center of every
+    /// type as OSC messages over UDP to this `host:port`, for audio and visual tools to react to
+    #[structopt(long)]
+    osc_target: Option<String>,
+    /// Ticks between the metrics sent with `--osc-target`, each of which reads all particles back
+    /// from the GPU
+    #[structopt(long, default_value = "30")]
+    osc_interval: u64,
+    #[structopt(flatten)]
+    explosion_check: ExplosionCheck,
+}
+
+/// Which graphics adapter to use and how, taken by every command that runs on the GPU
+#[derive(StructOpt)]
+struct GpuOptions {
+    /// Graphics API to use: `auto`, `vulkan`, `dx12`, `metal` or `gl`, or `fallback` for a
+    /// software renderer
+    #[structopt(long, default_value = "auto")]
+    backend: BackendChoice,
+    /// Graphics adapter to use, by its index or part of its name as shown by `--list-adapters`
+    #[structopt(long)]
+    adapter: Option<AdapterChoice>,
+    /// Prefer an integrated or otherwise power saving adapter over the fastest one
+    #[structopt(long)]
+    low_power: bool,
+    /// List the graphics adapters available with the chosen `--backend` and exit
+    #[structopt(long)]
+    list_adapters: bool,
+    /// Step the simulation on the CPU instead of in a compute shader. Far slower, but works on
+    /// adapters whose compute shaders are missing or broken.
+    #[structopt(long)]
+    cpu: bool,
+    /// Particles each compute shader workgroup handles, or `auto` to time a few sizes on startup
+    /// and use the fastest. 256 if omitted, or the most the adapter supports if that is less.
+    #[structopt(long)]
+    workgroup_size: Option<WorkgroupSizeChoice>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum BackendChoice {
+    /// The fastest adapter wgpu can find
+    Auto,
+    /// A software adapter running on the CPU, for machines without a GPU
+    Fallback,
+    Vulkan,
+    Dx12,
+    Metal,
+    Gl,
+}
+
+impl BackendChoice {
+    fn backends(self) -> BackendBit {
+        match self {
+            BackendChoice::Auto | BackendChoice::Fallback => BackendBit::all(),
+            BackendChoice::Vulkan => BackendBit::VULKAN,
+            BackendChoice::Dx12 => BackendBit::DX12,
+            BackendChoice::Metal => BackendBit::METAL,
+            BackendChoice::Gl => BackendBit::GL,
+        }
+    }
+}
+
+impl FromStr for BackendChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(BackendChoice::Auto),
+            "fallback" => Ok(BackendChoice::Fallback),
+            "vulkan" => Ok(BackendChoice::Vulkan),
+            "dx12" => Ok(BackendChoice::Dx12),
+            "metal" => Ok(BackendChoice::Metal),
+            "gl" => Ok(BackendChoice::Gl),
+            _ => Err(format!(
+                "Unknown backend `{}`, expected auto, vulkan, dx12, metal, gl or fallback",
+                s
+            )),
+        }
+    }
+}
+
+enum AdapterChoice {
+    /// Position in the list printed by `--list-adapters`
+    Index(usize),
+    /// Adapters whose names contain this, ignoring case
+    Name(String),
+}
+
+impl AdapterChoice {
+    fn matches(&self, index: usize, info: &AdapterInfo) -> bool {
+        match self {
+            AdapterChoice::Index(i) => *i == index,
+            AdapterChoice::Name(name) => info
+                .name
+                .to_ascii_lowercase()
+                .contains(&name.to_ascii_lowercase()),
+        }
+    }
+}
+
+impl FromStr for AdapterChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.parse() {
+            Ok(index) => AdapterChoice::Index(index),
+            Err(_) => AdapterChoice::Name(s.to_owned()),
+        })
+    }
+}
+
+/// An adapter running on the CPU, for machines without a GPU
+fn software_adapter(instance: &Instance) -> Option<Adapter> {
+    instance
+        .enumerate_adapters(BackendBit::all())
+        .find(|adapter| adapter.get_info().device_type == DeviceType::Cpu)
+}
+
+/// Explains why no adapter could be used, listing the adapters there are on any backend along
+/// with what to try next
+fn no_adapter(instance: &Instance, reason: &str) -> anyhow::Error {
+    let mut message = format!("{}.\n", reason);
+    let adapters = instance
+        .enumerate_adapters(BackendBit::all())
+        .map(|adapter| describe_adapter(&adapter.get_info()))
+        .collect::<Vec<_>>();
+    if adapters.is_empty() {
+        message += "No graphics adapters were found on any backend. Check that a Vulkan, Metal, DirectX 12 or OpenGL driver is installed, or install a software driver such as lavapipe, SwiftShader or llvmpipe to run on the CPU.";
+    } else {
+        message += "Adapters found on all backends:\n";
+        for adapter in &adapters {
+            message += &format!("  {}\n", adapter);
+        }
+        message += "Pick one with --backend and --adapter, or use --backend fallback to run on a software adapter.";
+    }
+    GpuError(message).into()
+}
+
+/// The kind of adapter, its name and its backend, e.g. `discrete GPU Foo (Vulkan)`
+fn describe_adapter(info: &AdapterInfo) -> String {
+    format!(
+        "{} {} ({})",
+        match info.device_type {
+            DeviceType::Other => "unclassified accelerator",
+            DeviceType::IntegratedGpu => "integrated GPU",
+            DeviceType::DiscreteGpu => "discrete GPU",
+            DeviceType::VirtualGpu => "virtualized GPU",
+            DeviceType::Cpu => "CPU",
+        },
+        info.name,
+        match info.backend {
+            Backend::Empty => "dummy backend",
+            Backend::Vulkan => "Vulkan",
+            Backend::Metal => "Metal",
+            Backend::Dx12 => "DirectX 12",
+            Backend::Dx11 => "DirectX 11",
+            Backend::Gl => "OpenGL",
+            Backend::BrowserWebGpu => "WebGPU",
+        }
+    )
+}
+
+#[derive(Clone, Copy)]
+enum WorkgroupSizeChoice {
+    Auto,
+    Fixed(u32),
+}
+
+impl FromStr for WorkgroupSizeChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(WorkgroupSizeChoice::Auto);
+        }
+        // Whether the adapter supports the size is only known once it has been chosen
+        match s.parse() {
+            Ok(size) if size > 0 => Ok(WorkgroupSizeChoice::Fixed(size)),
+            _ => Err(format!(
+                "Invalid workgroup size `{}`, expected auto or a positive number",
+                s
+            )),
+        }
+    }
+}
+
+/// Options for the visualization window
+#[derive(StructOpt)]
+struct WindowOptions {
+    /// Initial width of the window in logical pixels
+    #[structopt(long, default_value = "800")]
+    width: u32,
+    /// Initial height of the window in logical pixels
+    #[structopt(long, default_value = "600")]
+    height: u32,
+    /// Start in fullscreen; F11 switches back and forth
+    #[structopt(long)]
+    fullscreen: bool,
+    /// Window title; translated into the chosen language if omitted
+    #[structopt(long)]
+    title: Option<String>,
+    /// How frames are shown: immediate, fifo or mailbox; overrides the config
+    #[structopt(long)]
+    present_mode: Option<PresentModeChoice>,
+    /// Wait for the display before showing each frame, the same as `--present-mode fifo`
+    #[structopt(long, conflicts_with = "present-mode")]
+    vsync: bool,
+    /// Set trails, opacity, depth sorting, the density map threshold, the present mode and ticks
+    /// per frame together: performance, balanced or quality
+    #[structopt(long)]
+    profile: Option<Profile>,
+    /// Where F5 saves the session
+    #[structopt(long, default_value = "session.json")]
+    session_out: PathBuf,
+    /// Don't read the particles back every 10 seconds to restart from if the graphics device is
+    /// lost, which saves the readback with very many particles; losing the device then ends the
+    /// run
+    #[structopt(long)]
+    no_recovery: bool,
+    /// States kept for rewinding with J and K; 0 keeps none, which saves reading every particle
+    /// back from the GPU
+    #[structopt(long, default_value = "100")]
+    rewind_frames: usize,
+    /// Ticks between the states kept for rewinding
+    #[structopt(long, default_value = "60")]
+    rewind_interval: u64,
+    /// Split the window into columns and rows of simulations, e.g. `2x2`, all seen through the
+    /// same camera
+    #[structopt(long)]
+    grid: Option<Grid>,
+    /// Config of the next tile of the grid after the first, which runs the main config; may be
+    /// repeated. Tiles without one run the main config too, sampled anew.
+    #[structopt(long = "grid-config", number_of_values = 1)]
+    grid_configs: Vec<PathBuf>,
+    /// Seed of the first tile of the grid, with the others counting up from it, to compare the
+    /// same starts again later
+    #[structopt(long)]
+    grid_seed: Option<u64>,
+}
+
+/// Columns and rows of simulations shown with `--grid`
+#[derive(Clone, Copy)]
+struct Grid {
+    columns: u32,
+    rows: u32,
+}
+
+impl FromStr for Grid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid grid `{}`, expected columns and rows like 2x2", s);
+        let (columns, rows) = s
+            .to_ascii_lowercase()
+            .split_once('x')
+            .map(|(columns, rows)| (columns.parse::<u32>(), rows.parse::<u32>()))
+            .ok_or_else(invalid)?;
+        match (columns, rows) {
+            (Ok(columns), Ok(rows)) if columns > 0 && rows > 0 => Ok(Grid { columns, rows }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Options that only apply with `--headless`
+#[derive(StructOpt)]
+struct HeadlessOptions {
     #[structopt(long)]
     checkpoint: Option<u64>,
     #[structopt(long)]
     steps: Option<u64>,
+    /// Write statistics at every checkpoint to this file
+    #[structopt(long)]
+    stats_out: Option<PathBuf>,
+    /// Format of the statistics file (json or csv); guessed from its extension if omitted
+    #[structopt(long)]
+    stats_format: Option<StatsFormat>,
+    /// Print nothing but errors, in any mode, e.g. when reading statistics from `--stats-out -`
+    #[structopt(long)]
+    quiet: bool,
+    /// How much is printed at each checkpoint: minimal or full
+    #[structopt(long, default_value = "full")]
+    checkpoint_format: CheckpointFormat,
+    /// Count particle clusters at every checkpoint, which reads all particles back from the GPU
+    #[structopt(long)]
+    clusters: bool,
+    /// Particles closer than this belong to the same cluster
+    #[structopt(long, default_value = "10")]
+    cluster_radius: f32,
+    /// Measure the count, center of mass and radius of every type at every checkpoint
+    #[structopt(long)]
+    type_stats: bool,
+    /// Measure the distribution of distances to the nearest neighbour for every type at every
+    /// checkpoint
+    #[structopt(long)]
+    neighbour_stats: bool,
+    /// Record where particles of this type go, saving a heat map at every checkpoint and at the end
+    #[structopt(long)]
+    heatmap: Option<PointType>,
+    #[structopt(long, default_value = "heatmap.png")]
+    heatmap_out: PathBuf,
+    /// Steps between heat map samples, each of which reads all particles back from the GPU
+    #[structopt(long, default_value = "10")]
+    heatmap_interval: u64,
+    /// Time the compute pass of every step with GPU timestamps, reporting the average at every
+    /// checkpoint. Needs an adapter with timestamp queries.
+    #[structopt(long)]
+    profile_gpu: bool,
+    /// Write the id, type, position and velocity of every particle to this CSV file when the run
+    /// ends
+    #[structopt(long)]
+    export_final: Option<PathBuf>,
+    /// Serve the step count, speed, particle count and GPU timings on this port at `/metrics`,
+    /// for Prometheus to scrape
+    #[structopt(long)]
+    metrics_port: Option<u16>,
+    #[structopt(flatten)]
+    throttle: Throttle,
+}
+
+// Commands are parsed once at startup, so the size of the largest does not matter
+#[allow(clippy::large_enum_variant)]
+#[derive(StructOpt)]
+enum Command {
+    /// Run a config in a window
+    Run {
+        #[structopt(flatten)]
+        source: SourceOptions,
+        #[structopt(flatten)]
+        output: OutputOptions,
+        #[structopt(flatten)]
+        window: WindowOptions,
+        #[structopt(flatten)]
+        gpu: GpuOptions,
+    },
+    /// Run a config without a window, reporting on it at checkpoints
+    Headless {
+        #[structopt(flatten)]
+        source: SourceOptions,
+        #[structopt(flatten)]
+        output: OutputOptions,
+        #[structopt(flatten)]
+        options: HeadlessOptions,
+        #[structopt(flatten)]
+        watch: WatchOptions,
+        #[structopt(flatten)]
+        gpu: GpuOptions,
+    },
+    /// Play back a recording made with `--record-positions`, without simulating anything
+    Replay {
+        #[structopt(flatten)]
+        options: ReplayOptions,
+        #[structopt(flatten)]
+        window: WindowOptions,
+        #[structopt(flatten)]
+        gpu: GpuOptions,
+    },
+    /// Benchmark the graphics adapter and recommend settings for it
+    #[structopt(visible_alias = "bench")]
+    Doctor {
+        #[structopt(flatten)]
+        gpu: GpuOptions,
+    },
+    /// Run every config file in a directory headlessly and save the final state of each
+    Batch {
+        #[structopt(flatten)]
+        options: BatchOptions,
+        #[structopt(flatten)]
+        gpu: GpuOptions,
+    },
+    /// Search for rulesets that score well on a fitness measure by mutating and crossing them
+    Evolve {
+        #[structopt(flatten)]
+        options: EvolveOptions,
+        #[structopt(flatten)]
+        gpu: GpuOptions,
+    },
+    /// Look for settings in a config that are likely to be mistakes, and suggest better ones
+    Lint(LintOptions),
+    /// Write a random config with sensible ranges, to start a new one from
+    Generate(GenerateOptions),
+    /// Write a copy of a config with noise added to its attractions and radii
+    Mutate(MutateOptions),
+    /// Write a config whose types each take their rules from one of two configs
+    Cross(CrossOptions),
+    /// Run one ruleset from several seeds and compare how the runs turn out
+    Sweep {
+        #[structopt(flatten)]
+        options: SweepOptions,
+        #[structopt(flatten)]
+        gpu: GpuOptions,
+    },
+    /// Check that configs load and the simulation runs correctly on this machine
+    Selftest {
+        #[structopt(flatten)]
+        gpu: GpuOptions,
+    },
+    /// Run two configs side by side from the same seed, and list where their rulesets differ
+    Compare {
+        #[structopt(flatten)]
+        options: CompareOptions,
+        #[structopt(flatten)]
+        window: WindowOptions,
+        #[structopt(flatten)]
+        gpu: GpuOptions,
+    },
+}
+
+impl Command {
+    /// Puts the GPU and window options given to the command where those of the default
+    /// invocation are kept
+    fn take_options(&mut self, gpu: &mut GpuOptions, window_options: &mut WindowOptions) {
+        let (command_gpu, command_window) = match self {
+            Command::Run { gpu, window, .. }
+            | Command::Replay { gpu, window, .. }
+            | Command::Compare { gpu, window, .. } => (gpu, Some(window)),
+            Command::Headless { gpu, .. }
+            | Command::Doctor { gpu }
+            | Command::Batch { gpu, .. }
+            | Command::Evolve { gpu, .. }
+            | Command::Sweep { gpu, .. }
+            | Command::Selftest { gpu } => (gpu, None),
+            Command::Lint(_) | Command::Generate(_) | Command::Mutate(_) | Command::Cross(_) => {
+                return
+            }
+        };
+        mem::swap(gpu, command_gpu);
+        if let Some(command_window) = command_window {
+            mem::swap(window_options, command_window);
+        }
+    }
+}
+
+/// Sends log messages to stderr, keeping stdout for the output of commands and checkpoints
+fn init_logging(verbose: u8, quiet: bool) {
+    let filter = match (quiet, verbose) {
+        (true, _) => "error",
+        (false, 0) => "warn,plife=info",
+        (false, 1) => "info,plife=debug",
+        (false, _) => "debug,plife=trace",
+    };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(filter));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        // Spans are only there to say how long they took
+        .with_span_events(FmtSpan::CLOSE)
+        .with_target(verbose > 0)
+        .without_time()
+        .init();
 }
 
 #[paw::main]
 fn main(args: Args) {
-    futures::executor::block_on(main_async(args));
+    init_logging(args.verbose, args.quiet());
+    if let Err(e) = futures::executor::block_on(main_async(args)) {
+        exit_with(e)
+    }
 }
 
-async fn main_async(args: Args) {
+async fn main_async(args: Args) -> anyhow::Result<()> {
     let Args {
-        config_file,
-        headless,
-        checkpoint,
-        steps,
+        mut command,
+        mut headless,
+        verbose: _,
+        mut source,
+        mut output,
+        mut headless_options,
+        mut window_options,
+        mut watch,
+        mut gpu,
     } = args;
-    let instance = Instance::new(BackendBit::all());
+    if let Some(command) = &mut command {
+        command.take_options(&mut gpu, &mut window_options);
+    }
+    // `plife run` and `plife headless` are the default invocation, each without the options of
+    // the other
+    match command.take() {
+        Some(Command::Run {
+            source: run_source,
+            output: run_output,
+            ..
+        }) => {
+            source = run_source;
+            output = run_output;
+            headless = false;
+        }
+        Some(Command::Headless {
+            source: run_source,
+            output: run_output,
+            options,
+            watch: run_watch,
+            ..
+        }) => {
+            source = run_source;
+            output = run_output;
+            headless_options = options;
+            watch = run_watch;
+            headless = true;
+        }
+        other => command = other,
+    }
+    let SourceOptions {
+        mut config_file,
+        session,
+        preset,
+        list_presets,
+        format,
+        overrides,
+        reroll,
+    } = source;
+    let OutputOptions {
+        capture_frame,
+        record_positions,
+        record_interval,
+        export_npz,
+        npz_interval,
+        export_parquet,
+        parquet_interval,
+        serve,
+        serve_interval,
+        control,
+        osc_target,
+        osc_interval,
+        explosion_check,
+    } = output;
+    let GpuOptions {
+        backend,
+        adapter: adapter_choice,
+        low_power,
+        list_adapters,
+        cpu,
+        workgroup_size,
+    } = gpu;
+    // Linting and writing configs need no graphics adapter
+    if let Some(Command::Lint(options)) = &command {
+        if !lint::run(options) {
+            std::process::exit(EXIT_CONFIG)
+        }
+        return Ok(());
+    }
+    match &command {
+        Some(Command::Generate(options)) => {
+            return generate::run(options).context("Cannot write config")
+        }
+        Some(Command::Mutate(options)) => return breed::run_mutate(options),
+        Some(Command::Cross(options)) => return breed::run_cross(options),
+        _ => {}
+    }
+    if list_presets {
+        for preset in PRESETS {
+            println!("{:10} {}", preset.name, preset.description);
+        }
+        return Ok(());
+    }
+    if list_adapters {
+        let instance = Instance::new(backend.backends());
+        let mut any = false;
+        for (index, adapter) in instance.enumerate_adapters(backend.backends()).enumerate() {
+            println!("{}: {}", index, describe_adapter(&adapter.get_info()));
+            any = true;
+        }
+        if !any {
+            return Err(GpuError("No graphics adapters found".to_owned()).into());
+        }
+        return Ok(());
+    }
+    // Replays are shown like a simulation, built from the config stored in the recording
+    let replay = match &command {
+        Some(Command::Replay { options, .. }) => Some(options.open()?),
+        _ => None,
+    };
+    if replay.is_some() && headless {
+        return Err(
+            ConfigError("Replays need a window; run them without --headless".to_owned()).into(),
+        );
+    }
+    // Comparisons are a grid of the two configs, one beside the other
+    let comparing = match &command {
+        Some(Command::Compare { options, .. }) => {
+            config_file = Some(options.a.clone());
+            window_options.grid = Some(Grid {
+                columns: 2,
+                rows: 1,
+            });
+            window_options.grid_configs = vec![options.b.clone()];
+            window_options.grid_seed = Some(options.seed);
+            true
+        }
+        _ => false,
+    };
+    if comparing && headless {
+        return Err(ConfigError(
+            "Comparisons need a window; run them without --headless".to_owned(),
+        )
+        .into());
+    }
+    // Other subcommands and watch mode bring their own configs, or none at all
+    let watching = watch.is_active();
+    let headless = headless || (command.is_some() && replay.is_none() && !comparing) || watching;
+    let quiet = headless_options.quiet;
+
+    // Read the config before touching the GPU so that mistakes in it are reported quickly
+    let config_format =
+        |config_file: &Path| format.unwrap_or_else(|| source::config_format(config_file));
+    let parse_config = |text: &[u8], format: ConfigFormat| -> anyhow::Result<Config> {
+        Config::from_reader(text, format, &overrides)
+            .map_err(|e| ConfigError(format!("Invalid config file:\n{}", e)).into())
+    };
+    let load_config = |config_file: &Path| -> anyhow::Result<Config> {
+        let text = source::read_config(config_file).map_err(ConfigError)?;
+        parse_config(&text, config_format(config_file))
+    };
+    // Standard input and URLs are only read once, so the text of the main config is kept for
+    // reading it again, along with its format
+    let mut config_text = None;
+    let session = match session {
+        Some(path) => {
+            let session = Session::load(&path).map_err(|e| {
+                ConfigError(format!("Cannot load session {}: {}", path.display(), e))
+            })?;
+            Some(session)
+        }
+        None => None,
+    };
+    let config = if let Some(replay) = &replay {
+        Some(
+            replay
+                .config()
+                .map_err(|e| ConfigError(format!("Invalid config in recording:\n{}", e)))?,
+        )
+    } else if (command.is_some() && !comparing) || watching {
+        None
+    } else if let Some(session) = &session {
+        Some(
+            Config::from_value(session.config.clone())
+                .map_err(|e| ConfigError(format!("Invalid config in session:\n{}", e)))?,
+        )
+    } else {
+        let (text, format) = match (preset, config_file.as_deref()) {
+            (Some(preset), _) => (preset.config.as_bytes().to_vec(), ConfigFormat::Yaml),
+            (None, Some(config_file)) => (
+                source::read_config(config_file).map_err(ConfigError)?,
+                config_format(config_file),
+            ),
+            (None, None) => return Err(ConfigError(
+                "No config file given; pass one, pick one with --preset, continue a session with --session, or run a subcommand"
+                    .to_owned(),
+            )
+            .into()),
+        };
+        let config = parse_config(&text, format)?;
+        config_text = Some((text, format));
+        Some(config)
+    };
+    let locale = Locale::new(config.as_ref().and_then(|c| c.language.as_deref()))?;
+
+    let instance = Instance::new(backend.backends());
 
+    let title = window_options
+        .title
+        .unwrap_or_else(|| locale.get("window_title").to_owned());
     let window_stuff = if headless {
         None
     } else {
         let event_loop = EventLoop::new();
         let window = WindowBuilder::new()
             .with_resizable(true)
-            .with_title("plife visualization")
+            .with_title(&title)
             .with_inner_size(LogicalSize {
-                width: 800,
-                height: 600,
+                width: window_options.width.max(1),
+                height: window_options.height.max(1),
+            })
+            .with_fullscreen(if window_options.fullscreen {
+                Some(Fullscreen::Borderless(None))
+            } else {
+                None
             })
             .build(&event_loop)
-            .expect("Failed to create window");
+            .context("Failed to create window")?;
         let surface = unsafe { instance.create_surface(&window) };
         Some((window, event_loop, surface))
     };
 
-    let adapter = instance
-        .request_adapter(&RequestAdapterOptions {
-            power_preference: PowerPreference::HighPerformance,
-            compatible_surface: window_stuff.as_ref().map(|(_, _, surface)| surface),
-        })
-        .await
-        .expect("Unable to find a suitable graphics adapter");
-    let info = adapter.get_info();
-    println!(
-        "Using {} {} ({})",
-        match info.device_type {
-            DeviceType::Other => "unclassified accelerator",
-            DeviceType::IntegratedGpu => "integrated GPU",
-            DeviceType::DiscreteGpu => "discrete GPU",
-            DeviceType::VirtualGpu => "virtualized GPU",
-            DeviceType::Cpu => "CPU",
-        },
-        info.name,
-        match info.backend {
-            Backend::Empty => "dummy backend",
-            Backend::Vulkan => "Vulkan",
-            Backend::Metal => "Metal",
-            Backend::Dx12 => "DirectX 12",
-            Backend::Dx11 => "DirectX 11",
-            Backend::Gl => "OpenGL",
-            Backend::BrowserWebGpu => "WebGPU",
+    let device_setup = debug_span!("device_setup", backend = ?backend);
+    let entered = device_setup.enter();
+    let adapter = match (&adapter_choice, backend) {
+        (Some(choice), _) => instance
+            .enumerate_adapters(backend.backends())
+            .enumerate()
+            .find(|(index, adapter)| choice.matches(*index, &adapter.get_info()))
+            .map(|(_, adapter)| adapter)
+            .ok_or_else(|| no_adapter(&instance, "No adapter matches --adapter"))?,
+        (None, BackendChoice::Fallback) => software_adapter(&instance)
+            .ok_or_else(|| no_adapter(&instance, "Unable to find a software adapter"))?,
+        (None, _) => {
+            let adapter = instance
+                .request_adapter(&RequestAdapterOptions {
+                    power_preference: if low_power {
+                        PowerPreference::LowPower
+                    } else {
+                        PowerPreference::HighPerformance
+                    },
+                    compatible_surface: window_stuff.as_ref().map(|(_, _, surface)| surface),
+                })
+                .await;
+            match (adapter, backend) {
+                (Some(adapter), _) => adapter,
+                // Without a GPU, small simulations still run on a software adapter
+                (None, BackendChoice::Auto) => match software_adapter(&instance) {
+                    Some(adapter) => {
+                        warn!("No GPU found; falling back to a software adapter, which only keeps up with a few thousand particles");
+                        adapter
+                    }
+                    None => {
+                        return Err(no_adapter(
+                            &instance,
+                            "Unable to find a suitable graphics adapter",
+                        ))
+                    }
+                },
+                (None, _) => {
+                    return Err(no_adapter(
+                        &instance,
+                        "Unable to find a suitable graphics adapter",
+                    ))
+                }
+            }
         }
-    );
+    };
+    info!("Using {}", describe_adapter(&adapter.get_info()));
+    debug!("Adapter features: {:?}", adapter.features());
+    debug!("Adapter limits: {:?}", adapter.limits());
     let (device, queue) = adapter
         .request_device(
             &DeviceDescriptor {
                 label: Some("main device"),
-                features: Features::default(),
+                features: if headless_options.profile_gpu {
+                    adapter.features() & Features::TIMESTAMP_QUERY
+                } else {
+                    Features::default()
+                },
                 limits: Limits {
-                    max_storage_buffers_per_shader_stage: 7,
+                    max_storage_buffers_per_shader_stage: STORAGE_BUFFERS_PER_STAGE,
                     ..Limits::default()
                 },
             },
             None,
         )
         .await
-        .expect("Failed to get device handle");
+        .map_err(|e| GpuError(format!("Failed to get device handle: {}", e)))?;
 
-    let file = File::open(config_file).expect("Cannot open config file");
-    let config = serde_yaml::from_reader(file).expect("Invalid config file");
-    let simulation = Simulation::from_config(&device, config);
+    let adapter_info = adapter.get_info();
+    let workgroup_size = match workgroup_size {
+        Some(WorkgroupSizeChoice::Fixed(size)) => {
+            check_workgroup_size(size, &adapter_info).map_err(ConfigError)?
+        }
+        Some(WorkgroupSizeChoice::Auto) => {
+            let max = max_workgroup_size(&adapter_info);
+            let (fastest, _) = doctor::tune_workgroup_size(&device, &queue, max);
+            info!("Using a workgroup size of {}", fastest);
+            fastest
+        }
+        None => default_workgroup_size(&adapter_info),
+    };
+    drop(entered);
+
+    match command {
+        Some(Command::Doctor { .. }) => {
+            doctor::run(&device, &queue, &adapter, workgroup_size);
+            doctor::mark_probed();
+            return Ok(());
+        }
+        Some(Command::Batch { options, .. }) => {
+            return batch::run(&device, &queue, options, workgroup_size);
+        }
+        Some(Command::Evolve { options, .. }) => {
+            return evolve::run(&device, &queue, options, workgroup_size);
+        }
+        Some(Command::Sweep { options, .. }) => {
+            return sweep::run(&device, &queue, options, workgroup_size);
+        }
+        Some(Command::Selftest { .. }) => {
+            if !selftest::run(&device, &queue, workgroup_size) {
+                std::process::exit(EXIT_FAILURE)
+            }
+            return Ok(());
+        }
+        Some(Command::Lint(_))
+        | Some(Command::Generate(_))
+        | Some(Command::Mutate(_))
+        | Some(Command::Cross(_))
+        | Some(Command::Replay { .. })
+        | Some(Command::Compare { .. })
+        | Some(Command::Run { .. })
+        | Some(Command::Headless { .. })
+        | None => {}
+    }
+    if watching {
+        return watch.run(&device, &queue, workgroup_size);
+    }
+    if doctor::is_first_run() && !quiet {
+        info!("First run detected; checking what this adapter can handle. Run `plife doctor` to repeat this later.");
+        doctor::run(&device, &queue, &adapter, workgroup_size);
+        doctor::mark_probed();
+    }
+
+    let config = config.unwrap();
+    let mut display = config.display.clone();
+    let midi = config.midi.clone();
+    if let Some(profile) = window_options.profile {
+        profile.apply(&mut display);
+    }
+    if window_options.vsync {
+        display.present_mode = Some(PresentModeChoice::Fifo);
+    } else if window_options.present_mode.is_some() {
+        display.present_mode = window_options.present_mode;
+    }
+    // The config document the simulation came from, for saving sessions and recordings
+    let document = match (&session, &replay) {
+        (Some(session), _) => session.config.clone(),
+        (None, Some(replay)) => replay.document().clone(),
+        (None, None) => {
+            let (text, format) = config_text.as_ref().unwrap();
+            Config::read_document(&text[..], *format, &overrides)
+                .map_err(|e| ConfigError(format!("Invalid config file:\n{}", e)))?
+        }
+    };
+    let grid = window_options.grid.filter(|_| !headless);
+    if let (Some(_), Some(seed)) = (grid, window_options.grid_seed) {
+        seed_rng(seed);
+    }
+    let mut simulation = match &session {
+        Some(session) => session
+            .simulation(&device, &queue, workgroup_size)
+            .map_err(|e| ConfigError(format!("Cannot restore session: {}", e)))?,
+        None if replay.is_some() => Simulation::from_config(&device, config, workgroup_size)?,
+        None => {
+            // Rerolling starts with the config already read, and parses it again for later tries
+            let mut first_config = Some(config);
+            reroll.run(&device, &queue, workgroup_size, || {
+                first_config.take().unwrap_or_else(|| {
+                    let (text, format) = config_text.as_ref().unwrap();
+                    parse_config(text, *format).unwrap_or_else(|e| exit_with(e))
+                })
+            })?
+        }
+    };
+    simulation.set_cpu(cpu);
+    let mut tiles = Vec::new();
+    if let Some(grid) = grid {
+        let others = (grid.columns * grid.rows - 1) as usize;
+        if window_options.grid_configs.len() > others {
+            return Err(ConfigError(format!(
+                "A {}x{} grid has room for {} more configs, not {}",
+                grid.columns,
+                grid.rows,
+                others,
+                window_options.grid_configs.len()
+            ))
+            .into());
+        }
+        for index in 0..others {
+            // Compared configs start from the same seed, so only their differences tell them apart
+            if let Some(seed) = window_options.grid_seed {
+                seed_rng(if comparing {
+                    seed
+                } else {
+                    seed + index as u64 + 1
+                });
+            }
+            let config = match window_options.grid_configs.get(index) {
+                Some(path) => load_config(path)?,
+                None => Config::from_value(document.clone())
+                    .map_err(|e| ConfigError(format!("Invalid config file:\n{}", e)))?,
+            };
+            let mut tile = Simulation::from_config(&device, config, workgroup_size)?;
+            let num_types = simulation.ruleset.num_point_types;
+            if tile.ruleset.num_point_types != num_types {
+                return Err(ConfigError(format!(
+                    "Tile {} of the grid has {} types, but the first has {}; tiles share their colors, so they need the same number",
+                    index + 2,
+                    tile.ruleset.num_point_types,
+                    num_types
+                ))
+                .into());
+            }
+            tile.set_cpu(cpu);
+            tiles.push(tile);
+        }
+    }
+    if comparing {
+        compare::report(&simulation.ruleset, &tiles[0].ruleset);
+    }
+    let capture = capture_frame.map(FrameCapture::new);
+    let recorder = match record_positions {
+        Some(path) => Some(
+            Recorder::create(&path, &document, &simulation, record_interval)
+                .with_context(|| format!("Cannot create recording {}", path.display()))?,
+        ),
+        None => None,
+    };
+    let npz = match export_npz {
+        Some(path) => Some(
+            NpzExporter::create(&path, npz_interval)
+                .with_context(|| format!("Cannot create {}", path.display()))?,
+        ),
+        None => None,
+    };
+    let trajectory = match export_parquet {
+        Some(path) => Some(
+            TrajectoryWriter::create(&path, parquet_interval)
+                .with_context(|| format!("Cannot create {}", path.display()))?,
+        ),
+        None => None,
+    };
+    let server = match serve {
+        Some(address) => {
+            let server = Server::start(address, serve_interval)
+                .with_context(|| format!("Cannot stream particles on {}", address))?;
+            info!("Streaming particles on ws://{}", address);
+            Some(server)
+        }
+        None => None,
+    };
+    let controller = match control {
+        Some(address) => {
+            let controller = Controller::start(address)
+                .with_context(|| format!("Cannot take commands on {}", address))?;
+            info!("Taking commands on {}", address);
+            Some(controller)
+        }
+        None => None,
+    };
+    let osc = match osc_target {
+        Some(target) => {
+            let osc = OscSender::connect(&target, osc_interval)
+                .with_context(|| format!("Cannot send OSC messages to {}", target))?;
+            info!("Sending metrics as OSC messages to {}", target);
+            Some(osc)
+        }
+        None => None,
+    };
 
     if headless {
-        run_headless(&device, &queue, simulation, checkpoint, steps)
+        run_headless(
+            &device,
+            &queue,
+            simulation,
+            headless_options,
+            explosion_check,
+            capture,
+            Attachments {
+                recorder,
+                server,
+                controller,
+                osc,
+                npz,
+                trajectory,
+            },
+        )
     } else {
         let (window, event_loop, surface) = window_stuff.unwrap();
+        let mut visualization = Visualization::with_random_colors(
+            &device, &adapter, &surface, simulation, display, &window, locale,
+        )
+        .with_title(title)
+        .with_session_target(SessionTarget {
+            path: window_options.session_out,
+            config: document,
+        })
+        .with_rewind(Rewind::new(
+            window_options.rewind_frames,
+            window_options.rewind_interval,
+        ));
+        if let Some(recorder) = recorder {
+            visualization = visualization.with_recorder(recorder);
+        }
+        if let Some(server) = server {
+            visualization = visualization.with_server(server);
+        }
+        if let Some(controller) = controller {
+            visualization = visualization.with_controller(controller);
+        }
+        if let Some(osc) = osc {
+            visualization = visualization.with_osc(osc);
+        }
+        if let Some(npz) = npz {
+            visualization = visualization.with_npz(npz);
+        }
+        if let Some(trajectory) = trajectory {
+            visualization = visualization.with_trajectory(trajectory);
+        }
+        if let Some(midi) = midi {
+            match MidiControls::connect(&midi) {
+                Ok(controls) => visualization = visualization.with_midi(controls),
+                Err(e) => warn!("Cannot take MIDI controls: {}", e),
+            }
+        }
+        if explosion_check.is_active() {
+            visualization = visualization.with_explosion_check(explosion_check);
+        }
+        if let Some(replay) = replay {
+            visualization = visualization.with_replay(replay);
+        }
+        if window_options.no_recovery {
+            visualization = visualization.without_recovery();
+        }
+        if let Some(profile) = window_options.profile {
+            visualization.ticks_per_frame = profile.ticks_per_frame();
+        }
+        if let Some(session) = &session {
+            visualization.restore_session(&queue, session);
+        }
+        if let Some(grid) = grid {
+            visualization = visualization.with_grid(&device, grid.columns, grid.rows, tiles);
+        }
         run_headed(
-            device, queue, adapter, surface, simulation, window, event_loop,
+            device,
+            queue,
+            adapter,
+            surface,
+            visualization,
+            window,
+            event_loop,
+            capture,
         )
     }
 }
@@ -128,48 +1198,270 @@ fn run_headed(
     queue: Queue,
     adapter: Adapter,
     surface: Surface,
-    simulation: Simulation,
+    visualization: Visualization,
     window: Window,
     event_loop: EventLoop<()>,
+    capture: Option<FrameCapture>,
 ) -> ! {
-    let visualization = Visualization::with_random_colors(&device, &adapter, &surface, simulation);
-    visualization.run(device, queue, window, surface, event_loop)
+    visualization.run(device, queue, adapter, window, surface, event_loop, capture)
+}
+
+/// Most steps a headless run submits before checking whether it should stop
+const MAX_BATCH: u64 = 64;
+
+/// What a headless run hands its particles to, or takes commands from, as it goes
+struct Attachments {
+    recorder: Option<Recorder>,
+    server: Option<Server>,
+    controller: Option<Controller>,
+    osc: Option<OscSender>,
+    npz: Option<NpzExporter>,
+    trajectory: Option<TrajectoryWriter>,
 }
 
 fn run_headless(
     device: &Device,
     queue: &Queue,
     mut simulation: Simulation,
-    checkpoint: Option<u64>,
-    max_steps: Option<u64>,
-) {
+    options: HeadlessOptions,
+    explosion_check: ExplosionCheck,
+    mut capture: Option<FrameCapture>,
+    attachments: Attachments,
+) -> anyhow::Result<()> {
+    let Attachments {
+        mut recorder,
+        mut server,
+        mut controller,
+        mut osc,
+        mut npz,
+        mut trajectory,
+    } = attachments;
+    let HeadlessOptions {
+        checkpoint,
+        steps: max_steps,
+        stats_out,
+        stats_format,
+        quiet,
+        checkpoint_format,
+        clusters,
+        cluster_radius,
+        type_stats,
+        neighbour_stats,
+        heatmap: heatmap_type,
+        heatmap_out,
+        heatmap_interval,
+        profile_gpu,
+        export_final,
+        metrics_port,
+        throttle,
+    } = options;
+    if profile_gpu {
+        match GpuProfiler::new(device, queue) {
+            Some(profiler) => simulation.set_profiler(profiler),
+            None => warn!(
+                "This adapter does not support timestamp queries, so --profile-gpu does nothing"
+            ),
+        }
+    }
+    let mut heatmap = match heatmap_type {
+        Some(type_) if type_ >= simulation.ruleset.num_point_types => {
+            return Err(ConfigError(format!(
+                "Cannot record a heat map of type {}, there are only {} types",
+                type_, simulation.ruleset.num_point_types
+            ))
+            .into())
+        }
+        Some(type_) => Some(Heatmap::new(device, queue, &simulation, type_)),
+        None => None,
+    };
+    let mut heatmap_countdown = 0;
+    let mut metrics = match metrics_port {
+        Some(port) => {
+            let metrics = MetricsServer::start(port)
+                .with_context(|| format!("Cannot serve metrics on port {}", port))?;
+            info!("Serving metrics on http://0.0.0.0:{}/metrics", port);
+            Some(metrics)
+        }
+        None => None,
+    };
+    let mut stats_writer = match stats_out {
+        Some(path) => {
+            let format = stats_format.unwrap_or_else(|| StatsFormat::from_path(&path));
+            Some(
+                StatsWriter::create(&path, format)
+                    .with_context(|| format!("Cannot create stats file {}", path.display()))?,
+            )
+        }
+        None => None,
+    };
+
     let broken = Arc::new(AtomicBool::new(false));
     let b = broken.clone();
     ctrlc::set_handler(move || {
         b.store(true, Ordering::Relaxed);
     })
-    .expect("Error setting Ctrl-C handler");
+    .context("Error setting Ctrl-C handler")?;
 
     let mut steps: u64 = 0;
     let mut steps_since_checkpoint: u64 = 0;
     let start = Instant::now();
     let mut last_checkpoint = start;
+    let mut throttle_state = throttle.start();
+    let mut explosion_state = explosion_check.start();
+    let mut exploded = None;
+    let mut paused = false;
 
     loop {
-        simulation.step(&device, &queue);
-        steps += 1;
-        steps_since_checkpoint += 1;
+        if let Some(controller) = &mut controller {
+            controller.handle(device, queue, &mut simulation, steps, &mut paused);
+            if paused {
+                if broken.load(Ordering::Relaxed) {
+                    break;
+                }
+                std::thread::sleep(control::POLL_INTERVAL);
+                continue;
+            }
+        }
+        // Steps run back to back in as few submissions as possible, up to the next step that
+        // something has to look at the particles after
+        let mut batch = MAX_BATCH.min(throttle_state.steps_left(&throttle));
+        if let Some(controller) = &controller {
+            batch = batch.min(controller.due_in(steps).max(1));
+        }
+        if capture.is_some() {
+            batch = 1;
+        }
+        if heatmap.is_some() {
+            batch = batch.min(heatmap_countdown.max(1));
+        }
+        if let Some(checkpoint) = checkpoint {
+            batch = batch.min(checkpoint - steps % checkpoint);
+        }
+        if let Some(max_steps) = max_steps {
+            batch = batch.min(max_steps.saturating_sub(steps).max(1));
+        }
+        if let Some(recorder) = &recorder {
+            batch = batch.min(recorder.due_in(steps).max(1));
+        }
+        if let Some(server) = &server {
+            batch = batch.min(server.due_in(steps).max(1));
+        }
+        if let Some(osc) = &osc {
+            batch = batch.min(osc.due_in(steps).max(1));
+        }
+        if let Some(npz) = &npz {
+            batch = batch.min(npz.due_in(steps).max(1));
+        }
+        if let Some(trajectory) = &trajectory {
+            batch = batch.min(trajectory.due_in(steps).max(1));
+        }
+        batch = batch.min(explosion_state.due_in(&explosion_check, steps).max(1));
+
+        if let Some(capture) = &mut capture {
+            capture.begin(steps);
+        }
+        simulation.step_many(&device, &queue, batch);
+        if let Some(capture) = &mut capture {
+            capture.end(steps);
+        }
+        throttle_state.step(&throttle, device, batch);
+        if let Some(heatmap) = &mut heatmap {
+            if heatmap_countdown == 0 {
+                heatmap.add(device, queue, &simulation);
+                heatmap_countdown = heatmap_interval.max(1);
+            }
+            heatmap_countdown -= batch;
+        }
+        steps += batch;
+        steps_since_checkpoint += batch;
+        if let Some(recorder) = &mut recorder {
+            recorder
+                .record(device, queue, &simulation, steps)
+                .context("Cannot write recording")?;
+        }
+        if let Some(server) = &mut server {
+            server.send(device, queue, &simulation, steps);
+        }
+        if let Some(osc) = &mut osc {
+            osc.send(device, queue, &simulation, steps);
+        }
+        if let Some(npz) = &mut npz {
+            npz.record(device, queue, &simulation, steps);
+        }
+        if let Some(trajectory) = &mut trajectory {
+            trajectory
+                .record(device, queue, &simulation, steps)
+                .context("Cannot write trajectory")?;
+        }
+        if let Some(metrics) = &mut metrics {
+            metrics.update(steps, simulation.num_points);
+        }
+        if let Some(explosion) =
+            explosion_state.check(&explosion_check, device, queue, &mut simulation, steps)
+        {
+            exploded = Some(explosion);
+            break;
+        }
         if let Some(checkpoint) = checkpoint {
             if steps % checkpoint == 0 {
+                // Steps still running on the GPU would otherwise be counted in the next rate
+                device.poll(Maintain::Wait);
                 let now = Instant::now();
                 let tps = steps_since_checkpoint as f32 / (now - last_checkpoint).as_secs_f32();
-                println!("Checkpoint {}. {} steps total. Running time: {:#?}. Average steps per second since last checkpoint: {} ({}x realtime)",
-                    steps / checkpoint,
+                let points = if clusters || type_stats || neighbour_stats {
+                    simulation.read_points(device, queue)
+                } else {
+                    Vec::new()
+                };
+                let gpu = simulation.gpu_timings((now - last_checkpoint).as_secs_f64());
+                let stats = CheckpointStats {
+                    checkpoint: steps / checkpoint,
                     steps,
-                    now - start,
-                    tps as u32,
-                    (tps / 60.0) as u32
-                );
+                    wall_time: (now - start).as_secs_f64(),
+                    tps: tps as f64,
+                    // Reading the velocities back is only worth it when something shows them
+                    motion: if stats_writer.is_some()
+                        || (!quiet && checkpoint_format == CheckpointFormat::Full)
+                    {
+                        Some(MotionStats::measure(
+                            &simulation.read_velocities(device, queue),
+                        ))
+                    } else {
+                        None
+                    },
+                    clusters: if clusters {
+                        Some(ClusterStats::measure(&points, cluster_radius))
+                    } else {
+                        None
+                    },
+                    gpu,
+                    types: if type_stats {
+                        analysis::type_stats(&points, simulation.ruleset.num_point_types)
+                    } else {
+                        Vec::new()
+                    },
+                    neighbours: if neighbour_stats {
+                        analysis::neighbour_stats(&points, simulation.ruleset.num_point_types)
+                    } else {
+                        Vec::new()
+                    },
+                };
+                if let (Some(metrics), Some(gpu)) = (&metrics, stats.gpu) {
+                    metrics.set_gpu_timings(gpu);
+                }
+                if let Some(stats_writer) = &mut stats_writer {
+                    stats_writer
+                        .write(&stats)
+                        .context("Cannot write stats file")?;
+                }
+                if let Some(heatmap) = &heatmap {
+                    heatmap
+                        .save_png(device, queue, &heatmap_out)
+                        .with_context(|| format!("Cannot write {}", heatmap_out.display()))?;
+                }
+                if !quiet {
+                    print_checkpoint(&stats, now - start, checkpoint_format);
+                }
                 last_checkpoint = now;
                 steps_since_checkpoint = 0;
             }
@@ -183,6 +1475,97 @@ fn run_headless(
         }
     }
 
-    println!("Ran {} steps for {:#?}", steps, (Instant::now() - start));
+    device.poll(Maintain::Wait);
+    if let Some(heatmap) = &heatmap {
+        heatmap
+            .save_png(device, queue, &heatmap_out)
+            .with_context(|| format!("Cannot write {}", heatmap_out.display()))?;
+    }
+    if let Some(npz) = npz {
+        let path = npz.path().to_owned();
+        npz.finish()
+            .with_context(|| format!("Cannot write {}", path.display()))?;
+        info!("Exported positions to {}", path.display());
+    }
+    if let Some(trajectory) = trajectory {
+        trajectory.finish().context("Cannot finish trajectory")?;
+    }
+    if let Some(path) = &export_final {
+        write_csv_particles(
+            path,
+            &simulation.read_points(device, queue),
+            &simulation.read_velocities(device, queue),
+        )
+        .with_context(|| format!("Cannot write {}", path.display()))?;
+        info!("Exported the final particles to {}", path.display());
+    }
+    info!("Ran {} steps for {:#?}", steps, (Instant::now() - start));
+    if heatmap.is_some() {
+        info!("Saved heat map to {}", heatmap_out.display());
+    }
     // TODO: saving
+    match exploded {
+        Some(explosion) => Err(anyhow::anyhow!("{}", explosion)),
+        None => Ok(()),
+    }
+}
+
+fn print_checkpoint(stats: &CheckpointStats, running_time: Duration, format: CheckpointFormat) {
+    if format == CheckpointFormat::Minimal {
+        println!(
+            "Checkpoint {}: {} steps, {} steps per second",
+            stats.checkpoint, stats.steps, stats.tps as u32
+        );
+        if let Some(gpu) = &stats.gpu {
+            print_gpu_timings(gpu);
+        }
+        return;
+    }
+    println!("Checkpoint {}. {} steps total. Running time: {:#?}. Average steps per second since last checkpoint: {} ({}x realtime)",
+        stats.checkpoint,
+        stats.steps,
+        running_time,
+        stats.tps as u32,
+        (stats.tps / 60.0) as u32
+    );
+    if let Some(motion) = &stats.motion {
+        println!(
+            "Kinetic energy: {:.3}. Mean speed: {:.4}",
+            motion.kinetic_energy, motion.mean_speed
+        );
+    }
+    if let Some(gpu) = &stats.gpu {
+        print_gpu_timings(gpu);
+    }
+    if let Some(clusters) = &stats.clusters {
+        println!(
+            "{} clusters, largest {} particles, mean {:.1} particles",
+            clusters.count, clusters.largest, clusters.mean_size
+        );
+    }
+    for stats in &stats.types {
+        println!(
+            "Type {}: {} particles around ({:.1}, {:.1}) within {:.1}",
+            stats.type_, stats.count, stats.center[0], stats.center[1], stats.radius
+        );
+    }
+    for stats in &stats.neighbours {
+        println!(
+            "Type {}: nearest neighbour {:.2} on average, median {:.2}",
+            stats.type_, stats.mean, stats.median
+        );
+    }
+}
+
+fn print_gpu_timings(gpu: &GpuTimings) {
+    println!(
+        "GPU: {:.3} ms per step in the compute pass, busy {:.0}% of the time ({})",
+        gpu.compute_ms,
+        gpu.busy * 100.0,
+        if gpu.is_compute_bound() {
+            "compute-bound"
+        } else {
+            "bound by submissions and CPU work"
+        }
+    );
 }
@@ -0,0 +1,184 @@
+use wgpu::{util::*, *};
+
+use crate::{simulation::Ruleset, util::BindableBuffer};
+
+/// Side of a matrix cell in logical pixels, including the gap to the next one
+const CELL_SIZE: f64 = 12.0;
+/// Width of the type colored strips along the top and left of the matrix, in logical pixels
+const STRIP_WIDTH: f64 = 4.0;
+/// Space between cells and around the matrix, in logical pixels
+const GAP: f64 = 1.0;
+const MARGIN: f64 = 4.0;
+const BACKING_COLOR: [f32; 3] = [0.05, 0.05, 0.05];
+const NEUTRAL_COLOR: [f32; 3] = [0.2, 0.2, 0.2];
+const ATTRACT_COLOR: [f32; 3] = [0.2, 0.9, 0.3];
+const REPEL_COLOR: [f32; 3] = [0.9, 0.2, 0.2];
+/// x, y and color
+const VERTEX_SIZE: usize = std::mem::size_of::<f32>() * 5;
+
+/// The attraction between every pair of types as a grid of colored cells in the top right corner
+/// of the window. Row `a`, column `b` is how particles of type `a` are pulled toward type `b`:
+/// green for attraction, red for repulsion, brighter for stronger. Rows and columns are marked
+/// with the type colors, and switched off pairs are left blank.
+pub struct AttractionMatrix {
+    pipeline: RenderPipeline,
+    bind_group: BindGroup,
+    vertices: Option<Buffer>,
+    num_vertices: u32,
+}
+
+impl AttractionMatrix {
+    pub fn new(device: &Device, render_globals: &BindableBuffer, format: TextureFormat) -> Self {
+        let shader = device.create_shader_module(&ShaderModuleDescriptor {
+            label: Some("matrix_shader"),
+            source: ShaderSource::Wgsl(include_str!("matrix.wgsl").into()),
+            flags: ShaderFlags::VALIDATION,
+        });
+        let bind_group_layout = BindableBuffer::bind_group_layout(device, &[render_globals]);
+        let bind_group = BindableBuffer::bind_group(device, &[render_globals]);
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("matrix_pipeline"),
+            layout: Some(&device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("matrix_layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            })),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "main",
+                buffers: &[VertexBufferLayout {
+                    array_stride: VERTEX_SIZE as u64,
+                    step_mode: InputStepMode::Vertex,
+                    attributes: &[
+                        VertexAttribute {
+                            format: VertexFormat::Float2,
+                            offset: 0,
+                            shader_location: 0,
+                        },
+                        VertexAttribute {
+                            format: VertexFormat::Float3,
+                            offset: std::mem::size_of::<f32>() as u64 * 2,
+                            shader_location: 1,
+                        },
+                    ],
+                }],
+            },
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "main",
+                targets: &[format.into()],
+            }),
+        });
+        Self {
+            pipeline,
+            bind_group,
+            vertices: None,
+            num_vertices: 0,
+        }
+    }
+
+    /// Whether there is a matrix to show, or `set_rules` needs calling first
+    pub fn is_set(&self) -> bool {
+        self.vertices.is_some()
+    }
+
+    /// Forgets the matrix shown, for when the rules or colors it was built from change
+    pub fn clear(&mut self) {
+        self.vertices = None;
+    }
+
+    /// Replaces the matrix shown, sizing it for the given physical pixels per logical pixel
+    pub fn set_rules(
+        &mut self,
+        device: &Device,
+        ruleset: &Ruleset,
+        colors: &[[f32; 3]],
+        enabled: impl Fn(u32, u32) -> bool,
+        scale_factor: f64,
+    ) {
+        let num_types = ruleset.num_point_types as usize;
+        let scale = |logical: f64| (logical * scale_factor).round() as f32;
+        let (cell, strip, gap, margin) = (
+            scale(CELL_SIZE),
+            scale(STRIP_WIDTH),
+            scale(GAP).max(1.0),
+            scale(MARGIN),
+        );
+        let side = margin * 2.0 + strip + gap + cell * num_types as f32;
+        let mut contents = Vec::new();
+        let mut rect = |left: f32, top: f32, width: f32, height: f32, color: [f32; 3]| {
+            // Measured from the right edge of the window
+            let (left, right) = (left - side, left + width - side);
+            let bottom = top + height;
+            for &(x, y) in [
+                (left, top),
+                (left, bottom),
+                (right, top),
+                (right, top),
+                (left, bottom),
+                (right, bottom),
+            ]
+            .iter()
+            {
+                for value in [x, y, color[0], color[1], color[2]].iter() {
+                    contents.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+        };
+
+        rect(0.0, 0.0, side, side, BACKING_COLOR);
+        let first_cell = margin + strip + gap;
+        for (type_, &color) in colors.iter().enumerate().take(num_types) {
+            let offset = first_cell + type_ as f32 * cell;
+            rect(offset, margin, cell - gap, strip, color);
+            rect(margin, offset, strip, cell - gap, color);
+        }
+        let strongest = ruleset
+            .attractions
+            .iter()
+            .flatten()
+            .fold(0.0f32, |strongest, a| strongest.max(a.abs()));
+        for (row, attractions) in ruleset.attractions.iter().enumerate() {
+            for (column, &attraction) in attractions.iter().enumerate() {
+                if !enabled(row as u32, column as u32) {
+                    continue;
+                }
+                let (target, strength) = if attraction >= 0.0 {
+                    (ATTRACT_COLOR, attraction / strongest.max(f32::EPSILON))
+                } else {
+                    (REPEL_COLOR, -attraction / strongest.max(f32::EPSILON))
+                };
+                let mut color = NEUTRAL_COLOR;
+                for (channel, target) in color.iter_mut().zip(target.iter()) {
+                    *channel += (target - *channel) * strength;
+                }
+                rect(
+                    first_cell + column as f32 * cell,
+                    first_cell + row as f32 * cell,
+                    cell - gap,
+                    cell - gap,
+                    color,
+                );
+            }
+        }
+
+        self.num_vertices = (contents.len() / VERTEX_SIZE) as u32;
+        self.vertices = Some(device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("matrix"),
+            contents: &contents,
+            usage: BufferUsage::VERTEX,
+        }));
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        if let Some(vertices) = &self.vertices {
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, &self.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, vertices.slice(..));
+            render_pass.draw(0..self.num_vertices, 0..1);
+        }
+    }
+}
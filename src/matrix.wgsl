@@ -0,0 +1,40 @@
+// Attraction matrix overlay: colored rectangles given in physical pixels from the top right corner
+// of the window, x growing to the right so that it is negative everywhere inside the window
+
+[[block]]
+struct RenderGlobals {
+    x : f32;
+    y : f32;
+    width : u32;
+    height : u32;
+};
+
+[[group(0), binding(0)]] var<uniform> render_globals : RenderGlobals;
+
+[[location(0)]]
+var<in> in_pos: vec2<f32>;
+[[location(1)]]
+var<in> in_color: vec3<f32>;
+[[builtin(position)]]
+var<out> out_pos: vec4<f32>;
+[[location(0)]]
+var<out> out_color: vec3<f32>;
+
+[[stage(vertex)]]
+fn main() {
+    var size : vec2<f32> = vec2<f32>(f32(render_globals.width), f32(render_globals.height));
+    var from_top_left : vec2<f32> = in_pos + vec2<f32>(size.x, 0.0);
+    var pos : vec2<f32> = from_top_left / size * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    out_pos = vec4<f32>(pos, 0.0, 1.0);
+    out_color = in_color;
+}
+
+[[location(0)]]
+var<in> in_frag_color: vec3<f32>;
+[[location(0)]]
+var<out> out_frag_color: vec4<f32>;
+
+[[stage(fragment)]]
+fn main() {
+    out_frag_color = vec4<f32>(in_frag_color, 1.0);
+}
@@ -0,0 +1,148 @@
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::warn;
+
+use crate::gpu_profile::GpuTimings;
+
+/// Longest a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time over which `plife_steps_per_second` is averaged
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct Values {
+    steps_total: u64,
+    steps_per_second: f64,
+    particle_count: u32,
+    gpu: Option<GpuTimings>,
+}
+
+impl Values {
+    /// The Prometheus text exposition format
+    fn render(&self) -> String {
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: f64| {
+            writeln!(text, "# HELP {} {}", name, help).unwrap();
+            writeln!(text, "# TYPE {} {}", name, kind).unwrap();
+            writeln!(text, "{} {}", name, value).unwrap();
+        };
+        metric(
+            "plife_steps_total",
+            "counter",
+            "Steps simulated since the run started",
+            self.steps_total as f64,
+        );
+        metric(
+            "plife_steps_per_second",
+            "gauge",
+            "Steps simulated per second over the last second",
+            self.steps_per_second,
+        );
+        metric(
+            "plife_particle_count",
+            "gauge",
+            "Particles in the simulation",
+            self.particle_count as f64,
+        );
+        if let Some(gpu) = &self.gpu {
+            metric(
+                "plife_gpu_compute_seconds",
+                "gauge",
+                "Average time the compute pass of a step took on the GPU, as of the last checkpoint",
+                gpu.compute_ms / 1000.0,
+            );
+            metric(
+                "plife_gpu_busy_ratio",
+                "gauge",
+                "Fraction of the wall time the GPU spent in compute passes, as of the last checkpoint",
+                gpu.busy,
+            );
+        }
+        text
+    }
+}
+
+/// Serves metrics of a headless run over HTTP at `/metrics`, for Prometheus to scrape
+pub struct MetricsServer {
+    values: Arc<Mutex<Values>>,
+    /// When the current rate window started, and the steps at that time
+    window_start: (Instant, u64),
+}
+
+impl MetricsServer {
+    /// Starts answering requests on `port` of every interface in the background
+    pub fn start(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))?;
+        let values = Arc::new(Mutex::new(Values::default()));
+        let served = values.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        // A failed request only concerns whoever made it
+                        respond(stream, &served).ok();
+                    }
+                    Err(e) => warn!("Cannot accept a metrics request: {}", e),
+                }
+            }
+        });
+        Ok(Self {
+            values,
+            window_start: (Instant::now(), 0),
+        })
+    }
+
+    /// Updates the metrics after the steps up to `steps`
+    pub fn update(&mut self, steps: u64, particle_count: u32) {
+        let mut values = self.values.lock().unwrap();
+        values.steps_total = steps;
+        values.particle_count = particle_count;
+        let (start, start_steps) = self.window_start;
+        let elapsed = start.elapsed();
+        if elapsed >= RATE_WINDOW {
+            values.steps_per_second = (steps - start_steps) as f64 / elapsed.as_secs_f64();
+            self.window_start = (Instant::now(), steps);
+        }
+    }
+
+    /// Reports the GPU timings measured at a checkpoint
+    pub fn set_gpu_timings(&self, gpu: GpuTimings) {
+        self.values.lock().unwrap().gpu = Some(gpu);
+    }
+}
+
+fn respond(mut stream: TcpStream, values: &Mutex<Values>) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers say nothing that changes the answer
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = if path == "/metrics" {
+        ("200 OK", values.lock().unwrap().render())
+    } else {
+        (
+            "404 Not Found",
+            "Metrics are served at /metrics\n".to_owned(),
+        )
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
@@ -0,0 +1,114 @@
+#[cfg(feature = "midi")]
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+
+#[cfg(feature = "midi")]
+use midir::{MidiInput, MidiInputConnection};
+#[cfg(feature = "midi")]
+use tracing::info;
+
+use crate::{
+    serialize::{MidiConfig, MidiControlConfig},
+    simulation::Simulation,
+};
+
+/// Highest value of a MIDI control change
+const MAX_CC_VALUE: f32 = 127.0;
+
+/// A control change message: the channel from 0 to 15, the controller number and its value
+type ControlChange = (u8, u8, u8);
+
+/// Hardware knobs and sliders mapped to parameters by the `midi` section of a config. Messages
+/// arrive in the background and are applied between frames, when `apply` is called.
+pub struct MidiControls {
+    channel: Option<u8>,
+    controls: Vec<MidiControlConfig>,
+    changes: Receiver<ControlChange>,
+    /// Messages stop arriving once this is dropped
+    #[cfg(feature = "midi")]
+    _connection: MidiInputConnection<()>,
+}
+
+impl MidiControls {
+    /// Listens on the first MIDI input whose name contains `config.port`, or on the first input
+    /// there is
+    #[cfg(feature = "midi")]
+    pub fn connect(config: &MidiConfig) -> Result<Self, String> {
+        let input = MidiInput::new("plife").map_err(|e| e.to_string())?;
+        let ports = input.ports();
+        let mut names = Vec::new();
+        for port in &ports {
+            names.push(input.port_name(port).map_err(|e| e.to_string())?);
+        }
+        if names.is_empty() {
+            return Err("there are no MIDI inputs".to_owned());
+        }
+        let index = match &config.port {
+            Some(wanted) => names
+                .iter()
+                .position(|name| name.contains(wanted.as_str()))
+                .ok_or_else(|| {
+                    format!(
+                        "no MIDI input is called {}; there are: {}",
+                        wanted,
+                        names.join(", ")
+                    )
+                })?,
+            None => 0,
+        };
+        let (sender, changes) = mpsc::channel();
+        let connection = input
+            .connect(
+                &ports[index],
+                "plife-controls",
+                move |_, message, _| {
+                    if let [status, controller, value] = *message {
+                        if status & 0xf0 == 0xb0 {
+                            sender.send((status & 0x0f, controller, value)).ok();
+                        }
+                    }
+                },
+                (),
+            )
+            .map_err(|e| e.to_string())?;
+        info!("Taking MIDI controls from {}", names[index]);
+        Ok(Self {
+            channel: config.channel.map(|channel| channel - 1),
+            controls: config.controls.clone(),
+            changes,
+            _connection: connection,
+        })
+    }
+
+    #[cfg(not(feature = "midi"))]
+    pub fn connect(_config: &MidiConfig) -> Result<Self, String> {
+        Err("plife was built without the `midi` feature".to_owned())
+    }
+
+    /// Sets the parameters of every knob turned since the last call. Returns whether anything
+    /// changed.
+    pub fn apply(&self, simulation: &mut Simulation, ticks_per_frame: &mut u16) -> bool {
+        let mut changed = false;
+        while let Ok((channel, controller, value)) = self.changes.try_recv() {
+            if self.channel.map_or(false, |wanted| wanted != channel) {
+                continue;
+            }
+            for control in self
+                .controls
+                .iter()
+                .filter(|control| control.cc == controller)
+            {
+                let value = control.min + (control.max - control.min) * value as f32 / MAX_CC_VALUE;
+                match control.parameter.scheduled() {
+                    Some(parameter) => {
+                        // Values were checked along with the rest of the config
+                        simulation.set_parameter(parameter, value).ok();
+                    }
+                    None => *ticks_per_frame = value.round() as u16,
+                }
+                changed = true;
+            }
+        }
+        changed
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use tracing::warn;
+use wgpu::{Device, Queue};
+use zip::{result::ZipResult, write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::simulation::Simulation;
+
+/// Writes the header of a version 1.0 `.npy` array of little endian `descr` values, padded so
+/// that the data starts 64 byte aligned
+fn write_npy_header(out: &mut impl Write, descr: &str, shape: &[usize]) -> io::Result<()> {
+    let shape = match shape {
+        [length] => format!("({},)", length),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(|length| length.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        descr, shape
+    );
+    // Magic, version and header length take 10 bytes, and the header ends in a newline
+    let padding = (64 - (10 + header.len() + 1) % 64) % 64;
+    header.extend(std::iter::repeat(' ').take(padding));
+    header.push('\n');
+    out.write_all(b"\x93NUMPY\x01\x00")?;
+    out.write_all(&(header.len() as u16).to_le_bytes())?;
+    out.write_all(header.as_bytes())
+}
+
+/// Collects the positions of all particles every `interval` ticks, to be written to a NumPy `.npz`
+/// archive by `finish` with the arrays `positions[T, N, 2]` (float32), `ticks[T]` (uint64) and
+/// `types[N]` (uint32) of the first snapshot. Snapshots are kept in memory until then.
+pub struct NpzExporter {
+    path: PathBuf,
+    file: File,
+    interval: u64,
+    last_ticks: Option<u64>,
+    ticks: Vec<u64>,
+    positions: Vec<f32>,
+    types: Vec<u32>,
+    /// Set once the number of particles changes, since `positions` must be rectangular
+    stopped: bool,
+}
+
+impl NpzExporter {
+    /// Creates the file, overwriting it, so that a bad path is caught before the run starts
+    pub fn create(path: &Path, interval: u64) -> io::Result<Self> {
+        Ok(Self {
+            path: path.to_owned(),
+            file: File::create(path)?,
+            interval: interval.max(1),
+            last_ticks: None,
+            ticks: Vec::new(),
+            positions: Vec::new(),
+            types: Vec::new(),
+            stopped: false,
+        })
+    }
+
+    /// Ticks from `ticks` until the next snapshot is due
+    pub fn due_in(&self, ticks: u64) -> u64 {
+        if self.stopped {
+            return u64::MAX;
+        }
+        self.last_ticks
+            .map_or(0, |last| (last + self.interval).saturating_sub(ticks))
+    }
+
+    /// Takes a snapshot if `interval` ticks went by since the last one
+    pub fn record(&mut self, device: &Device, queue: &Queue, simulation: &Simulation, ticks: u64) {
+        if self.due_in(ticks) > 0 {
+            return;
+        }
+        self.last_ticks = Some(ticks);
+        let points = simulation.read_points(device, queue);
+        if self.ticks.is_empty() {
+            self.types = points
+                .iter()
+                .map(|&(_, _, type_)| type_.unwrap_or(0))
+                .collect();
+        } else if points.len() != self.types.len() {
+            warn!(
+                "The number of particles changed at tick {}, so {} ends at tick {}",
+                ticks,
+                self.path.display(),
+                self.ticks.last().unwrap()
+            );
+            self.stopped = true;
+            return;
+        }
+        self.ticks.push(ticks);
+        for &(x, y, _) in &points {
+            self.positions.push(x);
+            self.positions.push(y);
+        }
+    }
+
+    /// Writes the snapshots taken so far
+    pub fn finish(self) -> ZipResult<()> {
+        let frames = self.ticks.len();
+        let num_points = self.types.len();
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+        let mut zip = ZipWriter::new(BufWriter::new(self.file));
+
+        zip.start_file("positions.npy", options)?;
+        write_npy_header(&mut zip, "<f4", &[frames, num_points, 2])?;
+        for value in &self.positions {
+            zip.write_all(&value.to_le_bytes())?;
+        }
+        zip.start_file("ticks.npy", options)?;
+        write_npy_header(&mut zip, "<u8", &[frames])?;
+        for value in &self.ticks {
+            zip.write_all(&value.to_le_bytes())?;
+        }
+        zip.start_file("types.npy", options)?;
+        write_npy_header(&mut zip, "<u4", &[num_points])?;
+        for value in &self.types {
+            zip.write_all(&value.to_le_bytes())?;
+        }
+        zip.finish()?.flush()?;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    io,
+    net::{ToSocketAddrs, UdpSocket},
+};
+
+use tracing::warn;
+use wgpu::{Device, Queue};
+
+use crate::{
+    analysis::{self, ClusterStats, MotionStats, CLUSTER_RADIUS},
+    simulation::Simulation,
+};
+
+/// Argument of an OSC message
+enum Argument {
+    Int(i32),
+    Float(f32),
+}
+
+/// Appends an OSC string: the bytes, a terminating nul and more nuls up to a multiple of four
+fn push_string(packet: &mut Vec<u8>, string: &str) {
+    packet.extend_from_slice(string.as_bytes());
+    let padding = 4 - string.len() % 4;
+    packet.extend(std::iter::repeat(0).take(padding));
+}
+
+/// Encodes an OSC 1.0 message, whose arguments are big endian
+fn message(address: &str, arguments: &[Argument]) -> Vec<u8> {
+    let mut packet = Vec::new();
+    push_string(&mut packet, address);
+    let tags: String = std::iter::once(',')
+        .chain(arguments.iter().map(|argument| match argument {
+            Argument::Int(_) => 'i',
+            Argument::Float(_) => 'f',
+        }))
+        .collect();
+    push_string(&mut packet, &tags);
+    for argument in arguments {
+        match argument {
+            Argument::Int(value) => packet.extend_from_slice(&value.to_be_bytes()),
+            Argument::Float(value) => packet.extend_from_slice(&value.to_be_bytes()),
+        }
+    }
+    packet
+}
+
+/// Sends metrics of the simulation as OSC messages over UDP, for audio and visual tools to react
+/// to. Each time it sends:
+///
+/// - `/plife/tick` with the tick (int)
+/// - `/plife/kinetic_energy` and `/plife/mean_speed` (float)
+/// - `/plife/clusters` with the number of clusters and the size of the largest (two ints)
+/// - `/plife/type/<n>/centroid` with the center of mass of every type (two floats)
+/// - `/plife/type/<n>/count` with the number of particles of every type (int)
+pub struct OscSender {
+    socket: UdpSocket,
+    interval: u64,
+    last_ticks: Option<u64>,
+}
+
+impl OscSender {
+    /// Sends to `target`, a `host:port` pair
+    pub fn connect(target: &str, interval: u64) -> io::Result<Self> {
+        let address = target
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the host has no addresses"))?;
+        let local = if address.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(address)?;
+        Ok(Self {
+            socket,
+            interval: interval.max(1),
+            last_ticks: None,
+        })
+    }
+
+    /// Ticks from `ticks` until the next metrics are due
+    pub fn due_in(&self, ticks: u64) -> u64 {
+        self.last_ticks
+            .map_or(0, |last| (last + self.interval).saturating_sub(ticks))
+    }
+
+    /// Measures the particles and sends the metrics if `interval` ticks went by since they were
+    /// last sent
+    pub fn send(&mut self, device: &Device, queue: &Queue, simulation: &Simulation, ticks: u64) {
+        if self.due_in(ticks) > 0 {
+            return;
+        }
+        self.last_ticks = Some(ticks);
+        let points = simulation.read_points(device, queue);
+        let motion = MotionStats::measure(&simulation.read_velocities(device, queue));
+        let clusters = ClusterStats::measure(&points, CLUSTER_RADIUS);
+        let mut messages = vec![
+            message("/plife/tick", &[Argument::Int(ticks as i32)]),
+            message(
+                "/plife/kinetic_energy",
+                &[Argument::Float(motion.kinetic_energy as f32)],
+            ),
+            message(
+                "/plife/mean_speed",
+                &[Argument::Float(motion.mean_speed as f32)],
+            ),
+            message(
+                "/plife/clusters",
+                &[
+                    Argument::Int(clusters.count as i32),
+                    Argument::Int(clusters.largest as i32),
+                ],
+            ),
+        ];
+        for type_ in analysis::type_stats(&points, simulation.ruleset.num_point_types) {
+            messages.push(message(
+                &format!("/plife/type/{}/centroid", type_.type_),
+                &[
+                    Argument::Float(type_.center[0] as f32),
+                    Argument::Float(type_.center[1] as f32),
+                ],
+            ));
+            messages.push(message(
+                &format!("/plife/type/{}/count", type_.type_),
+                &[Argument::Int(type_.count as i32)],
+            ));
+        }
+        for message in &messages {
+            match self.socket.send(message) {
+                Ok(_) => {}
+                // Nothing listening yet, which is not worth a message every time
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => break,
+                Err(e) => {
+                    warn!("Cannot send OSC metrics: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
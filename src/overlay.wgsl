@@ -0,0 +1,36 @@
+[[location(0)]]
+var<in> in_pos: vec2<f32>;
+[[builtin(position)]]
+var<out> out_pos: vec4<f32>;
+
+[[block]]
+struct RenderGlobals {
+    x : f32;
+    y : f32;
+    width : u32;
+    height : u32;
+    zoom : f32;
+    outline : f32;
+};
+
+[[group(0), binding(0)]] var<uniform> render_globals : RenderGlobals;
+
+[[stage(vertex)]]
+fn main() {
+    var width : f32 = f32(render_globals.width);
+    var height : f32 = f32(render_globals.height);
+    var camera_pos : vec2<f32> = vec2<f32>(render_globals.x, render_globals.y);
+    var size : vec2<f32> = vec2<f32>(width, height);
+    var smallest_side : f32 = min(width, height);
+    var aspect_ratio : vec2<f32> = size / vec2<f32>(smallest_side, smallest_side);
+    var pos : vec2<f32> = (in_pos - camera_pos) / aspect_ratio * vec2<f32>(render_globals.zoom, render_globals.zoom);
+    out_pos = vec4<f32>(pos, 0.0, 1.0);
+}
+
+[[location(0)]]
+var<out> out_color: vec4<f32>;
+
+[[stage(fragment)]]
+fn main() {
+    out_color = vec4<f32>(0.3, 0.3, 0.3, 1.0);
+}
@@ -0,0 +1,71 @@
+use std::str::FromStr;
+
+/// A config built into plife, to run with `--preset` without writing one first
+#[derive(Clone, Copy)]
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// The config, in YAML
+    pub config: &'static str,
+}
+
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "cells",
+        description: "Round cells with a membrane around a nucleus, drifting through a soup",
+        config: include_str!("../examples/presets/cells.yml"),
+    },
+    Preset {
+        name: "chasers",
+        description: "Three types that each chase the next and flee the one before",
+        config: include_str!("../examples/presets/chasers.yml"),
+    },
+    Preset {
+        name: "mitosis",
+        description: "Blobs that grow by taking in loose particles until they split in two",
+        config: include_str!("../examples/presets/mitosis.yml"),
+    },
+    Preset {
+        name: "galaxies",
+        description: "A new ruleset every time, tuned to gather into spirals and clusters",
+        config: include_str!("../examples/presets/galaxies.yml"),
+    },
+];
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PRESETS
+            .iter()
+            .find(|preset| preset.name.eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| {
+                format!(
+                    "Unknown preset `{}`, expected one of {}",
+                    s,
+                    PRESETS
+                        .iter()
+                        .map(|preset| preset.name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::{assert_valid_config, Config, ConfigFormat};
+
+    #[test]
+    fn presets_are_valid_configs() {
+        for preset in PRESETS {
+            assert_valid_config(
+                &format!("preset {}", preset.name),
+                Config::from_reader(preset.config.as_bytes(), ConfigFormat::Yaml, &[]),
+            );
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::str::FromStr;
+
+use crate::serialize::{DisplayConfig, PresentModeChoice};
+
+/// Bundles of display settings traded off between speed and looks, chosen with `--profile`. A
+/// profile sets `trails`, `opacity`, `depth_sort`, `density_threshold` and `present_mode` in the
+/// display config, and the ticks per frame the window starts with; everything else comes from the
+/// config. There is no MSAA or bloom to trade off.
+#[derive(Clone, Copy)]
+pub enum Profile {
+    /// As many simulation steps as possible, with the cheapest drawing
+    Performance,
+    /// The defaults, switching to the density map for very large simulations
+    Balanced,
+    /// Smooth, vsynced motion with trails and translucent, depth sorted particles
+    Quality,
+}
+
+impl Profile {
+    /// Replaces the settings the profile covers, keeping the rest of the config
+    pub fn apply(self, display: &mut DisplayConfig) {
+        match self {
+            Profile::Performance => {
+                display.trails = false;
+                display.opacity = None;
+                display.depth_sort = false;
+                display.density_threshold = Some(20_000);
+                display.present_mode = Some(PresentModeChoice::Immediate);
+            }
+            Profile::Balanced => {
+                display.density_threshold = Some(100_000);
+                display.present_mode = Some(PresentModeChoice::Mailbox);
+            }
+            Profile::Quality => {
+                display.trails = true;
+                display.opacity = Some(0.8);
+                display.depth_sort = true;
+                display.density_threshold = None;
+                display.present_mode = Some(PresentModeChoice::Fifo);
+            }
+        }
+    }
+
+    /// Simulation steps per frame to start with
+    pub fn ticks_per_frame(self) -> u16 {
+        match self {
+            Profile::Performance => 2,
+            Profile::Balanced | Profile::Quality => 1,
+        }
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "performance" => Ok(Profile::Performance),
+            "balanced" => Ok(Profile::Balanced),
+            "quality" => Ok(Profile::Quality),
+            _ => Err(format!(
+                "Unknown profile `{}`, expected performance, balanced or quality",
+                s
+            )),
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    io::{Cursor, Write},
+    mem::size_of,
+};
+
+use rand::Rng;
+use wgpu::*;
+
+use crate::{
+    serialize::ReactionConfig,
+    simulation::PointType,
+    util::{with_rng, BindableBuffer, VEC2_SIZE},
+};
+
+/// Size of the rule array in reactions.wgsl
+pub const MAX_REACTIONS: usize = 16;
+const WORKGROUP_SIZE: u32 = 64;
+/// Bytes per rule: the types and radius, then the probability padded to a vec4
+const RULE_SIZE: usize = VEC2_SIZE * 4;
+
+/// Turns particles of one type into another when they come near a third, following the reaction
+/// rules of the config, in a compute pass after every step
+pub struct Reactions {
+    rules: Vec<ReactionConfig>,
+    /// Types as they were before the pass, copied from the simulation's every step
+    types_old: BindableBuffer,
+    pipeline: ComputePipeline,
+    advance_pipeline: ComputePipeline,
+    /// One for each of the simulation's position buffers being current
+    bind_groups: [BindGroup; 2],
+}
+
+impl Reactions {
+    /// `None` if there are no rules, so that simulations without reactions need no extra pass
+    pub fn new(
+        device: &Device,
+        rules: Vec<ReactionConfig>,
+        position_buffers: &[BindableBuffer; 2],
+        types: &BindableBuffer,
+        globals: &BindableBuffer,
+    ) -> Option<Self> {
+        if rules.is_empty() {
+            return None;
+        }
+        let types_old = BindableBuffer::new(
+            device,
+            BufferUsage::STORAGE | BufferUsage::COPY_DST,
+            ShaderStage::COMPUTE,
+            false,
+            types.size,
+            |_| {},
+        );
+        let rules_buffer = BindableBuffer::new(
+            device,
+            BufferUsage::UNIFORM,
+            ShaderStage::COMPUTE,
+            true,
+            MAX_REACTIONS * RULE_SIZE,
+            |buffer| {
+                let slice = buffer.slice(..);
+                let mut view = slice.get_mapped_range_mut();
+                let mut cursor = Cursor::new(&mut *view);
+                for rule in rules.iter().take(MAX_REACTIONS) {
+                    for value in [
+                        rule.catalyst as f32,
+                        rule.reactant as f32,
+                        rule.product as f32,
+                        rule.radius,
+                        rule.probability,
+                        0.0,
+                        0.0,
+                        0.0,
+                    ]
+                    .iter()
+                    {
+                        cursor.write_all(&value.to_le_bytes()).unwrap();
+                    }
+                }
+            },
+        );
+        let state = BindableBuffer::new(
+            device,
+            BufferUsage::STORAGE,
+            ShaderStage::COMPUTE,
+            false,
+            size_of::<u32>() * 3,
+            |buffer| {
+                let slice = buffer.slice(..);
+                let mut view = slice.get_mapped_range_mut();
+                let mut cursor = Cursor::new(&mut *view);
+                let num_rules = rules.len().min(MAX_REACTIONS) as u32;
+                let seed = with_rng(|rng| rng.gen::<u32>());
+                for value in [num_rules, seed, 0].iter() {
+                    cursor.write_all(&value.to_le_bytes()).unwrap();
+                }
+            },
+        );
+
+        let buffers = |positions: &BindableBuffer| {
+            [positions, &types_old, types, globals, &rules_buffer, &state]
+        };
+        let layout = BindableBuffer::bind_group_layout(device, &buffers(&position_buffers[0]));
+        let bind_groups =
+            [0, 1].map(|i| BindableBuffer::bind_group(device, &buffers(&position_buffers[i])));
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("reactions_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(&ShaderModuleDescriptor {
+            label: Some("reactions_shader"),
+            source: ShaderSource::Wgsl(include_str!("reactions.wgsl").into()),
+            flags: ShaderFlags::VALIDATION,
+        });
+        let compute_pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("reactions_compute"),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+
+        Some(Self {
+            rules,
+            types_old,
+            pipeline: compute_pipeline("main"),
+            advance_pipeline: compute_pipeline("advance"),
+            bind_groups,
+        })
+    }
+
+    pub fn rules(&self) -> &[ReactionConfig] {
+        &self.rules
+    }
+
+    /// Records the reactions of one step, with `current` the index of the position buffer
+    /// the step wrote
+    pub fn encode(
+        &self,
+        encoder: &mut CommandEncoder,
+        types: &Buffer,
+        current: usize,
+        num_points: u32,
+    ) {
+        encoder.copy_buffer_to_buffer(
+            types,
+            0,
+            &self.types_old.buffer,
+            0,
+            num_points as u64 * size_of::<PointType>() as u64,
+        );
+        let mut react_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("reactions_pass"),
+        });
+        react_pass.push_debug_group("react");
+        react_pass.set_bind_group(0, &self.bind_groups[current], &[]);
+        react_pass.set_pipeline(&self.pipeline);
+        react_pass.dispatch(num_points.div_ceil(WORKGROUP_SIZE), 1, 1);
+        react_pass.pop_debug_group();
+        drop(react_pass);
+
+        let mut advance_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("reactions_advance_pass"),
+        });
+        advance_pass.set_bind_group(0, &self.bind_groups[current], &[]);
+        advance_pass.set_pipeline(&self.advance_pipeline);
+        advance_pass.dispatch(1, 1, 1);
+    }
+}
@@ -0,0 +1,138 @@
+// Reactions: after every step, each particle rolls for every rule it is the reactant of, and
+// turns into the product if the roll succeeds and a catalyst is close enough. Types are read from
+// a copy taken before the pass, so that particles changing type in the pass do not catalyze
+// anything until the next step.
+
+[[block]]
+struct Positions {
+    data : [[stride(8)]] array< vec2<f32> >;
+};
+
+[[block]]
+struct Types {
+    data : [[stride(4)]] array<u32>;
+};
+
+[[block]]
+struct Globals {
+    num_points : u32;
+    num_types : u32;
+    friction : f32;
+    wrapping : u32;
+    dist : f32;
+};
+
+// Two entries per rule: (catalyst, reactant, product, radius) and (probability, 0, 0, 0)
+[[block]]
+struct Rules {
+    data : [[stride(16)]] array<vec4<f32>, 32u>;
+};
+
+[[block]]
+struct State {
+    num_rules : u32;
+    seed : u32;
+    // Steps reacted so far, so that every step rolls differently
+    tick : u32;
+};
+
+[[group(0), binding(0)]] var<storage> positions : [[access(read)]] Positions;
+[[group(0), binding(1)]] var<storage> types_old : [[access(read)]] Types;
+[[group(0), binding(2)]] var<storage> types : [[access(read_write)]] Types;
+[[group(0), binding(3)]] var<uniform> globals : Globals;
+[[group(0), binding(4)]] var<uniform> rules : Rules;
+[[group(0), binding(5)]] var<storage> state : [[access(read_write)]] State;
+
+[[builtin(global_invocation_id)]] var<in> global_invocation_id : vec3<u32>;
+
+// PCG hash
+fn hash(value : u32) -> u32 {
+    var x : u32 = value * 747796405u + 2891336453u;
+    x = ((x >> ((x >> 28u) + 4u)) ^ x) * 277803737u;
+    return (x >> 22u) ^ x;
+}
+
+// Uniform in [0, 1), different for every particle, rule and step
+fn random(i : u32, rule : u32) -> f32 {
+    var x : u32 = hash(i ^ state.seed);
+    x = hash(x ^ (state.tick * 16u + rule));
+    return f32(x >> 8u) / 16777216.0;
+}
+
+// The shortest way from one point to another, as in compute.wgsl
+fn nearest_image(direct : vec2<f32>) -> vec2<f32> {
+    var delta : vec2<f32> = direct;
+    if (globals.wrapping != 0u) {
+        if (delta.x > globals.dist) {
+            delta.x = delta.x - globals.dist * 2.0;
+        } else {
+            if (delta.x < -globals.dist) {
+                delta.x = delta.x + globals.dist * 2.0;
+            }
+        }
+
+        if (delta.y > globals.dist) {
+            delta.y = delta.y - globals.dist * 2.0;
+        } else {
+            if (delta.y < -globals.dist) {
+                delta.y = delta.y + globals.dist * 2.0;
+            }
+        }
+    }
+    return delta;
+}
+
+// Whether a particle of type `catalyst` other than particle `i` is within `radius` of `p`
+fn catalyst_near(i : u32, p : vec2<f32>, catalyst : u32, radius : f32) -> bool {
+    var j : u32 = 0u;
+    loop {
+        if (j >= globals.num_points) {
+            break;
+        }
+        if (j != i && types_old.data[j] == catalyst) {
+            var delta : vec2<f32> = nearest_image(positions.data[j] - p);
+            if (dot(delta, delta) <= radius * radius) {
+                return true;
+            }
+        }
+        continuing {
+            j = j + 1u;
+        }
+    }
+    return false;
+}
+
+[[stage(compute), workgroup_size(64)]]
+fn main() -> void {
+    var i : u32 = global_invocation_id.x;
+    if (i >= globals.num_points) {
+        return;
+    }
+    var p_type : u32 = types_old.data[i];
+    var p : vec2<f32> = positions.data[i];
+    var r : u32 = 0u;
+    loop {
+        if (r >= state.num_rules) {
+            break;
+        }
+        var rule : vec4<f32> = rules.data[r * 2u];
+        var probability : f32 = rules.data[r * 2u + 1u].x;
+        // Rolling first skips looking for a catalyst most of the time
+        if (u32(rule.y) == p_type && random(i, r) < probability) {
+            if (catalyst_near(i, p, u32(rule.x), rule.w)) {
+                types.data[i] = u32(rule.z);
+                return;
+            }
+        }
+        continuing {
+            r = r + 1u;
+        }
+    }
+}
+
+// Dispatched once after `main`, in a pass of its own so that no invocation of `main` can see the
+// tick change halfway through
+[[stage(compute), workgroup_size(1)]]
+fn advance() -> void {
+    state.tick = state.tick + 1u;
+}
@@ -0,0 +1,302 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use serde_yaml::Value;
+use structopt::StructOpt;
+use wgpu::*;
+
+use crate::{
+    error::ConfigError,
+    evolve::with_sampled,
+    serialize::Config,
+    simulation::{Point, PointType, Simulation},
+    util::VEC2_SIZE,
+};
+
+const MAGIC: &[u8; 4] = b"PLR1";
+/// Positions are stored as whole multiples of one over this many world units
+const QUANTUM: f32 = 64.0;
+
+#[derive(StructOpt)]
+pub struct ReplayOptions {
+    /// Recording made with `--record-positions`
+    file: PathBuf,
+}
+
+impl ReplayOptions {
+    pub fn open(&self) -> anyhow::Result<Replay> {
+        Replay::open(&self.file).map_err(|e| {
+            ConfigError(format!(
+                "Cannot open recording {}: {}",
+                self.file.display(),
+                e
+            ))
+            .into()
+        })
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(reader: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "varint too long",
+    ))
+}
+
+/// Maps small negative and positive numbers alike to small varints
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+fn quantize(value: f32) -> i64 {
+    (value * QUANTUM).round() as i64
+}
+
+/// Appends the positions and types of all particles to a file every so many ticks.
+///
+/// The file starts with a config that rebuilds the simulation, followed by one frame per
+/// recording: the tick, the particle count, the types if they changed since the previous frame,
+/// and every position as the difference from the previous frame, quantized and varint encoded.
+pub struct Recorder {
+    out: BufWriter<File>,
+    interval: u64,
+    last_ticks: Option<u64>,
+    /// Quantized positions and types in the previous frame
+    previous: Vec<(i64, i64)>,
+    previous_types: Vec<PointType>,
+}
+
+impl Recorder {
+    /// Creates the file, overwriting it, and writes the config of the simulation to it
+    pub fn create(
+        path: &Path,
+        document: &Value,
+        simulation: &Simulation,
+        interval: u64,
+    ) -> io::Result<Self> {
+        let mut header = with_sampled(
+            document,
+            &simulation.ruleset,
+            &simulation.walls,
+            Some(simulation.num_points),
+        );
+        if let Value::Mapping(mapping) = &mut header {
+            // Room for however many particles the run ends up with
+            let capacity = simulation.positions().size / VEC2_SIZE as u64;
+            mapping.insert(Value::from("max_points"), Value::from(capacity));
+        }
+        let header = serde_json::to_vec(&header)?;
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&(header.len() as u32).to_le_bytes())?;
+        out.write_all(&header)?;
+        Ok(Self {
+            out,
+            interval: interval.max(1),
+            last_ticks: None,
+            previous: Vec::new(),
+            previous_types: Vec::new(),
+        })
+    }
+
+    /// Ticks from `ticks` until the next frame is due
+    pub fn due_in(&self, ticks: u64) -> u64 {
+        self.last_ticks
+            .map_or(0, |last| (last + self.interval).saturating_sub(ticks))
+    }
+
+    /// Writes a frame if `interval` ticks went by since the last one
+    pub fn record(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        simulation: &Simulation,
+        ticks: u64,
+    ) -> io::Result<()> {
+        if self
+            .last_ticks
+            .is_some_and(|last| ticks < last + self.interval)
+        {
+            return Ok(());
+        }
+        self.last_ticks = Some(ticks);
+        let points = simulation.read_points(device, queue);
+        let types = points
+            .iter()
+            .map(|&(_, _, type_)| type_.unwrap_or(0))
+            .collect::<Vec<_>>();
+
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&ticks.to_le_bytes());
+        write_varint(&mut frame, points.len() as u64);
+        let types_changed = types != self.previous_types;
+        frame.push(types_changed as u8);
+        if types_changed {
+            for &type_ in &types {
+                write_varint(&mut frame, type_ as u64);
+            }
+        }
+        let positions = points
+            .iter()
+            .map(|&(x, y, _)| (quantize(x), quantize(y)))
+            .collect::<Vec<_>>();
+        for (i, &(x, y)) in positions.iter().enumerate() {
+            let (px, py) = self.previous.get(i).copied().unwrap_or((0, 0));
+            write_varint(&mut frame, zigzag(x - px));
+            write_varint(&mut frame, zigzag(y - py));
+        }
+        // Flushed every frame, so that the recording is usable however the run ends
+        self.out.write_all(&frame)?;
+        self.out.flush()?;
+        self.previous = positions;
+        self.previous_types = types;
+        Ok(())
+    }
+}
+
+/// Reads back a recording made by `Recorder`, one frame at a time
+pub struct Replay {
+    path: PathBuf,
+    document: Value,
+    reader: BufReader<File>,
+    previous: Vec<(i64, i64)>,
+    types: Vec<PointType>,
+    /// Tick of the first frame, which playback starts from
+    first_ticks: Option<u64>,
+    /// Frame read but not yet due
+    next: Option<(u64, Vec<Point>)>,
+    finished: bool,
+}
+
+impl Replay {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let mut reader = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+        let mut magic = [0; 4];
+        reader
+            .read_exact(&mut magic)
+            .map_err(|_| "not a recording".to_owned())?;
+        if &magic != MAGIC {
+            return Err("not a recording".to_owned());
+        }
+        let mut length = [0; 4];
+        reader.read_exact(&mut length).map_err(|e| e.to_string())?;
+        let mut header = vec![0; u32::from_le_bytes(length) as usize];
+        reader.read_exact(&mut header).map_err(|e| e.to_string())?;
+        let document = serde_json::from_slice(&header).map_err(|e| e.to_string())?;
+        Ok(Self {
+            path: path.to_owned(),
+            document,
+            reader,
+            previous: Vec::new(),
+            types: Vec::new(),
+            first_ticks: None,
+            next: None,
+            finished: false,
+        })
+    }
+
+    /// Config document that rebuilds the recorded simulation, with room for all its particles
+    pub fn document(&self) -> &Value {
+        &self.document
+    }
+
+    pub fn config(&self) -> Result<Config, String> {
+        Config::from_value(self.document.clone())
+    }
+
+    /// Reads the next frame, or `None` at the end of the file. A frame cut off part way, as by a
+    /// crash while it was being written, also ends the recording.
+    fn read_frame(&mut self) -> Result<Option<(u64, Vec<Point>)>, String> {
+        match self.try_read_frame() {
+            Ok(frame) => Ok(Some(frame)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn try_read_frame(&mut self) -> io::Result<(u64, Vec<Point>)> {
+        let mut ticks = [0; 8];
+        self.reader.read_exact(&mut ticks)?;
+        let ticks = u64::from_le_bytes(ticks);
+        let num_points = read_varint(&mut self.reader)? as usize;
+        let mut types_changed = [0];
+        self.reader.read_exact(&mut types_changed)?;
+        if types_changed[0] != 0 {
+            self.types = (0..num_points)
+                .map(|_| read_varint(&mut self.reader).map(|type_| type_ as PointType))
+                .collect::<io::Result<_>>()?;
+        }
+        let mut positions = Vec::with_capacity(num_points);
+        for i in 0..num_points {
+            let (px, py) = self.previous.get(i).copied().unwrap_or((0, 0));
+            let x = px + unzigzag(read_varint(&mut self.reader)?);
+            let y = py + unzigzag(read_varint(&mut self.reader)?);
+            positions.push((x, y));
+        }
+        let points = positions
+            .iter()
+            .zip(&self.types)
+            .map(|(&(x, y), &type_)| (x as f32 / QUANTUM, y as f32 / QUANTUM, Some(type_)))
+            .collect();
+        self.previous = positions;
+        Ok((ticks, points))
+    }
+
+    /// Reads every frame that is due `elapsed` ticks into playback, and returns the last of them,
+    /// if any
+    pub fn advance(&mut self, elapsed: u64) -> Result<Option<Vec<Point>>, String> {
+        let mut shown = None;
+        loop {
+            if self.next.is_none() {
+                self.next = self.read_frame()?;
+            }
+            let (ticks, _) = match &self.next {
+                Some(next) => next,
+                None => {
+                    self.finished = true;
+                    return Ok(shown);
+                }
+            };
+            let first_ticks = *self.first_ticks.get_or_insert(*ticks);
+            if ticks - first_ticks > elapsed {
+                return Ok(shown);
+            }
+            shown = self.next.take().map(|(_, points)| points);
+        }
+    }
+
+    /// Whether the last frame has been read
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Goes back to the first frame
+    pub fn restart(&mut self) -> Result<(), String> {
+        *self = Self::open(&self.path)?;
+        Ok(())
+    }
+}
@@ -1,3 +1,11 @@
+const CIRCLE_RADIUS : f32 = 5.0;
+// Smallest radius of the selected particle on screen, in pixels
+const SELECTED_RADIUS : f32 = 8.0;
+// Fraction of the selected particle's radius drawn as its outline
+const SELECTED_OUTLINE : f32 = 0.35;
+// Vertices of each circle in the vertex buffer: its center and its rim
+const CIRCLE_VERTICES : u32 = 17u;
+
 [[builtin(vertex_index)]]
 var<in> in_vertex_index : u32;
 [[builtin(instance_index)]]
@@ -10,22 +18,53 @@ var<in> in_pos: vec2<f32>;
 var<in> in_point_pos: vec2<f32>;
 [[location(0)]]
 var<out> out_color: vec3<f32>;
+[[location(1)]]
+var<out> out_edge: f32;
+[[location(2)]]
+var<out> out_selected: f32;
+[[location(3)]]
+var<out> out_fade: f32;
 
 [[block]]
 struct Types {
     data : [[stride(4)]] array<u32>;
 };
 
+[[block]]
+struct Velocities {
+    data : [[stride(8)]] array< vec2<f32> >;
+};
+
 [[block]]
 struct Colors {
     data: [[stride(12)]] array< vec3<f32> >;
 };
 
+// Color of each type's trails in rgb, or a negative red for the particle color, and the fraction
+// of the trails kept each frame in a
+[[block]]
+struct TrailStyles {
+    data: [[stride(16)]] array< vec4<f32> >;
+};
+
+[[block]]
+struct Positions {
+    data : [[stride(8)]] array< vec2<f32> >;
+};
+
+// Depth sort key in x and particle index in y, in drawing order
+[[block]]
+struct Order {
+    data : [[stride(8)]] array< vec2<u32> >;
+};
+
 [[block]]
 struct Globals {
     num_points : u32;
     num_types: u32;
     friction : f32;
+    wrapping: u32;
+    dist: f32;
 };
 
 [[block]]
@@ -35,24 +74,131 @@ struct RenderGlobals {
     width : u32;
     height : u32;
     zoom : f32;
+    outline : f32;
+    // Speed shown at the top of the colormap, or 0 to color by type
+    max_speed : f32;
+    // Index of the particle picked in the inspector, or 0xffffffff for none
+    selected : u32;
+    opacity : f32;
 };
 
 [[group(0), binding(0)]] var<uniform> globals : Globals;
 [[group(0), binding(1)]] var<uniform> render_globals : RenderGlobals;
 [[group(0), binding(2)]] var<storage> types : [[access(read)]] Types;
 [[group(0), binding(3)]] var<storage> colors : [[access(read)]] Colors;
+[[group(0), binding(4)]] var<storage> velocities : [[access(read)]] Velocities;
+[[group(0), binding(5)]] var<storage> trail_styles : [[access(read)]] TrailStyles;
+[[group(1), binding(0)]] var<storage> positions : [[access(read)]] Positions;
+[[group(1), binding(1)]] var<storage> order : [[access(read)]] Order;
 
-[[stage(vertex)]]
-fn main() {
+// Dark purple through red and orange to pale yellow
+fn speed_color(t : f32) -> vec3<f32> {
+    var scaled : f32 = clamp(t, 0.0, 1.0) * 3.0;
+    if (scaled < 1.0) {
+        return mix(vec3<f32>(0.05, 0.03, 0.3), vec3<f32>(0.6, 0.1, 0.5), vec3<f32>(scaled, scaled, scaled));
+    }
+    if (scaled < 2.0) {
+        var u : f32 = scaled - 1.0;
+        return mix(vec3<f32>(0.6, 0.1, 0.5), vec3<f32>(0.95, 0.45, 0.1), vec3<f32>(u, u, u));
+    }
+    var v : f32 = scaled - 2.0;
+    return mix(vec3<f32>(0.95, 0.45, 0.1), vec3<f32>(1.0, 1.0, 0.6), vec3<f32>(v, v, v));
+}
+
+// Position on screen of a point in the world
+fn screen_pos(world_pos : vec2<f32>) -> vec4<f32> {
     var width : f32 = f32(render_globals.width);
     var height : f32 = f32(render_globals.height);
     var camera_pos : vec2<f32> = vec2<f32>(render_globals.x, render_globals.y);
     var size : vec2<f32> = vec2<f32>(width, height);
     var smallest_side : f32 = min(width, height);
     var aspect_ratio : vec2<f32> = size / vec2<f32>(smallest_side, smallest_side);
-    var pos : vec2<f32> = (in_point_pos + in_pos - camera_pos) / aspect_ratio * vec2<f32>(render_globals.zoom, render_globals.zoom);
-    out_pos = vec4<f32>(pos, 0.0, 1.0);
-    out_color = colors.data[ types.data[in_instance_index] ];
+    var pos : vec2<f32> = (world_pos - camera_pos) / aspect_ratio * vec2<f32>(render_globals.zoom, render_globals.zoom);
+    return vec4<f32>(pos, 0.0, 1.0);
+}
+
+fn particle_color(index : u32) -> vec3<f32> {
+    if (render_globals.max_speed > 0.0) {
+        return speed_color(length(velocities.data[index]) / render_globals.max_speed);
+    }
+    return colors.data[ types.data[index] ];
+}
+
+// With wrapping walls, particles are drawn up to three more times from the further circles in the
+// vertex buffer: moved across the seam horizontally, vertically or both, so that particles
+// straddling the seam show on both sides of it. Returns how far the copy is moved in xy, and in z
+// whether it reaches into the world at all.
+fn seam_copy(point_pos : vec2<f32>, radius : f32) -> vec3<f32> {
+    var copy : u32 = in_vertex_index / CIRCLE_VERTICES;
+    var shift : vec2<f32> = vec2<f32>(0.0, 0.0);
+    var needed : f32 = 1.0;
+    var near_edge : f32 = globals.dist - radius;
+    if ((copy & 1u) != 0u) {
+        shift.x = -sign(point_pos.x) * globals.dist * 2.0;
+        if (abs(point_pos.x) <= near_edge) {
+            needed = 0.0;
+        }
+    }
+    if ((copy & 2u) != 0u) {
+        shift.y = -sign(point_pos.y) * globals.dist * 2.0;
+        if (abs(point_pos.y) <= near_edge) {
+            needed = 0.0;
+        }
+    }
+    return vec3<f32>(shift, needed);
+}
+
+// Places a particle and picks its color
+fn particle(index : u32, point_pos : vec2<f32>) {
+    var smallest_side : f32 = f32(min(render_globals.width, render_globals.height));
+    var offset : vec2<f32> = in_pos;
+    var radius : f32 = CIRCLE_RADIUS;
+    out_selected = 0.0;
+    if (index == render_globals.selected) {
+        // Grow the selected particle until its outline can be seen
+        var min_radius : f32 = SELECTED_RADIUS * 2.0 / smallest_side / render_globals.zoom;
+        var scale : f32 = max(1.0, min_radius / CIRCLE_RADIUS);
+        offset = in_pos * vec2<f32>(scale, scale);
+        radius = CIRCLE_RADIUS * scale;
+        out_selected = 1.0;
+    }
+    var seam : vec3<f32> = seam_copy(point_pos, radius);
+    out_pos = screen_pos(point_pos + seam.xy + offset);
+    if (seam.z == 0.0) {
+        // With every vertex in the same place there is nothing to draw
+        out_pos = vec4<f32>(2.0, 2.0, 0.0, 1.0);
+    }
+    out_color = particle_color(index);
+    out_edge = length(in_pos) / CIRCLE_RADIUS;
+}
+
+[[stage(vertex)]]
+fn main() {
+    particle(in_instance_index, in_point_pos);
+}
+
+// Draws the particles in the order left by the depth sort
+[[stage(vertex)]]
+fn sorted_main() {
+    var index : u32 = order.data[in_instance_index].y;
+    particle(index, positions.data[index]);
+}
+
+// Draws particles into the trails, in their trail colors
+[[stage(vertex)]]
+fn trail_vertex() {
+    var seam : vec3<f32> = seam_copy(in_point_pos, CIRCLE_RADIUS);
+    out_pos = screen_pos(in_point_pos + seam.xy + in_pos);
+    if (seam.z == 0.0) {
+        out_pos = vec4<f32>(2.0, 2.0, 0.0, 1.0);
+    }
+    var style : vec4<f32> = trail_styles.data[ types.data[in_instance_index] ];
+    if (style.r >= 0.0) {
+        out_color = style.rgb;
+    } else {
+        out_color = particle_color(in_instance_index);
+    }
+    out_fade = style.a;
 }
 
 [[builtin(frag_coord)]] var<in> frag_coord : vec4<f32>;
@@ -61,6 +207,12 @@ fn main() {
 var<out> out_color: vec4<f32>;
 [[location(0)]]
 var<in> in_color: vec3<f32>;
+[[location(1)]]
+var<in> in_edge: f32;
+[[location(2)]]
+var<in> in_selected: f32;
+[[location(3)]]
+var<in> in_fade: f32;
 
 [[stage(fragment)]]
 fn main() {
@@ -83,6 +235,21 @@ fn main() {
     //if (c == 1u) {
     //    out_color = vec4<f32>(color, 1.0);
     //} else {
-    out_color = vec4<f32>(in_color, 1.0);
+    out_color = vec4<f32>(in_color, render_globals.opacity);
+    if (render_globals.outline > 0.0) {
+        if (in_edge > 1.0 - render_globals.outline) {
+            out_color = vec4<f32>(1.0, 1.0, 1.0, 1.0);
+        }
+    }
+    if (in_selected > 0.5 && in_edge > 1.0 - SELECTED_OUTLINE) {
+        out_color = vec4<f32>(1.0, 1.0, 1.0, 1.0);
+    }
     //}
-}
\ No newline at end of file
+}
+
+// Alpha holds how much of the trail is lost each frame, which the fade pass of the trails reads
+// back, so that each type's trail fades at its own rate
+[[stage(fragment)]]
+fn trail_fragment() {
+    out_color = vec4<f32>(in_color, 1.0 - in_fade);
+}
@@ -0,0 +1,89 @@
+use std::str::FromStr;
+
+use structopt::StructOpt;
+use tracing::info;
+use wgpu::*;
+
+use crate::{error::ConfigError, evolve::Fitness, serialize::Config, simulation::Simulation};
+
+/// Options for sampling a config again and again until it turns out interesting
+#[derive(StructOpt)]
+pub struct RerollOptions {
+    /// Keep sampling the config until a short run scores above a threshold, e.g. `clusters>5`,
+    /// `variance>1e6` or `score>5` (clusters)
+    #[structopt(long)]
+    reroll_until: Option<RerollTarget>,
+    /// Most samples tried before settling for the best one
+    #[structopt(long, default_value = "20")]
+    max_tries: u32,
+    /// Steps each sample is simulated for before it is scored
+    #[structopt(long, default_value = "300")]
+    reroll_steps: u64,
+}
+
+/// A fitness and the score it has to exceed
+pub struct RerollTarget {
+    fitness: Fitness,
+    threshold: f32,
+}
+
+impl FromStr for RerollTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (fitness, threshold) = s
+            .split_once('>')
+            .ok_or_else(|| format!("Reroll target `{}` is not of the form fitness>value", s))?;
+        let fitness = match fitness.trim() {
+            "score" => Fitness::Clusters,
+            fitness => fitness.parse()?,
+        };
+        let threshold = threshold
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid threshold in reroll target `{}`", s))?;
+        Ok(Self { fitness, threshold })
+    }
+}
+
+impl RerollOptions {
+    pub fn is_active(&self) -> bool {
+        self.reroll_until.is_some()
+    }
+
+    /// Builds simulations from freshly sampled configs until one scores above the target, and
+    /// returns it as it was left after scoring. Settles for the best one if none does.
+    pub fn run(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        workgroup_size: u32,
+        mut load_config: impl FnMut() -> Config,
+    ) -> Result<Simulation, ConfigError> {
+        let target = match &self.reroll_until {
+            Some(target) => target,
+            None => return Simulation::from_config(device, load_config(), workgroup_size),
+        };
+        let mut best: Option<(f32, Simulation)> = None;
+        for try_ in 1..=self.max_tries.max(1) {
+            let mut simulation = Simulation::from_config(device, load_config(), workgroup_size)?;
+            simulation.step_many(device, queue, self.reroll_steps);
+            let score = target
+                .fitness
+                .measure(&simulation.read_points(device, queue));
+            info!("Try {}/{}: score {:.2}", try_, self.max_tries.max(1), score);
+            if score > target.threshold {
+                return Ok(simulation);
+            }
+            if best.as_ref().map_or(true, |(best, _)| score > *best) {
+                best = Some((score, simulation));
+            }
+        }
+        let (score, simulation) = best.unwrap();
+        info!(
+            "No sample scored above {}; keeping the best one (score {:.2})",
+            target.threshold, score
+        );
+        Ok(simulation)
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+
+use wgpu::*;
+
+use crate::simulation::{Point, Simulation};
+
+/// Characters in the timeline shown while scrubbing
+const TIMELINE_WIDTH: usize = 24;
+
+/// State of every particle at one tick, read back from the GPU
+pub struct Frame {
+    pub ticks: u64,
+    pub points: Vec<Point>,
+    pub velocities: Vec<[f32; 2]>,
+}
+
+/// The last few states of the simulation, taken every so many ticks, which the view can be taken
+/// back to and resumed from
+pub struct Rewind {
+    frames: VecDeque<Frame>,
+    /// Most frames kept; the oldest are dropped to make room. No frames are taken if zero.
+    max_frames: usize,
+    /// Ticks between frames
+    interval: u64,
+    /// Index of the frame shown while scrubbing through them
+    cursor: Option<usize>,
+}
+
+impl Rewind {
+    pub fn new(max_frames: usize, interval: u64) -> Self {
+        Self {
+            frames: VecDeque::with_capacity(max_frames),
+            max_frames,
+            interval: interval.max(1),
+            cursor: None,
+        }
+    }
+
+    /// Takes a frame if `interval` ticks went by since the last one
+    pub fn record(&mut self, device: &Device, queue: &Queue, simulation: &Simulation, ticks: u64) {
+        if self.max_frames == 0 || self.cursor.is_some() {
+            return;
+        }
+        if let Some(last) = self.frames.back() {
+            if ticks < last.ticks + self.interval {
+                return;
+            }
+        }
+        if self.frames.len() == self.max_frames {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(Frame {
+            ticks,
+            points: simulation.read_points(device, queue),
+            velocities: simulation.read_velocities(device, queue),
+        });
+    }
+
+    /// Moves `by` frames back (negative) or forward through the stored ones, starting from the
+    /// newest. Returns the frame moved to, or `None` if there are no frames.
+    pub fn scrub(&mut self, by: isize) -> Option<&Frame> {
+        if self.frames.is_empty() {
+            return None;
+        }
+        let newest = self.frames.len() - 1;
+        let from = self.cursor.unwrap_or(newest) as isize;
+        let to = (from + by).max(0).min(newest as isize) as usize;
+        self.cursor = Some(to);
+        self.frames.get(to)
+    }
+
+    /// Whether a stored frame is being shown instead of the running simulation
+    pub fn is_scrubbing(&self) -> bool {
+        self.cursor.is_some()
+    }
+
+    /// Stops scrubbing, forgetting the frames after the one shown since the simulation carries on
+    /// from there
+    pub fn resume(&mut self) {
+        if let Some(cursor) = self.cursor.take() {
+            self.frames.truncate(cursor + 1);
+        }
+    }
+
+    /// A bar marking where the frame shown is among the stored ones, and how many ticks before
+    /// the newest one it is
+    pub fn timeline(&self) -> Option<(String, u64)> {
+        let cursor = self.cursor?;
+        let newest = self.frames.back()?.ticks;
+        let marker = cursor * (TIMELINE_WIDTH - 1) / (self.frames.len() - 1).max(1);
+        let bar = (0..TIMELINE_WIDTH)
+            .map(|i| if i == marker { 'O' } else { '-' })
+            .collect();
+        Some((bar, newest - self.frames[cursor].ticks))
+    }
+}
@@ -0,0 +1,58 @@
+use std::f32::consts::PI;
+
+use crate::{
+    serialize::{ScheduleConfig, ScheduleCurve},
+    simulation::Globals,
+};
+
+/// Moves simulation parameters from one value to another over the course of a run, for
+/// annealing-style experiments
+pub struct Schedules {
+    schedules: Vec<ScheduleConfig>,
+    /// Steps taken so far
+    ticks: u64,
+}
+
+impl Schedules {
+    pub fn new(schedules: Vec<ScheduleConfig>) -> Self {
+        Self {
+            schedules,
+            ticks: 0,
+        }
+    }
+
+    /// Advances one step, returning whether `globals` changed and need to be uploaded again
+    pub fn step(&mut self, globals: &mut Globals) -> bool {
+        let mut changed = false;
+        for schedule in &self.schedules {
+            let value = match schedule.value_at(self.ticks) {
+                Some(value) => value,
+                None => continue,
+            };
+            let parameter = globals.parameter_mut(schedule.parameter);
+            if *parameter != value {
+                *parameter = value;
+                changed = true;
+            }
+        }
+        self.ticks += 1;
+        changed
+    }
+}
+
+impl ScheduleConfig {
+    /// Value of the parameter at step `ticks`, or `None` outside of the schedule, so that it can
+    /// be changed by other means after the schedule ends
+    fn value_at(&self, ticks: u64) -> Option<f32> {
+        if ticks < self.start || ticks > self.start + self.steps {
+            return None;
+        }
+        let t = ((ticks - self.start) as f32 / self.steps.max(1) as f32).min(1.0);
+        let (from, to) = (self.from, self.to);
+        Some(match self.curve {
+            ScheduleCurve::Linear => from + (to - from) * t,
+            ScheduleCurve::Exponential => from * (to / from).powf(t),
+            ScheduleCurve::Cosine => from + (to - from) * (1.0 - (PI * t).cos()) / 2.0,
+        })
+    }
+}
@@ -0,0 +1,138 @@
+use wgpu::*;
+
+use crate::{
+    serialize::{Config, ConfigFormat},
+    simulation::{PointType, Simulation},
+    util::VEC2_SIZE,
+};
+
+/// Two particles of one type, 20 apart along the x axis, attracting each other
+const CONFIG: &str = "---
+ruleset:
+  types:
+    - attractions: [0.5]
+      min_r: [10.0]
+      max_r: [50.0]
+  friction: 0.0
+walls:
+  type: square
+  dist: 100.0
+points:
+  - num: 1
+    x: 0.0
+    y: 0.0
+  - num: 1
+    x: 20.0
+    y: 0.0
+";
+/// How far each particle moves towards the other in the first step:
+/// `attraction * (1 - |2r - (max_r + min_r)| / (max_r - min_r))`
+const EXPECTED_PULL: f32 = 0.25;
+const TOLERANCE: f32 = 1e-4;
+
+fn check(name: &str, result: Result<(), String>, failures: &mut u32) {
+    match result {
+        Ok(()) => println!("ok      {}", name),
+        Err(e) => {
+            println!("FAILED  {}: {}", name, e);
+            *failures += 1;
+        }
+    }
+}
+
+fn check_size(name: &str, actual: u64, expected: u64) -> Result<(), String> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} buffer is {} bytes, expected {}",
+            name, actual, expected
+        ))
+    }
+}
+
+/// Checks config parsing, buffer layout, uploading and a single step of the compute shader
+/// end to end, returning whether everything passed
+pub fn run(device: &Device, queue: &Queue, workgroup_size: u32) -> bool {
+    let mut failures = 0;
+
+    let config = Config::from_reader(CONFIG.as_bytes(), ConfigFormat::Yaml, &[]);
+    let config = match config {
+        Ok(config) => {
+            check("parse config", Ok(()), &mut failures);
+            config
+        }
+        Err(e) => {
+            check("parse config", Err(e), &mut failures);
+            return false;
+        }
+    };
+    let mut simulation = match Simulation::from_config(device, config, workgroup_size) {
+        Ok(simulation) => {
+            check("build simulation", Ok(()), &mut failures);
+            simulation
+        }
+        Err(e) => {
+            check("build simulation", Err(e.0), &mut failures);
+            return false;
+        }
+    };
+
+    check(
+        "buffer layout",
+        check_size(
+            "positions",
+            simulation.positions().size,
+            2 * VEC2_SIZE as u64,
+        )
+        .and(check_size(
+            "velocities",
+            simulation.velocities.size,
+            2 * VEC2_SIZE as u64,
+        ))
+        .and(check_size(
+            "types",
+            simulation.types.size,
+            2 * std::mem::size_of::<PointType>() as u64,
+        )),
+        &mut failures,
+    );
+
+    let points = simulation.read_points(device, queue);
+    check(
+        "upload",
+        if points == [(0.0, 0.0, Some(0)), (20.0, 0.0, Some(0))] {
+            Ok(())
+        } else {
+            Err(format!("read back {:?}", points))
+        },
+        &mut failures,
+    );
+
+    simulation.step(device, queue);
+    let points = simulation.read_points(device, queue);
+    let expected = [(EXPECTED_PULL, 0.0), (20.0 - EXPECTED_PULL, 0.0)];
+    let close = points.len() == expected.len()
+        && points
+            .iter()
+            .zip(expected.iter())
+            .all(|(&(x, y, _), &(ex, ey))| {
+                (x - ex).abs() < TOLERANCE && (y - ey).abs() < TOLERANCE
+            });
+    check(
+        "simulation step",
+        if close {
+            Ok(())
+        } else {
+            Err(format!("expected {:?}, got {:?}", expected, points))
+        },
+        &mut failures,
+    );
+
+    if failures == 0 {
+        println!("All checks passed");
+    } else {
+        println!("{} checks failed", failures);
+    }
+    failures == 0
+}
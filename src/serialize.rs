@@ -1,47 +1,294 @@
-use rand::{thread_rng, Rng};
+use std::{
+    convert::TryInto,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use rand::{distributions::WeightedIndex, Rng};
 use rand_distr::{
-    num_traits::{NumCast, ToPrimitive},
-    Normal,
+    num_traits::{Bounded, NumCast, ToPrimitive},
+    Exp, LogNormal, Normal,
 };
 use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+use tracing::debug_span;
 
-use crate::simulation::{Ruleset, Walls};
+use crate::{
+    simulation::{Point, PointType, Ruleset, Walls},
+    util::with_rng,
+};
 
 #[derive(Deserialize)]
 pub struct Config {
     pub ruleset: RulesetConfig,
     pub walls: WallsConfig,
     pub points: PointsConfig,
+    /// Language of on-screen text, see [`crate::locale::Locale::new`]
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub display: DisplayConfig,
+    /// Random disturbances that keep long-running simulations varied
+    #[serde(default)]
+    pub events: Vec<EventConfig>,
+    /// Sources that keep adding particles
+    #[serde(default)]
+    pub faucets: Vec<FaucetConfig>,
+    /// Regions that remove particles entering them
+    #[serde(default)]
+    pub drains: Vec<DrainConfig>,
+    /// Sources that keep adding particles with random types and positions
+    #[serde(default)]
+    pub emitters: Vec<EmitterConfig>,
+    /// How long particles of some types live before they are removed
+    #[serde(default)]
+    pub lifetimes: Vec<LifetimeConfig>,
+    /// Most particles that can exist at once when faucets or the brush add more
+    #[serde(default)]
+    pub max_points: Option<u32>,
+    /// Regions that carry particles along at a constant velocity
+    #[serde(default)]
+    pub flows: Vec<FlowConfig>,
+    /// Fastest a particle may move per step; faster particles are slowed down to this speed
+    #[serde(default)]
+    pub max_velocity: Option<f32>,
+    /// How particles are moved by the forces on them
+    #[serde(default)]
+    pub integrator: Integrator,
+    /// How `ruleset.friction` slows particles down
+    #[serde(default)]
+    pub drag: Drag,
+    /// Parameters that change gradually while the simulation runs
+    #[serde(default)]
+    pub schedules: Vec<ScheduleConfig>,
+    /// Rules that turn particles of one type into another
+    #[serde(default)]
+    pub reactions: Vec<ReactionConfig>,
+    /// Springs between pairs of particles
+    #[serde(default)]
+    pub bonds: Vec<BondConfig>,
+    /// Regions where attraction and friction are scaled
+    #[serde(default)]
+    pub zones: Vec<ZoneConfig>,
+    /// Pull between every pair of particles, however far apart, on top of the ruleset
+    pub gravity: Option<GravityConfig>,
+    /// Knobs and sliders of a MIDI controller that change parameters in the window
+    pub midi: Option<MidiConfig>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+pub struct DisplayConfig {
+    /// Saturated colors with bold outlines on a black background, and larger overlay text
+    #[serde(default)]
+    pub high_contrast: bool,
+    /// Gentler zoom steps, slower keyboard panning, and a camera that glides after followed
+    /// particles instead of jumping
+    #[serde(default)]
+    pub reduced_motion: bool,
+    /// Start out coloring particles by speed rather than by type
+    #[serde(default)]
+    pub speed_colors: bool,
+    /// Speed at the top of the speed colormap
+    pub max_speed: Option<f32>,
+    /// How opaque particles are, so that overlapping ones show through each other; 1 if omitted
+    pub opacity: Option<f32>,
+    /// Draw particles from the most to the least crowded part of the screen, so that translucent
+    /// ones blend in the same order every frame
+    #[serde(default)]
+    pub depth_sort: bool,
+    /// Start out showing a density map instead of individual particles
+    #[serde(default)]
+    pub density: bool,
+    /// Start out showing the density map when there are more particles than this
+    pub density_threshold: Option<u32>,
+    /// Start out leaving trails behind moving particles
+    #[serde(default)]
+    pub trails: bool,
+    /// Fraction of the trails kept from one frame to the next
+    pub trail_fade: Option<f32>,
+    /// Trail fades and colors for particular types, overriding `trail_fade` and the type colors
+    #[serde(default)]
+    pub type_trails: Vec<TypeTrailConfig>,
+    /// How frames are handed to the screen; mailbox if omitted
+    #[serde(default)]
+    pub present_mode: Option<PresentModeChoice>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TypeTrailConfig {
+    #[serde(rename = "type")]
+    pub type_: PointType,
+    /// Fraction of this type's trails kept from one frame to the next, 0 for no trail
+    pub fade: Option<f32>,
+    /// Color of this type's trails, in place of the type color
+    pub color: Option<[f32; 3]>,
+}
+
+/// How finished frames are shown, and so whether rendering waits for the display
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum PresentModeChoice {
+    /// Shown straight away, which may tear
+    Immediate,
+    /// Queued and shown one per display refresh, i.e. vsync
+    Fifo,
+    /// Shown at the next display refresh, replacing any frame already waiting
+    Mailbox,
+}
+
+impl FromStr for PresentModeChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "immediate" => Ok(PresentModeChoice::Immediate),
+            "fifo" => Ok(PresentModeChoice::Fifo),
+            "mailbox" => Ok(PresentModeChoice::Mailbox),
+            _ => Err(format!(
+                "Unknown present mode `{}`, expected immediate, fifo or mailbox",
+                s
+            )),
+        }
+    }
+}
+
+/// How each step turns the forces on a particle into movement. The values are those of the
+/// `INTEGRATOR_*` constants in compute.wgsl.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Integrator {
+    /// Moves by the velocity from before the step, then adds the forces to it. Gains energy, so
+    /// it blows up with settings the others handle.
+    Euler = 0,
+    /// Adds the forces to the velocity, then moves by the new velocity
+    #[default]
+    SemiImplicit = 1,
+    /// Velocity Verlet: moves by the velocity plus half the forces, and adds the average of the
+    /// forces of consecutive steps to the velocity. The steadiest with stiff attractions.
+    Verlet = 2,
+}
+
+/// How moving particles are slowed down every step, with `ruleset.friction` as the coefficient.
+/// The values are those of the `DRAG_*` constants in compute.wgsl.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Drag {
+    /// Loses the fraction `friction` of the velocity
+    #[default]
+    Linear = 0,
+    /// Loses more the faster it goes: the velocity is divided by `1 + friction * speed`
+    Quadratic = 1,
+    /// Keeps its velocity; only `max_velocity` and walls slow particles down
+    None = 2,
+}
+
+#[derive(Clone, Copy)]
+pub enum ConfigFormat {
+    Yaml,
+    Json,
+    Toml,
 }
 
 #[derive(Deserialize, Clone)]
 #[serde(untagged)]
 pub enum Distribution<T> {
     Const(T),
-    Uniform { min: T, max: T },
-    Normal { mean: T, std: T },
+    // Uniform goes after the other map variants, since their clamp bounds are also named
+    // `min` and `max` and would otherwise be taken for a uniform distribution
+    Normal {
+        mean: T,
+        std: T,
+        #[serde(flatten)]
+        clamp: Clamp<T>,
+    },
+    Exponential {
+        lambda: f64,
+        #[serde(flatten)]
+        clamp: Clamp<T>,
+    },
+    LogNormal {
+        mu: f64,
+        sigma: f64,
+        #[serde(flatten)]
+        clamp: Clamp<T>,
+    },
+    /// One of `values`, picked with probability proportional to its weight (equal if omitted)
+    Choice {
+        values: Vec<T>,
+        #[serde(default)]
+        weights: Option<Vec<f64>>,
+        #[serde(flatten)]
+        clamp: Clamp<T>,
+    },
+    Uniform {
+        min: T,
+        max: T,
+        #[serde(default)]
+        round: Option<Rounding>,
+    },
 }
 
-#[derive(Deserialize)]
+/// Optional bounds that sampled values are clamped to, after rounding them if asked to
+#[derive(Deserialize, Clone)]
+pub struct Clamp<T> {
+    pub min: Option<T>,
+    pub max: Option<T>,
+    #[serde(default)]
+    pub round: Option<Rounding>,
+}
+
+/// How sampled values are turned into whole numbers. Integer fields without one are rounded down.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Rounding {
+    Floor,
+    Nearest,
+    Ceil,
+}
+
+#[derive(Deserialize, Clone)]
 #[serde(untagged)]
 pub enum RulesetConfig {
-    Procedural(RulesetGenerationConfig),
+    Procedural(Box<RulesetGenerationConfig>),
     Precise {
         types: Vec<TypeRuleset>,
         friction: Distribution<f32>,
     },
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct RulesetGenerationConfig {
     pub types: Distribution<u32>,
     pub attractions: Distribution<f32>,
     pub min_r: Distribution<f32>,
     pub max_r: Distribution<f32>,
     pub friction: Distribution<f32>,
+    #[serde(default)]
+    pub symmetry: Symmetry,
+    /// Leaves particles unaffected by others of their own type
+    #[serde(default)]
+    pub zero_diagonal: bool,
 }
 
-#[derive(Deserialize)]
+/// How the attraction of type `j` on type `i` is tied to that of `i` on `j` in sampled rulesets.
+/// Tied pairs also share their radii, so that they interact over the same range both ways.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Symmetry {
+    /// Every attraction is sampled on its own
+    #[default]
+    None,
+    /// Types attract each other equally, like in physics
+    Symmetric,
+    /// Whatever one type is pulled by, it pushes the other away with, so one chases the other; no
+    /// type affects itself
+    Antisymmetric,
+}
+
+#[derive(Deserialize, Clone)]
 pub struct TypeRuleset {
     pub attractions: Vec<Distribution<f32>>,
     pub min_r: Vec<Distribution<f32>>,
@@ -52,8 +299,19 @@ pub struct TypeRuleset {
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum WallsConfig {
     None,
-    Wrapping { dist: Distribution<f32> },
-    Square { dist: Distribution<f32> },
+    Wrapping {
+        dist: Distribution<f32>,
+    },
+    Square {
+        dist: Distribution<f32>,
+        /// Fraction of its speed a particle keeps when bouncing off a wall
+        #[serde(default = "default_restitution")]
+        restitution: f32,
+    },
+}
+
+fn default_restitution() -> f32 {
+    1.0
 }
 
 #[derive(Deserialize)]
@@ -61,6 +319,275 @@ pub enum WallsConfig {
 pub enum PointsConfig {
     Simple(Distribution<u32>),
     Complex(Vec<PointSpawnConfig>),
+    File {
+        path: PathBuf,
+        format: PointsFileFormat,
+        #[serde(default)]
+        typed: bool,
+        /// The particles in the file, read along with the config
+        #[serde(skip)]
+        points: Vec<Point>,
+    },
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum EventConfig {
+    Impulse(Box<ImpulseConfig>),
+    /// Flips the sign of every attraction for `duration` steps
+    Inversion {
+        /// Average number of steps between inversions
+        every: f32,
+        duration: u32,
+    },
+}
+
+/// Pushes particles near a random point outwards (or inwards, with negative strength)
+#[derive(Deserialize, Clone)]
+pub struct ImpulseConfig {
+    /// Average number of steps between impulses
+    pub every: f32,
+    pub strength: Distribution<f32>,
+    pub radius: Distribution<f32>,
+    /// Where impulses happen; anywhere inside the walls if omitted
+    pub x: Option<Distribution<f32>>,
+    pub y: Option<Distribution<f32>>,
+}
+
+/// Moves a parameter from `from` to `to` over `steps` steps, starting at step `start`. The
+/// parameter keeps its configured value before the start and stays at `to` after the end.
+#[derive(Deserialize, Clone)]
+pub struct ScheduleConfig {
+    pub parameter: ScheduledParameter,
+    pub from: f32,
+    pub to: f32,
+    pub steps: u64,
+    #[serde(default)]
+    pub start: u64,
+    #[serde(default)]
+    pub curve: ScheduleCurve,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduledParameter {
+    /// `ruleset.friction`
+    Friction,
+    /// `max_velocity`, where 0 is no limit
+    MaxVelocity,
+    /// `walls.restitution`
+    Restitution,
+    /// Multiplier on every attraction, 1 unless an inversion event is running
+    AttractionScale,
+}
+
+impl ScheduledParameter {
+    /// Values the parameter can take, and what to say about ones outside of them
+    pub fn range(self) -> (RangeInclusive<f32>, &'static str) {
+        match self {
+            ScheduledParameter::Friction | ScheduledParameter::Restitution => {
+                (0.0..=1.0, "must be between 0 and 1")
+            }
+            ScheduledParameter::MaxVelocity => (0.0..=f32::MAX, "must not be negative"),
+            ScheduledParameter::AttractionScale => (f32::MIN..=f32::MAX, "must be a number"),
+        }
+    }
+}
+
+/// How a scheduled parameter gets from one value to the other
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleCurve {
+    /// Changes by the same amount every step
+    #[default]
+    Linear,
+    /// Changes by the same factor every step, for values spanning orders of magnitude
+    Exponential,
+    /// Changes slowly at either end and fastest halfway through
+    Cosine,
+}
+
+/// `catalyst + reactant -> catalyst + product`: a particle of type `reactant` with a particle of
+/// type `catalyst` within `radius` turns into type `product` with chance `probability` each step
+#[derive(Deserialize, Clone)]
+pub struct ReactionConfig {
+    pub catalyst: PointType,
+    pub reactant: PointType,
+    pub product: PointType,
+    pub radius: f32,
+    pub probability: f32,
+}
+
+/// A spring between particles `a` and `b`, numbered in the order the points are given or sampled
+#[derive(Deserialize, Clone)]
+pub struct BondConfig {
+    pub a: u32,
+    pub b: u32,
+    /// Distance the spring pulls or pushes the particles toward
+    pub length: f32,
+    /// Velocity added per step for every unit of distance away from `length`
+    pub stiffness: f32,
+}
+
+/// Newtonian gravity, pulling every particle toward every other one by the other's mass over the
+/// square of their distance
+#[derive(Deserialize, Clone)]
+pub struct GravityConfig {
+    /// Pull of a particle of mass 1 at a distance of 1
+    pub strength: f32,
+    /// Added to distances so that close encounters do not fling particles apart
+    #[serde(default = "default_softening")]
+    pub softening: f32,
+    /// Mass of every type, in order, with 1 for types not listed
+    #[serde(default)]
+    pub masses: Vec<f32>,
+}
+
+fn default_softening() -> f32 {
+    1.0
+}
+
+/// A MIDI input whose control changes set parameters
+#[derive(Deserialize, Clone)]
+pub struct MidiConfig {
+    /// Part of the name of the input to listen on; the first input if omitted
+    #[serde(default)]
+    pub port: Option<String>,
+    /// Channel from 1 to 16 to listen on; every channel if omitted
+    #[serde(default)]
+    pub channel: Option<u8>,
+    pub controls: Vec<MidiControlConfig>,
+}
+
+/// A control change number whose values, from 0 to 127, are mapped onto `min..=max` of a
+/// parameter
+#[derive(Deserialize, Clone)]
+pub struct MidiControlConfig {
+    pub cc: u8,
+    pub parameter: MidiParameter,
+    pub min: f32,
+    pub max: f32,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum MidiParameter {
+    Friction,
+    MaxVelocity,
+    Restitution,
+    AttractionScale,
+    /// Steps simulated every frame, which is rounded to a whole number
+    TicksPerFrame,
+}
+
+impl MidiParameter {
+    /// The simulation parameter this sets, or `None` for ones of the visualization
+    pub fn scheduled(self) -> Option<ScheduledParameter> {
+        match self {
+            MidiParameter::Friction => Some(ScheduledParameter::Friction),
+            MidiParameter::MaxVelocity => Some(ScheduledParameter::MaxVelocity),
+            MidiParameter::Restitution => Some(ScheduledParameter::Restitution),
+            MidiParameter::AttractionScale => Some(ScheduledParameter::AttractionScale),
+            MidiParameter::TicksPerFrame => None,
+        }
+    }
+}
+
+/// A region whose particles feel their attractions and friction multiplied. Where zones overlap,
+/// their multipliers are multiplied together.
+#[derive(Deserialize, Clone)]
+pub struct ZoneConfig {
+    #[serde(flatten)]
+    pub shape: ZoneShape,
+    #[serde(default = "default_multiplier")]
+    pub attraction: f32,
+    #[serde(default = "default_multiplier")]
+    pub friction: f32,
+}
+
+fn default_multiplier() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(untagged)]
+pub enum ZoneShape {
+    Circle { center: [f32; 2], radius: f32 },
+    Rectangle(Region),
+}
+
+impl ZoneShape {
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        match self {
+            ZoneShape::Circle { center, radius } => {
+                let (dx, dy) = (x - center[0], y - center[1]);
+                (dx * dx + dy * dy).sqrt() <= *radius
+            }
+            ZoneShape::Rectangle(region) => region.contains(x, y),
+        }
+    }
+}
+
+/// An axis-aligned rectangle given by the ranges of x and y it covers
+#[derive(Deserialize, Clone, Copy)]
+pub struct Region {
+    pub x: [f32; 2],
+    pub y: [f32; 2],
+}
+
+#[derive(Deserialize, Clone)]
+pub struct FaucetConfig {
+    #[serde(rename = "type")]
+    pub type_: PointType,
+    /// Particles added per second, where a second is 60 steps
+    pub per_second: f32,
+    #[serde(flatten)]
+    pub region: Region,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct FlowConfig {
+    /// Distance particles inside the region drift per step
+    pub velocity: [f32; 2],
+    #[serde(flatten)]
+    pub region: Region,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct EmitterConfig {
+    /// Particles added per second, where a second is 60 steps
+    pub per_second: f32,
+    /// Type of each particle added; a random one if omitted
+    #[serde(rename = "type")]
+    pub type_: Option<Distribution<PointType>>,
+    pub x: Distribution<f32>,
+    pub y: Distribution<f32>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct LifetimeConfig {
+    #[serde(rename = "type")]
+    pub type_: PointType,
+    /// Steps particles of this type live for
+    pub steps: u64,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct DrainConfig {
+    /// Only remove particles of this type; all types if omitted
+    #[serde(rename = "type")]
+    pub type_: Option<PointType>,
+    #[serde(flatten)]
+    pub region: Region,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum PointsFileFormat {
+    /// One `x,y` or `x,y,type` row per line
+    Csv,
+    /// Little-endian `f32` x and y, followed by a `u32` type if `typed` is set
+    Binary,
 }
 
 #[derive(Deserialize)]
@@ -70,8 +597,197 @@ pub struct PointSpawnConfig {
     pub y: Distribution<f32>,
 }
 
+impl ConfigFormat {
+    /// Guesses the format from a file extension, defaulting to YAML
+    pub fn from_path(path: &Path) -> Self {
+        Self::from_extension(path).unwrap_or(ConfigFormat::Yaml)
+    }
+
+    /// The format of a file by its extension, in any case, or `None` if it is not a config file
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase)
+            .as_deref()
+        {
+            Some("yml") | Some("yaml") => Some(ConfigFormat::Yaml),
+            Some("json") => Some(ConfigFormat::Json),
+            Some("toml") => Some(ConfigFormat::Toml),
+            _ => None,
+        }
+    }
+
+    /// Parses `text` into a generic document without interpreting it as a config
+    pub fn parse_value(self, text: &str) -> Result<Value, String> {
+        match self {
+            ConfigFormat::Yaml => serde_yaml::from_str(text).map_err(|e| e.to_string()),
+            ConfigFormat::Json => serde_json::from_str(text).map_err(|e| e.to_string()),
+            ConfigFormat::Toml => toml::from_str(text).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            "json" => Ok(ConfigFormat::Json),
+            "toml" => Ok(ConfigFormat::Toml),
+            _ => Err(format!("Unknown config format `{}`", s)),
+        }
+    }
+}
+
+/// A `key.path=value` replacement applied to a config before it is interpreted
+pub struct ConfigOverride {
+    path: Vec<String>,
+    value: Value,
+}
+
+impl FromStr for ConfigOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Override `{}` is not of the form key=value", s))?;
+        let path = path
+            .trim()
+            .split('.')
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        if path.iter().any(String::is_empty) {
+            return Err(format!("Override `{}` has an empty key", s));
+        }
+        // Parse values as YAML so that numbers, booleans and whole distributions can be given
+        let value = serde_yaml::from_str(value)
+            .map_err(|e| format!("Invalid value in override `{}`: {}", s, e))?;
+        Ok(Self { path, value })
+    }
+}
+
+impl ConfigOverride {
+    fn apply(&self, mut target: &mut Value) -> Result<(), String> {
+        for key in &self.path {
+            // Overriding a field of a scalar replaces the scalar with a map
+            if !matches!(target, Value::Sequence(_) | Value::Mapping(_)) {
+                *target = Value::Mapping(Mapping::new());
+            }
+            target = match target {
+                Value::Sequence(seq) => {
+                    let index: usize = key.parse().map_err(|_| {
+                        format!(
+                            "`{}` is a list; expected an index, not `{}`",
+                            self.path.join("."),
+                            key
+                        )
+                    })?;
+                    let len = seq.len();
+                    seq.get_mut(index).ok_or_else(|| {
+                        format!(
+                            "Index {} of `{}` is out of range (length {})",
+                            index,
+                            self.path.join("."),
+                            len
+                        )
+                    })?
+                }
+                Value::Mapping(map) => map.entry(Value::String(key.clone())).or_insert(Value::Null),
+                _ => unreachable!(),
+            };
+        }
+        *target = self.value.clone();
+        Ok(())
+    }
+}
+
+/// Formats a deserialization error with the path of the field it occurred in
+fn describe_error<E: std::fmt::Display>(error: serde_path_to_error::Error<E>) -> String {
+    let path = error.path().to_string();
+    let message = error.inner().to_string();
+    // Some deserializers already mention the path themselves
+    if message.starts_with(&path) {
+        message
+    } else {
+        format!("{}: {}", path, message)
+    }
+}
+
+/// Panics with the reason `config`, named by `name`, failed to load, for testing configs that ship
+/// with plife or that it writes
+#[cfg(test)]
+pub(crate) fn assert_valid_config(name: &str, config: Result<Config, String>) {
+    if let Err(e) = config {
+        panic!("{} is invalid:\n{}", name, e);
+    }
+}
+
 impl Config {
-    pub fn sample(self) -> (Ruleset, Walls, Vec<(f32, f32)>) {
+    pub fn from_reader(
+        mut reader: impl Read,
+        format: ConfigFormat,
+        overrides: &[ConfigOverride],
+    ) -> Result<Self, String> {
+        let mut text = String::new();
+        reader
+            .read_to_string(&mut text)
+            .map_err(|e| e.to_string())?;
+        let mut config: Self = if overrides.is_empty() {
+            // Deserialize straight from the text so that errors can point at a line
+            match format {
+                ConfigFormat::Yaml => {
+                    serde_path_to_error::deserialize(serde_yaml::Deserializer::from_str(&text))
+                        .map_err(describe_error)?
+                }
+                ConfigFormat::Json => {
+                    serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_str(&text))
+                        .map_err(describe_error)?
+                }
+                ConfigFormat::Toml => {
+                    serde_path_to_error::deserialize(&mut toml::Deserializer::new(&text))
+                        .map_err(describe_error)?
+                }
+            }
+        } else {
+            return Self::from_value(Self::read_document(text.as_bytes(), format, overrides)?);
+        };
+        // Loaded first, so that the types of particles read from a file are checked too
+        config.points.load()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reads a config document with `overrides` applied, without interpreting it
+    pub fn read_document(
+        mut reader: impl Read,
+        format: ConfigFormat,
+        overrides: &[ConfigOverride],
+    ) -> Result<Value, String> {
+        let mut text = String::new();
+        reader
+            .read_to_string(&mut text)
+            .map_err(|e| e.to_string())?;
+        let mut value = format.parse_value(&text)?;
+        for config_override in overrides {
+            config_override.apply(&mut value)?;
+        }
+        Ok(value)
+    }
+
+    /// Reads a config from an already parsed document, e.g. one that has been edited
+    pub fn from_value(value: Value) -> Result<Self, String> {
+        let mut config: Self = serde_path_to_error::deserialize(value).map_err(describe_error)?;
+        // Loaded first, so that the types of particles read from a file are checked too
+        config.points.load()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn sample(self) -> (Ruleset, Walls, Vec<Point>) {
+        let _span = debug_span!("sample_config").entered();
         let ruleset = self.ruleset.sample();
         let walls = self.walls.sample();
         let points = self.points.sample(&walls);
@@ -95,7 +811,7 @@ macro_rules! typeruleset_map {
 }
 
 impl RulesetConfig {
-    fn sample(self) -> Ruleset {
+    pub fn sample(self) -> Ruleset {
         match self {
             RulesetConfig::Procedural(gen_rules) => gen_rules.sample(),
             RulesetConfig::Precise { types, friction } => Ruleset {
@@ -124,11 +840,34 @@ impl RulesetGenerationConfig {
         }
 
         let num_point_types = self.types.sample();
+        let mut min_r = sample_per_pair(num_point_types, self.min_r);
+        let mut max_r = sample_per_pair(num_point_types, self.max_r);
+        let mut attractions = sample_per_pair(num_point_types, self.attractions);
+        // The entries above the diagonal decide those below it
+        let sign = match self.symmetry {
+            Symmetry::None => None,
+            Symmetry::Symmetric => Some(1.0),
+            Symmetry::Antisymmetric => Some(-1.0),
+        };
+        if let Some(sign) = sign {
+            for i in 0..num_point_types as usize {
+                for j in 0..i {
+                    attractions[i][j] = sign * attractions[j][i];
+                    min_r[i][j] = min_r[j][i];
+                    max_r[i][j] = max_r[j][i];
+                }
+            }
+        }
+        if self.zero_diagonal || self.symmetry == Symmetry::Antisymmetric {
+            for (i, row) in attractions.iter_mut().enumerate() {
+                row[i] = 0.0;
+            }
+        }
         Ruleset {
             num_point_types,
-            min_r: sample_per_pair(num_point_types, self.min_r),
-            max_r: sample_per_pair(num_point_types, self.max_r),
-            attractions: sample_per_pair(num_point_types, self.attractions),
+            min_r,
+            max_r,
+            attractions,
             friction: self.friction.sample(),
         }
     }
@@ -139,31 +878,65 @@ impl WallsConfig {
         match self {
             WallsConfig::None => Walls::None,
             WallsConfig::Wrapping { dist } => Walls::Wrapping(dist.sample()),
-            WallsConfig::Square { dist } => Walls::Square(dist.sample()),
+            WallsConfig::Square { dist, .. } => Walls::Square(dist.sample()),
+        }
+    }
+
+    pub fn restitution(&self) -> f32 {
+        match self {
+            WallsConfig::Square { restitution, .. } => *restitution,
+            _ => default_restitution(),
         }
     }
 }
 
+impl Region {
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x[0] && x <= self.x[1] && y >= self.y[0] && y <= self.y[1]
+    }
+
+    /// A uniformly random point inside the region
+    pub fn sample(&self) -> (f32, f32) {
+        with_rng(|rng| {
+            (
+                self.x[0] + rng.gen::<f32>() * (self.x[1] - self.x[0]),
+                self.y[0] + rng.gen::<f32>() * (self.y[1] - self.y[0]),
+            )
+        })
+    }
+}
+
+/// Distribution of a coordinate placed anywhere inside `walls`
+pub fn position_distribution(walls: &Walls) -> Distribution<f32> {
+    match walls {
+        Walls::None => Distribution::Normal {
+            mean: 0.0,
+            std: 5.0,
+            clamp: Clamp {
+                min: None,
+                max: None,
+                round: None,
+            },
+        },
+        Walls::Square(dist) | Walls::Wrapping(dist) => Distribution::Uniform {
+            min: -dist,
+            max: *dist,
+            round: None,
+        },
+    }
+}
+
 impl PointsConfig {
-    fn sample(self, walls: &Walls) -> Vec<(f32, f32)> {
+    pub fn sample(self, walls: &Walls) -> Vec<Point> {
         match self {
             PointsConfig::Simple(dist) => {
-                let distribution = match walls {
-                    Walls::None => Distribution::Normal {
-                        mean: 0.0,
-                        std: 5.0,
-                    },
-                    Walls::Square(dist) | Walls::Wrapping(dist) => Distribution::Uniform {
-                        min: -dist,
-                        max: *dist,
-                    },
-                };
+                let distribution = position_distribution(walls);
                 let num_points = dist.sample();
                 let mut vec = Vec::with_capacity(num_points as usize);
                 for _ in 0..num_points {
                     let x = distribution.clone().sample();
                     let y = distribution.clone().sample();
-                    vec.push((x, y));
+                    vec.push((x, y, None));
                 }
                 vec
             }
@@ -175,34 +948,225 @@ impl PointsConfig {
                     for _ in 0..num {
                         let x = spawn.x.clone().sample();
                         let y = spawn.y.clone().sample();
-                        vec.push((x, y));
+                        vec.push((x, y, None));
                     }
                     vec
                 })
                 .flatten()
-                .collect::<Vec<(f32, f32)>>(),
+                .collect::<Vec<Point>>(),
+            PointsConfig::File { points, .. } => points,
+        }
+    }
+
+    /// Reads the particles of a points file, so that problems with it are reported along with the
+    /// rest of the config rather than once the simulation starts
+    fn load(&mut self) -> Result<(), String> {
+        if let PointsConfig::File {
+            path,
+            format,
+            typed,
+            points,
+        } = self
+        {
+            *points = match format {
+                PointsFileFormat::Csv => read_csv_points(path)?,
+                PointsFileFormat::Binary => read_binary_points(path, *typed)?,
+            };
+        }
+        Ok(())
+    }
+}
+
+fn read_csv_points(path: &Path) -> Result<Vec<Point>, String> {
+    let cannot_read = |e: io::Error| format!("Cannot read points file {}: {}", path.display(), e);
+    let file = File::open(path).map_err(cannot_read)?;
+    let mut vec = Vec::new();
+    let mut first_row = true;
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(cannot_read)?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+        let parsed = match fields.as_slice() {
+            [x, y] => x
+                .parse()
+                .ok()
+                .zip(y.parse().ok())
+                .map(|(x, y)| (x, y, None)),
+            [x, y, type_] => x
+                .parse()
+                .ok()
+                .zip(y.parse().ok())
+                .zip(type_.parse().ok())
+                .map(|((x, y), type_)| (x, y, Some(type_))),
+            _ => None,
+        };
+        let header_allowed = std::mem::replace(&mut first_row, false);
+        match parsed {
+            Some(point) => vec.push(point),
+            // Allow a header row, after any blank lines and comments
+            None if header_allowed => {}
+            None => {
+                return Err(format!(
+                    "Invalid row on line {} of {}: expected x,y or x,y,type",
+                    i + 1,
+                    path.display()
+                ))
+            }
+        }
+    }
+    Ok(vec)
+}
+
+/// Writes points as `x,y,type` rows that can be loaded again as a CSV points file
+pub fn write_csv_points(path: &Path, points: &[Point]) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "x,y,type")?;
+    for (x, y, type_) in points {
+        match type_ {
+            Some(type_) => writeln!(out, "{},{},{}", x, y, type_)?,
+            None => writeln!(out, "{},{}", x, y)?,
         }
     }
+    out.flush()
+}
+
+/// Writes a config document as YAML to `out`, or prints it if there is none
+pub fn write_yaml(value: &Value, out: Option<&Path>) -> io::Result<()> {
+    let text = serde_yaml::to_string(value).expect("Cannot serialize config");
+    match out {
+        Some(path) => fs::write(path, text),
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
+
+/// Writes every particle as an `id,type,x,y,vx,vy` row, where the id is its index and the type is
+/// left empty for particles without one
+pub fn write_csv_particles(
+    path: &Path,
+    points: &[Point],
+    velocities: &[[f32; 2]],
+) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "id,type,x,y,vx,vy")?;
+    for (id, (&(x, y, type_), &[vx, vy])) in points.iter().zip(velocities).enumerate() {
+        let type_ = type_.map_or_else(String::new, |type_| type_.to_string());
+        writeln!(out, "{},{},{},{},{},{}", id, type_, x, y, vx, vy)?;
+    }
+    out.flush()
+}
+
+fn read_binary_points(path: &Path, typed: bool) -> Result<Vec<Point>, String> {
+    let bytes =
+        fs::read(path).map_err(|e| format!("Cannot read points file {}: {}", path.display(), e))?;
+    let row_size = if typed { 12 } else { 8 };
+    if !bytes.chunks_exact(row_size).remainder().is_empty() {
+        return Err(format!(
+            "Size of {} is not a multiple of the {} byte row size",
+            path.display(),
+            row_size
+        ));
+    }
+    Ok(bytes
+        .chunks_exact(row_size)
+        .map(|row| {
+            let x = f32::from_le_bytes(row[0..4].try_into().unwrap());
+            let y = f32::from_le_bytes(row[4..8].try_into().unwrap());
+            let type_ = if typed {
+                Some(PointType::from_le_bytes(row[8..12].try_into().unwrap()))
+            } else {
+                None
+            };
+            (x, y, type_)
+        })
+        .collect())
 }
 
 impl<T> Distribution<T>
 where
-    T: ToPrimitive + NumCast,
+    T: ToPrimitive + NumCast + Bounded,
 {
-    fn sample(self) -> T {
-        match self {
-            Distribution::Const(t) => t,
-            Distribution::Uniform { min, max } => {
+    pub fn sample(self) -> T {
+        let (value, clamp) = match self {
+            Distribution::Const(t) => return t,
+            Distribution::Uniform { min, max, round } => {
                 let min: f64 = NumCast::from(min).unwrap();
                 let max: f64 = NumCast::from(max).unwrap();
-                NumCast::from(thread_rng().gen_range(min..max)).unwrap()
+                let value = with_rng(|rng| rng.gen_range(min..max));
+                return saturating_cast(round.map_or(value, |round| round.apply(value)));
             }
-            Distribution::Normal { mean, std } => {
+            Distribution::Normal { mean, std, clamp } => {
                 let mean: f64 = NumCast::from(mean).unwrap();
                 let std: f64 = NumCast::from(std).unwrap();
                 let normal = Normal::new(mean, std).unwrap();
-                NumCast::from(thread_rng().sample(normal)).unwrap()
+                (with_rng(|rng| rng.sample(normal)), clamp)
+            }
+            Distribution::Exponential { lambda, clamp } => {
+                let exp = Exp::new(lambda).unwrap();
+                (with_rng(|rng| rng.sample(exp)), clamp)
             }
+            Distribution::LogNormal { mu, sigma, clamp } => {
+                let log_normal = LogNormal::new(mu, sigma).unwrap();
+                (with_rng(|rng| rng.sample(log_normal)), clamp)
+            }
+            Distribution::Choice {
+                values,
+                weights,
+                clamp,
+            } => {
+                let index = match weights {
+                    Some(weights) => {
+                        let weights = WeightedIndex::new(weights).unwrap();
+                        with_rng(|rng| rng.sample(weights))
+                    }
+                    None => with_rng(|rng| rng.gen_range(0..values.len())),
+                };
+                let value = values.into_iter().nth(index).unwrap();
+                (NumCast::from(value).unwrap(), clamp)
+            }
+        };
+        saturating_cast(clamp.apply(value))
+    }
+}
+
+/// Converts a sampled value to `T`, going to the nearest end of its range if it is out of range,
+/// e.g. to 0 for a negative value sampled for an unsigned integer
+fn saturating_cast<T: NumCast + Bounded>(value: f64) -> T {
+    NumCast::from(value).unwrap_or_else(|| {
+        if value < 0.0 {
+            T::min_value()
+        } else {
+            T::max_value()
+        }
+    })
+}
+
+impl Rounding {
+    pub fn apply(self, value: f64) -> f64 {
+        match self {
+            Rounding::Floor => value.floor(),
+            Rounding::Nearest => value.round(),
+            Rounding::Ceil => value.ceil(),
+        }
+    }
+}
+
+impl<T: ToPrimitive> Clamp<T> {
+    fn apply(&self, mut value: f64) -> f64 {
+        if let Some(round) = self.round {
+            value = round.apply(value);
+        }
+        if let Some(min) = self.min.as_ref().and_then(ToPrimitive::to_f64) {
+            value = value.max(min);
+        }
+        if let Some(max) = self.max.as_ref().and_then(ToPrimitive::to_f64) {
+            value = value.min(max);
         }
+        value
     }
 }
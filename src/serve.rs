@@ -0,0 +1,117 @@
+use std::{
+    io,
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use tracing::{info, warn};
+use tungstenite::Message;
+use wgpu::{Device, Queue};
+
+use crate::simulation::Simulation;
+
+/// Frames a client can fall behind by before it misses some
+const CLIENT_BACKLOG: usize = 2;
+
+/// Streams the particles to every WebSocket client connected to an address, as binary frames of
+/// the tick (u64), the number of particles (u32), every particle's position (two f32s) and then
+/// every particle's type (u32), all little endian
+pub struct Server {
+    interval: u64,
+    last_ticks: Option<u64>,
+    /// One channel per client, each drained by a thread of its own so that a slow client cannot
+    /// hold up the simulation
+    clients: Arc<Mutex<Vec<SyncSender<Arc<Vec<u8>>>>>>,
+}
+
+impl Server {
+    /// Starts accepting clients on `address` in the background
+    pub fn start(address: SocketAddr, interval: u64) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let accepted = clients.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let (sender, receiver) = mpsc::sync_channel(CLIENT_BACKLOG);
+                        accepted.lock().unwrap().push(sender);
+                        thread::spawn(move || serve_client(stream, receiver));
+                    }
+                    Err(e) => warn!("Cannot accept a streaming client: {}", e),
+                }
+            }
+        });
+        Ok(Self {
+            interval: interval.max(1),
+            last_ticks: None,
+            clients,
+        })
+    }
+
+    /// Ticks from `ticks` until the next frame is due
+    pub fn due_in(&self, ticks: u64) -> u64 {
+        self.last_ticks
+            .map_or(0, |last| (last + self.interval).saturating_sub(ticks))
+    }
+
+    /// Sends a frame to every client if `interval` ticks went by since the last one. Nothing is
+    /// read back from the GPU while no one is connected.
+    pub fn send(&mut self, device: &Device, queue: &Queue, simulation: &Simulation, ticks: u64) {
+        if self.due_in(ticks) > 0 {
+            return;
+        }
+        self.last_ticks = Some(ticks);
+        let mut clients = self.clients.lock().unwrap();
+        if clients.is_empty() {
+            return;
+        }
+        let points = simulation.read_points(device, queue);
+        let mut frame = Vec::with_capacity(12 + points.len() * 12);
+        frame.extend_from_slice(&ticks.to_le_bytes());
+        frame.extend_from_slice(&(points.len() as u32).to_le_bytes());
+        for &(x, y, _) in &points {
+            frame.extend_from_slice(&x.to_le_bytes());
+            frame.extend_from_slice(&y.to_le_bytes());
+        }
+        for &(_, _, type_) in &points {
+            frame.extend_from_slice(&type_.unwrap_or(0).to_le_bytes());
+        }
+        let frame = Arc::new(frame);
+        // Clients that are behind miss this frame; ones that are gone are forgotten
+        clients.retain(|client| {
+            !matches!(
+                client.try_send(frame.clone()),
+                Err(TrySendError::Disconnected(_))
+            )
+        });
+    }
+}
+
+/// Completes the WebSocket handshake with a client and sends it frames until it goes away
+fn serve_client(stream: TcpStream, frames: Receiver<Arc<Vec<u8>>>) {
+    let peer = stream
+        .peer_addr()
+        .map_or_else(|_| "a client".to_owned(), |peer| peer.to_string());
+    let mut socket = match tungstenite::accept(stream) {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!("WebSocket handshake with {} failed: {}", peer, e);
+            return;
+        }
+    };
+    info!("Streaming particles to {}", peer);
+    for frame in frames {
+        if socket
+            .write_message(Message::Binary(frame.as_ref().clone()))
+            .is_err()
+        {
+            break;
+        }
+    }
+    info!("Stopped streaming particles to {}", peer);
+}
@@ -0,0 +1,136 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+use wgpu::*;
+
+use crate::{
+    evolve::with_sampled,
+    serialize::Config,
+    simulation::{PointType, Simulation},
+};
+
+/// Everything needed to reopen an interactive exploration where it was left
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    /// The config document, with its ruleset, walls and number of points replaced by the ones
+    /// that were sampled from it
+    pub config: Value,
+    /// `[x, y, vx, vy, type]` of every particle
+    pub particles: Vec<(f32, f32, f32, f32, PointType)>,
+    /// Color of every type
+    pub colors: Vec<[f32; 3]>,
+    pub view: ViewState,
+}
+
+/// Camera and display settings of the visualization
+#[derive(Serialize, Deserialize)]
+pub struct ViewState {
+    pub x: f32,
+    pub y: f32,
+    pub zoom: f32,
+    pub ticks: u64,
+    pub ticks_per_frame: u16,
+    pub paused: bool,
+    pub speed_colors: bool,
+    pub show_density: bool,
+    pub show_territory: bool,
+    pub show_trails: bool,
+    pub show_hud: bool,
+    pub show_clusters: bool,
+    pub show_energy: bool,
+    #[serde(default)]
+    pub show_matrix: bool,
+    /// Type pairs whose interaction was switched off
+    pub disabled_pairs: Vec<(PointType, PointType)>,
+    pub restitution: f32,
+}
+
+/// Where F5 saves the session, along with the config document the simulation came from
+pub struct SessionTarget {
+    pub path: PathBuf,
+    pub config: Value,
+}
+
+impl Session {
+    /// Captures the simulation as a config that rebuilds it exactly, along with its particles
+    pub fn capture(
+        device: &Device,
+        queue: &Queue,
+        simulation: &Simulation,
+        config: &Value,
+        colors: Vec<[f32; 3]>,
+        view: ViewState,
+    ) -> Self {
+        let config = with_sampled(
+            config,
+            &simulation.ruleset,
+            &simulation.walls,
+            Some(simulation.num_points),
+        );
+        let particles = simulation
+            .read_points(device, queue)
+            .into_iter()
+            .zip(simulation.read_velocities(device, queue))
+            .map(|((x, y, type_), [vx, vy])| (x, y, vx, vy, type_.unwrap_or(0)))
+            .collect();
+        Self {
+            config,
+            particles,
+            colors,
+            view,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        serde_json::to_writer(BufWriter::new(file), self).map_err(|e| e.to_string())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        serde_json::from_reader(BufReader::new(file)).map_err(|e| e.to_string())
+    }
+
+    /// Rebuilds the simulation with every particle where it was
+    pub fn simulation(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        workgroup_size: u32,
+    ) -> Result<Simulation, String> {
+        let config = Config::from_value(self.config.clone())?;
+        let simulation =
+            Simulation::from_config(device, config, workgroup_size).map_err(|e| e.0)?;
+        if simulation.num_points as usize != self.particles.len() {
+            return Err(format!(
+                "the config makes {} particles, but {} were saved",
+                simulation.num_points,
+                self.particles.len()
+            ));
+        }
+        let num_types = simulation.ruleset.num_point_types;
+        if let Some(&(_, _, _, _, type_)) = self.particles.iter().find(|p| p.4 >= num_types) {
+            return Err(format!(
+                "a particle has type {}, but there are only {} types",
+                type_, num_types
+            ));
+        }
+        let points = self
+            .particles
+            .iter()
+            .map(|&(x, y, _, _, type_)| (x, y, Some(type_)))
+            .collect::<Vec<_>>();
+        let velocities = self
+            .particles
+            .iter()
+            .map(|&(_, _, vx, vy, _)| [vx, vy])
+            .collect::<Vec<_>>();
+        simulation.restore(queue, &points, &velocities);
+        Ok(simulation)
+    }
+}
@@ -1,20 +1,50 @@
 use std::{
+    collections::HashSet,
+    convert::TryInto,
     io::{Cursor, Write},
     mem::size_of,
 };
 
-use rand::{thread_rng, Rng};
+use rand::Rng;
+use tracing::{trace_span, warn};
 use wgpu::*;
 
-use crate::{serialize::*, util::*};
+use crate::{
+    bonds::Bonds,
+    cpu::CpuSimulation,
+    error::ConfigError,
+    events::Events,
+    faucets::Faucets,
+    gpu_profile::{GpuProfiler, GpuTimings},
+    reactions::Reactions,
+    schedules::Schedules,
+    serialize::*,
+    util::*,
+};
 
 pub type Radius = f32;
 pub type Attraction = f32;
 pub type Friction = f32;
 pub type PointType = u32;
+/// Initial x and y position of a point, and its type if it should not be chosen randomly
+pub type Point = (f32, f32, Option<PointType>);
 
-const WORKGROUP_SIZE: u32 = 256;
+/// Invocations per workgroup of the compute shader, unless the adapter supports fewer
+pub const DEFAULT_WORKGROUP_SIZE: u32 = 256;
+/// Storage buffers the compute shader binds, which every device must be requested to allow
+pub const STORAGE_BUFFERS_PER_STAGE: u32 = 9;
+/// Size of the flow region array in compute.wgsl
+pub const MAX_FLOWS: usize = 16;
+/// Size of the zone array in compute.wgsl
+pub const MAX_ZONES: usize = 16;
+/// Bytes per flow region: min and max corners, then the velocity padded to a vec4
+const FLOW_SIZE: usize = VEC2_SIZE * 4;
+/// Room for particles added by faucets or the brush when `max_points` is not given
+const DEFAULT_ROOM: u32 = 16384;
+/// Submissions the CPU may get ahead of the GPU by before waiting for it to catch up
+const MAX_SUBMITS_IN_FLIGHT: u32 = 4;
 
+#[derive(Clone)]
 pub struct Ruleset {
     pub num_point_types: PointType,
     pub min_r: Vec<Vec<Radius>>,
@@ -29,56 +59,279 @@ pub enum Walls {
     Wrapping(f32),
 }
 
+/// Values of the `Globals` uniform in compute.wgsl
+pub struct Globals {
+    pub num_points: u32,
+    pub num_types: PointType,
+    pub friction: Friction,
+    pub wrapping: bool,
+    pub dist: f32,
+    /// Multiplies every attraction, set to -1 during an inversion event
+    pub attraction_scale: f32,
+    pub impulse_x: f32,
+    pub impulse_y: f32,
+    /// Velocity added at the impulse center this step, or 0 for no impulse
+    pub impulse_strength: f32,
+    pub impulse_radius: f32,
+    pub num_flows: u32,
+    /// Fraction of speed kept when bouncing off square walls
+    pub restitution: f32,
+    pub cursor_x: f32,
+    pub cursor_y: f32,
+    /// Velocity added toward the cursor at its center every step, negative to push particles
+    /// away, or 0 while the force tool is not in use
+    pub cursor_strength: f32,
+    pub cursor_radius: f32,
+    /// Speed particles are slowed down to if they go faster, or 0 for no limit
+    pub max_velocity: f32,
+    pub integrator: Integrator,
+    pub drag: Drag,
+    pub num_zones: u32,
+    /// Pull of a particle of mass 1 at a distance of 1, or 0 without gravity
+    pub gravity: f32,
+    pub softening: f32,
+}
+
+impl Globals {
+    const SIZE: usize = size_of::<u32>() * 8 + size_of::<f32>() * 14;
+
+    /// The value of a parameter that schedules and remote control can change
+    pub fn parameter_mut(&mut self, parameter: ScheduledParameter) -> &mut f32 {
+        match parameter {
+            ScheduledParameter::Friction => &mut self.friction,
+            ScheduledParameter::MaxVelocity => &mut self.max_velocity,
+            ScheduledParameter::Restitution => &mut self.restitution,
+            ScheduledParameter::AttractionScale => &mut self.attraction_scale,
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut cursor = Cursor::new(Vec::with_capacity(Self::SIZE));
+        cursor.write_all(&self.num_points.to_le_bytes()).unwrap();
+        cursor.write_all(&self.num_types.to_le_bytes()).unwrap();
+        cursor.write_all(&self.friction.to_le_bytes()).unwrap();
+        cursor
+            .write_all(&(self.wrapping as u32).to_le_bytes())
+            .unwrap();
+        cursor.write_all(&self.dist.to_le_bytes()).unwrap();
+        cursor
+            .write_all(&self.attraction_scale.to_le_bytes())
+            .unwrap();
+        cursor.write_all(&self.impulse_x.to_le_bytes()).unwrap();
+        cursor.write_all(&self.impulse_y.to_le_bytes()).unwrap();
+        cursor
+            .write_all(&self.impulse_strength.to_le_bytes())
+            .unwrap();
+        cursor
+            .write_all(&self.impulse_radius.to_le_bytes())
+            .unwrap();
+        cursor.write_all(&self.num_flows.to_le_bytes()).unwrap();
+        cursor.write_all(&self.restitution.to_le_bytes()).unwrap();
+        cursor.write_all(&self.cursor_x.to_le_bytes()).unwrap();
+        cursor.write_all(&self.cursor_y.to_le_bytes()).unwrap();
+        cursor
+            .write_all(&self.cursor_strength.to_le_bytes())
+            .unwrap();
+        cursor.write_all(&self.cursor_radius.to_le_bytes()).unwrap();
+        cursor.write_all(&self.max_velocity.to_le_bytes()).unwrap();
+        cursor
+            .write_all(&(self.integrator as u32).to_le_bytes())
+            .unwrap();
+        cursor.write_all(&(self.drag as u32).to_le_bytes()).unwrap();
+        cursor.write_all(&self.num_zones.to_le_bytes()).unwrap();
+        cursor.write_all(&self.gravity.to_le_bytes()).unwrap();
+        cursor.write_all(&self.softening.to_le_bytes()).unwrap();
+        cursor.into_inner()
+    }
+}
+
 pub struct Simulation {
     pub num_points: u32,
     pub ruleset: Ruleset,
     pub walls: Walls,
-    pub positions: BindableBuffer,
     pub globals: BindableBuffer,
     pub types: BindableBuffer,
-    positions_old: BindableBuffer,
-    bind_group: BindGroup,
+    pub flows: Vec<FlowConfig>,
+    pub zones: Vec<ZoneConfig>,
+    /// Mass of every type, which only matters with gravity
+    pub masses: Vec<f32>,
+    pub velocities: BindableBuffer,
+    /// Forces on every particle in the last step, kept for the verlet integrator
+    accelerations: BindableBuffer,
+    /// Positions are read from one buffer and written to the other, which swap roles every step
+    position_buffers: [BindableBuffer; 2],
+    /// Index of the buffer holding the latest positions
+    current: usize,
+    /// Number of points the buffers have room for
+    capacity: u32,
+    globals_values: Globals,
+    /// Whether `globals_values` changed since they were last uploaded
+    globals_dirty: bool,
+    /// Submissions since the last time the GPU was waited for
+    submits_in_flight: u32,
+    events: Events,
+    schedules: Schedules,
+    faucets: Faucets,
+    cache_max_r: BindableBuffer,
+    cache_min_r: BindableBuffer,
+    cache_attraction: BindableBuffer,
+    /// Type pairs whose attraction is currently zeroed, smaller type first
+    disabled_pairs: HashSet<(PointType, PointType)>,
+    /// The first writes the first position buffer and reads the second; the other the reverse
+    bind_groups: [BindGroup; 2],
+    /// Workgroup size the compute shader was built with
+    workgroup_size: u32,
+    /// Times the compute pass of every step, with `--profile-gpu`
+    profiler: Option<GpuProfiler>,
+    /// Whether steps run on the CPU, with `--cpu`, instead of in the compute shader
+    cpu: bool,
     pipeline: ComputePipeline,
+    /// Type changes after every step, if the config has reaction rules
+    reactions: Option<Reactions>,
+    /// Springs pulled after every step, if the config has bonds
+    bonds: Option<Bonds>,
+}
+
+/// Largest workgroup size the compute shader can be built with on an adapter. wgpu does not report
+/// compute limits, so this is the least every adapter of its kind supports: Direct3D fixes it at
+/// 1024, Metal at 512 on its oldest GPUs, WebGPU at 256 and OpenGL ES at 128. Vulkan only
+/// promises 128, but discrete GPUs all allow 1024 and the rest 256.
+pub fn max_workgroup_size(info: &AdapterInfo) -> u32 {
+    match (info.backend, info.device_type) {
+        (Backend::Dx11, _) | (Backend::Dx12, _) => 1024,
+        (Backend::Metal, _) => 512,
+        (Backend::BrowserWebGpu, _) => 256,
+        (Backend::Gl, _) => 128,
+        (_, DeviceType::DiscreteGpu) => 1024,
+        _ => 256,
+    }
+}
+
+/// Workgroup size used unless another is asked for: the default, or the most the adapter allows
+pub fn default_workgroup_size(info: &AdapterInfo) -> u32 {
+    DEFAULT_WORKGROUP_SIZE.min(max_workgroup_size(info))
+}
+
+/// Checks that `size` is one the compute shader can be built with on the adapter
+pub fn check_workgroup_size(size: u32, info: &AdapterInfo) -> Result<u32, String> {
+    let max = max_workgroup_size(info);
+    if (1..=max).contains(&size) {
+        Ok(size)
+    } else {
+        Err(format!(
+            "Workgroup size {} is not supported by {}, which allows 1 to {}",
+            size, info.name, max
+        ))
+    }
+}
+
+/// Source of the compute shader for the given workgroup size
+fn compute_shader(workgroup_size: u32) -> String {
+    include_str!("compute.wgsl").replace("{WORKGROUP_SIZE}", &workgroup_size.to_string())
 }
 
 impl Simulation {
-    pub fn from_config(device: &Device, config: Config) -> Self {
+    /// Builds a simulation whose compute shader handles `workgroup_size` particles per workgroup,
+    /// which must have passed `check_workgroup_size`. Which size is fastest differs between
+    /// adapters; see `doctor::tune_workgroup_size`. Fails before any GPU work if what was sampled
+    /// from the config does not fit together, e.g. particles of types the ruleset does not have.
+    pub fn from_config(
+        device: &Device,
+        mut config: Config,
+        workgroup_size: u32,
+    ) -> Result<Self, ConfigError> {
+        let events = Events::new(std::mem::take(&mut config.events));
+        let schedules = Schedules::new(std::mem::take(&mut config.schedules));
+        let reaction_rules = std::mem::take(&mut config.reactions);
+        let bonds = std::mem::take(&mut config.bonds);
+        let faucets = std::mem::take(&mut config.faucets);
+        let emitters = std::mem::take(&mut config.emitters);
+        let drains = std::mem::take(&mut config.drains);
+        let lifetimes = std::mem::take(&mut config.lifetimes);
+        let max_points = config.max_points;
+        let flows = std::mem::take(&mut config.flows);
+        let zones = std::mem::take(&mut config.zones);
+        let gravity = config.gravity.take();
+        let restitution = config.walls.restitution();
+        let max_velocity = config.max_velocity.unwrap_or(0.0);
+        let integrator = config.integrator;
+        let drag = config.drag;
         let (ruleset, walls, points) = config.sample();
         let num_points = points.len() as u32;
+        let faucets = Faucets::new(faucets, emitters, drains, lifetimes, num_points);
+        if ruleset.num_point_types == 0 {
+            return Err(ConfigError(
+                "The ruleset has no particle types; `ruleset.types` must be at least 1".to_owned(),
+            ));
+        }
+        if let Some(type_) = points
+            .iter()
+            .filter_map(|&(_, _, type_)| type_)
+            .find(|&type_| type_ >= ruleset.num_point_types)
+        {
+            return Err(ConfigError(format!(
+                "Point type {} is out of range for a ruleset with {} types",
+                type_, ruleset.num_point_types
+            )));
+        }
+        if let Some(gravity) = &gravity {
+            if gravity.masses.len() > ruleset.num_point_types as usize {
+                return Err(ConfigError(format!(
+                    "Gravity lists {} masses for a ruleset with {} types",
+                    gravity.masses.len(),
+                    ruleset.num_point_types
+                )));
+            }
+        }
+        if let Some(highest) = bonds.iter().map(|bond| bond.a.max(bond.b)).max() {
+            if highest >= num_points {
+                return Err(ConfigError(format!(
+                    "Bond to particle {} is out of range for a simulation starting with {} particles",
+                    highest, num_points
+                )));
+            }
+        }
+        if num_points == 0 && !faucets.has_faucets() {
+            warn!("The config produced no particles, so the simulation starts out empty; paint some in with the brush (B) in the window");
+        }
+        let capacity = max_points
+            .unwrap_or_else(|| num_points.saturating_add(DEFAULT_ROOM))
+            .max(num_points);
+        // Buffers cannot be empty, so keep room for at least one point
+        let capacity = capacity.max(1);
         // Buffers
         // TODO: BindableBuffer::using_cursor
-        let positions = BindableBuffer::new(
-            &device,
-            BufferUsage::STORAGE | BufferUsage::COPY_SRC | BufferUsage::VERTEX,
-            ShaderStage::all(),
-            false,
-            num_points as usize * VEC2_SIZE,
-            |positions: &mut Buffer| {
-                let slice = positions.slice(..);
-                let mut view = slice.get_mapped_range_mut();
-                let mut cursor = Cursor::new(&mut *view);
-                for point in points {
-                    cursor.write_all(&point.0.to_le_bytes()).unwrap();
-                    cursor.write_all(&point.1.to_le_bytes()).unwrap();
-                }
-            },
-        );
-
-        let positions_old = BindableBuffer::new(
-            &device,
-            BufferUsage::STORAGE | BufferUsage::COPY_DST,
-            ShaderStage::all(),
-            false,
-            num_points as usize * VEC2_SIZE,
-            |_| {},
-        );
+        let position_buffers = [true, false].map(|initial| {
+            BindableBuffer::new(
+                &device,
+                BufferUsage::STORAGE
+                    | BufferUsage::COPY_SRC
+                    | BufferUsage::COPY_DST
+                    | BufferUsage::VERTEX,
+                ShaderStage::all(),
+                false,
+                capacity as usize * VEC2_SIZE,
+                |positions: &mut Buffer| {
+                    if !initial {
+                        return;
+                    }
+                    let slice = positions.slice(..);
+                    let mut view = slice.get_mapped_range_mut();
+                    let mut cursor = Cursor::new(&mut *view);
+                    for point in &points {
+                        cursor.write_all(&point.0.to_le_bytes()).unwrap();
+                        cursor.write_all(&point.1.to_le_bytes()).unwrap();
+                    }
+                },
+            )
+        });
 
         let velocities = BindableBuffer::new(
             &device,
-            BufferUsage::STORAGE | BufferUsage::COPY_SRC,
-            ShaderStage::COMPUTE,
+            BufferUsage::STORAGE | BufferUsage::COPY_SRC | BufferUsage::COPY_DST,
+            ShaderStage::all(),
             false,
-            num_points as usize * VEC2_SIZE,
+            capacity as usize * VEC2_SIZE,
             |velocities| {
                 let slice = velocities.slice(..);
                 let mut view = slice.get_mapped_range_mut();
@@ -90,18 +343,33 @@ impl Simulation {
             },
         );
 
+        let accelerations = BindableBuffer::new(
+            &device,
+            BufferUsage::STORAGE | BufferUsage::COPY_SRC | BufferUsage::COPY_DST,
+            ShaderStage::COMPUTE,
+            false,
+            capacity as usize * VEC2_SIZE,
+            |_| {},
+        );
+
         let mut types_vec = Vec::with_capacity(num_points as usize);
-        for _ in 0..num_points {
-            types_vec.push(thread_rng().gen_range(0..ruleset.num_point_types));
+        for &(_, _, type_) in &points {
+            types_vec.push(match type_ {
+                Some(type_) => type_,
+                None => with_rng(|rng| rng.gen_range(0..ruleset.num_point_types)),
+            });
         }
 
         let types_vec = types_vec;
         let types = BindableBuffer::new(
             &device,
-            BufferUsage::STORAGE,
+            BufferUsage::STORAGE
+                | BufferUsage::COPY_SRC
+                | BufferUsage::COPY_DST
+                | BufferUsage::VERTEX,
             ShaderStage::all(),
             false,
-            num_points as usize * size_of::<PointType>(),
+            capacity as usize * size_of::<PointType>(),
             |types: &mut Buffer| {
                 let slice = types.slice(..);
                 let mut view = slice.get_mapped_range_mut();
@@ -117,7 +385,7 @@ impl Simulation {
 
         let cache_max_r = BindableBuffer::new(
             &device,
-            BufferUsage::STORAGE,
+            BufferUsage::STORAGE | BufferUsage::COPY_DST,
             ShaderStage::COMPUTE,
             false,
             num_type_pairs as usize * size_of::<Radius>(),
@@ -127,8 +395,7 @@ impl Simulation {
                 let mut cursor = Cursor::new(&mut *view);
                 for y in 0..ruleset.num_point_types {
                     for x in 0..ruleset.num_point_types {
-                        let max_r = ruleset.max_r[types_vec[y as usize] as usize]
-                            [types_vec[x as usize] as usize];
+                        let max_r = ruleset.max_r[y as usize][x as usize];
                         cursor.write_all(&max_r.to_le_bytes()).unwrap();
                     }
                 }
@@ -137,7 +404,7 @@ impl Simulation {
 
         let cache_min_r = BindableBuffer::new(
             &device,
-            BufferUsage::STORAGE,
+            BufferUsage::STORAGE | BufferUsage::COPY_DST,
             ShaderStage::COMPUTE,
             false,
             num_type_pairs as usize * size_of::<Radius>(),
@@ -147,8 +414,7 @@ impl Simulation {
                 let mut cursor = Cursor::new(&mut *view);
                 for y in 0..ruleset.num_point_types {
                     for x in 0..ruleset.num_point_types {
-                        let min_r = ruleset.min_r[types_vec[y as usize] as usize]
-                            [types_vec[x as usize] as usize];
+                        let min_r = ruleset.min_r[y as usize][x as usize];
                         cursor.write_all(&min_r.to_le_bytes()).unwrap();
                     }
                 }
@@ -157,7 +423,7 @@ impl Simulation {
 
         let cache_attraction = BindableBuffer::new(
             &device,
-            BufferUsage::STORAGE,
+            BufferUsage::STORAGE | BufferUsage::COPY_DST,
             ShaderStage::COMPUTE,
             false,
             num_type_pairs as usize * size_of::<Attraction>(),
@@ -167,71 +433,176 @@ impl Simulation {
                 let mut cursor = Cursor::new(&mut *view);
                 for y in 0..ruleset.num_point_types {
                     for x in 0..ruleset.num_point_types {
-                        let attraction = ruleset.attractions[types_vec[y as usize] as usize]
-                            [types_vec[x as usize] as usize];
+                        let attraction = ruleset.attractions[y as usize][x as usize];
                         cursor.write_all(&attraction.to_le_bytes()).unwrap();
                     }
                 }
             },
         );
 
+        let (wrapping, dist) = match walls {
+            Walls::None => (false, 0.0),
+            Walls::Square(dist) => (false, dist),
+            Walls::Wrapping(dist) => (true, dist),
+        };
+        let globals_values = Globals {
+            num_points,
+            num_types: ruleset.num_point_types,
+            friction: ruleset.friction,
+            wrapping,
+            dist,
+            attraction_scale: 1.0,
+            impulse_x: 0.0,
+            impulse_y: 0.0,
+            impulse_strength: 0.0,
+            impulse_radius: 0.0,
+            num_flows: flows.len() as u32,
+            restitution,
+            cursor_x: 0.0,
+            cursor_y: 0.0,
+            cursor_strength: 0.0,
+            cursor_radius: 0.0,
+            max_velocity,
+            integrator,
+            drag,
+            num_zones: zones.len() as u32,
+            gravity: gravity.as_ref().map_or(0.0, |gravity| gravity.strength),
+            softening: gravity.as_ref().map_or(0.0, |gravity| gravity.softening),
+        };
         let globals = BindableBuffer::new(
             &device,
-            BufferUsage::UNIFORM,
+            BufferUsage::UNIFORM | BufferUsage::COPY_DST,
             ShaderStage::all(),
             true,
-            size_of::<u32>()
-                + size_of::<PointType>()
-                + size_of::<Friction>()
-                + size_of::<u32>()
-                + size_of::<f32>(),
+            Globals::SIZE,
             |globals| {
                 let slice = globals.slice(..);
                 let mut view = slice.get_mapped_range_mut();
+                view.copy_from_slice(&globals_values.to_bytes());
+            },
+        );
+
+        let flows_buffer = BindableBuffer::new(
+            device,
+            BufferUsage::UNIFORM,
+            ShaderStage::COMPUTE,
+            true,
+            MAX_FLOWS * FLOW_SIZE,
+            |flows_buffer| {
+                let slice = flows_buffer.slice(..);
+                let mut view = slice.get_mapped_range_mut();
                 let mut cursor = Cursor::new(&mut *view);
-                cursor.write_all(&num_points.to_le_bytes()).unwrap();
-                cursor
-                    .write_all(&ruleset.num_point_types.to_le_bytes())
-                    .unwrap();
-                cursor.write_all(&ruleset.friction.to_le_bytes()).unwrap();
-                let (wrapping, dist) = match walls {
-                    Walls::None => (false, 0.0),
-                    Walls::Square(dist) => (false, dist),
-                    Walls::Wrapping(dist) => (true, dist),
-                };
-                cursor
-                    .write_all(&if wrapping {
-                        1u32.to_le_bytes()
-                    } else {
-                        0u32.to_le_bytes()
-                    })
-                    .unwrap();
-                cursor.write_all(&dist.to_le_bytes()).unwrap();
+                for flow in &flows {
+                    let region = flow.region;
+                    for value in [
+                        region.x[0],
+                        region.y[0],
+                        region.x[1],
+                        region.y[1],
+                        flow.velocity[0],
+                        flow.velocity[1],
+                        0.0,
+                        0.0,
+                    ]
+                    .iter()
+                    {
+                        cursor.write_all(&value.to_le_bytes()).unwrap();
+                    }
+                }
             },
         );
 
-        let buffers = [
-            &positions,
-            &positions_old,
+        let reactions = Reactions::new(device, reaction_rules, &position_buffers, &types, &globals);
+        let bonds = Bonds::new(
+            device,
+            bonds,
+            capacity,
+            &position_buffers,
             &velocities,
-            &types,
-            &cache_max_r,
-            &cache_min_r,
-            &cache_attraction,
             &globals,
-        ];
+        );
+
+        let mut masses = gravity.map_or_else(Vec::new, |gravity| gravity.masses);
+        masses.resize(ruleset.num_point_types as usize, 1.0);
+        let masses_buffer = BindableBuffer::new(
+            device,
+            BufferUsage::STORAGE,
+            ShaderStage::COMPUTE,
+            false,
+            masses.len() * size_of::<f32>(),
+            |masses_buffer| {
+                let slice = masses_buffer.slice(..);
+                let mut view = slice.get_mapped_range_mut();
+                let mut cursor = Cursor::new(&mut *view);
+                for mass in &masses {
+                    cursor.write_all(&mass.to_le_bytes()).unwrap();
+                }
+            },
+        );
+
+        let zones_buffer = BindableBuffer::new(
+            device,
+            BufferUsage::UNIFORM,
+            ShaderStage::COMPUTE,
+            true,
+            MAX_ZONES * FLOW_SIZE,
+            |zones_buffer| {
+                let slice = zones_buffer.slice(..);
+                let mut view = slice.get_mapped_range_mut();
+                let mut cursor = Cursor::new(&mut *view);
+                for zone in &zones {
+                    let (shape, is_circle) = match zone.shape {
+                        ZoneShape::Circle { center, radius } => {
+                            ([center[0], center[1], radius, 0.0], 1.0)
+                        }
+                        ZoneShape::Rectangle(region) => {
+                            ([region.x[0], region.y[0], region.x[1], region.y[1]], 0.0)
+                        }
+                    };
+                    for value in
+                        shape
+                            .iter()
+                            .chain(&[zone.attraction, zone.friction, is_circle, 0.0])
+                    {
+                        cursor.write_all(&value.to_le_bytes()).unwrap();
+                    }
+                }
+            },
+        );
+
+        let buffers = |written: usize| {
+            [
+                &position_buffers[written],
+                &position_buffers[1 - written],
+                &velocities,
+                &types,
+                &cache_max_r,
+                &cache_min_r,
+                &cache_attraction,
+                &globals,
+                &flows_buffer,
+                &accelerations,
+                &zones_buffer,
+                &masses_buffer,
+            ]
+        };
 
         // Bind groups
-        // 0: positions
-        // 1: positions_old
+        // 0: positions, written this step
+        // 1: positions as of the last step
         // 2: velocities
-        // 4: types
-        // 5: cache_max_r
-        // 6: cache_min_r
-        // 7: cache_attraction
-        // 8: globals
-        let bind_group_layout = BindableBuffer::bind_group_layout(&device, &buffers);
-        let bind_group = BindableBuffer::bind_group(&device, &buffers);
+        // 3: types
+        // 4: cache_max_r
+        // 5: cache_min_r
+        // 6: cache_attraction
+        // 7: globals
+        // 8: flows
+        // 9: accelerations
+        // 10: zones
+        // 11: masses
+        let bind_group_layout = BindableBuffer::bind_group_layout(&device, &buffers(0));
+        let bind_groups =
+            [0, 1].map(|written| BindableBuffer::bind_group(&device, &buffers(written)));
         // Pipeline
         let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
             label: Some("compute_pipeline"),
@@ -242,46 +613,1709 @@ impl Simulation {
             })),
             module: &device.create_shader_module(&ShaderModuleDescriptor {
                 label: Some("compute_shader"),
-                source: ShaderSource::Wgsl(include_str!("compute.wgsl").into()),
+                source: ShaderSource::Wgsl(compute_shader(workgroup_size).into()),
                 flags: ShaderFlags::VALIDATION,
             }),
             entry_point: "main",
         });
 
-        Self {
-            positions,
-            positions_old,
+        Ok(Self {
+            position_buffers,
+            current: 0,
+            velocities,
+            accelerations,
+            capacity,
             num_points,
             walls,
             globals,
+            globals_values,
+            globals_dirty: false,
+            submits_in_flight: 0,
+            events,
+            schedules,
+            faucets,
             types,
+            flows,
+            zones,
+            masses,
             ruleset,
-            bind_group,
+            cache_max_r,
+            cache_min_r,
+            cache_attraction,
+            disabled_pairs: HashSet::new(),
+            bind_groups,
+            workgroup_size,
+            profiler: None,
+            cpu: false,
             pipeline,
-        }
+            reactions,
+            bonds,
+        })
     }
     pub fn step(&mut self, device: &Device, queue: &Queue) {
-        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
-            label: Some("step"),
-        });
-        encoder.copy_buffer_to_buffer(
-            &self.positions.buffer,
-            0,
-            &self.positions_old.buffer,
-            0,
-            self.num_points as u64 * std::mem::size_of::<f32>() as u64 * 2,
-        );
+        self.step_many(device, queue, 1);
+    }
+
+    /// Runs `steps` steps, encoding as many of them as possible into each submission. The GPU is
+    /// only waited for when it falls `MAX_SUBMITS_IN_FLIGHT` submissions behind, so the steps may
+    /// still be running when this returns; reading particles back waits for them.
+    pub fn step_many(&mut self, device: &Device, queue: &Queue, steps: u64) {
+        let _span = trace_span!("step_many", steps).entered();
+        if self.cpu {
+            self.step_many_on_cpu(device, queue, steps);
+            return;
+        }
+        let mut encoder = None;
+        for _ in 0..steps {
+            self.globals_dirty |= self.events.step(&mut self.globals_values, &self.walls);
+            self.globals_dirty |= self.schedules.step(&mut self.globals_values);
+            if !self.faucets.is_empty() {
+                // Faucets read the particles back, so the steps so far have to be submitted
+                self.submit(device, queue, encoder.take());
+                self.globals_dirty |= self.run_faucets(device, queue);
+            }
+            if self.globals_dirty {
+                // Buffer writes take effect before everything in the next submission, so the
+                // steps encoded with the old values go first
+                self.submit(device, queue, encoder.take());
+                queue.write_buffer(&self.globals.buffer, 0, &self.globals_values.to_bytes());
+                self.globals_dirty = false;
+            }
+            if self.num_points == 0 {
+                continue;
+            }
+            if self.profiler.as_ref().is_some_and(GpuProfiler::is_full) {
+                self.submit(device, queue, encoder.take());
+            }
+            let encoder = encoder.get_or_insert_with(|| {
+                device.create_command_encoder(&CommandEncoderDescriptor {
+                    label: Some("step"),
+                })
+            });
+            if let Some(profiler) = &self.profiler {
+                profiler.begin_step(encoder);
+            }
+            self.encode_step(encoder);
+            if let Some(profiler) = &mut self.profiler {
+                profiler.end_step(encoder);
+            }
+            self.current = 1 - self.current;
+            if let Some(reactions) = &self.reactions {
+                reactions.encode(encoder, &self.types.buffer, self.current, self.num_points);
+            }
+            if let Some(bonds) = &self.bonds {
+                bonds.encode(encoder, self.current, self.num_points);
+            }
+        }
+        self.submit(device, queue, encoder);
+    }
+
+    /// Runs `steps` steps with `CpuSimulation`, reading the particles back from the GPU first and
+    /// writing them back after, so that everything else finds them where it always does
+    fn step_many_on_cpu(&mut self, device: &Device, queue: &Queue, steps: u64) {
+        let mut cpu: Option<CpuSimulation> = None;
+        for _ in 0..steps {
+            self.globals_dirty |= self.events.step(&mut self.globals_values, &self.walls);
+            self.globals_dirty |= self.schedules.step(&mut self.globals_values);
+            if !self.faucets.is_empty() {
+                // Faucets and drains work on the particles on the GPU
+                if let Some(cpu) = cpu.take() {
+                    self.restore_from_cpu(queue, &cpu);
+                }
+                self.globals_dirty |= self.run_faucets(device, queue);
+            }
+            if self.num_points == 0 {
+                continue;
+            }
+            let cpu = cpu.get_or_insert_with(|| {
+                CpuSimulation::new(
+                    &self.ruleset,
+                    |a, b| self.interaction_enabled(a, b),
+                    &self.read_points(device, queue),
+                    &self.read_velocities(device, queue),
+                    &self.read_vec2s(device, queue, &self.accelerations.buffer),
+                )
+            });
+            cpu.step(&self.globals_values, &self.flows, &self.zones, &self.masses);
+            if let Some(reactions) = &self.reactions {
+                cpu.react(reactions.rules(), &self.globals_values);
+            }
+            if let Some(bonds) = &self.bonds {
+                cpu.pull_bonds(bonds.bonds(), &self.globals_values);
+            }
+        }
+        if let Some(cpu) = cpu {
+            self.restore_from_cpu(queue, &cpu);
+        }
+        if self.globals_dirty {
+            queue.write_buffer(&self.globals.buffer, 0, &self.globals_values.to_bytes());
+            self.globals_dirty = false;
+        }
+    }
+
+    /// Writes back particles stepped by `CpuSimulation`, along with the forces the verlet
+    /// integrator needs next step
+    fn restore_from_cpu(&self, queue: &Queue, cpu: &CpuSimulation) {
+        self.restore(queue, &cpu.points(), &cpu.velocities);
+        let mut bytes = Vec::with_capacity(cpu.accelerations.len() * VEC2_SIZE);
+        for [ax, ay] in &cpu.accelerations {
+            bytes.extend_from_slice(&ax.to_le_bytes());
+            bytes.extend_from_slice(&ay.to_le_bytes());
+        }
+        queue.write_buffer(&self.accelerations.buffer, 0, &bytes);
+    }
+
+    /// Records a step reading the current positions and writing the other buffer, which becomes
+    /// current once the step is recorded
+    fn encode_step(&self, encoder: &mut CommandEncoder) {
         let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
             label: Some("step_pass"),
         });
-        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.push_debug_group("update particles");
+        compute_pass.set_bind_group(0, &self.bind_groups[1 - self.current], &[]);
         compute_pass.set_pipeline(&self.pipeline);
         // Dispatch
-        let workgroups = (self.num_points as f32 / WORKGROUP_SIZE as f32).ceil() as u32;
+        let workgroups = self.num_points.div_ceil(self.workgroup_size);
         compute_pass.dispatch(workgroups, 1, 1);
-        drop(compute_pass);
-        let cmd = encoder.finish();
-        queue.submit(Some(cmd));
-        device.poll(Maintain::Wait);
+        compute_pass.pop_debug_group();
+    }
+
+    fn submit(&mut self, device: &Device, queue: &Queue, encoder: Option<CommandEncoder>) {
+        let mut encoder = match encoder {
+            Some(encoder) => encoder,
+            None => return,
+        };
+        if let Some(profiler) = &self.profiler {
+            profiler.resolve(&mut encoder);
+        }
+        queue.submit(Some(encoder.finish()));
+        if let Some(profiler) = &mut self.profiler {
+            // Reading the timestamps back already waits for the GPU
+            profiler.read(device);
+            self.submits_in_flight = 0;
+            return;
+        }
+        self.submits_in_flight += 1;
+        if self.submits_in_flight >= MAX_SUBMITS_IN_FLIGHT {
+            device.poll(Maintain::Wait);
+            self.submits_in_flight = 0;
+        } else {
+            device.poll(Maintain::Poll);
+        }
+    }
+
+    /// Buffer holding the latest positions, which changes every step
+    pub fn positions(&self) -> &BindableBuffer {
+        &self.position_buffers[self.current]
+    }
+
+    /// Both position buffers, for building bind groups that cover either being current
+    pub fn position_buffers(&self) -> &[BindableBuffer; 2] {
+        &self.position_buffers
+    }
+
+    /// Index into `position_buffers` of the buffer holding the latest positions
+    pub fn current_positions(&self) -> usize {
+        self.current
+    }
+
+    /// Runs the steps from now on on the CPU, for adapters whose compute shaders are missing or
+    /// broken
+    pub fn set_cpu(&mut self, cpu: bool) {
+        self.cpu = cpu;
+    }
+
+    pub fn is_on_cpu(&self) -> bool {
+        self.cpu
+    }
+
+    /// Workgroup size the compute shader was built with, for building another simulation the same
+    /// way
+    pub fn workgroup_size(&self) -> u32 {
+        self.workgroup_size
+    }
+
+    /// Starts timing the compute pass of every step on the GPU
+    pub fn set_profiler(&mut self, profiler: GpuProfiler) {
+        self.profiler = Some(profiler);
+    }
+
+    /// GPU timings of the steps since the last call, which took `wall_time` seconds in all, or
+    /// `None` if the steps are not being timed
+    pub fn gpu_timings(&mut self, wall_time: f64) -> Option<GpuTimings> {
+        self.profiler.as_mut()?.take(wall_time)
+    }
+
+    pub fn drag(&self) -> Drag {
+        self.globals_values.drag
+    }
+
+    pub fn restitution(&self) -> f32 {
+        self.globals_values.restitution
+    }
+
+    /// Changes how bouncy square walls are, taking effect from the next step
+    pub fn set_restitution(&mut self, restitution: f32) {
+        self.globals_values.restitution = restitution.clamp(0.0, 1.0);
+        self.globals_dirty = true;
+    }
+
+    /// Changes one of the parameters schedules can change, taking effect from the next step.
+    /// Friction is kept in the ruleset too, so that sessions save the new value.
+    pub fn set_parameter(
+        &mut self,
+        parameter: ScheduledParameter,
+        value: f32,
+    ) -> Result<(), String> {
+        let (range, message) = parameter.range();
+        if !range.contains(&value) {
+            return Err(format!("{} {}", value, message));
+        }
+        if let ScheduledParameter::Friction = parameter {
+            self.ruleset.friction = value;
+        }
+        *self.globals_values.parameter_mut(parameter) = value;
+        self.globals_dirty = true;
+        Ok(())
+    }
+
+    /// Pulls particles within `radius` of `(x, y)` toward it every step, or pushes them away if
+    /// `strength` is negative, until called again with a strength of 0
+    pub fn set_cursor_force(&mut self, x: f32, y: f32, strength: f32, radius: f32) {
+        let globals = &mut self.globals_values;
+        if strength == 0.0 && globals.cursor_strength == 0.0 {
+            return;
+        }
+        globals.cursor_x = x;
+        globals.cursor_y = y;
+        globals.cursor_strength = strength;
+        globals.cursor_radius = radius;
+        self.globals_dirty = true;
+    }
+
+    /// Switches to another ruleset with the same number of types, keeping the particles where
+    /// they are. Interactions switched off stay off.
+    pub fn set_ruleset(&mut self, queue: &Queue, ruleset: Ruleset) -> Result<(), String> {
+        let num_types = self.ruleset.num_point_types;
+        if ruleset.num_point_types != num_types {
+            return Err(format!(
+                "the new ruleset has {} types instead of {}",
+                ruleset.num_point_types, num_types
+            ));
+        }
+        let per_pair = |values: &Vec<Vec<f32>>, disabled: bool| {
+            let mut bytes = Vec::with_capacity((num_types * num_types) as usize * size_of::<f32>());
+            for (from, row) in values.iter().enumerate() {
+                for (to, value) in row.iter().enumerate() {
+                    let value = if disabled && !self.interaction_enabled(from as u32, to as u32) {
+                        0.0
+                    } else {
+                        *value
+                    };
+                    bytes.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            bytes
+        };
+        queue.write_buffer(
+            &self.cache_max_r.buffer,
+            0,
+            &per_pair(&ruleset.max_r, false),
+        );
+        queue.write_buffer(
+            &self.cache_min_r.buffer,
+            0,
+            &per_pair(&ruleset.min_r, false),
+        );
+        queue.write_buffer(
+            &self.cache_attraction.buffer,
+            0,
+            &per_pair(&ruleset.attractions, true),
+        );
+        self.globals_values.friction = ruleset.friction;
+        self.globals_dirty = true;
+        self.ruleset = ruleset;
+        Ok(())
+    }
+
+    /// Replaces every particle with `points`, at rest, as many as there is room for. Points
+    /// without a type get a random one.
+    pub fn replace_points(&mut self, queue: &Queue, points: &[Point]) -> Result<(), String> {
+        let num_types = self.ruleset.num_point_types;
+        if let Some(&(_, _, Some(type_))) = points
+            .iter()
+            .find(|(_, _, type_)| type_.is_some_and(|type_| type_ >= num_types))
+        {
+            return Err(format!(
+                "point type {} is out of range for a ruleset with {} types",
+                type_, num_types
+            ));
+        }
+        let points = points
+            .iter()
+            .take(self.capacity as usize)
+            .map(|&(x, y, type_)| {
+                let type_ = type_.unwrap_or_else(|| with_rng(|rng| rng.gen_range(0..num_types)));
+                (x, y, Some(type_))
+            })
+            .collect::<Vec<_>>();
+        self.write_points(queue, 0, &points, &vec![0; points.len() * VEC2_SIZE]);
+        self.faucets.replaced(points.len());
+        if let Some(bonds) = &mut self.bonds {
+            bonds.clear(queue);
+        }
+        self.num_points = points.len() as u32;
+        self.globals_values.num_points = self.num_points;
+        // Uploaded straight away, so that they are drawn even while paused
+        queue.write_buffer(&self.globals.buffer, 0, &self.globals_values.to_bytes());
+        Ok(())
+    }
+
+    pub fn interaction_enabled(&self, a: PointType, b: PointType) -> bool {
+        !self.disabled_pairs.contains(&(a.min(b), a.max(b)))
+    }
+
+    /// Zeroes or restores the attraction between two types, in both directions. Particles that
+    /// get too close still repel each other.
+    pub fn set_interaction_enabled(
+        &mut self,
+        queue: &Queue,
+        a: PointType,
+        b: PointType,
+        enabled: bool,
+    ) {
+        let pair = (a.min(b), a.max(b));
+        if enabled {
+            self.disabled_pairs.remove(&pair);
+        } else {
+            self.disabled_pairs.insert(pair);
+        }
+        for &(from, to) in [(a, b), (b, a)].iter() {
+            let attraction = if enabled {
+                self.ruleset.attractions[from as usize][to as usize]
+            } else {
+                0.0
+            };
+            let index = from * self.ruleset.num_point_types + to;
+            queue.write_buffer(
+                &self.cache_attraction.buffer,
+                index as u64 * size_of::<Attraction>() as u64,
+                &attraction.to_le_bytes(),
+            );
+        }
+    }
+
+    /// Restores every interaction disabled with `set_interaction_enabled`
+    pub fn enable_all_interactions(&mut self, queue: &Queue) {
+        let pairs = self.disabled_pairs.drain().collect::<Vec<_>>();
+        for (a, b) in pairs {
+            self.set_interaction_enabled(queue, a, b, true);
+        }
+    }
+
+    /// Adds and removes points for faucets and drains, returning whether the count changed
+    fn run_faucets(&mut self, device: &Device, queue: &Queue) -> bool {
+        let spawned = self.faucets.spawn(self.ruleset.num_point_types);
+        let mut changed = self.add_points(queue, &spawned);
+        if self.faucets.drain_due() {
+            changed |= self.drain(device, queue);
+        }
+        changed
+    }
+
+    /// Appends as many of `points` as there is room for, with zero velocity
+    fn add_points(&mut self, queue: &Queue, points: &[Point]) -> bool {
+        let room = (self.capacity - self.num_points) as usize;
+        let points = &points[..points.len().min(room)];
+        if points.is_empty() {
+            return false;
+        }
+        let offset = self.num_points as u64;
+        let velocities = vec![0; points.len() * VEC2_SIZE];
+        self.write_points(queue, offset, points, &velocities);
+        self.faucets.added(points.len());
+        self.num_points += points.len() as u32;
+        self.globals_values.num_points = self.num_points;
+        true
+    }
+
+    /// Adds particles at rest, as many as there is room for, and returns how many were added
+    pub fn spawn(&mut self, queue: &Queue, points: &[Point]) -> usize {
+        let before = self.num_points;
+        if self.add_points(queue, points) {
+            // Uploaded straight away, so that they are drawn even while paused
+            queue.write_buffer(&self.globals.buffer, 0, &self.globals_values.to_bytes());
+        }
+        (self.num_points - before) as usize
+    }
+
+    /// Removes points inside drains or past their lifetime, moving the remaining ones to the
+    /// front of the buffers
+    fn drain(&mut self, device: &Device, queue: &Queue) -> bool {
+        let points = self.read_points(device, queue);
+        let removed = points
+            .iter()
+            .enumerate()
+            .map(|(i, &(x, y, type_))| {
+                let type_ = type_.unwrap_or(0);
+                self.faucets.drains(x, y, type_) || self.faucets.expired(i, type_)
+            })
+            .collect::<Vec<_>>();
+        self.remove_points(device, queue, &points, &removed) > 0
+    }
+
+    /// Removes the particles within `radius` of `(x, y)`, and returns how many there were
+    pub fn erase(&mut self, device: &Device, queue: &Queue, x: f32, y: f32, radius: f32) -> usize {
+        let points = self.read_points(device, queue);
+        let removed = points
+            .iter()
+            .map(|&(px, py, _)| (px - x) * (px - x) + (py - y) * (py - y) <= radius * radius)
+            .collect::<Vec<_>>();
+        let count = self.remove_points(device, queue, &points, &removed);
+        if count > 0 {
+            // Uploaded straight away, so that they disappear even while paused
+            queue.write_buffer(&self.globals.buffer, 0, &self.globals_values.to_bytes());
+        }
+        count
+    }
+
+    /// Removes the points marked in `removed`, moving the remaining ones to the front of the
+    /// buffers. Returns how many were removed.
+    fn remove_points(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        points: &[Point],
+        removed: &[bool],
+    ) -> usize {
+        let velocities = read_buffer(
+            device,
+            queue,
+            &self.velocities.buffer,
+            self.num_points as u64 * VEC2_SIZE as u64,
+        );
+        let (kept, kept_velocities): (Vec<Point>, Vec<&[u8]>) = points
+            .iter()
+            .zip(velocities.chunks_exact(VEC2_SIZE))
+            .zip(removed)
+            .filter(|(_, &removed)| !removed)
+            .map(|(kept, _)| kept)
+            .unzip();
+        if kept.len() == points.len() {
+            return 0;
+        }
+        self.write_points(queue, 0, &kept, &kept_velocities.concat());
+        self.faucets.removed(removed);
+        if let Some(bonds) = &mut self.bonds {
+            bonds.removed(queue, removed);
+        }
+        self.num_points = kept.len() as u32;
+        self.globals_values.num_points = self.num_points;
+        points.len() - kept.len()
+    }
+
+    /// Writes positions, types and raw velocities of `points` starting at index `offset`
+    fn write_points(&self, queue: &Queue, offset: u64, points: &[Point], velocities: &[u8]) {
+        if points.is_empty() {
+            return;
+        }
+        let mut positions = Vec::with_capacity(points.len() * VEC2_SIZE);
+        let mut types = Vec::with_capacity(points.len() * size_of::<PointType>());
+        for &(x, y, type_) in points {
+            positions.extend_from_slice(&x.to_le_bytes());
+            positions.extend_from_slice(&y.to_le_bytes());
+            types.extend_from_slice(&type_.unwrap_or(0).to_le_bytes());
+        }
+        queue.write_buffer(
+            &self.positions().buffer,
+            offset * VEC2_SIZE as u64,
+            &positions,
+        );
+        queue.write_buffer(
+            &self.velocities.buffer,
+            offset * VEC2_SIZE as u64,
+            velocities,
+        );
+        // The forces of the last step are forgotten, which costs the verlet integrator one step of
+        // accuracy for these points
+        queue.write_buffer(
+            &self.accelerations.buffer,
+            offset * VEC2_SIZE as u64,
+            &vec![0; points.len() * VEC2_SIZE],
+        );
+        queue.write_buffer(
+            &self.types.buffer,
+            offset * size_of::<PointType>() as u64,
+            &types,
+        );
+    }
+
+    /// Replaces the positions, types and velocities of the first `points.len()` points
+    pub fn restore(&self, queue: &Queue, points: &[Point], velocities: &[[f32; 2]]) {
+        let mut bytes = Vec::with_capacity(velocities.len() * VEC2_SIZE);
+        for [vx, vy] in velocities {
+            bytes.extend_from_slice(&vx.to_le_bytes());
+            bytes.extend_from_slice(&vy.to_le_bytes());
+        }
+        self.write_points(queue, 0, points, &bytes);
+    }
+
+    /// Goes back to particles read earlier with `read_points` and `read_velocities`, however many
+    /// there were then
+    pub fn rewind(&mut self, queue: &Queue, points: &[Point], velocities: &[[f32; 2]]) {
+        let count = points.len().min(self.capacity as usize);
+        self.restore(queue, &points[..count], &velocities[..count]);
+        // How old the particles were then is not known, so they start their lives over
+        self.faucets.replaced(count);
+        self.num_points = count as u32;
+        self.globals_values.num_points = self.num_points;
+        // Uploaded straight away, so that they are drawn even while paused
+        queue.write_buffer(&self.globals.buffer, 0, &self.globals_values.to_bytes());
+    }
+
+    /// Type pairs whose interaction is switched off, smaller type first
+    pub fn disabled_interactions(&self) -> Vec<(PointType, PointType)> {
+        self.disabled_pairs.iter().copied().collect()
+    }
+
+    /// Reads the current positions and types of all points back from the GPU
+    pub fn read_points(&self, device: &Device, queue: &Queue) -> Vec<Point> {
+        let num_points = self.num_points as u64;
+        let positions = read_buffer(
+            device,
+            queue,
+            &self.positions().buffer,
+            num_points * VEC2_SIZE as u64,
+        );
+        let types = read_buffer(
+            device,
+            queue,
+            &self.types.buffer,
+            num_points * size_of::<PointType>() as u64,
+        );
+        positions
+            .chunks_exact(VEC2_SIZE)
+            .zip(types.chunks_exact(size_of::<PointType>()))
+            .map(|(position, type_)| {
+                let x = f32::from_le_bytes(position[0..4].try_into().unwrap());
+                let y = f32::from_le_bytes(position[4..8].try_into().unwrap());
+                let type_ = PointType::from_le_bytes(type_.try_into().unwrap());
+                (x, y, Some(type_))
+            })
+            .collect()
+    }
+
+    /// Position, type and velocity of a single particle, without reading back all the others
+    pub fn read_particle(&self, device: &Device, queue: &Queue, index: u32) -> (Point, [f32; 2]) {
+        let vec2_at = |buffer: &Buffer| {
+            let bytes = read_buffer_at(
+                device,
+                queue,
+                buffer,
+                index as u64 * VEC2_SIZE as u64,
+                VEC2_SIZE as u64,
+            );
+            [
+                f32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+                f32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            ]
+        };
+        let [x, y] = vec2_at(&self.positions().buffer);
+        let velocity = vec2_at(&self.velocities.buffer);
+        let type_ = read_buffer_at(
+            device,
+            queue,
+            &self.types.buffer,
+            index as u64 * size_of::<PointType>() as u64,
+            size_of::<PointType>() as u64,
+        );
+        let type_ = PointType::from_le_bytes(type_[..].try_into().unwrap());
+        ((x, y, Some(type_)), velocity)
+    }
+
+    pub fn read_velocities(&self, device: &Device, queue: &Queue) -> Vec<[f32; 2]> {
+        self.read_vec2s(device, queue, &self.velocities.buffer)
+    }
+
+    /// Reads a vector for each point back from `buffer`
+    fn read_vec2s(&self, device: &Device, queue: &Queue, buffer: &Buffer) -> Vec<[f32; 2]> {
+        read_buffer(
+            device,
+            queue,
+            buffer,
+            self.num_points as u64 * VEC2_SIZE as u64,
+        )
+        .chunks_exact(VEC2_SIZE)
+        .map(|velocity| {
+            [
+                f32::from_le_bytes(velocity[0..4].try_into().unwrap()),
+                f32::from_le_bytes(velocity[4..8].try_into().unwrap()),
+            ]
+        })
+        .collect()
+    }
+}
+
+/// Runs the compute shader on a handful of particles and compares the result of a single step
+/// with the force law worked out by hand. Skipped when there is no graphics adapter.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::explosion::ExplosionCheck;
+    use structopt::StructOpt;
+
+    /// `R_SMOOTH` in compute.wgsl
+    const R_SMOOTH: f32 = 2.0;
+    const TOLERANCE: f32 = 1e-4;
+
+    fn gpu() -> Option<(Device, Queue)> {
+        let instance = Instance::new(BackendBit::all());
+        let adapter =
+            futures::executor::block_on(instance.request_adapter(&RequestAdapterOptions {
+                power_preference: PowerPreference::default(),
+                compatible_surface: None,
+            }))?;
+        futures::executor::block_on(adapter.request_device(
+            &DeviceDescriptor {
+                label: Some("test device"),
+                features: Features::default(),
+                limits: Limits {
+                    max_storage_buffers_per_shader_stage: STORAGE_BUFFERS_PER_STAGE,
+                    ..Limits::default()
+                },
+            },
+            None,
+        ))
+        .ok()
+    }
+
+    /// A single type attracted to itself, with particles given as `[x, y, vx, vy]` in place of
+    /// the randomly placed ones
+    fn simulation(
+        device: &Device,
+        queue: &Queue,
+        walls: &str,
+        attraction: f32,
+        min_r: f32,
+        max_r: f32,
+        particles: &[[f32; 4]],
+    ) -> Simulation {
+        let yaml = format!(
+            "---
+ruleset:
+  types:
+    - attractions: [{}]
+      min_r: [{}]
+      max_r: [{}]
+  friction: 0.0
+walls: {}
+points: {}
+",
+            attraction,
+            min_r,
+            max_r,
+            walls,
+            particles.len()
+        );
+        simulation_from_yaml(device, queue, &yaml, particles, &vec![0; particles.len()])
+    }
+
+    /// A simulation from a config asking for as many points as there are `particles`, given as
+    /// `[x, y, vx, vy]` in place of the randomly placed ones along with their `types`
+    fn simulation_from_yaml(
+        device: &Device,
+        queue: &Queue,
+        yaml: &str,
+        particles: &[[f32; 4]],
+        types: &[PointType],
+    ) -> Simulation {
+        let config = Config::from_reader(yaml.as_bytes(), ConfigFormat::Yaml, &[]).unwrap();
+        let simulation = Simulation::from_config(device, config, DEFAULT_WORKGROUP_SIZE).unwrap();
+        let points = particles
+            .iter()
+            .zip(types)
+            .map(|(&[x, y, _, _], &type_)| (x, y, Some(type_)))
+            .collect::<Vec<_>>();
+        let mut velocities = Vec::with_capacity(particles.len() * VEC2_SIZE);
+        for &[_, _, vx, vy] in particles {
+            velocities.extend_from_slice(&vx.to_le_bytes());
+            velocities.extend_from_slice(&vy.to_le_bytes());
+        }
+        simulation.write_points(queue, 0, &points, &velocities);
+        simulation
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() <= TOLERANCE * expected.abs().max(1.0),
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    macro_rules! gpu_or_skip {
+        () => {
+            match gpu() {
+                Some(gpu) => gpu,
+                None => {
+                    eprintln!("No graphics adapter available, skipping");
+                    return;
+                }
+            }
+        };
+    }
+
+    #[test]
+    fn attraction_between_min_r_and_max_r() {
+        let (device, queue) = gpu_or_skip!();
+        let (attraction, min_r, max_r, r) = (0.5, 10.0, 50.0, 20.0);
+        let mut simulation = simulation(
+            &device,
+            &queue,
+            "{type: none}",
+            attraction,
+            min_r,
+            max_r,
+            &[[0.0, 0.0, 0.0, 0.0], [r, 0.0, 0.0, 0.0]],
+        );
+        simulation.step(&device, &queue);
+        let points = simulation.read_points(&device, &queue);
+
+        let f = attraction * (1.0 - (2.0 * r - (max_r + min_r)).abs() / (max_r - min_r));
+        assert_close(points[0].0, f);
+        assert_close(points[1].0, r - f);
+        assert_close(points[0].1, 0.0);
+        assert_close(points[1].1, 0.0);
+    }
+
+    #[test]
+    fn repulsion_inside_min_r() {
+        let (device, queue) = gpu_or_skip!();
+        let (min_r, r) = (10.0, 4.0);
+        let mut simulation = simulation(
+            &device,
+            &queue,
+            "{type: none}",
+            1.0,
+            min_r,
+            50.0,
+            &[[0.0, 0.0, 0.0, 0.0], [0.0, r, 0.0, 0.0]],
+        );
+        simulation.step(&device, &queue);
+        let points = simulation.read_points(&device, &queue);
+
+        let f = R_SMOOTH * min_r * (1.0 / (min_r + R_SMOOTH) - 1.0 / (r + R_SMOOTH));
+        assert!(f < 0.0);
+        assert_close(points[0].1, f);
+        assert_close(points[1].1, r - f);
+    }
+
+    #[test]
+    fn no_force_beyond_max_r() {
+        let (device, queue) = gpu_or_skip!();
+        let mut simulation = simulation(
+            &device,
+            &queue,
+            "{type: none}",
+            1.0,
+            10.0,
+            50.0,
+            &[[0.0, 0.0, 0.0, 0.0], [60.0, 0.0, 0.0, 0.0]],
+        );
+        simulation.step(&device, &queue);
+        let points = simulation.read_points(&device, &queue);
+
+        assert_close(points[0].0, 0.0);
+        assert_close(points[1].0, 60.0);
+    }
+
+    #[test]
+    fn attraction_across_tiles() {
+        let (device, queue) = gpu_or_skip!();
+        let (attraction, min_r, max_r, r) = (0.5, 10.0, 50.0, 20.0);
+        // A pair split between the first tile and the second, with the particles in between too
+        // far apart to feel each other
+        let mut particles = (0..DEFAULT_WORKGROUP_SIZE + 10)
+            .map(|k| [1000.0 + 100.0 * k as f32, 1000.0, 0.0, 0.0])
+            .collect::<Vec<_>>();
+        particles[0] = [0.0, 0.0, 0.0, 0.0];
+        *particles.last_mut().unwrap() = [r, 0.0, 0.0, 0.0];
+        let mut simulation = simulation(
+            &device,
+            &queue,
+            "{type: none}",
+            attraction,
+            min_r,
+            max_r,
+            &particles,
+        );
+        simulation.step(&device, &queue);
+        let points = simulation.read_points(&device, &queue);
+
+        let f = attraction * (1.0 - (2.0 * r - (max_r + min_r)).abs() / (max_r - min_r));
+        assert_close(points[0].0, f);
+        assert_close(points.last().unwrap().0, r - f);
+        assert_close(points[1].0, 1100.0);
+    }
+
+    #[test]
+    fn compare_lists_changed_ruleset_entries() {
+        let ruleset = |attraction: f32, friction: f32| Ruleset {
+            num_point_types: 2,
+            min_r: vec![vec![5.0; 2]; 2],
+            max_r: vec![vec![20.0; 2]; 2],
+            attractions: vec![vec![0.5, attraction], vec![0.5, 0.5]],
+            friction,
+        };
+        assert!(crate::compare::differences(&ruleset(0.5, 0.1), &ruleset(0.5, 0.1)).is_empty());
+        assert_eq!(
+            crate::compare::differences(&ruleset(0.5, 0.1), &ruleset(0.25, 0.2)),
+            vec!["friction: 0.1 -> 0.2", "attractions[0][1]: 0.5 -> 0.25"]
+        );
+    }
+
+    #[test]
+    fn workgroup_size_is_checked_against_the_adapter() {
+        let adapter = |backend, device_type| AdapterInfo {
+            name: "test adapter".to_owned(),
+            vendor: 0,
+            device: 0,
+            device_type,
+            backend,
+        };
+        let gl = adapter(Backend::Gl, DeviceType::IntegratedGpu);
+        assert_eq!(default_workgroup_size(&gl), 128);
+        assert!(check_workgroup_size(256, &gl).is_err());
+        let vulkan = adapter(Backend::Vulkan, DeviceType::DiscreteGpu);
+        assert_eq!(default_workgroup_size(&vulkan), DEFAULT_WORKGROUP_SIZE);
+        assert_eq!(check_workgroup_size(1024, &vulkan), Ok(1024));
+        assert!(check_workgroup_size(0, &vulkan).is_err());
+        assert!(check_workgroup_size(2048, &vulkan).is_err());
+    }
+
+    #[test]
+    fn workgroup_size_is_filled_in() {
+        let shader = compute_shader(128);
+        assert!(!shader.contains("{WORKGROUP_SIZE}"));
+        for declaration in [
+            "TILE_SIZE : u32 = 128u;",
+            "tile_positions : array<vec2<f32>, 128>;",
+            "tile_types : array<u32, 128>;",
+            "workgroup_size(128)",
+        ]
+        .iter()
+        {
+            assert!(shader.contains(declaration), "missing `{}`", declaration);
+        }
+    }
+
+    #[test]
+    fn forces_from_several_particles_add_up() {
+        let (device, queue) = gpu_or_skip!();
+        let (attraction, min_r, max_r) = (0.5, 10.0, 50.0);
+        let mut simulation = simulation(
+            &device,
+            &queue,
+            "{type: none}",
+            attraction,
+            min_r,
+            max_r,
+            &[
+                [0.0, 0.0, 0.0, 0.0],
+                [20.0, 0.0, 0.0, 0.0],
+                [-30.0, 0.0, 0.0, 0.0],
+            ],
+        );
+        simulation.step(&device, &queue);
+        let points = simulation.read_points(&device, &queue);
+
+        let f = |r: f32| attraction * (1.0 - (2.0 * r - (max_r + min_r)).abs() / (max_r - min_r));
+        // The outer two are 50 apart, just out of each other's reach
+        assert_close(points[0].0, f(20.0) - f(30.0));
+        assert_close(points[1].0, 20.0 - f(20.0));
+        assert_close(points[2].0, -30.0 + f(30.0));
+    }
+
+    #[test]
+    fn attraction_only_acts_on_the_attracted_type() {
+        let (device, queue) = gpu_or_skip!();
+        let yaml = "---
+ruleset:
+  types:
+    - attractions: [0.0, 1.0]
+      min_r: [10.0, 10.0]
+      max_r: [50.0, 50.0]
+    - attractions: [0.0, 0.0]
+      min_r: [10.0, 10.0]
+      max_r: [50.0, 50.0]
+  friction: 0.0
+walls: {type: none}
+points: 2
+";
+        let mut simulation = simulation_from_yaml(
+            &device,
+            &queue,
+            yaml,
+            &[[0.0, 0.0, 0.0, 0.0], [20.0, 0.0, 0.0, 0.0]],
+            &[0, 1],
+        );
+        simulation.step(&device, &queue);
+        let points = simulation.read_points(&device, &queue);
+
+        let f = 1.0 * (1.0 - (2.0 * 20.0f32 - 60.0).abs() / 40.0);
+        assert_close(points[0].0, f);
+        assert_close(points[1].0, 20.0);
+    }
+
+    #[test]
+    fn friction_slows_particles_after_they_move() {
+        let (device, queue) = gpu_or_skip!();
+        let mut simulation = simulation(
+            &device,
+            &queue,
+            "{type: none}",
+            0.0,
+            0.0,
+            1.0,
+            &[[0.0, 0.0, 2.0, -1.0]],
+        );
+        simulation.globals_values.friction = 0.25;
+        simulation.globals_dirty = true;
+        simulation.step(&device, &queue);
+        let points = simulation.read_points(&device, &queue);
+        let velocities = simulation.read_velocities(&device, &queue);
+
+        assert_close(points[0].0, 2.0);
+        assert_close(points[0].1, -1.0);
+        assert_close(velocities[0][0], 1.5);
+        assert_close(velocities[0][1], -0.75);
+    }
+
+    #[test]
+    fn quadratic_drag_slows_fast_particles_more() {
+        let (device, queue) = gpu_or_skip!();
+        let mut simulation = simulation(
+            &device,
+            &queue,
+            "{type: none}",
+            0.0,
+            0.0,
+            1.0,
+            &[[0.0, 0.0, 6.0, -8.0], [50.0, 0.0, 1.0, 0.0]],
+        );
+        simulation.globals_values.friction = 0.1;
+        simulation.globals_values.drag = Drag::Quadratic;
+        simulation.globals_dirty = true;
+        simulation.step(&device, &queue);
+        let points = simulation.read_points(&device, &queue);
+        let velocities = simulation.read_velocities(&device, &queue);
+
+        assert_close(points[0].0, 6.0);
+        // A speed of 10 halves, a speed of 1 loses a tenth of itself
+        assert_close(velocities[0][0], 3.0);
+        assert_close(velocities[0][1], -4.0);
+        assert_close(velocities[1][0], 1.0 / 1.1);
+    }
+
+    #[test]
+    fn no_drag_ignores_friction() {
+        let (device, queue) = gpu_or_skip!();
+        let mut simulation = simulation(
+            &device,
+            &queue,
+            "{type: none}",
+            0.0,
+            0.0,
+            1.0,
+            &[[0.0, 0.0, 2.0, -1.0]],
+        );
+        simulation.globals_values.friction = 0.25;
+        simulation.globals_values.drag = Drag::None;
+        simulation.globals_dirty = true;
+        simulation.step(&device, &queue);
+        let velocities = simulation.read_velocities(&device, &queue);
+
+        assert_close(velocities[0][0], 2.0);
+        assert_close(velocities[0][1], -1.0);
+    }
+
+    #[test]
+    fn schedules_ramp_friction() {
+        let (device, queue) = gpu_or_skip!();
+        let yaml = "---
+ruleset:
+  types:
+    - attractions: [0.0]
+      min_r: [0.0]
+      max_r: [1.0]
+  friction: 0.0
+walls: {type: none}
+points: 1
+schedules:
+  - parameter: friction
+    from: 0.5
+    to: 0.0
+    steps: 2
+";
+        let mut simulation =
+            simulation_from_yaml(&device, &queue, yaml, &[[0.0, 0.0, 2.0, 0.0]], &[0]);
+        simulation.step(&device, &queue);
+        simulation.step(&device, &queue);
+        let points = simulation.read_points(&device, &queue);
+        let velocities = simulation.read_velocities(&device, &queue);
+
+        // Halved in the first step, then slowed by a quarter halfway through the ramp
+        assert_close(points[0].0, 3.0);
+        assert_close(velocities[0][0], 0.75);
+        assert_close(simulation.globals_values.friction, 0.25);
+    }
+
+    #[test]
+    fn zones_scale_attraction_and_friction() {
+        let (device, queue) = gpu_or_skip!();
+        let yaml = "---
+ruleset:
+  types:
+    - attractions: [1.0]
+      min_r: [0.0]
+      max_r: [10.0]
+  friction: 0.5
+walls: {type: none}
+points: 4
+zones:
+  - center: [2.5, 0.0]
+    radius: 4.0
+    attraction: 2.0
+    friction: 0.0
+";
+        let particles = [
+            [0.0, 0.0, 0.0, 0.0],
+            [5.0, 0.0, 0.0, 0.0],
+            [50.0, 0.0, 0.0, 0.0],
+            [55.0, 0.0, 0.0, 0.0],
+        ];
+        let mut simulation = simulation_from_yaml(&device, &queue, yaml, &particles, &[0; 4]);
+        simulation.step(&device, &queue);
+        let velocities = simulation.read_velocities(&device, &queue);
+
+        // Both pairs sit where the force is strongest. The pair inside the zone is pulled twice as
+        // hard and keeps all of it, the other pair loses half to friction.
+        assert_close(velocities[0][0].abs(), 2.0);
+        assert_close(velocities[2][0].abs(), 0.5);
+    }
+
+    #[test]
+    fn ruleset_caches_are_indexed_by_type() {
+        let (device, queue) = gpu_or_skip!();
+        // The first particle being of type 1 must not change whose rules apply to whom
+        let path = std::env::temp_dir().join("plife_cache_index_test.csv");
+        write_csv_points(&path, &[(0.0, 0.0, Some(1)), (20.0, 0.0, Some(0))]).unwrap();
+        let yaml = format!(
+            "---
+ruleset:
+  types:
+    - attractions: [0.0, 0.5]
+      min_r: [10.0, 10.0]
+      max_r: [50.0, 50.0]
+    - attractions: [0.0, 0.0]
+      min_r: [10.0, 10.0]
+      max_r: [50.0, 50.0]
+  friction: 0.0
+walls: {{type: none}}
+points:
+  path: {}
+  format: csv
+  typed: true
+",
+            path.display()
+        );
+        let config = Config::from_reader(yaml.as_bytes(), ConfigFormat::Yaml, &[]).unwrap();
+        let mut simulation =
+            Simulation::from_config(&device, config, DEFAULT_WORKGROUP_SIZE).unwrap();
+        simulation.step(&device, &queue);
+        let points = simulation.read_points(&device, &queue);
+
+        // Only type 0 is attracted to type 1
+        let f = 0.5 * (1.0 - (2.0 * 20.0 - 60.0f32).abs() / 40.0);
+        assert_close(points[0].0, 0.0);
+        assert_close(points[1].0, 20.0 - f);
+    }
+
+    #[test]
+    fn gravity_pulls_by_mass_beyond_max_r() {
+        let (device, queue) = gpu_or_skip!();
+        let yaml = "---
+ruleset:
+  types:
+    - attractions: [0.0, 0.0]
+      min_r: [0.0, 0.0]
+      max_r: [1.0, 1.0]
+    - attractions: [0.0, 0.0]
+      min_r: [0.0, 0.0]
+      max_r: [1.0, 1.0]
+  friction: 0.0
+walls: {type: none}
+points: 2
+gravity:
+  strength: 100.0
+  softening: 0.0
+  masses: [1.0, 4.0]
+";
+        let particles = [[0.0, 0.0, 0.0, 0.0], [10.0, 0.0, 0.0, 0.0]];
+        let mut simulation = simulation_from_yaml(&device, &queue, yaml, &particles, &[0, 1]);
+        simulation.step(&device, &queue);
+        let velocities = simulation.read_velocities(&device, &queue);
+
+        // Each is pulled by the other's mass times 100 over their distance squared
+        assert_close(velocities[0][0], 4.0);
+        assert_close(velocities[1][0], -1.0);
+    }
+
+    #[test]
+    fn set_parameter_changes_friction_from_the_next_step() {
+        let (device, queue) = gpu_or_skip!();
+        let mut simulation = simulation(
+            &device,
+            &queue,
+            "{type: none}",
+            0.0,
+            0.0,
+            1.0,
+            &[[0.0, 0.0, 2.0, 0.0]],
+        );
+        assert!(simulation
+            .set_parameter(ScheduledParameter::Friction, 1.5)
+            .is_err());
+        simulation
+            .set_parameter(ScheduledParameter::Friction, 0.5)
+            .unwrap();
+        simulation.step(&device, &queue);
+
+        assert_close(simulation.read_velocities(&device, &queue)[0][0], 1.0);
+        assert_close(simulation.ruleset.friction, 0.5);
+    }
+
+    #[test]
+    fn reactions_change_types_near_catalysts() {
+        let (device, queue) = gpu_or_skip!();
+        let yaml = "---
+ruleset:
+  types:
+    - attractions: [0.0, 0.0, 0.0]
+      min_r: [0.0, 0.0, 0.0]
+      max_r: [1.0, 1.0, 1.0]
+    - attractions: [0.0, 0.0, 0.0]
+      min_r: [0.0, 0.0, 0.0]
+      max_r: [1.0, 1.0, 1.0]
+    - attractions: [0.0, 0.0, 0.0]
+      min_r: [0.0, 0.0, 0.0]
+      max_r: [1.0, 1.0, 1.0]
+  friction: 0.0
+walls: {type: none}
+points: 4
+reactions:
+  - {catalyst: 0, reactant: 1, product: 2, radius: 10.0, probability: 1.0}
+  - {catalyst: 1, reactant: 0, product: 2, radius: 10.0, probability: 0.0}
+";
+        let mut simulation = simulation_from_yaml(
+            &device,
+            &queue,
+            yaml,
+            &[
+                [0.0, 0.0, 0.0, 0.0],
+                [5.0, 0.0, 0.0, 0.0],
+                [50.0, 0.0, 0.0, 0.0],
+                [0.0, 8.0, 0.0, 0.0],
+            ],
+            &[0, 1, 1, 1],
+        );
+        simulation.step(&device, &queue);
+        let types = simulation
+            .read_points(&device, &queue)
+            .iter()
+            .map(|&(_, _, type_)| type_.unwrap())
+            .collect::<Vec<_>>();
+
+        // The far one has no catalyst nearby, and the catalyst itself never rolls a reaction
+        assert_eq!(types, [0, 2, 1, 2]);
+    }
+
+    #[test]
+    fn bonds_pull_toward_their_rest_length() {
+        let (device, queue) = gpu_or_skip!();
+        let yaml = "---
+ruleset:
+  types:
+    - attractions: [0.0]
+      min_r: [0.0]
+      max_r: [1.0]
+  friction: 0.0
+walls: {type: none}
+points: 4
+bonds:
+  - {a: 0, b: 1, length: 10.0, stiffness: 0.1}
+  - {a: 2, b: 3, length: 10.0, stiffness: 0.1}
+";
+        let mut simulation = simulation_from_yaml(
+            &device,
+            &queue,
+            yaml,
+            &[
+                [0.0, 0.0, 0.0, 0.0],
+                [20.0, 0.0, 0.0, 0.0],
+                [0.0, 100.0, 0.0, 0.0],
+                [0.0, 105.0, 0.0, 0.0],
+            ],
+            &[0, 0, 0, 0],
+        );
+        simulation.step(&device, &queue);
+        let velocities = simulation.read_velocities(&device, &queue);
+
+        // Stretched by 10 and squeezed by 5
+        assert_close(velocities[0][0], 1.0);
+        assert_close(velocities[1][0], -1.0);
+        assert_close(velocities[2][1], -0.5);
+        assert_close(velocities[3][1], 0.5);
+    }
+
+    #[test]
+    fn max_velocity_slows_runaway_particles() {
+        let (device, queue) = gpu_or_skip!();
+        let mut simulation = simulation(
+            &device,
+            &queue,
+            "{type: none}",
+            0.0,
+            0.0,
+            1.0,
+            &[[0.0, 0.0, 6.0, -8.0], [50.0, 0.0, 1.0, 0.0]],
+        );
+        simulation.globals_values.max_velocity = 5.0;
+        simulation.globals_dirty = true;
+        simulation.step(&device, &queue);
+        let points = simulation.read_points(&device, &queue);
+        let velocities = simulation.read_velocities(&device, &queue);
+
+        assert_close(points[0].0, 3.0);
+        assert_close(points[0].1, -4.0);
+        assert_close(velocities[0][0], 3.0);
+        assert_close(points[1].0, 51.0);
+    }
+
+    #[test]
+    fn integrators_move_by_different_velocities() {
+        let (device, queue) = gpu_or_skip!();
+        let (attraction, min_r, max_r) = (0.5, 10.0, 50.0);
+        let f = attraction * (1.0 - (2.0 * 20.0f32 - (max_r + min_r)).abs() / (max_r - min_r));
+        // Where the first particle is after one step, and how fast it goes
+        let expected = [
+            (Integrator::Euler, 1.0, 1.0 + f),
+            (Integrator::SemiImplicit, 1.0 + f, 1.0 + f),
+            // Without forces from a step before, half of this step's force goes into the velocity
+            (Integrator::Verlet, 1.0 + f, 1.0 + f / 2.0),
+        ];
+        for &(integrator, x, velocity) in expected.iter() {
+            let mut simulation = simulation(
+                &device,
+                &queue,
+                "{type: none}",
+                attraction,
+                min_r,
+                max_r,
+                &[[0.0, 0.0, 1.0, 0.0], [20.0, 0.0, 1.0, 0.0]],
+            );
+            simulation.globals_values.integrator = integrator;
+            simulation.globals_dirty = true;
+            simulation.step(&device, &queue);
+            let points = simulation.read_points(&device, &queue);
+            let velocities = simulation.read_velocities(&device, &queue);
+
+            assert_close(points[0].0, x);
+            assert_close(velocities[0][0], velocity);
+        }
+    }
+
+    #[test]
+    fn verlet_averages_forces_of_consecutive_steps() {
+        let (device, queue) = gpu_or_skip!();
+        let (attraction, min_r, max_r) = (0.5, 10.0, 50.0);
+        let mut simulation = simulation(
+            &device,
+            &queue,
+            "{type: none}",
+            attraction,
+            min_r,
+            max_r,
+            &[[0.0, 0.0, 0.0, 0.0], [20.0, 0.0, 0.0, 0.0]],
+        );
+        simulation.globals_values.integrator = Integrator::Verlet;
+        simulation.globals_dirty = true;
+        simulation.step(&device, &queue);
+        simulation.step(&device, &queue);
+        let points = simulation.read_points(&device, &queue);
+        let velocities = simulation.read_velocities(&device, &queue);
+
+        let f = |r: f32| attraction * (1.0 - (2.0 * r - (max_r + min_r)).abs() / (max_r - min_r));
+        let first = f(20.0);
+        // Both moved `first` toward each other in the first step
+        let second = f(20.0 - 2.0 * first);
+        let velocity = first / 2.0 + (first + second) / 2.0;
+        assert_close(velocities[0][0], velocity);
+        assert_close(points[0].0, first + velocity + second / 2.0);
+    }
+
+    #[test]
+    fn disabled_interactions_do_not_attract() {
+        let (device, queue) = gpu_or_skip!();
+        let mut simulation = simulation(
+            &device,
+            &queue,
+            "{type: none}",
+            0.5,
+            10.0,
+            50.0,
+            &[[0.0, 0.0, 0.0, 0.0], [20.0, 0.0, 0.0, 0.0]],
+        );
+        simulation.set_interaction_enabled(&queue, 0, 0, false);
+        simulation.step(&device, &queue);
+        let points = simulation.read_points(&device, &queue);
+
+        assert_close(points[0].0, 0.0);
+        assert_close(points[1].0, 20.0);
+    }
+
+    #[test]
+    fn flows_carry_particles_inside_them() {
+        let (device, queue) = gpu_or_skip!();
+        let yaml = "---
+ruleset:
+  types:
+    - attractions: [0.0]
+      min_r: [0.0]
+      max_r: [1.0]
+  friction: 0.0
+walls: {type: none}
+points: 2
+flows:
+  - velocity: [1.5, -0.5]
+    x: [-5.0, 5.0]
+    y: [-5.0, 5.0]
+";
+        let mut simulation = simulation_from_yaml(
+            &device,
+            &queue,
+            yaml,
+            &[[0.0, 0.0, 0.0, 0.0], [20.0, 0.0, 0.0, 0.0]],
+            &[0, 0],
+        );
+        simulation.step(&device, &queue);
+        let points = simulation.read_points(&device, &queue);
+        let velocities = simulation.read_velocities(&device, &queue);
+
+        assert_close(points[0].0, 1.5);
+        assert_close(points[0].1, -0.5);
+        // Flows move particles without speeding them up
+        assert_close(velocities[0][0], 0.0);
+        assert_close(points[1].0, 20.0);
+    }
+
+    #[test]
+    fn square_walls_reflect() {
+        let (device, queue) = gpu_or_skip!();
+        let mut simulation = simulation(
+            &device,
+            &queue,
+            "{type: square, dist: 10.0, restitution: 0.5}",
+            0.0,
+            0.0,
+            1.0,
+            &[[9.5, 0.0, 1.0, 0.0]],
+        );
+        simulation.step(&device, &queue);
+        let points = simulation.read_points(&device, &queue);
+        let velocities = simulation.read_velocities(&device, &queue);
+
+        assert_close(points[0].0, 10.0);
+        assert_close(velocities[0][0], -0.5);
+    }
+
+    #[test]
+    fn wrapping_walls_wrap() {
+        let (device, queue) = gpu_or_skip!();
+        let mut simulation = simulation(
+            &device,
+            &queue,
+            "{type: wrapping, dist: 10.0}",
+            0.0,
+            0.0,
+            1.0,
+            &[[9.5, 0.0, 1.0, 0.0]],
+        );
+        simulation.step(&device, &queue);
+        let points = simulation.read_points(&device, &queue);
+
+        assert_close(points[0].0, -9.5);
+    }
+
+    #[test]
+    fn wrapping_walls_attract_across_the_seam() {
+        let (device, queue) = gpu_or_skip!();
+        let (attraction, min_r, max_r) = (0.5, 10.0, 50.0);
+        let mut simulation = simulation(
+            &device,
+            &queue,
+            "{type: wrapping, dist: 100.0}",
+            attraction,
+            min_r,
+            max_r,
+            &[[90.0, 0.0, 0.0, 0.0], [-90.0, 0.0, 0.0, 0.0]],
+        );
+        simulation.step(&device, &queue);
+        let points = simulation.read_points(&device, &queue);
+
+        // 180 apart directly, but only 20 across the seam
+        let f = attraction * (1.0 - (2.0 * 20.0f32 - (max_r + min_r)).abs() / (max_r - min_r));
+        assert_close(points[0].0, 90.0 + f);
+        assert_close(points[1].0, -90.0 - f);
+    }
+
+    #[test]
+    fn emitters_add_particles_and_lifetimes_remove_them() {
+        let (device, queue) = gpu_or_skip!();
+        let yaml = "---
+ruleset:
+  types:
+    - attractions: [0.0, 0.0]
+      min_r: [0.0, 0.0]
+      max_r: [1.0, 1.0]
+    - attractions: [0.0, 0.0]
+      min_r: [0.0, 0.0]
+      max_r: [1.0, 1.0]
+  friction: 0.0
+walls: {type: none}
+points: 2
+emitters:
+  - per_second: 60
+    type: 1
+    x: 5.0
+    y: -5.0
+lifetimes:
+  - type: 0
+    steps: 5
+";
+        let mut simulation = simulation_from_yaml(
+            &device,
+            &queue,
+            yaml,
+            &[[0.0, 0.0, 0.0, 0.0], [10.0, 0.0, 0.0, 0.0]],
+            &[0, 1],
+        );
+        simulation.step_many(&device, &queue, 3);
+        let points = simulation.read_points(&device, &queue);
+        assert_eq!(points.len(), 5);
+        assert_eq!(points[4], (5.0, -5.0, Some(1)));
+
+        // Lifetimes are checked along with drains, every 10 steps
+        simulation.step_many(&device, &queue, 7);
+        let points = simulation.read_points(&device, &queue);
+        assert_eq!(points.len(), 11);
+        assert!(points.iter().all(|&(_, _, type_)| type_ == Some(1)));
+    }
+
+    #[test]
+    fn empty_simulation_steps() {
+        let (device, queue) = gpu_or_skip!();
+        let mut simulation = simulation(&device, &queue, "{type: none}", 0.0, 0.0, 1.0, &[]);
+        simulation.step(&device, &queue);
+
+        assert!(simulation.read_points(&device, &queue).is_empty());
+        assert!(simulation.read_velocities(&device, &queue).is_empty());
+    }
+
+    #[test]
+    fn empty_simulation_can_be_populated() {
+        let (device, queue) = gpu_or_skip!();
+        let mut simulation = simulation(&device, &queue, "{type: none}", 0.0, 0.0, 1.0, &[]);
+        simulation.step(&device, &queue);
+        let added = simulation.spawn(&queue, &[(1.0, 2.0, Some(0)), (-3.0, 4.0, Some(0))]);
+        simulation.step(&device, &queue);
+        let points = simulation.read_points(&device, &queue);
+
+        assert_eq!(added, 2);
+        assert_eq!(points.len(), 2);
+        assert_close(points[0].0, 1.0);
+        assert_close(points[1].1, 4.0);
+    }
+
+    #[test]
+    fn explosion_check_goes_back_to_the_last_good_particles() {
+        let (device, queue) = gpu_or_skip!();
+        let mut simulation = simulation(
+            &device,
+            &queue,
+            "{type: none}",
+            0.0,
+            0.0,
+            1.0,
+            &[[1.0, 2.0, 0.0, 0.0], [3.0, 4.0, 0.0, 0.0]],
+        );
+        let check =
+            ExplosionCheck::from_iter(&["plife", "--explosion-check", "1", "--explosion-restore"]);
+        let mut state = check.start();
+        assert!(state
+            .check(&check, &device, &queue, &mut simulation, 0)
+            .is_none());
+
+        simulation.restore(
+            &queue,
+            &[(f32::NAN, 2.0, Some(0)), (3.0, 4.0, Some(0))],
+            &[[0.0, 0.0], [500.0, 0.0]],
+        );
+        let explosion = state
+            .check(&check, &device, &queue, &mut simulation, 1)
+            .expect("explosion was not caught");
+        let points = simulation.read_points(&device, &queue);
+
+        assert_eq!(explosion.non_finite, 1);
+        assert_eq!(explosion.too_fast, 1);
+        assert_eq!(explosion.restored_to, Some(0));
+        assert_close(points[0].0, 1.0);
+        assert_close(simulation.read_velocities(&device, &queue)[1][0], 0.0);
+    }
+
+    #[test]
+    fn cpu_steps_match_gpu() {
+        let (device, queue) = gpu_or_skip!();
+        let cases = [
+            ("{type: square, dist: 60.0}", "semi_implicit", "linear"),
+            ("{type: wrapping, dist: 60.0}", "semi_implicit", "linear"),
+            ("{type: square, dist: 60.0}", "euler", "linear"),
+            ("{type: square, dist: 60.0}", "verlet", "linear"),
+            ("{type: square, dist: 60.0}", "semi_implicit", "quadratic"),
+        ];
+        for (walls, integrator, drag) in cases.iter() {
+            let yaml = format!(
+                "---
+ruleset:
+  types: 3
+  attractions: {{mean: 0.0, std: 0.5}}
+  min_r: {{min: 5.0, max: 10.0}}
+  max_r: {{min: 20.0, max: 40.0}}
+  friction: 0.05
+walls: {}
+points:
+  - num: 200
+    x: {{min: -60.0, max: 60.0}}
+    y: {{min: -60.0, max: 60.0}}
+integrator: {}
+drag: {}
+",
+                walls, integrator, drag
+            );
+            seed_rng(3);
+            let config = Config::from_reader(yaml.as_bytes(), ConfigFormat::Yaml, &[]).unwrap();
+            let mut simulation =
+                Simulation::from_config(&device, config, DEFAULT_WORKGROUP_SIZE).unwrap();
+            let mut cpu = CpuSimulation::new(
+                &simulation.ruleset,
+                |_, _| true,
+                &simulation.read_points(&device, &queue),
+                &simulation.read_velocities(&device, &queue),
+                &simulation.read_vec2s(&device, &queue, &simulation.accelerations.buffer),
+            );
+            for _ in 0..5 {
+                simulation.step(&device, &queue);
+                cpu.step(
+                    &simulation.globals_values,
+                    &simulation.flows,
+                    &simulation.zones,
+                    &simulation.masses,
+                );
+            }
+
+            let gpu_points = simulation.read_points(&device, &queue);
+            for (gpu, cpu) in gpu_points.iter().zip(cpu.points()) {
+                // Particles that wrapped around on one side and not the other are far apart
+                if (gpu.0 - cpu.0).abs() > 60.0 || (gpu.1 - cpu.1).abs() > 60.0 {
+                    continue;
+                }
+                assert!(
+                    (gpu.0 - cpu.0).abs() < 1e-2 && (gpu.1 - cpu.1).abs() < 1e-2,
+                    "GPU moved a particle to {:?}, CPU to {:?}",
+                    gpu,
+                    cpu
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn seeding_reproduces_sampled_configs() {
+        let yaml = "---
+ruleset:
+  types: 3
+  attractions: {mean: 0.0, std: 0.1}
+  min_r: {min: 0.0, max: 20.0}
+  max_r: {min: 20.0, max: 100.0}
+  friction: 0.05
+walls: {type: square, dist: 100.0}
+points:
+  - num: 20
+    x: {min: -100.0, max: 100.0}
+    y: {mean: 0.0, std: 10.0}
+";
+        let sample = |seed| {
+            seed_rng(seed);
+            Config::from_reader(yaml.as_bytes(), ConfigFormat::Yaml, &[])
+                .unwrap()
+                .sample()
+        };
+        let ((ruleset_a, _, points_a), (ruleset_b, _, points_b)) = (sample(7), sample(7));
+        assert_eq!(ruleset_a.attractions, ruleset_b.attractions);
+        assert_eq!(points_a, points_b);
+        let (ruleset_c, _, _) = sample(8);
+        assert_ne!(ruleset_a.attractions, ruleset_c.attractions);
+    }
+
+    #[test]
+    fn symmetry_ties_sampled_attractions() {
+        let sample = |symmetry: &str, zero_diagonal: bool| {
+            let yaml = format!(
+                "---
+ruleset:
+  types: 4
+  attractions: {{min: -1.0, max: 1.0}}
+  min_r: {{min: 0.0, max: 20.0}}
+  max_r: {{min: 20.0, max: 100.0}}
+  friction: 0.05
+  symmetry: {}
+  zero_diagonal: {}
+walls: {{type: none}}
+points: 0
+",
+                symmetry, zero_diagonal
+            );
+            let (ruleset, _, _) = Config::from_reader(yaml.as_bytes(), ConfigFormat::Yaml, &[])
+                .unwrap()
+                .sample();
+            ruleset
+        };
+        let symmetric = sample("symmetric", false);
+        let antisymmetric = sample("antisymmetric", false);
+        let zero_diagonal = sample("none", true);
+        for i in 0..4 {
+            for j in 0..4 {
+                assert_eq!(symmetric.attractions[i][j], symmetric.attractions[j][i]);
+                assert_eq!(symmetric.max_r[i][j], symmetric.max_r[j][i]);
+                assert_eq!(
+                    antisymmetric.attractions[i][j],
+                    -antisymmetric.attractions[j][i]
+                );
+                assert_eq!(antisymmetric.min_r[i][j], antisymmetric.min_r[j][i]);
+            }
+            assert_eq!(antisymmetric.attractions[i][i], 0.0);
+            assert_eq!(zero_diagonal.attractions[i][i], 0.0);
+        }
     }
 }
@@ -0,0 +1,106 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+};
+
+use crate::{
+    simulation::{Point, PointType, Walls},
+    util::hue_to_rgb,
+};
+
+const SIZE: u32 = 800;
+/// Size of each image in a grid
+const TILE_SIZE: u32 = 256;
+/// Gap between the images in a grid
+const TILE_GAP: u32 = 4;
+const POINT_RADIUS: i64 = 2;
+
+/// Renders points as colored dots on black and saves them as a PNG image. Types get evenly
+/// spaced hues so that images of different runs can be compared.
+pub fn save_png(
+    path: &Path,
+    points: &[Point],
+    num_types: PointType,
+    walls: &Walls,
+) -> io::Result<()> {
+    write_png(path, &render(points, num_types, walls, SIZE), SIZE, SIZE)
+}
+
+/// Renders several sets of points side by side, in rows of `columns` images, and saves them as a
+/// single PNG image
+pub fn save_grid_png(
+    path: &Path,
+    tiles: &[Vec<Point>],
+    num_types: PointType,
+    walls: &Walls,
+    columns: usize,
+) -> io::Result<()> {
+    let columns = columns.clamp(1, tiles.len().max(1));
+    let rows = (tiles.len() + columns - 1) / columns;
+    let width = columns as u32 * (TILE_SIZE + TILE_GAP) - TILE_GAP;
+    let height = (rows.max(1) as u32 * (TILE_SIZE + TILE_GAP))
+        .saturating_sub(TILE_GAP)
+        .max(1);
+    let mut pixels = vec![0u8; (width * height * 3) as usize];
+    for (i, points) in tiles.iter().enumerate() {
+        let tile = render(points, num_types, walls, TILE_SIZE);
+        let left = (i % columns) as u32 * (TILE_SIZE + TILE_GAP);
+        let top = (i / columns) as u32 * (TILE_SIZE + TILE_GAP);
+        for y in 0..TILE_SIZE {
+            let from = (y * TILE_SIZE * 3) as usize;
+            let to = (((top + y) * width + left) * 3) as usize;
+            pixels[to..to + (TILE_SIZE * 3) as usize]
+                .copy_from_slice(&tile[from..from + (TILE_SIZE * 3) as usize]);
+        }
+    }
+    write_png(path, &pixels, width, height)
+}
+
+/// RGB pixels of a `size` by `size` image of the points
+fn render(points: &[Point], num_types: PointType, walls: &Walls, size: u32) -> Vec<u8> {
+    let extent = match walls {
+        Walls::Square(dist) | Walls::Wrapping(dist) => *dist,
+        Walls::None => points
+            .iter()
+            .map(|&(x, y, _)| x.abs().max(y.abs()))
+            .filter(|d| d.is_finite())
+            .fold(1.0, f32::max),
+    };
+    let scale = size as f32 / (extent * 2.0);
+
+    let mut pixels = vec![0u8; (size * size * 3) as usize];
+    for &(x, y, type_) in points {
+        let hue = type_.unwrap_or(0) as f32 / num_types.max(1) as f32;
+        let color = hue_to_rgb(hue);
+        let px = ((x + extent) * scale) as i64;
+        let py = ((extent - y) * scale) as i64;
+        for dy in -POINT_RADIUS..=POINT_RADIUS {
+            for dx in -POINT_RADIUS..=POINT_RADIUS {
+                let (sx, sy) = (px + dx, py + dy);
+                if dx * dx + dy * dy > POINT_RADIUS * POINT_RADIUS
+                    || sx < 0
+                    || sy < 0
+                    || sx >= size as i64
+                    || sy >= size as i64
+                {
+                    continue;
+                }
+                let i = (sy as usize * size as usize + sx as usize) * 3;
+                for (channel, value) in color.iter().enumerate() {
+                    pixels[i + channel] = (value * 255.0) as u8;
+                }
+            }
+        }
+    }
+    pixels
+}
+
+fn write_png(path: &Path, pixels: &[u8], width: u32, height: u32) -> io::Result<()> {
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(pixels)?;
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+    time::Duration,
+};
+
+use crate::serialize::ConfigFormat;
+
+/// Longest a config may take to download
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+/// Most bytes read from a URL, so that a wrong link cannot fill up memory
+const MAX_FETCH_BYTES: u64 = 16 << 20;
+
+/// A config given as `-`, read from standard input
+pub fn is_stdin(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// A config given as an `http://` or `https://` URL
+fn url(path: &Path) -> Option<&str> {
+    path.to_str()
+        .filter(|s| s.starts_with("http://") || s.starts_with("https://"))
+}
+
+/// Reads the text of a config from a file, from standard input for `-`, or from an `http://` or
+/// `https://` URL
+pub fn read_config(path: &Path) -> Result<Vec<u8>, String> {
+    let mut text = Vec::new();
+    if is_stdin(path) {
+        io::stdin()
+            .read_to_end(&mut text)
+            .map_err(|e| format!("Cannot read config from standard input: {}", e))?;
+    } else if let Some(url) = url(path) {
+        let response = ureq::AgentBuilder::new()
+            .timeout(FETCH_TIMEOUT)
+            .build()
+            .get(url)
+            .call()
+            .map_err(|e| format!("Cannot fetch config from {}: {}", url, e))?;
+        response
+            .into_reader()
+            .take(MAX_FETCH_BYTES + 1)
+            .read_to_end(&mut text)
+            .map_err(|e| format!("Cannot fetch config from {}: {}", url, e))?;
+        if text.len() as u64 > MAX_FETCH_BYTES {
+            return Err(format!(
+                "Cannot fetch config from {}: it exceeds {} MB",
+                url,
+                MAX_FETCH_BYTES >> 20
+            ));
+        }
+    } else {
+        text = fs::read(path)
+            .map_err(|e| format!("Cannot open config file {}: {}", path.display(), e))?;
+    }
+    Ok(text)
+}
+
+/// The format of a config by its extension, ignoring the query and fragment of URLs. Configs read
+/// from standard input are taken to be YAML, which JSON is also read as.
+pub fn config_format(path: &Path) -> ConfigFormat {
+    match url(path) {
+        Some(url) => {
+            let end = url.find(|c| c == '?' || c == '#').unwrap_or(url.len());
+            ConfigFormat::from_path(Path::new(&url[..end]))
+        }
+        None => ConfigFormat::from_path(path),
+    }
+}
@@ -0,0 +1,252 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use serde::Serialize;
+
+use crate::{
+    analysis::{ClusterStats, MotionStats, NeighbourStats, TypeStats},
+    gpu_profile::GpuTimings,
+};
+
+/// Measurements taken at a headless checkpoint
+#[derive(Serialize)]
+pub struct CheckpointStats {
+    pub checkpoint: u64,
+    pub steps: u64,
+    /// Seconds since the simulation started
+    pub wall_time: f64,
+    /// Average steps per second since the previous checkpoint
+    pub tps: f64,
+    /// Only measured when written to a stats file or printed in full
+    #[serde(flatten)]
+    pub motion: Option<MotionStats>,
+    /// Only measured with `--clusters`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clusters: Option<ClusterStats>,
+    /// Only measured with `--profile-gpu`, on adapters with timestamp queries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gpu: Option<GpuTimings>,
+    /// Only measured with `--type-stats`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub types: Vec<TypeStats>,
+    /// Only measured with `--neighbour-stats`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub neighbours: Vec<NeighbourStats>,
+}
+
+impl CheckpointStats {
+    const CSV_HEADER: &'static str =
+        "checkpoint,steps,wall_time,tps,kinetic_energy,mean_speed,clusters,largest_cluster,mean_cluster_size";
+
+    /// The fixed columns, the GPU timings if measured, then four columns for each type for each
+    /// per-type measurement, in the same order as `to_csv_row`
+    fn csv_header(&self) -> String {
+        let mut header = Self::CSV_HEADER.to_owned();
+        if self.gpu.is_some() {
+            header += ",gpu_compute_ms,gpu_busy";
+        }
+        for stats in &self.types {
+            let t = stats.type_;
+            header += &format!(",type{}_count,type{}_x,type{}_y,type{}_radius", t, t, t, t);
+        }
+        for stats in &self.neighbours {
+            let t = stats.type_;
+            header += &format!(
+                ",type{}_nn_mean,type{}_nn_p10,type{}_nn_median,type{}_nn_p90",
+                t, t, t, t
+            );
+        }
+        header
+    }
+
+    /// Motion and cluster columns are left empty when they were not measured
+    fn to_csv_row(&self) -> String {
+        let motion = match &self.motion {
+            Some(motion) => format!("{},{}", motion.kinetic_energy, motion.mean_speed),
+            None => ",".to_owned(),
+        };
+        let clusters = match &self.clusters {
+            Some(clusters) => format!(
+                "{},{},{}",
+                clusters.count, clusters.largest, clusters.mean_size
+            ),
+            None => ",,".to_owned(),
+        };
+        let gpu = match &self.gpu {
+            Some(gpu) => format!(",{},{}", gpu.compute_ms, gpu.busy),
+            None => String::new(),
+        };
+        format!(
+            "{},{},{},{},{},{}{}",
+            self.checkpoint, self.steps, self.wall_time, self.tps, motion, clusters, gpu
+        ) + &self
+            .types
+            .iter()
+            .map(|stats| {
+                format!(
+                    ",{},{},{},{}",
+                    stats.count, stats.center[0], stats.center[1], stats.radius
+                )
+            })
+            .collect::<String>()
+            + &self
+                .neighbours
+                .iter()
+                .map(|stats| {
+                    format!(
+                        ",{},{},{},{}",
+                        stats.mean, stats.p10, stats.median, stats.p90
+                    )
+                })
+                .collect::<String>()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum StatsFormat {
+    /// One JSON object per line
+    Json,
+    /// Comma separated values with a header row
+    Csv,
+}
+
+impl StatsFormat {
+    /// Guesses the format from a file extension, defaulting to JSON lines
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => StatsFormat::Csv,
+            _ => StatsFormat::Json,
+        }
+    }
+}
+
+impl FromStr for StatsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" | "jsonl" => Ok(StatsFormat::Json),
+            "csv" => Ok(StatsFormat::Csv),
+            _ => Err(format!(
+                "Unknown stats format `{}`, expected json or csv",
+                s
+            )),
+        }
+    }
+}
+
+/// How much is printed at each headless checkpoint
+#[derive(Clone, Copy, PartialEq)]
+pub enum CheckpointFormat {
+    /// A single line with the step count and speed
+    Minimal,
+    /// Every measurement taken, over several lines
+    Full,
+}
+
+impl FromStr for CheckpointFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "minimal" => Ok(CheckpointFormat::Minimal),
+            "full" => Ok(CheckpointFormat::Full),
+            _ => Err(format!(
+                "Unknown checkpoint format `{}`, expected minimal or full",
+                s
+            )),
+        }
+    }
+}
+
+/// Appends checkpoint statistics to a file, flushing after every record so it can be followed live
+pub struct StatsWriter {
+    out: BufWriter<Box<dyn Write>>,
+    format: StatsFormat,
+    /// CSV headers depend on what was measured, so they are written along with the first record
+    needs_header: bool,
+}
+
+impl StatsWriter {
+    /// Writes to standard output if `path` is `-`
+    pub fn create(path: &Path, format: StatsFormat) -> io::Result<Self> {
+        let out: Box<dyn Write> = if path == Path::new("-") {
+            Box::new(io::stdout())
+        } else {
+            Box::new(File::create(path)?)
+        };
+        let out = BufWriter::new(out);
+        Ok(Self {
+            out,
+            format,
+            needs_header: matches!(format, StatsFormat::Csv),
+        })
+    }
+
+    pub fn write(&mut self, stats: &CheckpointStats) -> io::Result<()> {
+        match self.format {
+            StatsFormat::Json => {
+                serde_json::to_writer(&mut self.out, stats)?;
+                writeln!(self.out)?;
+            }
+            StatsFormat::Csv => {
+                if self.needs_header {
+                    writeln!(self.out, "{}", stats.csv_header())?;
+                    self.needs_header = false;
+                }
+                writeln!(self.out, "{}", stats.to_csv_row())?
+            }
+        }
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_header_matches_rows() {
+        let stats = CheckpointStats {
+            checkpoint: 1,
+            steps: 100,
+            wall_time: 1.0,
+            tps: 100.0,
+            motion: Some(MotionStats {
+                kinetic_energy: 1.0,
+                mean_speed: 1.0,
+            }),
+            clusters: None,
+            gpu: Some(GpuTimings {
+                steps: 100,
+                compute_ms: 1.0,
+                busy: 0.5,
+            }),
+            types: (0..2)
+                .map(|type_| TypeStats {
+                    type_,
+                    count: 10,
+                    center: [0.0, 0.0],
+                    radius: 1.0,
+                })
+                .collect(),
+            neighbours: (0..2)
+                .map(|type_| NeighbourStats {
+                    type_,
+                    mean: 1.0,
+                    p10: 0.5,
+                    median: 1.0,
+                    p90: 1.5,
+                })
+                .collect(),
+        };
+        assert_eq!(
+            stats.csv_header().split(',').count(),
+            stats.to_csv_row().split(',').count()
+        );
+    }
+}
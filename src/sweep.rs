@@ -0,0 +1,221 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde_yaml::Value;
+use structopt::StructOpt;
+use tracing::info;
+use wgpu::*;
+
+use crate::{
+    analysis::{self, ClusterStats, MotionStats, CLUSTER_RADIUS},
+    error::ConfigError,
+    evolve::with_sampled,
+    serialize::{Config, ConfigFormat},
+    simulation::{Point, Simulation},
+    snapshot,
+    util::seed_rng,
+};
+
+/// Steps each seed runs before the next one gets its turn when interleaving
+const INTERLEAVE_BATCH: u64 = 64;
+
+#[derive(StructOpt)]
+pub struct SweepOptions {
+    /// Config to run; its ruleset and walls are sampled once and shared by every seed
+    config_file: PathBuf,
+    /// Number of seeds to run
+    #[structopt(long, default_value = "8")]
+    seeds: u64,
+    /// Seed of the first run; the others count up from it
+    #[structopt(long, default_value = "0")]
+    first_seed: u64,
+    /// Steps each seed is simulated for
+    #[structopt(long, default_value = "1000")]
+    steps: u64,
+    /// Step all seeds side by side instead of one after another, keeping them all on the GPU
+    #[structopt(long)]
+    interleave: bool,
+    /// Directory to write the metrics and the image of the final states to
+    #[structopt(long, default_value = "sweep")]
+    out: PathBuf,
+    /// Images per row in the image of the final states
+    #[structopt(long, default_value = "4")]
+    columns: usize,
+}
+
+/// Summary of where one seed ended up
+struct SeedMetrics {
+    seed: u64,
+    clusters: ClusterStats,
+    variance: f32,
+    motion: MotionStats,
+}
+
+impl SeedMetrics {
+    fn measure(seed: u64, device: &Device, queue: &Queue, simulation: &Simulation) -> Self {
+        let points = simulation.read_points(device, queue);
+        Self {
+            seed,
+            clusters: ClusterStats::measure(&points, CLUSTER_RADIUS),
+            variance: analysis::spatial_variance(&points),
+            motion: MotionStats::measure(&simulation.read_velocities(device, queue)),
+        }
+    }
+}
+
+/// Runs the same ruleset from differently seeded starting positions and reports how much the
+/// outcomes differ
+pub fn run(
+    device: &Device,
+    queue: &Queue,
+    options: SweepOptions,
+    workgroup_size: u32,
+) -> anyhow::Result<()> {
+    let path = &options.config_file;
+    let invalid =
+        |e: String| ConfigError(format!("Invalid config file {}:\n{}", path.display(), e));
+    let format = ConfigFormat::from_path(path);
+    let text = fs::read_to_string(path)
+        .map_err(|e| ConfigError(format!("Cannot open config file {}: {}", path.display(), e)))?;
+    let config = |value: Value| Config::from_value(value).map_err(invalid);
+    let base = format.parse_value(&text).map_err(invalid)?;
+    seed_rng(options.first_seed);
+    let (ruleset, walls, _) = config(base.clone())?.sample();
+    let pinned = with_sampled(&base, &ruleset, &walls, None);
+    let seeds = options.first_seed..options.first_seed + options.seeds.max(1);
+    let build = |seed: u64| -> Result<Simulation, ConfigError> {
+        seed_rng(seed);
+        Simulation::from_config(device, config(pinned.clone())?, workgroup_size)
+    };
+
+    let mut results = Vec::new();
+    if options.interleave {
+        let mut simulations = seeds
+            .map(|seed| Ok((seed, build(seed)?)))
+            .collect::<Result<Vec<_>, ConfigError>>()?;
+        let mut step = 0;
+        while step < options.steps {
+            let batch = INTERLEAVE_BATCH.min(options.steps - step);
+            for (_, simulation) in &mut simulations {
+                simulation.step_many(device, queue, batch);
+            }
+            step += batch;
+        }
+        for (seed, simulation) in &simulations {
+            results.push((
+                SeedMetrics::measure(*seed, device, queue, simulation),
+                simulation.read_points(device, queue),
+            ));
+        }
+    } else {
+        for seed in seeds {
+            let mut simulation = build(seed)?;
+            simulation.step_many(device, queue, options.steps);
+            results.push((
+                SeedMetrics::measure(seed, device, queue, &simulation),
+                simulation.read_points(device, queue),
+            ));
+            info!("Seed {} done", seed);
+        }
+    }
+
+    fs::create_dir_all(&options.out)
+        .with_context(|| format!("Cannot create output directory {}", options.out.display()))?;
+    let metrics_path = options.out.join("metrics.csv");
+    write_metrics(&metrics_path, &results)
+        .with_context(|| format!("Cannot write {}", metrics_path.display()))?;
+    let image_path = options.out.join("final.png");
+    let finals = results
+        .iter()
+        .map(|(_, points)| points.clone())
+        .collect::<Vec<_>>();
+    snapshot::save_grid_png(
+        &image_path,
+        &finals,
+        ruleset.num_point_types,
+        &walls,
+        options.columns,
+    )
+    .with_context(|| format!("Cannot write {}", image_path.display()))?;
+
+    let metrics = results
+        .iter()
+        .map(|(metrics, _)| metrics)
+        .collect::<Vec<_>>();
+    for (name, values) in &[
+        (
+            "Clusters",
+            metrics
+                .iter()
+                .map(|m| m.clusters.count as f64)
+                .collect::<Vec<_>>(),
+        ),
+        (
+            "Largest cluster",
+            metrics.iter().map(|m| m.clusters.largest as f64).collect(),
+        ),
+        (
+            "Spatial variance",
+            metrics.iter().map(|m| m.variance as f64).collect(),
+        ),
+        (
+            "Kinetic energy",
+            metrics.iter().map(|m| m.motion.kinetic_energy).collect(),
+        ),
+        (
+            "Mean speed",
+            metrics.iter().map(|m| m.motion.mean_speed).collect(),
+        ),
+    ] {
+        let (mean, std) = mean_and_std(values);
+        println!(
+            "{}: mean {:.3}, standard deviation {:.3} ({:.0}% of the mean)",
+            name,
+            mean,
+            std,
+            if mean == 0.0 {
+                0.0
+            } else {
+                std / mean.abs() * 100.0
+            }
+        );
+    }
+    info!(
+        "Wrote {} and {}",
+        metrics_path.display(),
+        image_path.display()
+    );
+    Ok(())
+}
+
+fn write_metrics(path: &Path, results: &[(SeedMetrics, Vec<Point>)]) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(
+        out,
+        "seed,clusters,largest_cluster,variance,kinetic_energy,mean_speed"
+    )?;
+    for (metrics, _) in results {
+        writeln!(
+            out,
+            "{},{},{},{},{},{}",
+            metrics.seed,
+            metrics.clusters.count,
+            metrics.clusters.largest,
+            metrics.variance,
+            metrics.motion.kinetic_energy,
+            metrics.motion.mean_speed
+        )?;
+    }
+    out.flush()
+}
+
+fn mean_and_std(values: &[f64]) -> (f64, f64) {
+    let n = values.len().max(1) as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / n;
+    (mean, variance.sqrt())
+}
@@ -0,0 +1,205 @@
+use std::{io::Write, mem::size_of};
+
+use wgpu::*;
+
+use crate::{simulation::Simulation, util::BindableBuffer};
+
+/// Width and height of a territory cell in physical pixels
+const CELL_SIZE: u32 = 4;
+const WORKGROUP_SIZE: u32 = 64;
+
+/// Colors the screen by the type of the nearest particle, found on the GPU by jump flooding
+pub struct Territory {
+    /// Screen size the grid was built for
+    width: u32,
+    height: u32,
+    num_cells: u32,
+    clear_pipeline: ComputePipeline,
+    seed_pipeline: ComputePipeline,
+    flood_pipeline: ComputePipeline,
+    render_pipeline: RenderPipeline,
+    /// Writes into the first seed buffer, for clearing and seeding. One for each of the
+    /// simulation's position buffers, as are the flood bind groups.
+    seed_bind_groups: [BindGroup; 2],
+    /// One per flood step, alternating between the seed buffers
+    flood_bind_groups: [Vec<BindGroup>; 2],
+    /// Reads whichever seed buffer the last flood step wrote
+    render_bind_group: BindGroup,
+}
+
+impl Territory {
+    pub fn new(
+        device: &Device,
+        format: TextureFormat,
+        render_globals: &BindableBuffer,
+        simulation: &Simulation,
+        colors: &BindableBuffer,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let grid_width = width.div_ceil(CELL_SIZE);
+        let grid_height = height.div_ceil(CELL_SIZE);
+        let num_cells = (grid_width * grid_height).max(1);
+
+        let seeds = [0, 1].map(|_| {
+            BindableBuffer::new(
+                device,
+                BufferUsage::STORAGE,
+                ShaderStage::COMPUTE | ShaderStage::FRAGMENT,
+                false,
+                num_cells as usize * size_of::<u32>(),
+                |_| {},
+            )
+        });
+        let pass = |step: i32| {
+            BindableBuffer::new(
+                device,
+                BufferUsage::UNIFORM,
+                ShaderStage::COMPUTE | ShaderStage::FRAGMENT,
+                true,
+                size_of::<u32>() * 3 + size_of::<f32>(),
+                |buffer| {
+                    let slice = buffer.slice(..);
+                    let mut view = slice.get_mapped_range_mut();
+                    let mut cursor = std::io::Cursor::new(&mut *view);
+                    cursor.write_all(&grid_width.to_le_bytes()).unwrap();
+                    cursor.write_all(&grid_height.to_le_bytes()).unwrap();
+                    cursor.write_all(&(CELL_SIZE as f32).to_le_bytes()).unwrap();
+                    cursor.write_all(&step.to_le_bytes()).unwrap();
+                },
+            )
+        };
+        let position_buffers = simulation.position_buffers();
+        let bind_group = |pass: &BindableBuffer, from: usize, positions: usize| {
+            BindableBuffer::bind_group(
+                device,
+                &[
+                    render_globals,
+                    &simulation.globals,
+                    pass,
+                    &position_buffers[positions],
+                    &seeds[from],
+                    &seeds[1 - from],
+                    &simulation.types,
+                    colors,
+                ],
+            )
+        };
+
+        // Halve the step from about half the grid down to a single cell
+        let mut steps = Vec::new();
+        let mut step = grid_width.max(grid_height).next_power_of_two() / 2;
+        while step >= 1 {
+            steps.push(step as i32);
+            step /= 2;
+        }
+        let passes = steps.iter().map(|&step| pass(step)).collect::<Vec<_>>();
+        let flood_bind_groups = [0, 1].map(|positions| {
+            passes
+                .iter()
+                .enumerate()
+                .map(|(i, pass)| bind_group(pass, i % 2, positions))
+                .collect::<Vec<_>>()
+        });
+        let seed_pass = pass(0);
+        let seed_bind_groups = [0, 1].map(|positions| bind_group(&seed_pass, 1, positions));
+        // Drawing only reads the seeds, so either position buffer will do
+        let render_bind_group = bind_group(&seed_pass, passes.len() % 2, 0);
+
+        let layout = BindableBuffer::bind_group_layout(
+            device,
+            &[
+                render_globals,
+                &simulation.globals,
+                &seed_pass,
+                &position_buffers[0],
+                &seeds[0],
+                &seeds[1],
+                &simulation.types,
+                colors,
+            ],
+        );
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("territory_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(&ShaderModuleDescriptor {
+            label: Some("territory_shader"),
+            source: ShaderSource::Wgsl(include_str!("territory.wgsl").into()),
+            flags: ShaderFlags::VALIDATION,
+        });
+        let compute_pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("territory_compute"),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("territory_render"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "main",
+                buffers: &[],
+            },
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "main",
+                targets: &[format.into()],
+            }),
+        });
+
+        Self {
+            width,
+            height,
+            num_cells,
+            clear_pipeline: compute_pipeline("clear"),
+            seed_pipeline: compute_pipeline("seed"),
+            flood_pipeline: compute_pipeline("flood"),
+            render_pipeline,
+            seed_bind_groups,
+            flood_bind_groups,
+            render_bind_group,
+        }
+    }
+
+    /// Whether the grid was built for a screen of this size
+    pub fn fits(&self, width: u32, height: u32) -> bool {
+        self.width == width && self.height == height
+    }
+
+    /// Finds the nearest particle to every cell. Must be recorded after the render globals for
+    /// this frame have been written.
+    pub fn update(&self, encoder: &mut CommandEncoder, simulation: &Simulation) {
+        let positions = simulation.current_positions();
+        let cell_workgroups = self.num_cells.div_ceil(WORKGROUP_SIZE);
+        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("territory_pass"),
+        });
+        pass.set_bind_group(0, &self.seed_bind_groups[positions], &[]);
+        pass.set_pipeline(&self.clear_pipeline);
+        pass.dispatch(cell_workgroups, 1, 1);
+        // Every cell stays unclaimed while there are no particles
+        if simulation.num_points > 0 {
+            pass.set_pipeline(&self.seed_pipeline);
+            pass.dispatch(simulation.num_points.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+        pass.set_pipeline(&self.flood_pipeline);
+        for bind_group in &self.flood_bind_groups[positions] {
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch(cell_workgroups, 1, 1);
+        }
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, &self.render_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,179 @@
+// Territory map: every cell of a coarse grid over the screen finds its nearest particle by jump
+// flooding, and is then filled with that particle's color.
+
+[[block]]
+struct RenderGlobals {
+    x : f32;
+    y : f32;
+    width : u32;
+    height : u32;
+    zoom : f32;
+    outline : f32;
+};
+
+[[block]]
+struct Globals {
+    num_points : u32;
+};
+
+[[block]]
+struct Pass {
+    grid_width : u32;
+    grid_height : u32;
+    cell_size : f32;
+    step : i32;
+};
+
+[[block]]
+struct Positions {
+    data : [[stride(8)]] array< vec2<f32> >;
+};
+
+// Index of the nearest particle plus one, or 0 if none was found yet
+[[block]]
+struct Seeds {
+    data : [[stride(4)]] array<u32>;
+};
+
+[[block]]
+struct Types {
+    data : [[stride(4)]] array<u32>;
+};
+
+[[block]]
+struct Colors {
+    data: [[stride(12)]] array< vec3<f32> >;
+};
+
+[[group(0), binding(0)]] var<uniform> render_globals : RenderGlobals;
+[[group(0), binding(1)]] var<uniform> globals : Globals;
+[[group(0), binding(2)]] var<uniform> pass : Pass;
+[[group(0), binding(3)]] var<storage> positions : [[access(read)]] Positions;
+[[group(0), binding(4)]] var<storage> seeds_in : [[access(read)]] Seeds;
+[[group(0), binding(5)]] var<storage> seeds_out : [[access(read_write)]] Seeds;
+
+[[builtin(global_invocation_id)]] var<in> global_invocation_id : vec3<u32>;
+
+fn aspect_ratio() -> vec2<f32> {
+    var size : vec2<f32> = vec2<f32>(f32(render_globals.width), f32(render_globals.height));
+    var smallest_side : f32 = min(size.x, size.y);
+    return size / vec2<f32>(smallest_side, smallest_side);
+}
+
+// World position at the center of a grid cell
+fn cell_to_world(cx : i32, cy : i32) -> vec2<f32> {
+    var px : f32 = (f32(cx) + 0.5) * pass.cell_size;
+    var py : f32 = (f32(cy) + 0.5) * pass.cell_size;
+    var ndc : vec2<f32> = vec2<f32>(
+        px / f32(render_globals.width) * 2.0 - 1.0,
+        1.0 - py / f32(render_globals.height) * 2.0
+    );
+    return ndc * aspect_ratio() / vec2<f32>(render_globals.zoom, render_globals.zoom)
+        + vec2<f32>(render_globals.x, render_globals.y);
+}
+
+[[stage(compute), workgroup_size(64)]]
+fn clear() -> void {
+    var i : u32 = global_invocation_id.x;
+    if (i >= pass.grid_width * pass.grid_height) {
+        return;
+    }
+    seeds_out.data[i] = 0u;
+}
+
+[[stage(compute), workgroup_size(64)]]
+fn seed() -> void {
+    var i : u32 = global_invocation_id.x;
+    if (i >= globals.num_points) {
+        return;
+    }
+    var ndc : vec2<f32> = (positions.data[i] - vec2<f32>(render_globals.x, render_globals.y))
+        / aspect_ratio() * vec2<f32>(render_globals.zoom, render_globals.zoom);
+    var px : f32 = (ndc.x + 1.0) * 0.5 * f32(render_globals.width) / pass.cell_size;
+    var py : f32 = (1.0 - ndc.y) * 0.5 * f32(render_globals.height) / pass.cell_size;
+    if (px < 0.0 || py < 0.0 || px >= f32(pass.grid_width) || py >= f32(pass.grid_height)) {
+        return;
+    }
+    seeds_out.data[u32(py) * pass.grid_width + u32(px)] = i + 1u;
+}
+
+[[stage(compute), workgroup_size(64)]]
+fn flood() -> void {
+    var i : u32 = global_invocation_id.x;
+    if (i >= pass.grid_width * pass.grid_height) {
+        return;
+    }
+    var cx : i32 = i32(i % pass.grid_width);
+    var cy : i32 = i32(i / pass.grid_width);
+    var here : vec2<f32> = cell_to_world(cx, cy);
+
+    var best : u32 = 0u;
+    var best_distance : f32 = 0.0;
+    var dy : i32 = -1;
+    loop {
+        if (dy > 1) {
+            break;
+        }
+        var dx : i32 = -1;
+        loop {
+            if (dx > 1) {
+                break;
+            }
+            var nx : i32 = cx + dx * pass.step;
+            var ny : i32 = cy + dy * pass.step;
+            if (nx >= 0 && ny >= 0 && nx < i32(pass.grid_width) && ny < i32(pass.grid_height)) {
+                var candidate : u32 = seeds_in.data[u32(ny) * pass.grid_width + u32(nx)];
+                if (candidate != 0u) {
+                    var d : f32 = distance(here, positions.data[candidate - 1u]);
+                    if (best == 0u || d < best_distance) {
+                        best = candidate;
+                        best_distance = d;
+                    }
+                }
+            }
+            continuing {
+                dx = dx + 1;
+            }
+        }
+        continuing {
+            dy = dy + 1;
+        }
+    }
+    seeds_out.data[i] = best;
+}
+
+[[group(0), binding(6)]] var<storage> types : [[access(read)]] Types;
+[[group(0), binding(7)]] var<storage> colors : [[access(read)]] Colors;
+
+[[builtin(vertex_index)]]
+var<in> in_vertex_index : u32;
+[[builtin(position)]]
+var<out> out_pos: vec4<f32>;
+
+// A single triangle covering the whole screen
+[[stage(vertex)]]
+fn main() {
+    var x : f32 = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    var y : f32 = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    out_pos = vec4<f32>(x, y, 0.0, 1.0);
+}
+
+[[builtin(frag_coord)]] var<in> frag_coord : vec4<f32>;
+[[location(0)]]
+var<out> out_color: vec4<f32>;
+
+const TERRITORY_BRIGHTNESS : f32 = 0.35;
+
+[[stage(fragment)]]
+fn main() {
+    var cx : u32 = u32(frag_coord.x / pass.cell_size);
+    var cy : u32 = u32(frag_coord.y / pass.cell_size);
+    out_color = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    if (cx < pass.grid_width && cy < pass.grid_height) {
+        var nearest : u32 = seeds_in.data[cy * pass.grid_width + cx];
+        if (nearest != 0u) {
+            var color : vec3<f32> = colors.data[ types.data[nearest - 1u] ] * TERRITORY_BRIGHTNESS;
+            out_color = vec4<f32>(color, 1.0);
+        }
+    }
+}
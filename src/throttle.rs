@@ -0,0 +1,73 @@
+use std::time::{Duration, Instant};
+
+use structopt::StructOpt;
+use wgpu::{Device, Maintain};
+
+/// Options for leaving GPU time to other programs during headless runs
+#[derive(StructOpt)]
+pub struct Throttle {
+    /// Target GPU utilisation in percent; idles after each batch in proportion to how long it took
+    #[structopt(long = "throttle")]
+    utilisation: Option<f32>,
+    /// Milliseconds to sleep after each batch of steps
+    #[structopt(long = "throttle-sleep")]
+    sleep_ms: Option<u64>,
+    /// Steps to run back to back before throttling
+    #[structopt(long = "throttle-batch", default_value = "1")]
+    batch: u64,
+}
+
+impl Throttle {
+    fn is_active(&self) -> bool {
+        self.utilisation.is_some() || self.sleep_ms.is_some()
+    }
+
+    /// Returns the pause owed after a batch that took `busy`
+    fn pause(&self, busy: Duration) -> Duration {
+        let mut pause = Duration::from_millis(self.sleep_ms.unwrap_or(0));
+        if let Some(utilisation) = self.utilisation {
+            let utilisation = utilisation.clamp(1.0, 100.0) / 100.0;
+            pause += busy.mul_f32((1.0 - utilisation) / utilisation);
+        }
+        pause
+    }
+
+    /// Starts timing a batch of steps
+    pub fn start(&self) -> ThrottleState {
+        ThrottleState {
+            batch_start: Instant::now(),
+            steps_in_batch: 0,
+        }
+    }
+}
+
+pub struct ThrottleState {
+    batch_start: Instant,
+    steps_in_batch: u64,
+}
+
+impl ThrottleState {
+    /// Most steps to run before the next call to `step`, so that none runs past the end of a batch
+    pub fn steps_left(&self, throttle: &Throttle) -> u64 {
+        if throttle.is_active() {
+            throttle.batch.max(1) - self.steps_in_batch
+        } else {
+            u64::MAX
+        }
+    }
+
+    /// Call after every `steps` steps; waits for the GPU to finish them and sleeps once a full
+    /// batch has run
+    pub fn step(&mut self, throttle: &Throttle, device: &Device, steps: u64) {
+        if !throttle.is_active() {
+            return;
+        }
+        self.steps_in_batch += steps;
+        if self.steps_in_batch >= throttle.batch.max(1) {
+            device.poll(Maintain::Wait);
+            std::thread::sleep(throttle.pause(self.batch_start.elapsed()));
+            self.batch_start = Instant::now();
+            self.steps_in_batch = 0;
+        }
+    }
+}
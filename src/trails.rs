@@ -0,0 +1,172 @@
+use std::cell::Cell;
+use wgpu::*;
+
+/// Keeps the particles drawn in earlier frames in an offscreen texture, fading them a little every
+/// frame, so that moving particles leave streaks behind them. Each pixel's alpha holds how much of
+/// it is lost per frame, so that types can fade at different rates.
+pub struct Trails {
+    /// Screen size the texture was made for
+    width: u32,
+    height: u32,
+    view: TextureView,
+    /// Whether the texture still holds whatever it was created with
+    fresh: Cell<bool>,
+    fade_pipeline: RenderPipeline,
+    show_pipeline: RenderPipeline,
+    show_bind_group: BindGroup,
+}
+
+impl Trails {
+    pub fn new(device: &Device, format: TextureFormat, width: u32, height: u32) -> Self {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("trails"),
+            size: Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsage::RENDER_ATTACHMENT | TextureUsage::SAMPLED,
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+
+        let shader = device.create_shader_module(&ShaderModuleDescriptor {
+            label: Some("trails_shader"),
+            source: ShaderSource::Wgsl(include_str!("trails.wgsl").into()),
+            flags: ShaderFlags::VALIDATION,
+        });
+
+        // The previous frames are multiplied by one minus their own alpha, and the fade fragment is
+        // then subtracted from them
+        let fade_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("trails_fade_pipeline"),
+            layout: Some(&device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("trails_fade_layout"),
+                bind_group_layouts: &[],
+                push_constant_ranges: &[],
+            })),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "fullscreen_vertex",
+                buffers: &[],
+            },
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "fade_fragment",
+                targets: &[ColorTargetState {
+                    format,
+                    alpha_blend: BlendState::REPLACE,
+                    color_blend: BlendState {
+                        src_factor: BlendFactor::One,
+                        dst_factor: BlendFactor::OneMinusDstAlpha,
+                        operation: BlendOperation::ReverseSubtract,
+                    },
+                    write_mask: ColorWrite::COLOR,
+                }],
+            }),
+        });
+
+        let show_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("trails_show_layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        });
+        let show_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("trails_show_bind_group"),
+            layout: &show_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&view),
+            }],
+        });
+        let show_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("trails_show_pipeline"),
+            layout: Some(&device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("trails_show_layout"),
+                bind_group_layouts: &[&show_layout],
+                push_constant_ranges: &[],
+            })),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "fullscreen_vertex",
+                buffers: &[],
+            },
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "show_fragment",
+                // Added to the scene, which shows through wherever there are no trails
+                targets: &[ColorTargetState {
+                    format,
+                    alpha_blend: BlendState::REPLACE,
+                    color_blend: BlendState {
+                        src_factor: BlendFactor::One,
+                        dst_factor: BlendFactor::One,
+                        operation: BlendOperation::Add,
+                    },
+                    write_mask: ColorWrite::COLOR,
+                }],
+            }),
+        });
+
+        Self {
+            width,
+            height,
+            view,
+            fresh: Cell::new(true),
+            fade_pipeline,
+            show_pipeline,
+            show_bind_group,
+        }
+    }
+
+    /// Whether the texture was made for a screen of this size
+    pub fn fits(&self, width: u32, height: u32) -> bool {
+        self.width == width && self.height == height
+    }
+
+    /// The attachment the particles are drawn into, in their trail colors. Only the first frame
+    /// starts from black, later ones draw on top of the earlier ones.
+    pub fn attachment(&self) -> RenderPassColorAttachmentDescriptor {
+        let load = if self.fresh.replace(false) {
+            LoadOp::Clear(Color::BLACK)
+        } else {
+            LoadOp::Load
+        };
+        RenderPassColorAttachmentDescriptor {
+            attachment: &self.view,
+            resolve_target: None,
+            ops: Operations { load, store: true },
+        }
+    }
+
+    /// Darkens the earlier frames by the fade each pixel was drawn with. Must be recorded before
+    /// anything else in the pass drawing into `attachment`.
+    pub fn fade<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        render_pass.set_pipeline(&self.fade_pipeline);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    /// Adds the trails to what is already on screen
+    pub fn show<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        render_pass.set_pipeline(&self.show_pipeline);
+        render_pass.set_bind_group(0, &self.show_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
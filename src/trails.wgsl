@@ -0,0 +1,37 @@
+// Motion trails: particles are drawn on top of what was left of the previous frames, which is first
+// faded a little, and the result is then added to the scene on screen.
+
+// Taken off every channel on top of the fade, so that 8 bit colors that would round back up to
+// themselves still go all the way to black
+const FADE_FLOOR : f32 = 0.001;
+
+[[builtin(vertex_index)]]
+var<in> in_vertex_index : u32;
+[[builtin(position)]]
+var<out> out_pos: vec4<f32>;
+
+// A single triangle covering the whole screen
+[[stage(vertex)]]
+fn fullscreen_vertex() {
+    var x : f32 = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    var y : f32 = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    out_pos = vec4<f32>(x, y, 0.0, 1.0);
+}
+
+[[location(0)]]
+var<out> out_color: vec4<f32>;
+
+// Subtracted from the faded trails, which are scaled by the blend color
+[[stage(fragment)]]
+fn fade_fragment() {
+    out_color = vec4<f32>(FADE_FLOOR, FADE_FLOOR, FADE_FLOOR, 0.0);
+}
+
+[[group(0), binding(0)]] var trails : texture_2d<f32>;
+[[builtin(frag_coord)]] var<in> frag_coord : vec4<f32>;
+
+[[stage(fragment)]]
+fn show_fragment() {
+    var color : vec4<f32> = textureLoad(trails, vec2<i32>(i32(frag_coord.x), i32(frag_coord.y)), 0);
+    out_color = vec4<f32>(color.rgb, 1.0);
+}
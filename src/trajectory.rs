@@ -0,0 +1,183 @@
+#[cfg(feature = "parquet")]
+use std::{fs::File, sync::Arc};
+use std::{io, path::Path};
+
+#[cfg(feature = "parquet")]
+use parquet::{
+    basic::Compression,
+    column::writer::ColumnWriter,
+    errors::ParquetError,
+    file::{
+        properties::WriterProperties,
+        writer::{FileWriter, SerializedFileWriter},
+    },
+    schema::parser::parse_message_type,
+};
+use wgpu::{Device, Queue};
+
+use crate::simulation::Simulation;
+
+/// Rows buffered before they are compressed and written out as a row group
+#[cfg(feature = "parquet")]
+const ROW_GROUP_ROWS: usize = 1 << 20;
+
+#[cfg(feature = "parquet")]
+const SCHEMA: &str = "
+    message trajectory {
+        REQUIRED INT64 step;
+        REQUIRED INT32 particle;
+        REQUIRED FLOAT x;
+        REQUIRED FLOAT y;
+        REQUIRED INT32 type;
+    }
+";
+
+/// Rows not yet written, one column each
+#[cfg(feature = "parquet")]
+#[derive(Default)]
+struct Rows {
+    step: Vec<i64>,
+    particle: Vec<i32>,
+    x: Vec<f32>,
+    y: Vec<f32>,
+    type_: Vec<i32>,
+}
+
+#[cfg(feature = "parquet")]
+impl Rows {
+    fn len(&self) -> usize {
+        self.step.len()
+    }
+
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Writes every particle every `interval` ticks to a zstd compressed Parquet file, as one row
+/// per particle per snapshot with the columns `step`, `particle`, `x`, `y` and `type`. Rows are
+/// written out in row groups as the run goes, so that long runs take little memory.
+pub struct TrajectoryWriter {
+    interval: u64,
+    last_ticks: Option<u64>,
+    #[cfg(feature = "parquet")]
+    writer: SerializedFileWriter<File>,
+    #[cfg(feature = "parquet")]
+    rows: Rows,
+}
+
+#[cfg(feature = "parquet")]
+fn to_io(e: ParquetError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+impl TrajectoryWriter {
+    /// Creates the file, overwriting it
+    #[cfg(feature = "parquet")]
+    pub fn create(path: &Path, interval: u64) -> io::Result<Self> {
+        let schema = Arc::new(parse_message_type(SCHEMA).unwrap());
+        let properties = Arc::new(
+            WriterProperties::builder()
+                .set_compression(Compression::ZSTD)
+                .build(),
+        );
+        let writer =
+            SerializedFileWriter::new(File::create(path)?, schema, properties).map_err(to_io)?;
+        Ok(Self {
+            interval: interval.max(1),
+            last_ticks: None,
+            writer,
+            rows: Rows::default(),
+        })
+    }
+
+    #[cfg(not(feature = "parquet"))]
+    pub fn create(_path: &Path, _interval: u64) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "plife was built without the `parquet` feature",
+        ))
+    }
+
+    /// Ticks from `ticks` until the next snapshot is due
+    pub fn due_in(&self, ticks: u64) -> u64 {
+        self.last_ticks
+            .map_or(0, |last| (last + self.interval).saturating_sub(ticks))
+    }
+
+    /// Adds a snapshot if `interval` ticks went by since the last one
+    pub fn record(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        simulation: &Simulation,
+        ticks: u64,
+    ) -> io::Result<()> {
+        if self.due_in(ticks) > 0 {
+            return Ok(());
+        }
+        self.last_ticks = Some(ticks);
+        #[cfg(feature = "parquet")]
+        {
+            let points = simulation.read_points(device, queue);
+            for (particle, &(x, y, type_)) in points.iter().enumerate() {
+                self.rows.step.push(ticks as i64);
+                self.rows.particle.push(particle as i32);
+                self.rows.x.push(x);
+                self.rows.y.push(y);
+                self.rows.type_.push(type_.unwrap_or(0) as i32);
+            }
+            if self.rows.len() >= ROW_GROUP_ROWS {
+                self.write_row_group().map_err(to_io)?;
+            }
+        }
+        #[cfg(not(feature = "parquet"))]
+        let _ = (device, queue, simulation);
+        Ok(())
+    }
+
+    /// Writes the rows not written yet and finishes the file
+    pub fn finish(self) -> io::Result<()> {
+        #[cfg(feature = "parquet")]
+        {
+            let mut this = self;
+            if !this.rows.step.is_empty() {
+                this.write_row_group().map_err(to_io)?;
+            }
+            this.writer.close().map_err(to_io)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "parquet")]
+    fn write_row_group(&mut self) -> Result<(), ParquetError> {
+        let rows = &self.rows;
+        let mut row_group = self.writer.next_row_group()?;
+        let mut index = 0;
+        while let Some(mut column) = row_group.next_column()? {
+            match (&mut column, index) {
+                (ColumnWriter::Int64ColumnWriter(writer), 0) => {
+                    writer.write_batch(&rows.step, None, None)?;
+                }
+                (ColumnWriter::Int32ColumnWriter(writer), 1) => {
+                    writer.write_batch(&rows.particle, None, None)?;
+                }
+                (ColumnWriter::FloatColumnWriter(writer), 2) => {
+                    writer.write_batch(&rows.x, None, None)?;
+                }
+                (ColumnWriter::FloatColumnWriter(writer), 3) => {
+                    writer.write_batch(&rows.y, None, None)?;
+                }
+                (ColumnWriter::Int32ColumnWriter(writer), 4) => {
+                    writer.write_batch(&rows.type_, None, None)?;
+                }
+                _ => unreachable!("columns come in the order of SCHEMA"),
+            }
+            row_group.close_column(column)?;
+            index += 1;
+        }
+        self.writer.close_row_group(row_group)?;
+        self.rows.clear();
+        Ok(())
+    }
+}
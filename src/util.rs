@@ -1,10 +1,25 @@
-use std::{convert::TryInto, fmt::Debug, mem::size_of, num::NonZeroU64};
+use std::{cell::RefCell, convert::TryInto, fmt::Debug, mem::size_of, num::NonZeroU64};
 
+use rand::{rngs::StdRng, SeedableRng};
 use wgpu::*;
 
 pub const VEC2_SIZE: usize = size_of::<f32>() * 2;
 pub const VEC3_SIZE: usize = size_of::<f32>() * 3;
 
+thread_local! {
+    /// Source of the randomness in configs and simulations, so that seeding it reproduces them
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// Makes everything sampled from now on follow from `seed`
+pub fn seed_rng(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+pub fn with_rng<T>(f: impl FnOnce(&mut StdRng) -> T) -> T {
+    RNG.with(|rng| f(&mut rng.borrow_mut()))
+}
+
 pub struct BindableBuffer {
     pub buffer: Buffer,
     pub size: u64,
@@ -93,3 +108,55 @@ impl BindableBuffer {
         })
     }
 }
+
+/// Copies the first `size` bytes of `buffer`, which needs `COPY_SRC` usage, back to the CPU.
+/// Blocks until the GPU has finished all submitted work.
+pub fn read_buffer(device: &Device, queue: &Queue, buffer: &Buffer, size: u64) -> Vec<u8> {
+    read_buffer_at(device, queue, buffer, 0, size)
+}
+
+/// Reads `size` bytes starting `offset` bytes into the buffer
+pub fn read_buffer_at(
+    device: &Device,
+    queue: &Queue,
+    buffer: &Buffer,
+    offset: u64,
+    size: u64,
+) -> Vec<u8> {
+    // Mapping an empty buffer is not allowed
+    if size == 0 {
+        return Vec::new();
+    }
+    let staging = device.create_buffer(&BufferDescriptor {
+        label: Some("readback"),
+        size,
+        usage: BufferUsage::MAP_READ | BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("readback"),
+    });
+    encoder.copy_buffer_to_buffer(buffer, offset, &staging, 0, size);
+    queue.submit(Some(encoder.finish()));
+    let slice = staging.slice(..);
+    let mapped = slice.map_async(MapMode::Read);
+    device.poll(Maintain::Wait);
+    futures::executor::block_on(mapped).expect("Failed to read buffer from GPU");
+    let bytes = slice.get_mapped_range().to_vec();
+    staging.unmap();
+    bytes
+}
+
+/// Converts a hue in `0.0..1.0` to a fully saturated, fully bright RGB color
+pub fn hue_to_rgb(hue: f32) -> [f32; 3] {
+    let h = hue.fract() * 6.0;
+    let x = 1.0 - (h % 2.0 - 1.0).abs();
+    match h as u32 {
+        0 => [1.0, x, 0.0],
+        1 => [x, 1.0, 0.0],
+        2 => [0.0, 1.0, x],
+        3 => [0.0, x, 1.0],
+        4 => [x, 0.0, 1.0],
+        _ => [1.0, 0.0, x],
+    }
+}
@@ -0,0 +1,699 @@
+use rand_distr::num_traits::ToPrimitive;
+
+use crate::{
+    reactions::MAX_REACTIONS,
+    serialize::*,
+    simulation::{MAX_FLOWS, MAX_ZONES},
+};
+
+/// Collects problems found while checking a config, each prefixed by the path of the field
+#[derive(Default)]
+struct Problems(Vec<String>);
+
+impl Problems {
+    fn push(&mut self, path: &str, message: impl AsRef<str>) {
+        self.0.push(format!("{}: {}", path, message.as_ref()));
+    }
+}
+
+impl<T: ToPrimitive> Distribution<T> {
+    fn check(&self, path: &str, problems: &mut Problems) {
+        match self {
+            Distribution::Const(_) => {}
+            Distribution::Uniform { min, max, .. } => {
+                if min.to_f64() >= max.to_f64() {
+                    problems.push(path, "uniform distribution needs min < max");
+                }
+            }
+            Distribution::Normal { std, clamp, .. } => {
+                if std.to_f64().map(|std| std < 0.0).unwrap_or(true) {
+                    problems.push(path, "normal distribution needs std >= 0");
+                }
+                clamp.check(path, problems);
+            }
+            Distribution::Exponential { lambda, clamp } => {
+                if *lambda <= 0.0 {
+                    problems.push(path, "exponential distribution needs lambda > 0");
+                }
+                clamp.check(path, problems);
+            }
+            Distribution::LogNormal { sigma, clamp, .. } => {
+                if *sigma < 0.0 {
+                    problems.push(path, "log-normal distribution needs sigma >= 0");
+                }
+                clamp.check(path, problems);
+            }
+            Distribution::Choice {
+                values,
+                weights,
+                clamp,
+            } => {
+                if values.is_empty() {
+                    problems.push(path, "choice needs at least one value");
+                }
+                if let Some(weights) = weights {
+                    if weights.len() != values.len() {
+                        problems.push(
+                            path,
+                            format!(
+                                "choice has {} values but {} weights",
+                                values.len(),
+                                weights.len()
+                            ),
+                        );
+                    } else if weights.iter().any(|w| *w < 0.0) || weights.iter().sum::<f64>() <= 0.0
+                    {
+                        problems.push(
+                            path,
+                            "choice weights must not be negative and must not all be zero",
+                        );
+                    }
+                }
+                clamp.check(path, problems);
+            }
+        }
+    }
+
+    fn clamp(&self) -> Option<&Clamp<T>> {
+        match self {
+            Distribution::Const(_) | Distribution::Uniform { .. } => None,
+            Distribution::Normal { clamp, .. }
+            | Distribution::Exponential { clamp, .. }
+            | Distribution::LogNormal { clamp, .. }
+            | Distribution::Choice { clamp, .. } => Some(clamp),
+        }
+    }
+
+    fn rounding(&self) -> Option<Rounding> {
+        match self {
+            Distribution::Const(_) => None,
+            Distribution::Uniform { round, .. } => *round,
+            _ => self.clamp().and_then(|clamp| clamp.round),
+        }
+    }
+
+    /// The lowest value this distribution can produce, if it is bounded below
+    pub fn lower_bound(&self) -> Option<f64> {
+        let unclamped = match self {
+            Distribution::Const(t) => t.to_f64(),
+            Distribution::Uniform { min, .. } => min.to_f64(),
+            Distribution::Normal { .. } => None,
+            Distribution::Exponential { .. } | Distribution::LogNormal { .. } => Some(0.0),
+            Distribution::Choice { values, .. } => values
+                .iter()
+                .filter_map(ToPrimitive::to_f64)
+                .fold(None, |min: Option<f64>, v| {
+                    Some(min.map_or(v, |min| min.min(v)))
+                }),
+        };
+        let unclamped = match self.rounding() {
+            Some(round) => unclamped.map(|value| round.apply(value)),
+            None => unclamped,
+        };
+        let clamped = self
+            .clamp()
+            .and_then(|clamp| clamp.min.as_ref())
+            .and_then(ToPrimitive::to_f64);
+        match (unclamped, clamped) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// The highest value this distribution can produce, if it is bounded above
+    pub fn upper_bound(&self) -> Option<f64> {
+        let unclamped = match self {
+            Distribution::Const(t) => t.to_f64(),
+            Distribution::Uniform { max, .. } => max.to_f64(),
+            Distribution::Normal { .. }
+            | Distribution::Exponential { .. }
+            | Distribution::LogNormal { .. } => None,
+            Distribution::Choice { values, .. } => values
+                .iter()
+                .filter_map(ToPrimitive::to_f64)
+                .fold(None, |max: Option<f64>, v| {
+                    Some(max.map_or(v, |max| max.max(v)))
+                }),
+        };
+        let unclamped = match self.rounding() {
+            Some(round) => unclamped.map(|value| round.apply(value)),
+            None => unclamped,
+        };
+        let clamped = self
+            .clamp()
+            .and_then(|clamp| clamp.max.as_ref())
+            .and_then(ToPrimitive::to_f64);
+        match (unclamped, clamped) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// The range nearly all sampled values fall in: the bounds where there are any, and three
+    /// standard deviations out from the mean where there are not
+    pub fn likely_range(&self) -> Option<(f64, f64)> {
+        let (low, high) = match self {
+            Distribution::Normal { mean, std, .. } => {
+                let (mean, std) = (mean.to_f64()?, std.to_f64()?);
+                (mean - std * 3.0, mean + std * 3.0)
+            }
+            Distribution::Exponential { lambda, .. } => (0.0, 3.0 / lambda),
+            Distribution::LogNormal { mu, sigma, .. } => (0.0, (mu + sigma * 3.0).exp()),
+            _ => (self.lower_bound()?, self.upper_bound()?),
+        };
+        Some((
+            self.lower_bound().map_or(low, |bound| low.max(bound)),
+            self.upper_bound().map_or(high, |bound| high.min(bound)),
+        ))
+    }
+
+    fn check_non_negative(&self, path: &str, problems: &mut Problems) {
+        self.check(path, problems);
+        if self.lower_bound().map(|min| min < 0.0).unwrap_or(false) {
+            problems.push(path, "must not be negative");
+        }
+    }
+}
+
+impl<T: ToPrimitive> Clamp<T> {
+    fn check(&self, path: &str, problems: &mut Problems) {
+        if let (Some(min), Some(max)) = (&self.min, &self.max) {
+            if min.to_f64() > max.to_f64() {
+                problems.push(path, "clamp needs min <= max");
+            }
+        }
+    }
+}
+
+/// Checks that `min_r` does not always exceed `max_r`. Procedural rulesets sample both
+/// independently, so ranges that merely overlap are allowed.
+fn check_radii(
+    min_r: &Distribution<f32>,
+    max_r: &Distribution<f32>,
+    path: &str,
+    problems: &mut Problems,
+) {
+    if let (Some(min), Some(max)) = (min_r.lower_bound(), max_r.upper_bound()) {
+        if min > max {
+            problems.push(
+                path,
+                format!(
+                    "min_r (at least {}) is larger than max_r (at most {})",
+                    min, max
+                ),
+            );
+        }
+    }
+}
+
+fn check_friction(friction: &Distribution<f32>, path: &str, problems: &mut Problems) {
+    friction.check_non_negative(path, problems);
+    if friction.upper_bound().map(|max| max > 1.0).unwrap_or(false) {
+        problems.push(path, "must not be larger than 1");
+    }
+}
+
+/// Checks that particles of type `type_` can exist in a ruleset with `types` types, as returned
+/// by `RulesetConfig::fewest_types`
+fn check_type(type_: f64, types: Option<(u32, bool)>, path: &str, problems: &mut Problems) {
+    if let Some((fewest, exact)) = types {
+        if type_ >= fewest as f64 {
+            let there = if exact {
+                "are only"
+            } else {
+                "can be as few as"
+            };
+            problems.push(
+                path,
+                format!(
+                    "type {} is out of range; there {} {} types",
+                    type_, there, fewest
+                ),
+            );
+        }
+    }
+}
+
+impl RulesetConfig {
+    /// The fewest types the ruleset can have, and whether it always has exactly that many
+    fn fewest_types(&self) -> Option<(u32, bool)> {
+        match self {
+            RulesetConfig::Procedural(rules) => {
+                let fewest = rules.types.lower_bound()?.floor().max(0.0) as u32;
+                let exact = rules.types.upper_bound().map(f64::floor) == Some(fewest as f64);
+                Some((fewest, exact))
+            }
+            RulesetConfig::Precise { types, .. } => Some((types.len() as u32, true)),
+        }
+    }
+
+    fn check(&self, problems: &mut Problems) {
+        match self {
+            RulesetConfig::Procedural(rules) => {
+                rules.types.check("ruleset.types", problems);
+                // Type counts are rounded down unless the distribution says otherwise
+                match rules.types.lower_bound().map(f64::floor) {
+                    Some(min) if min >= 1.0 => {}
+                    Some(_) => problems.push(
+                        "ruleset.types",
+                        "can be less than 1; there must be at least one type",
+                    ),
+                    None => problems.push(
+                        "ruleset.types",
+                        "has no lower bound; add `min: 1` so that there is always at least one type",
+                    ),
+                }
+                rules.attractions.check("ruleset.attractions", problems);
+                rules.min_r.check_non_negative("ruleset.min_r", problems);
+                rules.max_r.check_non_negative("ruleset.max_r", problems);
+                check_radii(&rules.min_r, &rules.max_r, "ruleset", problems);
+                check_friction(&rules.friction, "ruleset.friction", problems);
+            }
+            RulesetConfig::Precise { types, friction } => {
+                if types.is_empty() {
+                    problems.push("ruleset.types", "there must be at least one type");
+                }
+                for (i, type_) in types.iter().enumerate() {
+                    let path = format!("ruleset.types[{}]", i);
+                    for (name, row) in [
+                        ("attractions", &type_.attractions),
+                        ("min_r", &type_.min_r),
+                        ("max_r", &type_.max_r),
+                    ]
+                    .iter()
+                    {
+                        if row.len() != types.len() {
+                            problems.push(
+                                &format!("{}.{}", path, name),
+                                format!(
+                                    "has {} entries, but there are {} types",
+                                    row.len(),
+                                    types.len()
+                                ),
+                            );
+                        }
+                    }
+                    for (j, attraction) in type_.attractions.iter().enumerate() {
+                        attraction.check(&format!("{}.attractions[{}]", path, j), problems);
+                    }
+                    for (j, (min_r, max_r)) in type_.min_r.iter().zip(&type_.max_r).enumerate() {
+                        min_r.check_non_negative(&format!("{}.min_r[{}]", path, j), problems);
+                        max_r.check_non_negative(&format!("{}.max_r[{}]", path, j), problems);
+                        check_radii(min_r, max_r, &format!("{}.min_r[{}]", path, j), problems);
+                    }
+                }
+                check_friction(friction, "ruleset.friction", problems);
+            }
+        }
+    }
+}
+
+impl WallsConfig {
+    fn check(&self, problems: &mut Problems) {
+        match self {
+            WallsConfig::None => {}
+            WallsConfig::Wrapping { dist } | WallsConfig::Square { dist, .. } => {
+                dist.check("walls.dist", problems);
+                if dist.lower_bound().map(|min| min <= 0.0).unwrap_or(false) {
+                    problems.push("walls.dist", "must be positive");
+                }
+            }
+        }
+        let restitution = self.restitution();
+        if !(0.0..=1.0).contains(&restitution) {
+            problems.push("walls.restitution", "must be between 0 and 1");
+        }
+    }
+}
+
+impl PointsConfig {
+    /// The fewest particles the config can start with
+    fn fewest_points(&self) -> Option<f64> {
+        match self {
+            PointsConfig::Simple(num) => num.lower_bound(),
+            PointsConfig::Complex(spawns) => {
+                spawns.iter().map(|spawn| spawn.num.lower_bound()).sum()
+            }
+            PointsConfig::File { points, .. } => Some(points.len() as f64),
+        }
+    }
+
+    fn check(&self, types: Option<(u32, bool)>, problems: &mut Problems) {
+        match self {
+            PointsConfig::Simple(num) => num.check("points", problems),
+            PointsConfig::Complex(spawns) => {
+                for (i, spawn) in spawns.iter().enumerate() {
+                    spawn.num.check(&format!("points[{}].num", i), problems);
+                    spawn.x.check(&format!("points[{}].x", i), problems);
+                    spawn.y.check(&format!("points[{}].y", i), problems);
+                }
+            }
+            PointsConfig::File { path, points, .. } => {
+                if !path.is_file() {
+                    problems.push("points.path", format!("{} does not exist", path.display()));
+                }
+                let highest = points.iter().filter_map(|&(_, _, type_)| type_).max();
+                if let Some(highest) = highest {
+                    check_type(highest as f64, types, "points.path", problems);
+                }
+            }
+        }
+    }
+}
+
+impl EventConfig {
+    fn check(&self, path: &str, problems: &mut Problems) {
+        match self {
+            EventConfig::Impulse(impulse) => {
+                check_every(impulse.every, path, problems);
+                impulse
+                    .strength
+                    .check(&format!("{}.strength", path), problems);
+                impulse
+                    .radius
+                    .check_non_negative(&format!("{}.radius", path), problems);
+                if let Some(x) = &impulse.x {
+                    x.check(&format!("{}.x", path), problems);
+                }
+                if let Some(y) = &impulse.y {
+                    y.check(&format!("{}.y", path), problems);
+                }
+            }
+            EventConfig::Inversion { every, duration } => {
+                check_every(*every, path, problems);
+                if *duration == 0 {
+                    problems.push(&format!("{}.duration", path), "must be at least 1 step");
+                }
+            }
+        }
+    }
+}
+
+impl Region {
+    fn check(&self, path: &str, problems: &mut Problems) {
+        for (axis, range) in [("x", self.x), ("y", self.y)].iter() {
+            if range[0] > range[1] {
+                problems.push(
+                    &format!("{}.{}", path, axis),
+                    "range must be given as [min, max]",
+                );
+            }
+        }
+    }
+}
+
+impl ScheduleConfig {
+    fn check(&self, path: &str, problems: &mut Problems) {
+        if self.steps == 0 {
+            problems.push(&format!("{}.steps", path), "must be at least 1 step");
+        }
+        let (range, message) = self.parameter.range();
+        for (field, value) in [("from", self.from), ("to", self.to)].iter() {
+            if !range.contains(value) {
+                problems.push(&format!("{}.{}", path, field), message);
+            }
+        }
+        if let ScheduleCurve::Exponential = self.curve {
+            if !(self.from > 0.0 && self.to > 0.0) {
+                problems.push(
+                    &format!("{}.curve", path),
+                    "exponential schedules need from and to to be positive",
+                );
+            }
+        }
+    }
+}
+
+impl MidiConfig {
+    fn check(&self, problems: &mut Problems) {
+        if let Some(channel) = self.channel {
+            if !(1..=16).contains(&channel) {
+                problems.push("midi.channel", "must be between 1 and 16");
+            }
+        }
+        for (i, control) in self.controls.iter().enumerate() {
+            let path = format!("midi.controls[{}]", i);
+            if control.cc > 127 {
+                problems.push(&format!("{}.cc", path), "must be between 0 and 127");
+            }
+            let (range, message) = match control.parameter.scheduled() {
+                Some(parameter) => parameter.range(),
+                None => (0.0..=u16::MAX as f32, "must be between 0 and 65535"),
+            };
+            for (field, value) in [("min", control.min), ("max", control.max)].iter() {
+                if !range.contains(value) {
+                    problems.push(&format!("{}.{}", path, field), message);
+                }
+            }
+        }
+    }
+}
+
+fn check_every(every: f32, path: &str, problems: &mut Problems) {
+    if every < 1.0 {
+        problems.push(&format!("{}.every", path), "must be at least 1 step");
+    }
+}
+
+impl Config {
+    /// Checks for values that deserialize fine but would break sampling or the simulation
+    pub fn validate(&self) -> Result<(), String> {
+        let mut problems = Problems::default();
+        self.ruleset.check(&mut problems);
+        let types = self.ruleset.fewest_types();
+        self.walls.check(&mut problems);
+        self.points.check(types, &mut problems);
+        for (i, event) in self.events.iter().enumerate() {
+            event.check(&format!("events[{}]", i), &mut problems);
+        }
+        for (i, schedule) in self.schedules.iter().enumerate() {
+            schedule.check(&format!("schedules[{}]", i), &mut problems);
+        }
+        if self.reactions.len() > MAX_REACTIONS {
+            problems.push(
+                "reactions",
+                format!("at most {} reaction rules are supported", MAX_REACTIONS),
+            );
+        }
+        for (i, reaction) in self.reactions.iter().enumerate() {
+            let path = format!("reactions[{}]", i);
+            if !(reaction.radius > 0.0) {
+                problems.push(&format!("{}.radius", path), "must be positive");
+            }
+            if !(0.0..=1.0).contains(&reaction.probability) {
+                problems.push(&format!("{}.probability", path), "must be between 0 and 1");
+            }
+            for (field, type_) in [
+                ("catalyst", reaction.catalyst),
+                ("reactant", reaction.reactant),
+                ("product", reaction.product),
+            ]
+            .iter()
+            {
+                check_type(
+                    *type_ as f64,
+                    types,
+                    &format!("{}.{}", path, field),
+                    &mut problems,
+                );
+            }
+        }
+        let fewest_points = self.points.fewest_points().map(f64::floor);
+        for (i, bond) in self.bonds.iter().enumerate() {
+            let path = format!("bonds[{}]", i);
+            if bond.a == bond.b {
+                problems.push(&path, "a particle cannot be bonded to itself");
+            }
+            let highest = bond.a.max(bond.b);
+            if let Some(fewest) = fewest_points.filter(|&fewest| highest as f64 >= fewest) {
+                problems.push(
+                    &path,
+                    format!(
+                        "particle {} is out of range; there can be as few as {} particles",
+                        highest, fewest
+                    ),
+                );
+            }
+            if !(bond.length >= 0.0) {
+                problems.push(&format!("{}.length", path), "must not be negative");
+            }
+            if !(0.0..=0.5).contains(&bond.stiffness) {
+                problems.push(
+                    &format!("{}.stiffness", path),
+                    "must be between 0 and 0.5, or the spring overshoots",
+                );
+            }
+        }
+        for (i, faucet) in self.faucets.iter().enumerate() {
+            let path = format!("faucets[{}]", i);
+            faucet.region.check(&path, &mut problems);
+            check_type(
+                faucet.type_ as f64,
+                types,
+                &format!("{}.type", path),
+                &mut problems,
+            );
+            if faucet.per_second < 0.0 {
+                problems.push(&format!("{}.per_second", path), "must not be negative");
+            }
+        }
+        for (i, emitter) in self.emitters.iter().enumerate() {
+            let path = format!("emitters[{}]", i);
+            if emitter.per_second < 0.0 {
+                problems.push(&format!("{}.per_second", path), "must not be negative");
+            }
+            if let Some(type_) = &emitter.type_ {
+                type_.check(&format!("{}.type", path), &mut problems);
+                if let Some(highest) = type_.upper_bound() {
+                    check_type(highest, types, &format!("{}.type", path), &mut problems);
+                }
+            }
+            emitter.x.check(&format!("{}.x", path), &mut problems);
+            emitter.y.check(&format!("{}.y", path), &mut problems);
+        }
+        for (i, lifetime) in self.lifetimes.iter().enumerate() {
+            if lifetime.steps == 0 {
+                problems.push(
+                    &format!("lifetimes[{}].steps", i),
+                    "must be at least 1 step",
+                );
+            }
+        }
+        for (i, drain) in self.drains.iter().enumerate() {
+            drain.region.check(&format!("drains[{}]", i), &mut problems);
+        }
+        if self.flows.len() > MAX_FLOWS {
+            problems.push(
+                "flows",
+                format!("at most {} flow regions are supported", MAX_FLOWS),
+            );
+        }
+        for (i, flow) in self.flows.iter().enumerate() {
+            flow.region.check(&format!("flows[{}]", i), &mut problems);
+        }
+        if self.zones.len() > MAX_ZONES {
+            problems.push(
+                "zones",
+                format!("at most {} zones are supported", MAX_ZONES),
+            );
+        }
+        for (i, zone) in self.zones.iter().enumerate() {
+            let path = format!("zones[{}]", i);
+            match zone.shape {
+                ZoneShape::Circle { radius, .. } => {
+                    if !(radius > 0.0) {
+                        problems.push(&format!("{}.radius", path), "must be positive");
+                    }
+                }
+                ZoneShape::Rectangle(region) => region.check(&path, &mut problems),
+            }
+            if zone.friction < 0.0 {
+                problems.push(&format!("{}.friction", path), "must not be negative");
+            }
+        }
+        if let Some(gravity) = &self.gravity {
+            if !gravity.strength.is_finite() {
+                problems.push("gravity.strength", "must be a number");
+            }
+            if !(gravity.softening >= 0.0) {
+                problems.push("gravity.softening", "must not be negative");
+            }
+            for (i, &mass) in gravity.masses.iter().enumerate() {
+                if !(mass >= 0.0) {
+                    problems.push(&format!("gravity.masses[{}]", i), "must not be negative");
+                }
+            }
+            match types {
+                Some((fewest, exact)) if gravity.masses.len() > fewest as usize => problems.push(
+                    "gravity.masses",
+                    format!(
+                        "lists {} masses, but there {} {} types",
+                        gravity.masses.len(),
+                        if exact {
+                            "are only"
+                        } else {
+                            "can be as few as"
+                        },
+                        fewest
+                    ),
+                ),
+                _ => {}
+            }
+        }
+        if let Some(midi) = &self.midi {
+            midi.check(&mut problems);
+        }
+        if let Some(max_velocity) = self.max_velocity {
+            if !(max_velocity > 0.0) {
+                problems.push("max_velocity", "must be positive");
+            }
+        }
+        if let Some(max_speed) = self.display.max_speed {
+            if max_speed <= 0.0 {
+                problems.push("display.max_speed", "must be positive");
+            }
+        }
+        if let Some(opacity) = self.display.opacity {
+            if !(opacity > 0.0 && opacity <= 1.0) {
+                problems.push("display.opacity", "must be more than 0 and at most 1");
+            }
+        }
+        if let Some(trail_fade) = self.display.trail_fade {
+            if !(0.0..1.0).contains(&trail_fade) {
+                problems.push("display.trail_fade", "must be at least 0 and less than 1");
+            }
+        }
+        for (i, trail) in self.display.type_trails.iter().enumerate() {
+            let path = format!("display.type_trails[{}]", i);
+            if let Some(fade) = trail.fade {
+                if !(0.0..1.0).contains(&fade) {
+                    problems.push(
+                        &format!("{}.fade", path),
+                        "must be at least 0 and less than 1",
+                    );
+                }
+            }
+            if let Some(color) = trail.color {
+                if color.iter().any(|channel| !(0.0..=1.0).contains(channel)) {
+                    problems.push(
+                        &format!("{}.color", path),
+                        "channels must be between 0 and 1",
+                    );
+                }
+            }
+        }
+        if problems.0.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.0.join("\n"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_types_are_a_config_error() {
+        for types in ["0", "{min: 0, max: 3}"].iter() {
+            let yaml = format!(
+                "---
+ruleset:
+  types: {}
+  attractions: {{min: -0.01, max: 0.01}}
+  min_r: 10.0
+  max_r: 50.0
+  friction: 0.05
+walls: {{type: wrapping, dist: 1000.0}}
+points: 100
+",
+                types
+            );
+            match Config::from_reader(yaml.as_bytes(), ConfigFormat::Yaml, &[]) {
+                Ok(_) => panic!("types: {} was accepted", types),
+                Err(e) => assert!(e.contains("ruleset.types"), "unexpected error: {}", e),
+            }
+        }
+    }
+}
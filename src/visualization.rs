@@ -1,20 +1,44 @@
 use crate::{
-    simulation::Simulation,
-    util::{BindableBuffer, VEC2_SIZE, VEC3_SIZE},
+    analysis::{ClusterStats, MotionStats, CLUSTER_RADIUS},
+    arrows::FlowArrows,
+    capture::FrameCapture,
+    control::{self, Controller},
+    density::Density,
+    depth_sort::DepthSort,
+    error::{exit_with, GpuError},
+    explosion::{ExplosionCheck, ExplosionCheckState},
+    hud::Hud,
+    locale::Locale,
+    matrix::AttractionMatrix,
+    midi::MidiControls,
+    npz::NpzExporter,
+    osc::OscSender,
+    recording::{Recorder, Replay},
+    rewind::Rewind,
+    serialize::{Config, DisplayConfig, PresentModeChoice},
+    serve::Server,
+    session::{Session, SessionTarget, ViewState},
+    simulation::{PointType, Simulation, Walls},
+    territory::Territory,
+    trails::Trails,
+    trajectory::TrajectoryWriter,
+    util::{hue_to_rgb, with_rng, BindableBuffer, VEC2_SIZE, VEC3_SIZE},
 };
 use async_executor::LocalExecutor;
+use rand::Rng;
 use std::{
     io::{Cursor, Write},
     mem::size_of,
     num::NonZeroU64,
     time::{Duration, Instant},
 };
+use tracing::{error, info, warn};
 use wgpu::util::*;
 use wgpu::*;
 use winit::{
     event::{VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window::Window,
+    window::{Fullscreen, Window},
 };
 
 pub struct Visualization {
@@ -25,44 +49,220 @@ pub struct Visualization {
     index_buffer: BindableBuffer,
     ticks_just_now: u16,
     last_update_duration: Duration,
+    paused: bool,
+    /// Whether the cluster count is shown in the corner of the window
+    show_clusters: bool,
+    /// Whether the kinetic energy and mean speed are shown in the corner of the window
+    show_energy: bool,
+    /// First type of a pair being chosen for interaction toggling
+    picked_type: Option<PointType>,
+    /// Whether the right mouse button is held, pulling particles toward the cursor
+    force_held: bool,
+    /// Modifier keys held down, shift pushing particles away from the cursor instead
+    modifiers: winit::event::ModifiersState,
+    /// Whether something on screen changed since the last frame, used to skip redraws while paused
+    needs_redraw: bool,
     pipeline: RenderPipeline,
+    flow_arrows: Option<FlowArrows>,
+    colors: BindableBuffer,
+    /// What is in `colors`, kept for saving sessions
+    color_values: Vec<[f32; 3]>,
+    /// Where F5 saves the session, if anywhere
+    session_target: Option<SessionTarget>,
+    /// Whether the particles are read back every so often to restart from if the device is lost
+    recover: bool,
+    /// Earlier states to scrub back to
+    rewind: Rewind,
+    /// Where the particles are written as they move, if anywhere
+    recorder: Option<Recorder>,
+    /// Recording played back instead of running the simulation
+    replay: Option<Replay>,
+    /// Where the particles are streamed to as they move, if anywhere
+    server: Option<Server>,
+    /// Takes commands from scripts, if they are allowed to connect
+    controller: Option<Controller>,
+    /// Where metrics are sent as OSC messages, if anywhere
+    osc: Option<OscSender>,
+    /// Knobs of a MIDI controller that change parameters, if the config maps any
+    midi: Option<MidiControls>,
+    /// Collects snapshots of the particles to export when the window closes
+    npz: Option<NpzExporter>,
+    /// Where the particles are written to a Parquet file as they move, if anywhere
+    trajectory: Option<TrajectoryWriter>,
+    /// Pauses the simulation when its particles blow up
+    explosion_check: Option<(ExplosionCheck, ExplosionCheckState)>,
+    /// Built when first shown, and rebuilt when the window size changes
+    territory: Option<Territory>,
+    show_territory: bool,
+    /// Built when first shown, and rebuilt when the window size changes
+    density: Option<Density>,
+    /// Whether a density map is drawn instead of individual particles
+    show_density: bool,
+    /// Whether particles are colored by speed instead of by type
+    speed_colors: bool,
+    hud: Hud,
+    /// Rebuilt before it is drawn whenever the rules or colors it shows changed
+    matrix: AttractionMatrix,
+    show_matrix: bool,
+    /// Whether frame and tick rates are shown in the corner of the window
+    show_hud: bool,
+    /// Whether left clicks select particles instead of panning
+    inspecting: bool,
+    /// Whether left clicks add particles instead of panning
+    brushing: bool,
+    /// Type of the particles the brush adds
+    brush_type: PointType,
+    /// Whether left clicks remove particles instead of panning
+    erasing: bool,
+    /// Whether the brush or eraser is held down
+    painting: bool,
+    /// Index of the particle whose details are shown
+    selected: Option<u32>,
+    /// Whether the camera stays centered on the selected particle
+    following: bool,
+    /// Built when first shown, and rebuilt when the window size changes or trails are switched
+    /// back on
+    trails: Option<Trails>,
+    show_trails: bool,
+    /// Draws particles into the trails
+    trail_pipeline: RenderPipeline,
+    /// Orders the particles before they are drawn, and draws them in that order
+    depth_sort: Option<(DepthSort, RenderPipeline)>,
     swapchain: SwapChain,
     sc_desc: SwapChainDescriptor,
     bind_group: BindGroup,
+    /// Layout of `bind_group`, for the bind groups of grid tiles
+    bind_group_layout: BindGroupLayout,
+    trail_styles: BindableBuffer,
+    /// Simulations shown beside this one with `--grid`, left to right and then top to bottom
+    tiles: Vec<Tile>,
+    /// Columns and rows the window is split into, 1 by 1 without `--grid`
+    grid: (u32, u32),
     render_globals: BindableBuffer,
     staging_belt: StagingBelt,
     executor: LocalExecutor<'static>,
+    display: DisplayConfig,
+    locale: Locale,
+    /// Window title, without any measurements
+    title: String,
+    /// Physical pixels per logical pixel of the monitor the window is on
+    scale_factor: f64,
     // Camera
     x: f32,
     y: f32,
     zoom: f32,
+    /// Arrow and WASD keys held down
+    held_pan_keys: Vec<VirtualKeyCode>,
+    /// Fingers on a touch screen, by id
+    touches: Vec<(u64, winit::dpi::PhysicalPosition<f64>)>,
     last_mouse_position: Option<winit::dpi::PhysicalPosition<f64>>,
 }
 
+/// Another simulation stepped and drawn alongside the main one, in its own part of the window
+struct Tile {
+    simulation: Simulation,
+    bind_group: BindGroup,
+}
+
 impl Visualization {
     const CIRCLE_VERTS: u32 = 16;
     const CIRCLE_RADIUS: f32 = 5.0;
+    /// Circles in the vertex buffer, one for each copy of a particle drawn across the seam of
+    /// wrapping walls; see `seam_copy` in render.wgsl
+    const SEAM_COPIES: u32 = 4;
+    /// Fraction of the circle radius drawn as an outline in high contrast mode
+    const OUTLINE_WIDTH: f32 = 0.3;
+    const ZOOM_STEP: f32 = 1.1;
+    const DEFAULT_ZOOM: f32 = 0.0007;
+    /// Camera speed when panning with the keyboard, in half screens per second
+    const PAN_SPEED: f32 = 1.0;
+    const REDUCED_MOTION_PAN_SPEED: f32 = 0.3;
+    /// Longest frame time keyboard panning accounts for, so that a stalled frame does not make
+    /// the camera jump
+    const MAX_PAN_FRAME_TIME: f32 = 0.1;
+    /// Space left around the particles when fitting them into view, as a fraction of their extent
+    const FIT_MARGIN: f32 = 0.05;
+    const RESTITUTION_STEP: f32 = 0.05;
+    /// Speed at the top of the speed colormap when the config does not give one
+    const DEFAULT_MAX_SPEED: f32 = 2.0;
+    /// Fraction of the trails kept each frame when the config does not give one
+    const DEFAULT_TRAIL_FADE: f32 = 0.9;
+    /// Procedural rulesets sampled when rerolling before giving up on matching the number of types
+    const REROLL_TRIES: u32 = 100;
+    /// Furthest a click may be from a particle to pick it, in world units
+    const PICK_RADIUS: f32 = Self::CIRCLE_RADIUS * 2.0;
+    /// Furthest a click may be from a particle to select it, in physical pixels, when that is
+    /// further than `PICK_RADIUS`
+    const SELECT_DISTANCE: f32 = 12.0;
+    /// Time between readbacks of the particles to restart from if the device is lost
+    const RECOVERY_INTERVAL: Duration = Duration::from_secs(10);
+    const REDUCED_MOTION_ZOOM_STEP: f32 = 1.03;
+    /// Radius of the brush, in logical pixels
+    const BRUSH_RADIUS: f64 = 10.0;
+    /// Particles the brush adds every frame while held down
+    const BRUSH_PER_FRAME: usize = 4;
+    /// Radius of the eraser, in logical pixels
+    const ERASER_RADIUS: f64 = 20.0;
+    /// Reach of the force tool, in logical pixels
+    const FORCE_RADIUS: f64 = 80.0;
+    /// Velocity the force tool adds at the cursor every step, as a fraction of its reach
+    const FORCE_PULL: f32 = 0.02;
+    /// Logical pixels of smooth scrolling that zoom as much as one line of a mouse wheel
+    const PIXELS_PER_LINE: f64 = 20.0;
+    /// Largest camera movement per frame while following a particle in reduced motion mode, in
+    /// logical pixels
+    const REDUCED_MOTION_MAX_PAN: f64 = 8.0;
 
     pub fn with_random_colors(
         device: &Device,
         adapter: &Adapter,
         surface: &Surface,
         simulation: Simulation,
+        display: DisplayConfig,
+        window: &Window,
+        locale: Locale,
     ) -> Self {
+        let color_values = random_colors(simulation.ruleset.num_point_types, display.high_contrast);
         let colors = BindableBuffer::new(
             &device,
-            BufferUsage::STORAGE,
-            ShaderStage::VERTEX,
+            BufferUsage::STORAGE | BufferUsage::COPY_DST,
+            ShaderStage::VERTEX | ShaderStage::FRAGMENT,
             false,
             simulation.ruleset.num_point_types as usize * VEC3_SIZE,
             |colors| {
                 let slice = colors.slice(..);
                 let mut range = slice.get_mapped_range_mut();
                 let mut cursor = Cursor::new(&mut *range);
-                for _ in 0..simulation.ruleset.num_point_types * 3 {
-                    cursor
-                        .write_all(&rand::random::<f32>().to_le_bytes())
-                        .unwrap();
+                for color in &color_values {
+                    for channel in color.iter() {
+                        cursor.write_all(&channel.to_le_bytes()).unwrap();
+                    }
+                }
+            },
+        );
+
+        // A negative red leaves the trail in the particle color
+        let trail_styles = BindableBuffer::new(
+            &device,
+            BufferUsage::STORAGE,
+            ShaderStage::VERTEX,
+            false,
+            simulation.ruleset.num_point_types as usize * size_of::<[f32; 4]>(),
+            |trail_styles| {
+                let slice = trail_styles.slice(..);
+                let mut range = slice.get_mapped_range_mut();
+                let mut cursor = Cursor::new(&mut *range);
+                for type_ in 0..simulation.ruleset.num_point_types {
+                    let mut color = None;
+                    let mut fade = display.trail_fade.unwrap_or(Self::DEFAULT_TRAIL_FADE);
+                    for trail in display.type_trails.iter().filter(|t| t.type_ == type_) {
+                        color = trail.color.or(color);
+                        fade = trail.fade.unwrap_or(fade);
+                    }
+                    let [r, g, b] = color.unwrap_or([-1.0, 0.0, 0.0]);
+                    for channel in [r, g, b, fade].iter() {
+                        cursor.write_all(&channel.to_le_bytes()).unwrap();
+                    }
                 }
             },
         );
@@ -70,9 +270,9 @@ impl Visualization {
         let render_globals = BindableBuffer::new(
             &device,
             BufferUsage::UNIFORM | BufferUsage::COPY_DST,
-            ShaderStage::VERTEX,
+            ShaderStage::VERTEX | ShaderStage::FRAGMENT | ShaderStage::COMPUTE,
             true,
-            size_of::<f32>() * 3 + size_of::<u32>() * 2, // x + y + + width + height + zoom
+            size_of::<f32>() * 6 + size_of::<u32>() * 3, // x + y + width + height + zoom + outline + max_speed + selected + opacity
             |_| {},
         );
 
@@ -81,20 +281,22 @@ impl Visualization {
             BufferUsage::VERTEX,
             ShaderStage::VERTEX,
             false,
-            VEC2_SIZE as u32 * (Self::CIRCLE_VERTS + 1),
+            VEC2_SIZE as u32 * (Self::CIRCLE_VERTS + 1) * Self::SEAM_COPIES,
             |vert_buf| {
                 let slice = vert_buf.slice(..);
                 let mut range = slice.get_mapped_range_mut();
                 let mut cursor = Cursor::new(&mut *range);
-                for _ in 0..2 {
-                    cursor.write_all(&0.0f32.to_le_bytes()).unwrap();
-                }
-                for i in 0..Self::CIRCLE_VERTS {
-                    let i = i as f32 / Self::CIRCLE_VERTS as f32 * 2.0 * std::f32::consts::PI;
-                    let x = i.cos() * Self::CIRCLE_RADIUS;
-                    let y = i.sin() * Self::CIRCLE_RADIUS;
-                    cursor.write_all(&x.to_le_bytes()).unwrap();
-                    cursor.write_all(&y.to_le_bytes()).unwrap();
+                for _ in 0..Self::SEAM_COPIES {
+                    for _ in 0..2 {
+                        cursor.write_all(&0.0f32.to_le_bytes()).unwrap();
+                    }
+                    for i in 0..Self::CIRCLE_VERTS {
+                        let i = i as f32 / Self::CIRCLE_VERTS as f32 * 2.0 * std::f32::consts::PI;
+                        let x = i.cos() * Self::CIRCLE_RADIUS;
+                        let y = i.sin() * Self::CIRCLE_RADIUS;
+                        cursor.write_all(&x.to_le_bytes()).unwrap();
+                        cursor.write_all(&y.to_le_bytes()).unwrap();
+                    }
                 }
             },
         );
@@ -137,19 +339,19 @@ impl Visualization {
                 render_globals.bind_group_layout_entry(1),
                 simulation.types.bind_group_layout_entry(2),
                 colors.bind_group_layout_entry(3),
+                simulation.velocities.bind_group_layout_entry(4),
+                trail_styles.bind_group_layout_entry(5),
             ],
         });
 
-        let bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: Some("render_bind_group"),
-            layout: &bind_group_layout,
-            entries: &[
-                simulation.globals.bind_group_entry(0),
-                render_globals.bind_group_entry(1),
-                simulation.types.bind_group_entry(2),
-                colors.bind_group_entry(3),
-            ],
-        });
+        let bind_group = render_bind_group(
+            device,
+            &bind_group_layout,
+            &simulation,
+            &render_globals,
+            &colors,
+            &trail_styles,
+        );
 
         let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("render_layout"),
@@ -159,94 +361,653 @@ impl Visualization {
 
         let swapchain_format = adapter.get_swap_chain_preferred_format(&surface);
 
-        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("render_pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: VertexState {
-                module: &shader,
-                entry_point: "main",
-                buffers: &[
-                    VertexBufferLayout {
-                        array_stride: VEC2_SIZE as u64,
-                        step_mode: InputStepMode::Vertex,
-                        attributes: &[VertexAttribute {
-                            format: VertexFormat::Float2,
-                            offset: 0,
-                            shader_location: 0,
-                        }],
-                    },
-                    VertexBufferLayout {
-                        array_stride: VEC2_SIZE as u64,
-                        step_mode: InputStepMode::Instance,
-                        attributes: &[VertexAttribute {
-                            format: VertexFormat::Float2,
-                            offset: 0,
-                            shader_location: 1,
-                        }],
-                    },
-                ],
+        // Particles blend over what is behind them by their opacity, without touching the alpha
+        // of the screen
+        let blended = ColorTargetState {
+            format: swapchain_format,
+            alpha_blend: BlendState::REPLACE,
+            color_blend: BlendState {
+                src_factor: BlendFactor::SrcAlpha,
+                dst_factor: BlendFactor::OneMinusSrcAlpha,
+                operation: BlendOperation::Add,
             },
-            primitive: PrimitiveState::default(),
-            depth_stencil: None,
-            multisample: MultisampleState::default(),
-            fragment: Some(FragmentState {
-                module: &shader,
-                entry_point: "main",
-                targets: &[swapchain_format.into()],
-            }),
-        });
+            write_mask: ColorWrite::COLOR,
+        };
+        let particle_pipeline = |label: &str,
+                                 layout: &PipelineLayout,
+                                 vertex_entry_point: &str,
+                                 fragment_entry_point: &str,
+                                 target: ColorTargetState| {
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: VertexState {
+                    module: &shader,
+                    entry_point: vertex_entry_point,
+                    buffers: &[
+                        VertexBufferLayout {
+                            array_stride: VEC2_SIZE as u64,
+                            step_mode: InputStepMode::Vertex,
+                            attributes: &[VertexAttribute {
+                                format: VertexFormat::Float2,
+                                offset: 0,
+                                shader_location: 0,
+                            }],
+                        },
+                        VertexBufferLayout {
+                            array_stride: VEC2_SIZE as u64,
+                            step_mode: InputStepMode::Instance,
+                            attributes: &[VertexAttribute {
+                                format: VertexFormat::Float2,
+                                offset: 0,
+                                shader_location: 1,
+                            }],
+                        },
+                    ],
+                },
+                primitive: PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: MultisampleState::default(),
+                fragment: Some(FragmentState {
+                    module: &shader,
+                    entry_point: fragment_entry_point,
+                    targets: &[target],
+                }),
+            })
+        };
+        let pipeline = particle_pipeline(
+            "render_pipeline",
+            &pipeline_layout,
+            "main",
+            "main",
+            blended.clone(),
+        );
+        let trail_pipeline = particle_pipeline(
+            "trail_pipeline",
+            &pipeline_layout,
+            "trail_vertex",
+            "trail_fragment",
+            swapchain_format.into(),
+        );
+        let depth_sort = if display.depth_sort {
+            let depth_sort = DepthSort::new(device, &render_globals, &simulation);
+            let sorted_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("sorted_render_layout"),
+                bind_group_layouts: &[&bind_group_layout, depth_sort.draw_layout()],
+                push_constant_ranges: &[],
+            });
+            let sorted_pipeline = particle_pipeline(
+                "sorted_render_pipeline",
+                &sorted_layout,
+                "sorted_main",
+                "main",
+                blended,
+            );
+            Some((depth_sort, sorted_pipeline))
+        } else {
+            None
+        };
 
+        let flow_arrows =
+            FlowArrows::new(device, &render_globals, swapchain_format, &simulation.flows);
+        let hud = Hud::new(
+            device,
+            &render_globals,
+            swapchain_format,
+            display.high_contrast,
+        );
+        let matrix = AttractionMatrix::new(device, &render_globals, swapchain_format);
+
+        let size = window.inner_size();
         let sc_desc = SwapChainDescriptor {
             usage: TextureUsage::RENDER_ATTACHMENT,
             format: swapchain_format,
-            width: 800,
-            height: 600,
-            present_mode: PresentMode::Mailbox,
+            width: size.width,
+            height: size.height,
+            // Surfaces cannot be asked which modes they support; ones that are not are replaced by
+            // fifo, which every surface supports, when the swapchain is created
+            present_mode: match display.present_mode {
+                Some(PresentModeChoice::Immediate) => PresentMode::Immediate,
+                Some(PresentModeChoice::Fifo) => PresentMode::Fifo,
+                Some(PresentModeChoice::Mailbox) | None => PresentMode::Mailbox,
+            },
         };
 
         let swapchain = device.create_swap_chain(&surface, &sc_desc);
+        let show_density = display.density
+            || display
+                .density_threshold
+                .is_some_and(|threshold| simulation.num_points > threshold);
 
         Visualization {
             simulation,
             swapchain,
             sc_desc,
             bind_group,
+            bind_group_layout,
+            trail_styles,
+            tiles: Vec::new(),
+            grid: (1, 1),
             ticks: 0,
             ticks_per_frame: 1,
             ticks_just_now: 0,
             last_update_duration: Duration::from_millis(1),
+            paused: false,
+            show_clusters: false,
+            colors,
+            color_values,
+            session_target: None,
+            recover: true,
+            rewind: Rewind::new(0, 1),
+            recorder: None,
+            replay: None,
+            server: None,
+            controller: None,
+            osc: None,
+            midi: None,
+            npz: None,
+            trajectory: None,
+            explosion_check: None,
+            territory: None,
+            show_territory: false,
+            density: None,
+            show_density,
+            speed_colors: display.speed_colors,
+            trails: None,
+            show_trails: display.trails,
+            trail_pipeline,
+            depth_sort,
+            hud,
+            show_hud: false,
+            matrix,
+            show_matrix: false,
+            inspecting: false,
+            brushing: false,
+            brush_type: 0,
+            erasing: false,
+            painting: false,
+            selected: None,
+            following: false,
+            show_energy: false,
+            picked_type: None,
+            force_held: false,
+            modifiers: winit::event::ModifiersState::empty(),
+            title: locale.get("window_title").to_owned(),
+            locale,
+            needs_redraw: true,
             pipeline,
+            flow_arrows,
             render_globals,
             staging_belt,
             executor: LocalExecutor::new(),
+            display,
+            scale_factor: window.scale_factor(),
             x: 0.0,
             y: 0.0,
-            zoom: 0.0007,
+            zoom: Self::DEFAULT_ZOOM,
+            held_pan_keys: Vec::new(),
+            touches: Vec::new(),
             last_mouse_position: None,
             vertex_buffer,
             index_buffer,
         }
     }
 
+    /// Splits the window into `columns` by `rows` tiles, the first showing this simulation and the
+    /// others `simulations`, all seen through the same camera. They need as many types as this
+    /// one, since they share its colors. Density maps, territories and depth sorting need the
+    /// whole window, and are switched off.
+    pub fn with_grid(
+        mut self,
+        device: &Device,
+        columns: u32,
+        rows: u32,
+        simulations: Vec<Simulation>,
+    ) -> Self {
+        self.grid = (columns.max(1), rows.max(1));
+        self.tiles = simulations
+            .into_iter()
+            .map(|simulation| Tile {
+                bind_group: render_bind_group(
+                    device,
+                    &self.bind_group_layout,
+                    &simulation,
+                    &self.render_globals,
+                    &self.colors,
+                    &self.trail_styles,
+                ),
+                simulation,
+            })
+            .collect();
+        self.show_density = false;
+        self.show_territory = false;
+        self.depth_sort = None;
+        self
+    }
+
+    /// Size in pixels of each tile of the grid, or of the whole window without one
+    fn tile_size(&self) -> (u32, u32) {
+        let (columns, rows) = self.grid;
+        (
+            (self.sc_desc.width / columns).max(1),
+            (self.sc_desc.height / rows).max(1),
+        )
+    }
+
+    /// Uses `title` in place of the translated window title
+    pub fn with_title(mut self, title: String) -> Self {
+        self.title = title;
+        self
+    }
+
+    /// Keeps earlier states to go back to with J and K
+    pub fn with_rewind(mut self, rewind: Rewind) -> Self {
+        self.rewind = rewind;
+        self
+    }
+
+    /// Appends the particles to a recording every so often, for `plife replay`
+    pub fn with_recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Streams the particles to connected WebSocket clients every so often
+    pub fn with_server(mut self, server: Server) -> Self {
+        self.server = Some(server);
+        self
+    }
+
+    /// Carries out commands from scripts connected over TCP between frames
+    pub fn with_controller(mut self, controller: Controller) -> Self {
+        self.controller = Some(controller);
+        self
+    }
+
+    /// Sends measurements of the simulation as OSC messages every so often
+    pub fn with_osc(mut self, osc: OscSender) -> Self {
+        self.osc = Some(osc);
+        self
+    }
+
+    /// Lets MIDI knobs and sliders change parameters as the simulation runs
+    pub fn with_midi(mut self, midi: MidiControls) -> Self {
+        self.midi = Some(midi);
+        self
+    }
+
+    /// Collects snapshots of the particles to write to a NumPy archive when the window closes
+    pub fn with_npz(mut self, npz: NpzExporter) -> Self {
+        self.npz = Some(npz);
+        self
+    }
+
+    /// Writes snapshots of the particles to a Parquet file as the simulation runs
+    pub fn with_trajectory(mut self, trajectory: TrajectoryWriter) -> Self {
+        self.trajectory = Some(trajectory);
+        self
+    }
+
+    /// Checks every so often whether the particles blew up, pausing and explaining why if they did
+    pub fn with_explosion_check(mut self, check: ExplosionCheck) -> Self {
+        let state = check.start();
+        self.explosion_check = Some((check, state));
+        self
+    }
+
+    /// Shows the particles of a recording instead of simulating them
+    pub fn with_replay(mut self, replay: Replay) -> Self {
+        self.replay = Some(replay);
+        self
+    }
+
+    /// Lets F5 save the session to `target`
+    pub fn with_session_target(mut self, target: SessionTarget) -> Self {
+        self.session_target = Some(target);
+        self
+    }
+
+    /// Skips reading the particles back to restart from, so that losing the device ends the run
+    pub fn without_recovery(mut self) -> Self {
+        self.recover = false;
+        self
+    }
+
+    /// Puts the camera, display settings and colors back the way they were in a saved session
+    pub fn restore_session(&mut self, queue: &Queue, session: &Session) {
+        let view = &session.view;
+        self.x = view.x;
+        self.y = view.y;
+        self.zoom = view.zoom;
+        self.ticks = view.ticks;
+        self.ticks_per_frame = view.ticks_per_frame;
+        self.paused = view.paused;
+        self.speed_colors = view.speed_colors;
+        self.show_density = view.show_density;
+        self.show_territory = view.show_territory;
+        self.show_trails = view.show_trails;
+        self.show_hud = view.show_hud;
+        self.show_clusters = view.show_clusters;
+        self.show_energy = view.show_energy;
+        self.show_matrix = view.show_matrix;
+        for &(a, b) in &view.disabled_pairs {
+            self.simulation.set_interaction_enabled(queue, a, b, false);
+        }
+        self.matrix.clear();
+        self.simulation.set_restitution(view.restitution);
+        if session.colors.len() == self.color_values.len() {
+            self.set_colors(queue, session.colors.clone());
+        }
+    }
+
+    fn set_colors(&mut self, queue: &Queue, colors: Vec<[f32; 3]>) {
+        let mut bytes = Vec::with_capacity(colors.len() * VEC3_SIZE);
+        for color in &colors {
+            for channel in color.iter() {
+                bytes.extend_from_slice(&channel.to_le_bytes());
+            }
+        }
+        queue.write_buffer(&self.colors.buffer, 0, &bytes);
+        self.color_values = colors;
+        self.matrix.clear();
+    }
+
+    /// The config the simulation was started from, to sample new positions or rulesets from
+    fn start_config(&self) -> Option<Config> {
+        let target = self.session_target.as_ref()?;
+        match Config::from_value(target.config.clone()) {
+            Ok(config) => Some(config),
+            Err(e) => {
+                warn!("Cannot sample the config again:\n{}", e);
+                None
+            }
+        }
+    }
+
+    /// Replaces the particles with new ones sampled from the config, keeping the ruleset
+    fn reroll_points(&mut self, queue: &Queue) {
+        let config = match self.start_config() {
+            Some(config) => config,
+            None => return,
+        };
+        let points = config.points.sample(&self.simulation.walls);
+        match self.simulation.replace_points(queue, &points) {
+            Ok(()) => {
+                // Particles kept their indices but not their identities
+                self.selected = None;
+                self.following = false;
+                self.trails = None;
+                info!("New positions for {} particles", self.simulation.num_points);
+            }
+            Err(e) => warn!("Cannot use the new positions: {}", e),
+        }
+    }
+
+    /// Switches to a new ruleset sampled from the config, keeping the particles where they are
+    fn reroll_ruleset(&mut self, queue: &Queue) {
+        let config = match self.start_config() {
+            Some(config) => config,
+            None => return,
+        };
+        let num_types = self.simulation.ruleset.num_point_types;
+        // Procedural rulesets may pick a different number of types, which the particles and
+        // colors cannot follow
+        let ruleset = (0..Self::REROLL_TRIES)
+            .map(|_| config.ruleset.clone().sample())
+            .find(|ruleset| ruleset.num_point_types == num_types);
+        match ruleset.map(|ruleset| self.simulation.set_ruleset(queue, ruleset)) {
+            Some(Ok(())) => {
+                self.matrix.clear();
+                info!("New ruleset");
+            }
+            Some(Err(e)) => warn!("Cannot use the new ruleset: {}", e),
+            None => warn!(
+                "Cannot sample a ruleset with {} types from the config; restart to change the number of types",
+                num_types
+            ),
+        }
+    }
+
+    fn reroll_colors(&mut self, queue: &Queue) {
+        let colors = random_colors(
+            self.simulation.ruleset.num_point_types,
+            self.display.high_contrast,
+        );
+        self.set_colors(queue, colors);
+    }
+
+    /// Reads the particles back and captures them along with the view, if there is a config
+    /// document to capture them with
+    fn capture_session(&self, device: &Device, queue: &Queue) -> Option<Session> {
+        let target = self.session_target.as_ref()?;
+        let view = ViewState {
+            x: self.x,
+            y: self.y,
+            zoom: self.zoom,
+            ticks: self.ticks,
+            ticks_per_frame: self.ticks_per_frame,
+            paused: self.paused,
+            speed_colors: self.speed_colors,
+            show_density: self.show_density,
+            show_territory: self.show_territory,
+            show_trails: self.show_trails,
+            show_hud: self.show_hud,
+            show_clusters: self.show_clusters,
+            show_energy: self.show_energy,
+            show_matrix: self.show_matrix,
+            disabled_pairs: self.simulation.disabled_interactions(),
+            restitution: self.simulation.restitution(),
+        };
+        Some(Session::capture(
+            device,
+            queue,
+            &self.simulation,
+            &target.config,
+            self.color_values.clone(),
+            view,
+        ))
+    }
+
+    /// The session to restart from if the device is lost, unless recovery was switched off
+    fn recovery_point(&self, device: &Device, queue: &Queue) -> Option<Session> {
+        if self.recover {
+            self.capture_session(device, queue)
+        } else {
+            None
+        }
+    }
+
+    fn save_session(&self, device: &Device, queue: &Queue) {
+        let (target, session) = match (&self.session_target, self.capture_session(device, queue)) {
+            (Some(target), Some(session)) => (target, session),
+            _ => return,
+        };
+        match session.save(&target.path) {
+            Ok(()) => info!("Saved session to {}", target.path.display()),
+            Err(e) => error!("Cannot save session to {}: {}", target.path.display(), e),
+        }
+    }
+
     fn update(&mut self, device: &Device, queue: &Queue) {
+        if self.replay.is_some() {
+            self.play(queue);
+            return;
+        }
         self.ticks_just_now = 0;
+        // Steps stop short where a script asked to pause
+        let steps = self
+            .controller
+            .as_ref()
+            .map_or(u64::MAX, |controller| controller.due_in(self.ticks))
+            .min(self.ticks_per_frame as u64);
         let start = Instant::now();
-        for _ in 0..self.ticks_per_frame {
-            self.simulation.step(device, queue);
-            self.ticks += 1;
-            self.ticks_just_now += 1;
+        self.simulation.step_many(device, queue, steps);
+        for tile in &mut self.tiles {
+            tile.simulation.step_many(device, queue, steps);
         }
+        // Once a frame, so that the step time shown is the time the GPU took
+        device.poll(Maintain::Wait);
+        self.ticks += steps;
+        self.ticks_just_now = steps as u16;
         let end = Instant::now();
         self.last_update_duration = end - start;
+        self.rewind
+            .record(device, queue, &self.simulation, self.ticks);
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(e) = recorder.record(device, queue, &self.simulation, self.ticks) {
+                exit_with(anyhow::Error::new(e).context("Cannot write recording"));
+            }
+        }
+        if let Some(server) = &mut self.server {
+            server.send(device, queue, &self.simulation, self.ticks);
+        }
+        if let Some(osc) = &mut self.osc {
+            osc.send(device, queue, &self.simulation, self.ticks);
+        }
+        if let Some(npz) = &mut self.npz {
+            npz.record(device, queue, &self.simulation, self.ticks);
+        }
+        if let Some(trajectory) = &mut self.trajectory {
+            if let Err(e) = trajectory.record(device, queue, &self.simulation, self.ticks) {
+                exit_with(anyhow::Error::new(e).context("Cannot write trajectory"));
+            }
+        }
+        if let Some((check, state)) = &mut self.explosion_check {
+            if let Some(explosion) =
+                state.check(check, device, queue, &mut self.simulation, self.ticks)
+            {
+                error!("{}", explosion);
+                if let Some(ticks) = explosion.restored_to {
+                    self.ticks = ticks;
+                }
+                self.paused = true;
+            }
+        }
+    }
+
+    /// Moves the replay on by a frame's worth of ticks, starting over once it ends
+    fn play(&mut self, queue: &Queue) {
+        let replay = match &mut self.replay {
+            Some(replay) => replay,
+            None => return,
+        };
+        if replay.is_finished() {
+            if let Err(e) = replay.restart() {
+                warn!("Cannot restart the replay: {}", e);
+                self.paused = true;
+                return;
+            }
+            self.ticks = 0;
+        }
+        self.ticks += self.ticks_per_frame as u64;
+        self.ticks_just_now = self.ticks_per_frame;
+        match replay.advance(self.ticks) {
+            Ok(Some(points)) => {
+                // Nothing is simulated, so velocities only matter for coloring by speed
+                let velocities = vec![[0.0; 2]; points.len()];
+                self.simulation.rewind(queue, &points, &velocities);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!("Cannot read the replay: {}", e);
+                self.paused = true;
+            }
+        }
+    }
+
+    /// Rebuilds everything on the GPU for a new device after the old one was lost, with the
+    /// particles and view of `recovery_point`. Recordings, replays and rewind frames are kept.
+    fn recover(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        adapter: &Adapter,
+        surface: &Surface,
+        window: &Window,
+        recovery_point: &Session,
+    ) -> Result<(), String> {
+        let mut simulation =
+            recovery_point.simulation(device, queue, self.simulation.workgroup_size())?;
+        simulation.set_cpu(self.simulation.is_on_cpu());
+        if !self.tiles.is_empty() {
+            warn!("Only the first tile of the grid is recovered; the others were lost with the device");
+        }
+        let mut recovered = Visualization::with_random_colors(
+            device,
+            adapter,
+            surface,
+            simulation,
+            self.display.clone(),
+            window,
+            self.locale.clone(),
+        );
+        recovered.restore_session(queue, recovery_point);
+        recovered.title = std::mem::take(&mut self.title);
+        recovered.session_target = self.session_target.take();
+        recovered.recover = self.recover;
+        recovered.rewind = std::mem::replace(&mut self.rewind, Rewind::new(0, 1));
+        recovered.recorder = self.recorder.take();
+        recovered.server = self.server.take();
+        recovered.controller = self.controller.take();
+        recovered.osc = self.osc.take();
+        recovered.midi = self.midi.take();
+        recovered.npz = self.npz.take();
+        recovered.trajectory = self.trajectory.take();
+        recovered.replay = self.replay.take();
+        recovered.explosion_check = self.explosion_check.take();
+        *self = recovered;
+        Ok(())
+    }
+
+    /// Follows the window to a new size. Minimized windows have no size, and keep the swapchain
+    /// they had until they are shown again.
+    fn resize(&mut self, device: &Device, surface: &Surface, width: u32, height: u32) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.sc_desc.width = width;
+        self.sc_desc.height = height;
+        self.swapchain = device.create_swap_chain(surface, &self.sc_desc);
     }
 
-    fn render(&mut self, device: &Device, queue: &Queue) {
-        let frame = self.swapchain.get_current_frame().unwrap().output;
+    /// The texture to draw the next frame into, or `None` to skip this frame. A swapchain that no
+    /// longer matches the surface, as after a resize or a GPU reset on some drivers, is recreated
+    /// once; errors that recreating it does not fix mean the device is lost.
+    fn next_frame(
+        &mut self,
+        device: &Device,
+        surface: &Surface,
+    ) -> Result<Option<SwapChainTexture>, SwapChainError> {
+        match self.swapchain.get_current_frame() {
+            Ok(frame) => return Ok(Some(frame.output)),
+            Err(SwapChainError::Timeout) => return Ok(None),
+            Err(SwapChainError::Outdated) | Err(SwapChainError::Lost) => {}
+            Err(e) => return Err(e),
+        }
+        self.swapchain = device.create_swap_chain(surface, &self.sc_desc);
+        match self.swapchain.get_current_frame() {
+            Ok(frame) => Ok(Some(frame.output)),
+            Err(SwapChainError::Timeout) | Err(SwapChainError::Outdated) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn render(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        surface: &Surface,
+    ) -> Result<(), SwapChainError> {
+        let frame = match self.next_frame(device, surface)? {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
         let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
             label: Some("render"),
         });
         // Write render globals
         {
+            encoder.push_debug_group("write render globals");
             let mut view = self.staging_belt.write_buffer(
                 &mut encoder,
                 &self.render_globals.buffer,
@@ -254,17 +1015,117 @@ impl Visualization {
                 NonZeroU64::new(self.render_globals.size).unwrap(),
                 &device,
             );
+            // With a grid, everything is drawn as if the window were the size of one tile
+            let (tile_width, tile_height) = self.tile_size();
             let mut cursor = Cursor::new(&mut *view);
             cursor.write_all(&self.x.to_le_bytes()).unwrap();
             cursor.write_all(&self.y.to_le_bytes()).unwrap();
-            cursor.write_all(&self.sc_desc.width.to_le_bytes()).unwrap();
+            cursor.write_all(&tile_width.to_le_bytes()).unwrap();
+            cursor.write_all(&tile_height.to_le_bytes()).unwrap();
+            cursor.write_all(&self.zoom.to_le_bytes()).unwrap();
+            let outline = if self.display.high_contrast {
+                Self::OUTLINE_WIDTH
+            } else {
+                0.0
+            };
+            cursor.write_all(&outline.to_le_bytes()).unwrap();
+            let max_speed = if self.speed_colors {
+                self.display.max_speed.unwrap_or(Self::DEFAULT_MAX_SPEED)
+            } else {
+                0.0
+            };
+            cursor.write_all(&max_speed.to_le_bytes()).unwrap();
             cursor
-                .write_all(&self.sc_desc.height.to_le_bytes())
+                .write_all(&self.selected.unwrap_or(u32::MAX).to_le_bytes())
                 .unwrap();
-            cursor.write_all(&self.zoom.to_le_bytes()).unwrap();
+            let opacity = self.display.opacity.unwrap_or(1.0);
+            cursor.write_all(&opacity.to_le_bytes()).unwrap();
             drop(cursor);
             drop(view);
             self.staging_belt.finish();
+            encoder.pop_debug_group();
+        }
+        let (width, height) = (self.sc_desc.width, self.sc_desc.height);
+        if self.show_density {
+            if !self
+                .density
+                .as_ref()
+                .is_some_and(|density| density.fits(width, height))
+            {
+                self.density = Some(Density::new(
+                    device,
+                    self.sc_desc.format,
+                    &self.render_globals,
+                    width,
+                    height,
+                ));
+            }
+            encoder.push_debug_group("count density");
+            self.density.as_ref().unwrap().update(
+                &mut encoder,
+                &self.simulation.positions().buffer,
+                self.simulation.num_points,
+            );
+            encoder.pop_debug_group();
+        } else if self.show_territory {
+            if !self
+                .territory
+                .as_ref()
+                .is_some_and(|territory| territory.fits(width, height))
+            {
+                self.territory = Some(Territory::new(
+                    device,
+                    self.sc_desc.format,
+                    &self.render_globals,
+                    &self.simulation,
+                    &self.colors,
+                    width,
+                    height,
+                ));
+            }
+            encoder.push_debug_group("find territories");
+            self.territory
+                .as_ref()
+                .unwrap()
+                .update(&mut encoder, &self.simulation);
+            encoder.pop_debug_group();
+        }
+        if self.show_trails
+            && !self
+                .trails
+                .as_ref()
+                .is_some_and(|trails| trails.fits(width, height))
+        {
+            self.trails = Some(Trails::new(device, self.sc_desc.format, width, height));
+        }
+        if let (false, Some((depth_sort, _))) = (self.show_density, &mut self.depth_sort) {
+            depth_sort.fit(
+                device,
+                self.sc_desc.format,
+                &self.render_globals,
+                width,
+                height,
+            );
+            encoder.push_debug_group("sort particles");
+            depth_sort.update(&mut encoder, &self.simulation);
+            encoder.pop_debug_group();
+        }
+        let trails = self
+            .trails
+            .as_ref()
+            .filter(|_| self.show_trails && !self.show_density);
+        if let Some(trails) = trails {
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("trails_pass"),
+                color_attachments: &[trails.attachment()],
+                depth_stencil_attachment: None,
+            });
+            render_pass.push_debug_group("fade trails");
+            trails.fade(&mut render_pass);
+            render_pass.pop_debug_group();
+            render_pass.push_debug_group("draw trails");
+            self.draw_tiles(&mut render_pass, &self.trail_pipeline);
+            render_pass.pop_debug_group();
         }
         // Render pass
         {
@@ -280,20 +1141,562 @@ impl Visualization {
                 }],
                 depth_stencil_attachment: None,
             });
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.buffer.slice(..));
-            render_pass.set_vertex_buffer(1, self.simulation.positions.buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.buffer.slice(..), IndexFormat::Uint32);
-            render_pass.set_pipeline(&self.pipeline);
-            render_pass.set_bind_group(0, &self.bind_group, &[]);
-            render_pass.draw_indexed(
-                0..(Self::CIRCLE_VERTS * 3),
-                0,
-                0..self.simulation.num_points,
+            if let (true, Some(density)) = (self.show_density, &self.density) {
+                render_pass.push_debug_group("draw density");
+                density.draw(&mut render_pass);
+                render_pass.pop_debug_group();
+            } else if let (true, Some(territory)) = (self.show_territory, &self.territory) {
+                render_pass.push_debug_group("draw territories");
+                territory.draw(&mut render_pass);
+                render_pass.pop_debug_group();
+            }
+            if let Some(trails) = trails {
+                render_pass.push_debug_group("show trails");
+                trails.show(&mut render_pass);
+                render_pass.pop_debug_group();
+            }
+            if let Some(flow_arrows) = &self.flow_arrows {
+                render_pass.push_debug_group("draw flow arrows");
+                self.set_tile_viewport(&mut render_pass, 0);
+                flow_arrows.draw(&mut render_pass);
+                render_pass.pop_debug_group();
+            }
+            if !self.show_density {
+                render_pass.push_debug_group("draw particles");
+                match &self.depth_sort {
+                    Some((depth_sort, sorted_pipeline)) => {
+                        render_pass.set_bind_group(
+                            1,
+                            depth_sort.draw_bind_group(&self.simulation),
+                            &[],
+                        );
+                        self.draw_particles(
+                            &mut render_pass,
+                            sorted_pipeline,
+                            &self.simulation,
+                            &self.bind_group,
+                        );
+                    }
+                    None => self.draw_tiles(&mut render_pass, &self.pipeline),
+                }
+                render_pass.pop_debug_group();
+            }
+        }
+        if self.show_matrix && !self.matrix.is_set() {
+            let simulation = &self.simulation;
+            self.matrix.set_rules(
+                device,
+                &simulation.ruleset,
+                &self.color_values,
+                |a, b| simulation.interaction_enabled(a, b),
+                self.scale_factor,
             );
         }
+        if self.hud_shown() || self.show_matrix {
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("hud_pass"),
+                color_attachments: &[RenderPassColorAttachmentDescriptor {
+                    attachment: &frame.view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Load,
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            // The overlays describe the main simulation, so they go in its tile
+            self.set_tile_viewport(&mut render_pass, 0);
+            if self.hud_shown() {
+                render_pass.push_debug_group("draw hud");
+                self.hud.draw(&mut render_pass);
+                render_pass.pop_debug_group();
+            }
+            if self.show_matrix {
+                render_pass.push_debug_group("draw attraction matrix");
+                self.matrix.draw(&mut render_pass);
+                render_pass.pop_debug_group();
+            }
+        }
         queue.submit(Some(encoder.finish()));
 
         self.executor.spawn(self.staging_belt.recall()).detach();
+        Ok(())
+    }
+
+    /// Limits drawing to tile `index` of the grid, counting left to right and then top to bottom.
+    /// Does nothing without a grid, where the whole window is drawn to.
+    fn set_tile_viewport(&self, render_pass: &mut RenderPass, index: u32) {
+        if self.tiles.is_empty() {
+            return;
+        }
+        let (width, height) = self.tile_size();
+        let (columns, _) = self.grid;
+        render_pass.set_viewport(
+            (index % columns * width) as f32,
+            (index / columns * height) as f32,
+            width as f32,
+            height as f32,
+            0.0,
+            1.0,
+        );
+    }
+
+    /// Draws the main simulation and those of the other tiles, each into its own tile
+    fn draw_tiles<'a>(&'a self, render_pass: &mut RenderPass<'a>, pipeline: &'a RenderPipeline) {
+        self.set_tile_viewport(render_pass, 0);
+        self.draw_particles(render_pass, pipeline, &self.simulation, &self.bind_group);
+        for (index, tile) in self.tiles.iter().enumerate() {
+            self.set_tile_viewport(render_pass, index as u32 + 1);
+            self.draw_particles(render_pass, pipeline, &tile.simulation, &tile.bind_group);
+        }
+    }
+
+    fn draw_particles<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        pipeline: &'a RenderPipeline,
+        simulation: &'a Simulation,
+        bind_group: &'a BindGroup,
+    ) {
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.buffer.slice(..));
+        render_pass.set_vertex_buffer(1, simulation.positions().buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.buffer.slice(..), IndexFormat::Uint32);
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        let copies = match simulation.walls {
+            Walls::Wrapping(_) => Self::SEAM_COPIES,
+            Walls::None | Walls::Square(_) => 1,
+        };
+        for copy in 0..copies {
+            render_pass.draw_indexed(
+                0..(Self::CIRCLE_VERTS * 3),
+                (copy * (Self::CIRCLE_VERTS + 1)) as i32,
+                0..simulation.num_points,
+            );
+        }
+    }
+
+    fn hud_shown(&self) -> bool {
+        self.show_hud
+            || self.show_clusters
+            || self.show_energy
+            || self.selected.is_some()
+            || self.rewind.is_scrubbing()
+    }
+
+    /// Shows a stored state `by` frames back (negative) or forward from the one shown, pausing
+    /// until Space resumes from it
+    fn scrub(&mut self, device: &Device, queue: &Queue, by: isize) {
+        let frame = match self.rewind.scrub(by) {
+            Some(frame) => frame,
+            None => {
+                info!("Nothing to rewind to yet");
+                return;
+            }
+        };
+        self.simulation
+            .rewind(queue, &frame.points, &frame.velocities);
+        self.ticks = frame.ticks;
+        self.paused = true;
+        // Particles kept their indices but not their identities
+        self.selected = None;
+        self.following = false;
+        self.trails = None;
+        self.update_hud(device, queue);
+    }
+
+    /// Refreshes the rates and counts shown in the corner of the window
+    fn update_hud(&mut self, device: &Device, queue: &Queue) {
+        let mut lines = Vec::new();
+        if self.show_hud {
+            let frame_time = if self.hud.fps > 0.0 {
+                1000.0 / self.hud.fps
+            } else {
+                0.0
+            };
+            lines.extend_from_slice(&[
+                format!("{} {:.0}", self.locale.get("fps"), self.hud.fps),
+                format!("{} {:.0}", self.locale.get("tps"), self.hud.tps),
+                format!("{} {:.1} ms", self.locale.get("frame_time"), frame_time),
+                format!(
+                    "{} {:.1} ms",
+                    self.locale.get("step_time"),
+                    self.last_update_duration.as_secs_f32() * 1000.0
+                ),
+                format!("{} {}", self.locale.get("ticks"), self.ticks),
+                format!(
+                    "{} {}",
+                    self.locale.get("particles"),
+                    self.simulation.num_points
+                ),
+            ]);
+        }
+        if self.show_clusters {
+            let points = self.simulation.read_points(device, queue);
+            let clusters = ClusterStats::measure(&points, CLUSTER_RADIUS);
+            lines.push(format!(
+                "{} {}",
+                self.locale.get("clusters"),
+                clusters.count
+            ));
+        }
+        if self.show_energy {
+            let motion = MotionStats::measure(&self.simulation.read_velocities(device, queue));
+            lines.extend_from_slice(&[
+                format!(
+                    "{} {:.3}",
+                    self.locale.get("kinetic_energy"),
+                    motion.kinetic_energy
+                ),
+                format!("{} {:.4}", self.locale.get("mean_speed"), motion.mean_speed),
+            ]);
+        }
+        // Drains can remove the selected particle
+        self.selected = self
+            .selected
+            .filter(|&index| index < self.simulation.num_points);
+        if let Some(index) = self.selected {
+            let ((x, y, type_), [vx, vy]) = self.simulation.read_particle(device, queue, index);
+            if !lines.is_empty() {
+                lines.push(String::new());
+            }
+            lines.extend_from_slice(&[
+                format!("{} {}", self.locale.get("particle"), index),
+                format!("{} {}", self.locale.get("type"), type_.unwrap_or(0)),
+                format!("{} {:.1} {:.1}", self.locale.get("position"), x, y),
+                format!("{} {:.3} {:.3}", self.locale.get("velocity"), vx, vy),
+            ]);
+        }
+        if let Some((bar, back)) = self.rewind.timeline() {
+            if !lines.is_empty() {
+                lines.push(String::new());
+            }
+            lines.push(format!(
+                "{} {} -{} {}",
+                self.locale.get("rewind"),
+                bar,
+                back,
+                self.locale.get("ticks")
+            ));
+        }
+        self.hud.set_text(device, &lines, self.scale_factor);
+    }
+
+    /// Selects the particle under the cursor for the inspector, or clears the selection if there
+    /// is none
+    fn select_particle(&mut self, device: &Device, queue: &Queue) {
+        let position = match self.last_mouse_position {
+            Some(position) => position,
+            None => return,
+        };
+        let (x, y) = self.screen_to_world(position);
+        // Small particles far away are hard to hit, so allow a few pixels around them
+        let (width, height) = self.tile_size();
+        let smallest_dimension = width.min(height) as f32;
+        let radius =
+            Self::PICK_RADIUS.max(Self::SELECT_DISTANCE * 2.0 / smallest_dimension / self.zoom);
+        self.selected = self
+            .simulation
+            .read_points(device, queue)
+            .into_iter()
+            .enumerate()
+            .map(|(i, (px, py, _))| ((px - x) * (px - x) + (py - y) * (py - y), i as u32))
+            .filter(|&(d2, _)| d2 <= radius * radius)
+            .min_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap())
+            .map(|(_, i)| i);
+        self.update_hud(device, queue);
+    }
+
+    /// Converts a position in the window to simulation coordinates. With a grid, every tile
+    /// shows the same part of the world, so the position is taken within the tile it is in.
+    fn screen_to_world(&self, position: winit::dpi::PhysicalPosition<f64>) -> (f32, f32) {
+        let (width, height) = self.tile_size();
+        let (width, height) = (width as f32, height as f32);
+        let smallest_dimension = width.min(height);
+        let ndc_x = (position.x as f32).rem_euclid(width) / width * 2.0 - 1.0;
+        let ndc_y = 1.0 - (position.y as f32).rem_euclid(height) / height * 2.0;
+        (
+            ndc_x * width / smallest_dimension / self.zoom + self.x,
+            ndc_y * height / smallest_dimension / self.zoom + self.y,
+        )
+    }
+
+    /// Multiplies the zoom by `factor`, keeping the point under the cursor where it is
+    fn zoom_toward_cursor(&mut self, factor: f32) {
+        self.zoom_toward(factor, self.last_mouse_position);
+    }
+
+    /// Multiplies the zoom by `factor`, keeping the point at `position` in the window where it is
+    fn zoom_toward(&mut self, factor: f32, position: Option<winit::dpi::PhysicalPosition<f64>>) {
+        // A followed particle stays in the middle of the window
+        let position = match position.filter(|_| !self.is_following()) {
+            Some(position) => position,
+            None => {
+                self.zoom *= factor;
+                return;
+            }
+        };
+        let (x, y) = self.screen_to_world(position);
+        self.zoom *= factor;
+        let (new_x, new_y) = self.screen_to_world(position);
+        self.x += x - new_x;
+        self.y += y - new_y;
+    }
+
+    fn is_following(&self) -> bool {
+        self.following && self.selected.is_some()
+    }
+
+    /// Centers the camera on the selected particle if it is being followed. In reduced motion
+    /// mode the camera glides over to it instead of jumping.
+    fn follow_selected(&mut self, device: &Device, queue: &Queue) {
+        let index = match self.selected {
+            Some(index) if self.following && index < self.simulation.num_points => index,
+            _ => return,
+        };
+        let ((x, y, _), _) = self.simulation.read_particle(device, queue, index);
+        if !x.is_finite() || !y.is_finite() {
+            return;
+        }
+        let (mut dx, mut dy) = (x - self.x, y - self.y);
+        if self.display.reduced_motion {
+            // Half the smallest side of the window covers 1 / zoom world units
+            let (width, height) = self.tile_size();
+            let max_pan = (Self::REDUCED_MOTION_MAX_PAN * self.scale_factor) as f32 * 2.0
+                / width.min(height) as f32
+                / self.zoom;
+            let length = (dx * dx + dy * dy).sqrt();
+            if length > max_pan {
+                dx *= max_pan / length;
+                dy *= max_pan / length;
+            }
+        }
+        self.x += dx;
+        self.y += dy;
+    }
+
+    fn zoom_step(&self) -> f32 {
+        if self.display.reduced_motion {
+            Self::REDUCED_MOTION_ZOOM_STEP
+        } else {
+            Self::ZOOM_STEP
+        }
+    }
+
+    /// Zooms when two fingers pinch, keeping the point between them where it is
+    fn handle_touch(&mut self, touch: winit::event::Touch) {
+        use winit::event::TouchPhase;
+
+        let before = self.pinch();
+        match touch.phase {
+            TouchPhase::Started | TouchPhase::Moved => {
+                match self.touches.iter_mut().find(|(id, _)| *id == touch.id) {
+                    Some((_, location)) => *location = touch.location,
+                    None => self.touches.push((touch.id, touch.location)),
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.touches.retain(|(id, _)| *id != touch.id);
+            }
+        }
+        if touch.phase != TouchPhase::Moved {
+            return;
+        }
+        if let (Some((_, spread)), Some((center, new_spread))) = (before, self.pinch()) {
+            if spread > 0.0 {
+                self.zoom_toward((new_spread / spread) as f32, Some(center));
+            }
+        }
+    }
+
+    /// The point between the first two fingers on the screen, and how far apart they are
+    fn pinch(&self) -> Option<(winit::dpi::PhysicalPosition<f64>, f64)> {
+        match self.touches.as_slice() {
+            [(_, a), (_, b), ..] => Some((
+                winit::dpi::PhysicalPosition {
+                    x: (a.x + b.x) / 2.0,
+                    y: (a.y + b.y) / 2.0,
+                },
+                ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt(),
+            )),
+            _ => None,
+        }
+    }
+
+    /// Moves the camera for the arrow and WASD keys held down, over a frame that took `elapsed`
+    /// seconds. Returns whether it moved.
+    fn pan_with_keys(&mut self, elapsed: f32) -> bool {
+        let (mut dx, mut dy) = (0.0f32, 0.0f32);
+        for key in &self.held_pan_keys {
+            match key {
+                VirtualKeyCode::Left | VirtualKeyCode::A => dx -= 1.0,
+                VirtualKeyCode::Right | VirtualKeyCode::D => dx += 1.0,
+                VirtualKeyCode::Up | VirtualKeyCode::W => dy += 1.0,
+                VirtualKeyCode::Down | VirtualKeyCode::S => dy -= 1.0,
+                _ => {}
+            }
+        }
+        if dx == 0.0 && dy == 0.0 {
+            return false;
+        }
+        self.following = false;
+        let speed = if self.display.reduced_motion {
+            Self::REDUCED_MOTION_PAN_SPEED
+        } else {
+            Self::PAN_SPEED
+        };
+        // Half the smallest side of the window covers 1 / zoom world units
+        let distance = speed * elapsed.min(Self::MAX_PAN_FRAME_TIME) / self.zoom;
+        let length = (dx * dx + dy * dy).sqrt();
+        self.x += dx / length * distance;
+        self.y += dy / length * distance;
+        true
+    }
+
+    /// Centers the camera on the particles and zooms so that all of them are in view, or the
+    /// whole area inside the walls if there are any
+    fn fit_particles(&mut self, device: &Device, queue: &Queue) {
+        let (center, half_extent) = match self.simulation.walls {
+            Walls::Square(dist) | Walls::Wrapping(dist) => ((0.0, 0.0), dist),
+            Walls::None => {
+                let points = self.simulation.read_points(device, queue);
+                let (mut min_x, mut min_y) = (f32::INFINITY, f32::INFINITY);
+                let (mut max_x, mut max_y) = (f32::NEG_INFINITY, f32::NEG_INFINITY);
+                for &(x, y, _) in points
+                    .iter()
+                    .filter(|(x, y, _)| x.is_finite() && y.is_finite())
+                {
+                    min_x = min_x.min(x);
+                    min_y = min_y.min(y);
+                    max_x = max_x.max(x);
+                    max_y = max_y.max(y);
+                }
+                if min_x > max_x {
+                    return;
+                }
+                (
+                    ((min_x + max_x) / 2.0, (min_y + max_y) / 2.0),
+                    ((max_x - min_x).max(max_y - min_y) / 2.0).max(Self::CIRCLE_RADIUS),
+                )
+            }
+        };
+        self.x = center.0;
+        self.y = center.1;
+        self.zoom = 1.0 / (half_extent * (1.0 + Self::FIT_MARGIN) + Self::CIRCLE_RADIUS);
+    }
+
+    /// Adds particles around the cursor while the brush is held down, or removes them while the
+    /// eraser is. Returns whether any were added or removed.
+    fn paint(&mut self, device: &Device, queue: &Queue) -> bool {
+        let position = match self.last_mouse_position.filter(|_| self.painting) {
+            Some(position) => position,
+            None => return false,
+        };
+        let (x, y) = self.screen_to_world(position);
+        let (width, height) = self.tile_size();
+        let smallest_dimension = width.min(height) as f32;
+        let to_world = |pixels: f64| {
+            (pixels * self.scale_factor) as f32 * 2.0 / smallest_dimension / self.zoom
+        };
+        if self.erasing {
+            let radius = to_world(Self::ERASER_RADIUS);
+            if self.simulation.erase(device, queue, x, y, radius) == 0 {
+                return false;
+            }
+            // The remaining particles moved, so the selection would point at another one
+            self.selected = None;
+            return true;
+        }
+        let radius = to_world(Self::BRUSH_RADIUS);
+        let points = (0..Self::BRUSH_PER_FRAME)
+            .map(|_| {
+                // Evenly spread over the disc under the cursor
+                let (angle, distance) = with_rng(|rng| {
+                    (
+                        rng.gen_range(0.0..std::f32::consts::TAU),
+                        radius * rng.gen::<f32>().sqrt(),
+                    )
+                });
+                (
+                    x + angle.cos() * distance,
+                    y + angle.sin() * distance,
+                    Some(self.brush_type),
+                )
+            })
+            .collect::<Vec<_>>();
+        if self.simulation.spawn(queue, &points) == 0 {
+            info!("No room for more particles; raise max_points in the config");
+            self.painting = false;
+            return false;
+        }
+        true
+    }
+
+    /// Pulls particles toward the cursor, or pushes them away while shift is held, for as long as
+    /// the right mouse button is held
+    fn apply_cursor_force(&mut self) {
+        let position = match self.last_mouse_position.filter(|_| self.force_held) {
+            Some(position) => position,
+            None => {
+                self.simulation.set_cursor_force(0.0, 0.0, 0.0, 0.0);
+                return;
+            }
+        };
+        let (x, y) = self.screen_to_world(position);
+        // Half the smallest side of the window covers 1 / zoom world units
+        let (width, height) = self.tile_size();
+        let smallest_dimension = width.min(height) as f32;
+        let radius =
+            (Self::FORCE_RADIUS * self.scale_factor) as f32 * 2.0 / smallest_dimension / self.zoom;
+        let direction = if self.modifiers.shift() { -1.0 } else { 1.0 };
+        self.simulation
+            .set_cursor_force(x, y, radius * Self::FORCE_PULL * direction, radius);
+    }
+
+    /// Picks the type of the particle under the cursor. Once two types have been picked, the
+    /// interaction between them is switched off, or back on if it already was.
+    fn pick_type(&mut self, device: &Device, queue: &Queue) {
+        let position = match self.last_mouse_position {
+            Some(position) => position,
+            None => return,
+        };
+        let (x, y) = self.screen_to_world(position);
+        let nearest = self
+            .simulation
+            .read_points(device, queue)
+            .into_iter()
+            .map(|(px, py, type_)| ((px - x) * (px - x) + (py - y) * (py - y), type_))
+            .filter(|&(d2, _)| d2 <= Self::PICK_RADIUS * Self::PICK_RADIUS)
+            .min_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
+        let type_ = match nearest {
+            Some((_, Some(type_))) => type_,
+            _ => {
+                info!("No particle there");
+                return;
+            }
+        };
+        match self.picked_type.take() {
+            None => {
+                info!(
+                    "Picked type {}, middle click another particle to toggle",
+                    type_
+                );
+                self.picked_type = Some(type_);
+            }
+            Some(first) => {
+                let enabled = !self.simulation.interaction_enabled(first, type_);
+                self.simulation
+                    .set_interaction_enabled(queue, first, type_, enabled);
+                self.matrix.clear();
+                info!(
+                    "Interaction between types {} and {} {}",
+                    first,
+                    type_,
+                    if enabled { "restored" } else { "disabled" }
+                );
+            }
+        }
     }
 
     fn handle_window_event(
@@ -301,14 +1704,35 @@ impl Visualization {
         window_event: WindowEvent,
         control_flow: &mut ControlFlow,
         device: &Device,
+        queue: &Queue,
         surface: &Surface,
+        window: &Window,
         mouse_down: &mut bool,
     ) {
+        self.needs_redraw = true;
         match window_event {
             WindowEvent::Resized(size) => {
-                self.sc_desc.width = size.width;
-                self.sc_desc.height = size.height;
-                self.swapchain = device.create_swap_chain(&surface, &self.sc_desc);
+                self.resize(device, surface, size.width, size.height);
+            }
+            WindowEvent::ScaleFactorChanged {
+                scale_factor,
+                new_inner_size,
+            } => {
+                self.scale_factor = scale_factor;
+                self.matrix.clear();
+                self.resize(device, surface, new_inner_size.width, new_inner_size.height);
+                if self.hud_shown() {
+                    self.update_hud(device, queue);
+                }
+            }
+            // Keys released while another window has focus are never reported
+            WindowEvent::Focused(false) => {
+                self.held_pan_keys.clear();
+                self.force_held = false;
+                self.painting = false;
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers;
             }
             WindowEvent::CloseRequested => {
                 *control_flow = ControlFlow::Exit;
@@ -317,6 +1741,172 @@ impl Visualization {
                 Some(VirtualKeyCode::Escape) => {
                     *control_flow = ControlFlow::Exit;
                 }
+                Some(key @ VirtualKeyCode::Left)
+                | Some(key @ VirtualKeyCode::Right)
+                | Some(key @ VirtualKeyCode::Up)
+                | Some(key @ VirtualKeyCode::Down)
+                | Some(key @ VirtualKeyCode::W)
+                | Some(key @ VirtualKeyCode::A)
+                | Some(key @ VirtualKeyCode::S)
+                | Some(key @ VirtualKeyCode::D) => {
+                    self.held_pan_keys.retain(|&held| held != key);
+                    if input.state == winit::event::ElementState::Pressed {
+                        self.held_pan_keys.push(key);
+                    }
+                }
+                Some(VirtualKeyCode::Home)
+                    if input.state == winit::event::ElementState::Pressed =>
+                {
+                    self.x = 0.0;
+                    self.y = 0.0;
+                    self.zoom = Self::DEFAULT_ZOOM;
+                    self.following = false;
+                }
+                Some(VirtualKeyCode::F) if input.state == winit::event::ElementState::Pressed => {
+                    self.fit_particles(device, queue);
+                    self.following = false;
+                }
+                Some(VirtualKeyCode::L) if input.state == winit::event::ElementState::Pressed => {
+                    self.following = !self.following;
+                    self.follow_selected(device, queue);
+                }
+                Some(VirtualKeyCode::Space)
+                    if input.state == winit::event::ElementState::Pressed =>
+                {
+                    self.paused = !self.paused;
+                    if !self.paused && self.rewind.is_scrubbing() {
+                        self.rewind.resume();
+                        self.update_hud(device, queue);
+                    }
+                }
+                Some(VirtualKeyCode::J) if input.state == winit::event::ElementState::Pressed => {
+                    self.scrub(device, queue, -1);
+                }
+                Some(VirtualKeyCode::K) if input.state == winit::event::ElementState::Pressed => {
+                    self.scrub(device, queue, 1);
+                }
+                Some(VirtualKeyCode::C) if input.state == winit::event::ElementState::Pressed => {
+                    self.show_clusters = !self.show_clusters;
+                    self.update_hud(device, queue);
+                }
+                Some(VirtualKeyCode::E) if input.state == winit::event::ElementState::Pressed => {
+                    self.show_energy = !self.show_energy;
+                    self.update_hud(device, queue);
+                }
+                Some(VirtualKeyCode::T) if input.state == winit::event::ElementState::Pressed => {
+                    if self.tiles.is_empty() {
+                        self.show_territory = !self.show_territory;
+                    } else {
+                        info!("Territories are not shown with --grid");
+                    }
+                }
+                Some(VirtualKeyCode::V) if input.state == winit::event::ElementState::Pressed => {
+                    self.speed_colors = !self.speed_colors;
+                }
+                Some(VirtualKeyCode::G) if input.state == winit::event::ElementState::Pressed => {
+                    if self.tiles.is_empty() {
+                        self.show_density = !self.show_density;
+                    } else {
+                        info!("The density map is not shown with --grid");
+                    }
+                }
+                Some(VirtualKeyCode::F11) if input.state == winit::event::ElementState::Pressed => {
+                    window.set_fullscreen(match window.fullscreen() {
+                        Some(_) => None,
+                        None => Some(Fullscreen::Borderless(None)),
+                    });
+                }
+                Some(VirtualKeyCode::F5) if input.state == winit::event::ElementState::Pressed => {
+                    self.save_session(device, queue);
+                }
+                Some(VirtualKeyCode::P) if input.state == winit::event::ElementState::Pressed => {
+                    self.reroll_points(queue);
+                }
+                Some(VirtualKeyCode::R) if input.state == winit::event::ElementState::Pressed => {
+                    self.reroll_ruleset(queue);
+                }
+                Some(VirtualKeyCode::O) if input.state == winit::event::ElementState::Pressed => {
+                    self.reroll_colors(queue);
+                }
+                Some(VirtualKeyCode::H) if input.state == winit::event::ElementState::Pressed => {
+                    self.show_matrix = !self.show_matrix;
+                }
+                Some(VirtualKeyCode::F3) if input.state == winit::event::ElementState::Pressed => {
+                    self.show_hud = !self.show_hud;
+                    self.update_hud(device, queue);
+                }
+                Some(VirtualKeyCode::B) if input.state == winit::event::ElementState::Pressed => {
+                    self.brushing = !self.brushing;
+                    self.painting = false;
+                    if self.brushing {
+                        self.inspecting = false;
+                        self.erasing = false;
+                        info!(
+                            "Brush on, adding particles of type {}; Tab changes the type",
+                            self.brush_type
+                        );
+                    } else {
+                        info!("Brush off");
+                    }
+                }
+                Some(VirtualKeyCode::X) if input.state == winit::event::ElementState::Pressed => {
+                    self.erasing = !self.erasing;
+                    self.painting = false;
+                    if self.erasing {
+                        self.inspecting = false;
+                        self.brushing = false;
+                        info!("Eraser on");
+                    } else {
+                        info!("Eraser off");
+                    }
+                }
+                Some(VirtualKeyCode::Tab)
+                    if self.brushing && input.state == winit::event::ElementState::Pressed =>
+                {
+                    let num_types = self.simulation.ruleset.num_point_types;
+                    self.brush_type = if self.modifiers.shift() {
+                        (self.brush_type + num_types - 1) % num_types
+                    } else {
+                        (self.brush_type + 1) % num_types
+                    };
+                    info!("Brush type {}", self.brush_type);
+                }
+                Some(VirtualKeyCode::I) if input.state == winit::event::ElementState::Pressed => {
+                    self.inspecting = !self.inspecting;
+                    if self.inspecting {
+                        self.brushing = false;
+                        self.erasing = false;
+                        self.painting = false;
+                    }
+                    if !self.inspecting && self.selected.take().is_some() {
+                        self.update_hud(device, queue);
+                    }
+                }
+                Some(VirtualKeyCode::M) if input.state == winit::event::ElementState::Pressed => {
+                    self.show_trails = !self.show_trails;
+                    // Start from black again rather than from wherever the old trails were left
+                    self.trails = None;
+                }
+                Some(VirtualKeyCode::Back)
+                    if input.state == winit::event::ElementState::Pressed =>
+                {
+                    self.simulation.enable_all_interactions(queue);
+                    self.matrix.clear();
+                    self.picked_type = None;
+                    info!("All interactions restored");
+                }
+                Some(VirtualKeyCode::Comma) | Some(VirtualKeyCode::Period)
+                    if input.state == winit::event::ElementState::Pressed =>
+                {
+                    let step = if input.virtual_keycode == Some(VirtualKeyCode::Comma) {
+                        -Self::RESTITUTION_STEP
+                    } else {
+                        Self::RESTITUTION_STEP
+                    };
+                    let restitution = self.simulation.restitution() + step;
+                    self.simulation.set_restitution(restitution);
+                    info!("Wall restitution: {:.2}", self.simulation.restitution());
+                }
                 Some(VirtualKeyCode::LBracket) => {
                     if let Some(new_tps) = self.ticks_per_frame.checked_sub(1) {
                         self.ticks_per_frame = new_tps;
@@ -332,21 +1922,16 @@ impl Visualization {
             WindowEvent::CursorMoved { position, .. } => {
                 if let Some(last_pos) = self.last_mouse_position {
                     if *mouse_down {
+                        self.following = false;
+                        let (dx, dy) = (position.x - last_pos.x, position.y - last_pos.y);
+                        let (width, height) = self.tile_size();
                         let delta = winit::dpi::PhysicalPosition {
-                            x: (position.x - last_pos.x)
-                                / self.zoom as f64
-                                / self.sc_desc.width as f64,
-                            y: (position.y - last_pos.y)
-                                / self.zoom as f64
-                                / self.sc_desc.height as f64,
+                            x: dx / self.zoom as f64 / width as f64,
+                            y: dy / self.zoom as f64 / height as f64,
                         };
-                        let smallest_dimension = self.sc_desc.width.min(self.sc_desc.height);
-                        self.x -= delta.x as f32 * self.sc_desc.width as f32
-                            / smallest_dimension as f32
-                            * 2.0;
-                        self.y += delta.y as f32 * self.sc_desc.height as f32
-                            / smallest_dimension as f32
-                            * 2.0;
+                        let smallest_dimension = width.min(height);
+                        self.x -= delta.x as f32 * width as f32 / smallest_dimension as f32 * 2.0;
+                        self.y += delta.y as f32 * height as f32 / smallest_dimension as f32 * 2.0;
                     }
                 }
                 self.last_mouse_position = Some(position);
@@ -356,32 +1941,75 @@ impl Visualization {
                 phase: winit::event::TouchPhase::Moved,
                 ..
             } => {
-                if lines > 0.0 {
-                    self.zoom *= 1.1;
-                } else {
-                    self.zoom /= 1.1;
-                }
+                let step = self.zoom_step();
+                self.zoom_toward_cursor(if lines > 0.0 { step } else { 1.0 / step });
+            }
+            // Touchpads scroll smoothly, by a number of pixels rather than whole lines
+            WindowEvent::MouseWheel {
+                delta: winit::event::MouseScrollDelta::PixelDelta(delta),
+                ..
+            } => {
+                let lines = delta.y / self.scale_factor / Self::PIXELS_PER_LINE;
+                self.zoom_toward_cursor(self.zoom_step().powf(lines as f32));
             }
-            WindowEvent::MouseInput { state, button, .. } => {
-                if let winit::event::MouseButton::Left = button {
+            WindowEvent::Touch(touch) => self.handle_touch(touch),
+            WindowEvent::MouseInput { state, button, .. } => match button {
+                winit::event::MouseButton::Left if self.brushing || self.erasing => {
+                    self.painting = state == winit::event::ElementState::Pressed;
+                }
+                winit::event::MouseButton::Left
+                    if self.inspecting && state == winit::event::ElementState::Pressed =>
+                {
+                    self.select_particle(device, queue);
+                }
+                winit::event::MouseButton::Left => {
                     *mouse_down = state == winit::event::ElementState::Pressed;
                 }
-            }
+                winit::event::MouseButton::Right => {
+                    self.force_held = state == winit::event::ElementState::Pressed;
+                }
+                winit::event::MouseButton::Middle
+                    if state == winit::event::ElementState::Pressed =>
+                {
+                    self.pick_type(device, queue);
+                }
+                _ => {}
+            },
             _ => {}
         }
     }
 
     pub fn run(
         mut self,
-        device: Device,
-        queue: Queue,
-        _window: Window,
+        mut device: Device,
+        mut queue: Queue,
+        adapter: Adapter,
+        window: Window,
         surface: Surface,
         event_loop: EventLoop<()>,
+        mut capture: Option<FrameCapture>,
     ) -> ! {
+        // A device that is lost is replaced by one with the same features and limits
+        let features = device.features();
+        let limits = device.limits();
+        let mut recovery_point = self.recovery_point(&device, &queue);
+        let mut last_recovery_point = Instant::now();
         let mut mouse_down = false;
+        let mut frame: u64 = 0;
+        let mut last_frame = Instant::now();
         event_loop.run(move |event, _, control_flow| {
-            *control_flow = ControlFlow::Poll;
+            // While paused, sleep until an event arrives instead of redrawing continuously, unless
+            // the camera is being moved with the keyboard or particles are being painted
+            *control_flow = if self.paused && self.held_pan_keys.is_empty() && !self.painting {
+                // Scripts can resume the simulation and knobs can be turned, so commands and MIDI
+                // messages are looked for now and then
+                match (&self.controller, &self.midi) {
+                    (None, None) => ControlFlow::Wait,
+                    _ => ControlFlow::WaitUntil(Instant::now() + control::POLL_INTERVAL),
+                }
+            } else {
+                ControlFlow::Poll
+            };
             match event {
                 winit::event::Event::WindowEvent {
                     event: window_event,
@@ -391,18 +2019,159 @@ impl Visualization {
                         window_event,
                         control_flow,
                         &device,
+                        &queue,
                         &surface,
+                        &window,
                         &mut mouse_down,
                     );
                 }
                 winit::event::Event::MainEventsCleared => {
                     while self.executor.try_tick() {}
-                    self.update(&device, &queue);
-                    self.render(&device, &queue);
+                    let now = Instant::now();
+                    if self.pan_with_keys((now - last_frame).as_secs_f32()) {
+                        self.needs_redraw = true;
+                    }
+                    if self.paint(&device, &queue) {
+                        self.needs_redraw = true;
+                    }
+                    if let Some(controller) = &mut self.controller {
+                        if controller.handle(
+                            &device,
+                            &queue,
+                            &mut self.simulation,
+                            self.ticks,
+                            &mut self.paused,
+                        ) {
+                            self.needs_redraw = true;
+                        }
+                    }
+                    if let Some(midi) = &self.midi {
+                        if midi.apply(&mut self.simulation, &mut self.ticks_per_frame) {
+                            self.needs_redraw = true;
+                        }
+                    }
+                    last_frame = now;
+                    if self.paused && !self.needs_redraw {
+                        return;
+                    }
+                    self.needs_redraw = false;
+                    if let Some(capture) = &mut capture {
+                        capture.begin(frame);
+                    }
+                    if !self.paused {
+                        self.apply_cursor_force();
+                        self.update(&device, &queue);
+                        self.follow_selected(&device, &queue);
+                    }
+                    if let Err(e) = self.render(&device, &queue, &surface) {
+                        let recovered = futures::executor::block_on(adapter.request_device(
+                            &DeviceDescriptor {
+                                label: Some("main device"),
+                                features,
+                                limits: limits.clone(),
+                            },
+                            None,
+                        ))
+                        .map_err(|e| e.to_string())
+                        .and_then(|(new_device, new_queue)| {
+                            let recovery_point = recovery_point
+                                .as_ref()
+                                .ok_or_else(|| "nothing to restart from".to_owned())?;
+                            self.recover(
+                                &new_device,
+                                &new_queue,
+                                &adapter,
+                                &surface,
+                                &window,
+                                recovery_point,
+                            )?;
+                            Ok((new_device, new_queue))
+                        });
+                        match recovered {
+                            Ok((new_device, new_queue)) => {
+                                warn!(
+                                    "The graphics device was lost ({}); restarted it from {:.0} seconds ago",
+                                    e,
+                                    last_recovery_point.elapsed().as_secs_f32()
+                                );
+                                device = new_device;
+                                queue = new_queue;
+                                window.set_title(&self.title);
+                            }
+                            Err(reason) => exit_with(
+                                GpuError(format!(
+                                    "The graphics device was lost ({}), and cannot be restarted: {}",
+                                    e, reason
+                                ))
+                                .into(),
+                            ),
+                        }
+                        return;
+                    }
+                    if last_recovery_point.elapsed() >= Self::RECOVERY_INTERVAL {
+                        recovery_point = self.recovery_point(&device, &queue);
+                        last_recovery_point = Instant::now();
+                    }
+                    let ticks = if self.paused { 0 } else { self.ticks_just_now };
+                    if self.hud.record_frame(ticks) && self.hud_shown() {
+                        self.update_hud(&device, &queue);
+                    }
+                    if let Some(capture) = &mut capture {
+                        capture.end(frame);
+                    }
+                    frame += 1;
+                }
+                winit::event::Event::LoopDestroyed => {
+                    if let Some(npz) = self.npz.take() {
+                        let path = npz.path().to_owned();
+                        match npz.finish() {
+                            Ok(()) => info!("Exported positions to {}", path.display()),
+                            Err(e) => error!("Cannot write {}: {}", path.display(), e),
+                        }
+                    }
+                    if let Some(trajectory) = self.trajectory.take() {
+                        if let Err(e) = trajectory.finish() {
+                            error!("Cannot finish trajectory: {}", e);
+                        }
+                    }
                 }
-                winit::event::Event::LoopDestroyed => {}
                 _ => {}
             }
         })
     }
 }
+
+/// Binds what render.wgsl reads of `simulation`, along with what all simulations are drawn with
+fn render_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    simulation: &Simulation,
+    render_globals: &BindableBuffer,
+    colors: &BindableBuffer,
+    trail_styles: &BindableBuffer,
+) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        label: Some("render_bind_group"),
+        layout,
+        entries: &[
+            simulation.globals.bind_group_entry(0),
+            render_globals.bind_group_entry(1),
+            simulation.types.bind_group_entry(2),
+            colors.bind_group_entry(3),
+            simulation.velocities.bind_group_entry(4),
+            trail_styles.bind_group_entry(5),
+        ],
+    })
+}
+
+fn random_colors(num_types: PointType, high_contrast: bool) -> Vec<[f32; 3]> {
+    (0..num_types)
+        .map(|_| {
+            if high_contrast {
+                hue_to_rgb(rand::random::<f32>())
+            } else {
+                [rand::random(), rand::random(), rand::random()]
+            }
+        })
+        .collect()
+}
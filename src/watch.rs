@@ -0,0 +1,199 @@
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use structopt::StructOpt;
+use tracing::{info, warn};
+use wgpu::*;
+
+use crate::{
+    batch::config_files,
+    serialize::{write_csv_points, Config, ConfigFormat},
+    simulation::Simulation,
+    snapshot,
+};
+
+/// Most steps submitted at once between checks for Ctrl-C
+const STEPS_PER_BATCH: u64 = 64;
+
+/// Options for rendering every config dropped into a directory, unattended
+#[derive(StructOpt)]
+pub struct WatchOptions {
+    /// Keep rendering config files as they appear in this directory, moving each one to
+    /// `--watch-done` when it is finished
+    #[structopt(long)]
+    watch_dir: Option<PathBuf>,
+    /// Steps to run each config for
+    #[structopt(long, default_value = "3000")]
+    watch_steps: u64,
+    /// Directory to write renders to, one subdirectory per config
+    #[structopt(long, default_value = "renders")]
+    watch_out: PathBuf,
+    /// Directory finished configs are moved to; `done` inside the watched directory if omitted
+    #[structopt(long)]
+    watch_done: Option<PathBuf>,
+    /// Also save a frame every this many steps, for assembling into a video
+    #[structopt(long)]
+    frame_interval: Option<u64>,
+    /// Seconds between looks at the watched directory
+    #[structopt(long, default_value = "2")]
+    poll_interval: f32,
+}
+
+impl WatchOptions {
+    pub fn is_active(&self) -> bool {
+        self.watch_dir.is_some()
+    }
+
+    /// Renders configs from the watched directory as they arrive, until interrupted with Ctrl-C
+    pub fn run(&self, device: &Device, queue: &Queue, workgroup_size: u32) -> anyhow::Result<()> {
+        let dir = self.watch_dir.as_deref().unwrap();
+        let done_dir = self.watch_done.clone().unwrap_or_else(|| dir.join("done"));
+        let failed_dir = dir.join("failed");
+        for dir in [&done_dir, &failed_dir, &self.watch_out].iter() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Cannot create directory {}", dir.display()))?;
+        }
+        let poll_interval = Duration::from_secs_f32(self.poll_interval.max(0.1));
+
+        let broken = Arc::new(AtomicBool::new(false));
+        let b = broken.clone();
+        ctrlc::set_handler(move || {
+            b.store(true, Ordering::Relaxed);
+        })
+        .expect("Error setting Ctrl-C handler");
+
+        info!("Watching {} for config files", dir.display());
+        let mut rendered = 0;
+        while !broken.load(Ordering::Relaxed) {
+            // Files still being written are left for a later look
+            let arrived = config_files(dir)?
+                .into_iter()
+                .filter(|path| is_settled(path, poll_interval))
+                .collect::<Vec<_>>();
+            if arrived.is_empty() {
+                thread::sleep(poll_interval);
+                continue;
+            }
+            for path in arrived {
+                if broken.load(Ordering::Relaxed) {
+                    break;
+                }
+                let file_name = path.file_name().unwrap_or_default();
+                info!("Rendering {}", path.display());
+                let config = File::open(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|file| {
+                        Config::from_reader(file, ConfigFormat::from_path(&path), &[])
+                    });
+                let config = match config {
+                    Ok(config) => config,
+                    Err(e) => {
+                        warn!("Skipping invalid config {}:\n{}", path.display(), e);
+                        move_file(&path, &failed_dir.join(file_name))?;
+                        continue;
+                    }
+                };
+                let out_dir = self.watch_out.join(path.file_stem().unwrap_or_default());
+                if self.render(device, queue, config, workgroup_size, &out_dir, &broken)? {
+                    move_file(&path, &done_dir.join(file_name))?;
+                    rendered += 1;
+                    info!(
+                        "Finished {}, render is in {}",
+                        path.display(),
+                        out_dir.display()
+                    );
+                }
+            }
+        }
+        info!("Stopped watching after rendering {} configs", rendered);
+        Ok(())
+    }
+
+    /// Simulates one config, saving frames along the way and a thumbnail and the final state at
+    /// the end. Returns false if interrupted, leaving the config to be rendered again next time.
+    fn render(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        config: Config,
+        workgroup_size: u32,
+        out_dir: &Path,
+        broken: &AtomicBool,
+    ) -> anyhow::Result<bool> {
+        let frames_dir = out_dir.join("frames");
+        let render_dir = if self.frame_interval.is_some() {
+            &frames_dir
+        } else {
+            out_dir
+        };
+        fs::create_dir_all(render_dir)
+            .with_context(|| format!("Cannot create render directory {}", render_dir.display()))?;
+
+        let mut simulation = Simulation::from_config(device, config, workgroup_size)?;
+        let num_types = simulation.ruleset.num_point_types;
+        let mut frame = 0;
+        // Steps between frames are submitted together, a few at a time so that Ctrl-C is noticed
+        let interval = self.frame_interval.unwrap_or(STEPS_PER_BATCH).max(1);
+        let mut step = 0;
+        while step < self.watch_steps {
+            if broken.load(Ordering::Relaxed) {
+                return Ok(false);
+            }
+            let batch = (interval - step % interval)
+                .min(STEPS_PER_BATCH)
+                .min(self.watch_steps - step);
+            simulation.step_many(device, queue, batch);
+            step += batch;
+            if let Some(interval) = self.frame_interval {
+                if step % interval.max(1) == 0 {
+                    let frame_path = frames_dir.join(format!("{:06}.png", frame));
+                    snapshot::save_png(
+                        &frame_path,
+                        &simulation.read_points(device, queue),
+                        num_types,
+                        &simulation.walls,
+                    )
+                    .with_context(|| format!("Cannot write {}", frame_path.display()))?;
+                    frame += 1;
+                }
+            }
+        }
+
+        let points = simulation.read_points(device, queue);
+        let final_path = out_dir.join("final.csv");
+        write_csv_points(&final_path, &points)
+            .with_context(|| format!("Cannot write {}", final_path.display()))?;
+        let thumbnail_path = out_dir.join("thumbnail.png");
+        snapshot::save_png(&thumbnail_path, &points, num_types, &simulation.walls)
+            .with_context(|| format!("Cannot write {}", thumbnail_path.display()))?;
+        Ok(true)
+    }
+}
+
+/// Whether a file has gone unmodified for at least `quiet_time`
+fn is_settled(path: &Path, quiet_time: Duration) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map(|modified| {
+            SystemTime::now()
+                .duration_since(modified)
+                .map_or(false, |age| age >= quiet_time)
+        })
+        .unwrap_or(false)
+}
+
+/// Moves a config out of the watched directory. Watching stops if that fails, since the config
+/// would otherwise be rendered over and over.
+fn move_file(from: &Path, to: &Path) -> anyhow::Result<()> {
+    fs::rename(from, to)
+        .with_context(|| format!("Cannot move {} to {}", from.display(), to.display()))
+}